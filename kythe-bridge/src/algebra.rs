@@ -0,0 +1,391 @@
+//! Set operations between two graphs, such as finding the dependencies present
+//! in a release build but not in a debug build.
+//!
+//! Nodes are matched by stable id (see `StableId`) and edges by their kind and
+//! the stable ids of their endpoints, so the graphs may come from entirely
+//! separate runs. As the root is left out of the stable id, the same file
+//! generated under different output directories (e.g. `bazel-out/k8-opt` and
+//! `bazel-out/k8-dbg`) is matched too.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::io::Ticket;
+use crate::ir::{EdgeKind, FilePath, NodeIndex, RawEdge, RawGraph, RawNodeValue, StableId};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Every node and edge in either graph. Edges in both keep the larger
+    /// count.
+    Union,
+    /// Only the nodes and edges in both graphs. Edges keep the smaller count.
+    Intersect,
+    /// The edges of the first graph which are not in the second, along with
+    /// their endpoints and any nodes only in the first graph. Each of these
+    /// nodes keeps what places it (see `Side::add_containment`), so that the
+    /// result can still be turned into entities.
+    Subtract,
+}
+
+type EdgeKey = (EdgeKind, StableId, StableId);
+
+fn stable_id(ticket: &Ticket) -> StableId {
+    StableId::new(&FilePath::from(ticket), ticket.signature.as_deref())
+}
+
+/// Collects the nodes and edges of the result, merging the facts of nodes
+/// which are added more than once. The first ticket added for each stable id
+/// is kept.
+#[derive(Default)]
+struct Builder {
+    tickets: Vec<Ticket>,
+    nodes: Vec<RawNodeValue>,
+    indices: HashMap<StableId, NodeIndex>,
+    edges: Vec<RawEdge>,
+}
+
+impl Builder {
+    fn node(&mut self, id: StableId, ticket: &Ticket, value: &RawNodeValue) -> NodeIndex {
+        match self.indices.get(&id) {
+            Some(index) => {
+                self.nodes[index.0].merge(value.clone());
+                *index
+            }
+            None => {
+                let index = NodeIndex(self.nodes.len());
+                self.tickets.push(ticket.clone());
+                self.nodes.push(value.clone());
+                self.indices.insert(id, index);
+                index
+            }
+        }
+    }
+
+    fn edge(&mut self, kind: EdgeKind, src: NodeIndex, tgt: NodeIndex, count: usize) {
+        self.edges.push((kind, src, tgt, count));
+    }
+
+    fn build(self) -> RawGraph {
+        RawGraph::from_parts(self.tickets, self.nodes, self.edges)
+    }
+}
+
+/// One side of an operation, decomposed so that nodes and edges can be looked
+/// up by stable id.
+struct Side {
+    tickets: Vec<Ticket>,
+    ids: Vec<StableId>,
+    nodes: Vec<RawNodeValue>,
+    edges: Vec<RawEdge>,
+}
+
+impl Side {
+    fn new(graph: RawGraph) -> Self {
+        let (tickets, nodes, mut edges) = graph.into_parts();
+        // So that the result does not depend on the order edges are stored in
+        edges.sort();
+        let ids = tickets.iter().map(stable_id).collect();
+        Side { tickets, ids, nodes, edges }
+    }
+
+    fn node_indices(&self) -> HashMap<StableId, NodeIndex> {
+        self.ids.iter().enumerate().map(|(i, id)| (*id, NodeIndex(i))).collect()
+    }
+
+    fn edge_key(&self, kind: EdgeKind, src: NodeIndex, tgt: NodeIndex) -> EdgeKey {
+        (kind, self.ids[src.0], self.ids[tgt.0])
+    }
+
+    fn edge_counts(&self) -> HashMap<EdgeKey, usize> {
+        self.edges
+            .iter()
+            .map(|&(kind, src, tgt, count)| (self.edge_key(kind, src, tgt), count))
+            .collect()
+    }
+
+    fn add_node(&self, builder: &mut Builder, index: NodeIndex) -> NodeIndex {
+        builder.node(self.ids[index.0], &self.tickets[index.0], &self.nodes[index.0])
+    }
+
+    fn add_edge(&self, builder: &mut Builder, position: usize) {
+        let (kind, src, tgt, count) = self.edges[position];
+        let (src, tgt) = (self.add_node(builder, src), self.add_node(builder, tgt));
+        builder.edge(kind, src, tgt, count);
+    }
+
+    /// Add to `nodes` (and `edges`, as positions in `self.edges`) everything
+    /// needed to place the nodes already in `nodes`: their `childof`
+    /// ancestors, the anchors which bind them, and the file each belongs to,
+    /// along with the edges between them.
+    fn add_containment(&self, nodes: &mut BTreeSet<NodeIndex>, edges: &mut BTreeSet<usize>) {
+        let indices = self.node_indices();
+        let mut parents: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+        let mut bindings: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+
+        for (position, (kind, src, tgt, _)) in self.edges.iter().enumerate() {
+            match kind {
+                EdgeKind::Childof | EdgeKind::ChildofContext => {
+                    parents.entry(*src).or_default().push(position)
+                }
+                EdgeKind::DefinesBinding => bindings.entry(*tgt).or_default().push(position),
+                _ => {}
+            }
+        }
+
+        let mut stack = nodes.iter().copied().collect::<Vec<_>>();
+
+        while let Some(index) = stack.pop() {
+            let mut related = Vec::new();
+
+            for &position in parents.get(&index).into_iter().flatten() {
+                edges.insert(position);
+                related.push(self.edges[position].2);
+            }
+
+            for &position in bindings.get(&index).into_iter().flatten() {
+                edges.insert(position);
+                related.push(self.edges[position].1);
+            }
+
+            let file = StableId::new(&FilePath::from(&self.tickets[index.0]), None);
+            related.extend(indices.get(&file).copied());
+
+            for index in related {
+                if nodes.insert(index) {
+                    stack.push(index);
+                }
+            }
+        }
+    }
+}
+
+/// Combine two graphs with the given operation.
+pub fn combine(a: RawGraph, b: RawGraph, op: Operation) -> RawGraph {
+    let (a, b) = (Side::new(a), Side::new(b));
+    let mut builder = Builder::default();
+
+    match op {
+        Operation::Union => {
+            for side in [&a, &b] {
+                for i in 0..side.tickets.len() {
+                    side.add_node(&mut builder, NodeIndex(i));
+                }
+            }
+
+            // The position of each edge in the builder, so that an edge in
+            // both graphs is only added once
+            let mut positions: HashMap<EdgeKey, usize> = HashMap::new();
+
+            for side in [&a, &b] {
+                for &(kind, src, tgt, count) in &side.edges {
+                    let key = side.edge_key(kind, src, tgt);
+
+                    match positions.get(&key) {
+                        Some(&position) => {
+                            let max = &mut builder.edges[position].3;
+                            *max = (*max).max(count);
+                        }
+                        None => {
+                            positions.insert(key, builder.edges.len());
+                            let (src, tgt) = (builder.indices[&key.1], builder.indices[&key.2]);
+                            builder.edge(kind, src, tgt, count);
+                        }
+                    }
+                }
+            }
+        }
+        Operation::Intersect => {
+            let b_indices = b.node_indices();
+            let b_counts = b.edge_counts();
+
+            for (i, id) in a.ids.iter().enumerate() {
+                if let Some(b_index) = b_indices.get(id) {
+                    a.add_node(&mut builder, NodeIndex(i));
+                    b.add_node(&mut builder, *b_index);
+                }
+            }
+
+            for &(kind, src, tgt, count) in &a.edges {
+                let key = a.edge_key(kind, src, tgt);
+
+                if let Some(b_count) = b_counts.get(&key) {
+                    let (src, tgt) = (builder.indices[&key.1], builder.indices[&key.2]);
+                    builder.edge(kind, src, tgt, count.min(*b_count));
+                }
+            }
+        }
+        Operation::Subtract => {
+            let b_ids: HashSet<StableId> = b.ids.iter().copied().collect();
+            let b_counts = b.edge_counts();
+            let mut nodes = BTreeSet::new();
+            let mut edges = BTreeSet::new();
+
+            for (i, id) in a.ids.iter().enumerate() {
+                if !b_ids.contains(id) {
+                    nodes.insert(NodeIndex(i));
+                }
+            }
+
+            for (position, &(kind, src, tgt, _)) in a.edges.iter().enumerate() {
+                if !b_counts.contains_key(&a.edge_key(kind, src, tgt)) {
+                    nodes.extend([src, tgt]);
+                    edges.insert(position);
+                }
+            }
+
+            a.add_containment(&mut nodes, &mut edges);
+
+            for index in nodes {
+                a.add_node(&mut builder, index);
+            }
+
+            for position in edges {
+                a.add_edge(&mut builder, position);
+            }
+        }
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TicketEdge = (EdgeKind, Option<String>, Option<String>, usize);
+
+    fn ticket(root: &str, signature: Option<&str>) -> Ticket {
+        Ticket {
+            corpus: Some("corpus".to_string()),
+            path: Some("a.cc".to_string()),
+            root: Some(root.to_string()),
+            signature: signature.map(str::to_string),
+            ..Ticket::default()
+        }
+    }
+
+    /// A file, a class, a method of the class bound by an anchor in the file,
+    /// and one other node (in that order).
+    fn graph(edges: &[(EdgeKind, usize, usize, usize)]) -> RawGraph {
+        graph_in("", edges)
+    }
+
+    fn graph_in(root: &str, edges: &[(EdgeKind, usize, usize, usize)]) -> RawGraph {
+        let signatures = [None, Some("C"), Some("M"), Some("anchor"), Some("D")];
+        let tickets = signatures.map(|signature| ticket(root, signature));
+        let nodes = vec![RawNodeValue::default(); tickets.len()];
+        let edges = edges
+            .iter()
+            .map(|&(kind, src, tgt, count)| (kind, NodeIndex(src), NodeIndex(tgt), count))
+            .collect();
+        RawGraph::from_parts(tickets.into(), nodes, edges)
+    }
+
+    fn a() -> RawGraph {
+        graph(&[
+            (EdgeKind::Childof, 2, 1, 1),
+            (EdgeKind::Childof, 3, 0, 1),
+            (EdgeKind::DefinesBinding, 3, 2, 1),
+            (EdgeKind::Ref, 2, 4, 3),
+        ])
+    }
+
+    fn b() -> RawGraph {
+        graph(&[
+            (EdgeKind::Childof, 2, 1, 2),
+            (EdgeKind::Childof, 3, 0, 1),
+            (EdgeKind::DefinesBinding, 3, 2, 1),
+            (EdgeKind::Ref, 2, 1, 1),
+        ])
+    }
+
+    fn ticket_edges(graph: RawGraph) -> (usize, Vec<TicketEdge>) {
+        let (tickets, _, edges) = graph.into_parts();
+        let signature = |index: NodeIndex| tickets[index.0].signature.clone();
+        let edges = edges
+            .into_iter()
+            .map(|(kind, src, tgt, count)| (kind, signature(src), signature(tgt), count))
+            .collect();
+        (tickets.len(), edges)
+    }
+
+    fn edge(kind: EdgeKind, src: &str, tgt: Option<&str>, count: usize) -> TicketEdge {
+        (kind, Some(src.to_string()), tgt.map(str::to_string), count)
+    }
+
+    #[test]
+    fn test_union() {
+        let (num_nodes, mut edges) = ticket_edges(combine(a(), b(), Operation::Union));
+        edges.sort();
+
+        assert_eq!(num_nodes, 5);
+        assert_eq!(
+            edges,
+            vec![
+                edge(EdgeKind::Childof, "M", Some("C"), 2),
+                edge(EdgeKind::Childof, "anchor", None, 1),
+                edge(EdgeKind::DefinesBinding, "anchor", Some("M"), 1),
+                edge(EdgeKind::Ref, "M", Some("C"), 1),
+                edge(EdgeKind::Ref, "M", Some("D"), 3),
+            ]
+        );
+
+        // The same every time, regardless of hashing
+        let tickets = |graph: RawGraph| graph.into_parts().0;
+        let first = tickets(combine(a(), b(), Operation::Union));
+        assert_eq!(first, tickets(combine(a(), b(), Operation::Union)));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let (num_nodes, mut edges) = ticket_edges(combine(a(), b(), Operation::Intersect));
+        edges.sort();
+
+        assert_eq!(num_nodes, 5);
+        assert_eq!(
+            edges,
+            vec![
+                edge(EdgeKind::Childof, "M", Some("C"), 1),
+                edge(EdgeKind::Childof, "anchor", None, 1),
+                edge(EdgeKind::DefinesBinding, "anchor", Some("M"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subtract_keeps_containment() {
+        let (num_nodes, mut edges) = ticket_edges(combine(a(), b(), Operation::Subtract));
+        edges.sort();
+
+        // Only the ref to D differs, but M still needs its class, its binding
+        // anchor, and their file
+        assert_eq!(num_nodes, 5);
+        assert_eq!(
+            edges,
+            vec![
+                edge(EdgeKind::Childof, "M", Some("C"), 1),
+                edge(EdgeKind::Childof, "anchor", None, 1),
+                edge(EdgeKind::DefinesBinding, "anchor", Some("M"), 1),
+                edge(EdgeKind::Ref, "M", Some("D"), 3),
+            ]
+        );
+
+        let (num_nodes, edges) = ticket_edges(combine(a(), a(), Operation::Subtract));
+        assert_eq!((num_nodes, edges), (0, Vec::new()));
+    }
+
+    #[test]
+    fn test_matches_across_roots() {
+        let edges = [(EdgeKind::Childof, 2, 1, 1), (EdgeKind::Ref, 2, 4, 1)];
+        let opt = || graph_in("bazel-out/k8-opt", &edges);
+        let dbg = || graph_in("bazel-out/k8-dbg", &edges);
+
+        let (num_nodes, edges) = ticket_edges(combine(opt(), dbg(), Operation::Intersect));
+        assert_eq!((num_nodes, edges.len()), (5, 2));
+
+        let (num_nodes, edges) = ticket_edges(combine(opt(), dbg(), Operation::Union));
+        assert_eq!((num_nodes, edges.len()), (5, 2));
+
+        let (num_nodes, edges) = ticket_edges(combine(opt(), dbg(), Operation::Subtract));
+        assert_eq!((num_nodes, edges), (0, Vec::new()));
+    }
+}
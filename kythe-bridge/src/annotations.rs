@@ -0,0 +1,87 @@
+//! Join user-provided columns (e.g. owning team or criticality) onto entities
+//! so that they can be carried through to reports.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::ir::EntityGraph;
+
+/// The rows of a CSV file keyed by their first column, which holds either the
+/// qualified name of an entity or the path of a file.
+#[derive(Debug, Default)]
+pub struct Annotations {
+    columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl Annotations {
+    /// Read a CSV file with a header row. The first column is the key and
+    /// every other column is an annotation, for example:
+    ///
+    /// ```text
+    /// key,team,tier
+    /// src/billing/invoice.cc,payments,1
+    /// com.example.auth.TokenCache,identity,2
+    /// ```
+    pub fn read(path: &Path) -> csv::Result<Self> {
+        Annotations::from_reader(std::fs::File::open(path)?)
+    }
+
+    pub fn from_reader<R: std::io::Read>(reader: R) -> csv::Result<Self> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let columns = reader.headers()?.iter().skip(1).map(str::to_string).collect();
+        let mut rows = HashMap::new();
+        let mut num_duplicates = 0;
+
+        for record in reader.records() {
+            let record = record?;
+            let mut fields = record.iter().map(str::to_string);
+            let key = fields.next().unwrap_or_default();
+
+            if rows.insert(key, fields.collect()).is_some() {
+                num_duplicates += 1;
+            }
+        }
+
+        if num_duplicates > 0 {
+            log::warn!(
+                "Found {} duplicate annotation key(s). The last row of each was kept.",
+                num_duplicates
+            );
+        }
+
+        Ok(Self { columns, rows })
+    }
+
+    /// The names of the annotations, in the order of the file.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Annotate each entity with the row keyed by its qualified name, or
+    /// failing that, by its path. Empty cells are skipped. Returns the number
+    /// of entities annotated.
+    pub fn apply(&self, graph: &mut EntityGraph) -> usize {
+        let mut num_annotated = 0;
+
+        for entity in graph.entities.values_mut() {
+            let row = match self.rows.get(&entity.qualified_name) {
+                Some(row) => row,
+                None => match self.rows.get(&entity.path) {
+                    Some(row) => row,
+                    None => continue,
+                },
+            };
+
+            for (column, value) in self.columns.iter().zip(row) {
+                if !value.is_empty() {
+                    entity.annotations.insert(column.clone(), value.clone());
+                }
+            }
+
+            num_annotated += 1;
+        }
+
+        num_annotated
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::{fs, io};
+
+use bimap::BiHashMap;
+use sha2::{Digest, Sha256};
+
+use crate::io::Ticket;
+use crate::ir::{Entity, EntityGraph, NodeKind, StableId};
+
+/// Consistently replaces paths and names with opaque tokens.
+///
+/// Each component of a path is replaced on its own, so the depth of the
+/// directory structure (and the file extension) survives anonymization. The
+/// same input always maps to the same token, and the mapping can be saved to a
+/// local file to reverse the process or to keep later exports consistent.
+///
+/// Tokens are keyed by a random secret which is saved along with the mapping,
+/// so that they cannot be reversed by hashing guesses (e.g. common names like
+/// "src" or "main") without the mapping file.
+#[derive(Debug)]
+pub struct Anonymizer {
+    secret: String,
+    /// Left is the original text, right is the token.
+    mapping: BiHashMap<String, String>,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Self { secret: random_secret(), mapping: BiHashMap::new() }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MappingFile {
+    secret: String,
+    /// From token to original text.
+    tokens: BTreeMap<String, String>,
+}
+
+/// A mapping file as saved by this or (without a secret) an older version.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum SavedMapping {
+    Keyed(MappingFile),
+    Unkeyed(BTreeMap<String, String>),
+}
+
+impl Anonymizer {
+    /// Load a previously saved mapping. If the file does not exist yet, start
+    /// with an empty mapping and a new secret. A mapping saved without a
+    /// secret keeps its tokens, but new tokens are keyed by a new secret.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut anonymizer = Self::default();
+
+        if !path.exists() {
+            return Ok(anonymizer);
+        }
+
+        let text = fs::read_to_string(path)?;
+        let tokens = match serde_json::from_str(&text)? {
+            SavedMapping::Keyed(file) => {
+                anonymizer.secret = file.secret;
+                file.tokens
+            }
+            SavedMapping::Unkeyed(tokens) => tokens,
+        };
+
+        for (token, original) in tokens {
+            anonymizer.mapping.insert(original, token);
+        }
+
+        Ok(anonymizer)
+    }
+
+    /// Save the secret and the mapping (from token to original text) as JSON.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = MappingFile {
+            secret: self.secret.clone(),
+            tokens: self
+                .mapping
+                .iter()
+                .map(|(text, token)| (token.clone(), text.clone()))
+                .collect(),
+        };
+        fs::write(path, serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Find the original text of a token, if known.
+    #[allow(dead_code)]
+    pub fn reverse(&self, token: &str) -> Option<&String> {
+        self.mapping.get_by_right(token)
+    }
+
+    pub fn token(&mut self, text: &str) -> String {
+        if let Some(token) = self.mapping.get_by_left(text) {
+            return token.clone();
+        }
+
+        // Re-hash with a counter on the (unlikely) event of a collision
+        let mut attempt = 0u32;
+
+        loop {
+            let digest = Sha256::digest(format!("{}\0{}#{}", self.secret, text, attempt));
+            let token = digest[..6].iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+
+            if !self.mapping.contains_right(&token) {
+                self.mapping.insert(text.to_string(), token.clone());
+                return token;
+            }
+
+            attempt += 1;
+        }
+    }
+
+    pub fn path(&mut self, path: &str) -> String {
+        path.split('/')
+            .map(|component| match component {
+                "" | "." | ".." => component.to_string(),
+                _ => match component.rsplit_once('.') {
+                    Some((stem, ext)) if !stem.is_empty() => {
+                        format!("{}.{}", self.token(stem), ext)
+                    }
+                    _ => self.token(component),
+                },
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Replace each identifier in a qualified name (e.g. `a::b(int)`) while
+    /// keeping the punctuation between them.
+    pub fn qualified_name(&mut self, name: &str) -> String {
+        let mut result = String::new();
+        let mut word = String::new();
+
+        for c in name.chars() {
+            if c.is_alphanumeric() || c == '_' {
+                word.push(c);
+                continue;
+            }
+
+            if !word.is_empty() {
+                result.push_str(&self.token(&word));
+                word.clear();
+            }
+
+            result.push(c);
+        }
+
+        if !word.is_empty() {
+            result.push_str(&self.token(&word));
+        }
+
+        result
+    }
+
+    /// Rehash a stable id with the secret, since the unkeyed id could be
+    /// matched against the ids of guessed paths and signatures.
+    pub fn stable_id(&self, id: StableId) -> StableId {
+        let digest = Sha256::digest(format!("{}\0{}", self.secret, id));
+        StableId(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+    }
+
+    /// Replace every part of a ticket except its language.
+    pub fn ticket(&mut self, ticket: &mut Ticket) {
+        ticket.corpus = ticket.corpus.as_deref().map(|corpus| self.token(corpus));
+        ticket.root = ticket.root.as_deref().map(|root| self.path(root));
+        ticket.path = ticket.path.as_deref().map(|path| self.path(path));
+        ticket.signature = ticket.signature.as_deref().map(|signature| self.token(signature));
+    }
+
+    pub fn entity(&mut self, entity: &mut Entity) {
+        entity.stable_id = self.stable_id(entity.stable_id);
+        entity.name = self.token(&entity.name);
+        entity.qualified_name = self.qualified_name(&entity.qualified_name);
+        entity.path = self.path(&entity.path);
+
+        // Some kinds carry source text which cannot be shared at all
+        entity.kind = match &entity.kind {
+            NodeKind::Constant(text) => NodeKind::Constant(self.token(text)),
+            NodeKind::Diagnostic(_) => NodeKind::Diagnostic(String::new()),
+            NodeKind::Doc(_) => NodeKind::Doc(String::new()),
+            NodeKind::Lookup(text) => NodeKind::Lookup(self.token(text)),
+            NodeKind::Vcs(_) => NodeKind::Vcs(None),
+            kind => kind.clone(),
+        };
+
+        for param in &mut entity.params {
+            param.name = self.token(&param.name);
+        }
+
+        if let Some(package) = &entity.package {
+            let package = package.split('.').map(|part| self.token(part)).collect::<Vec<_>>();
+            entity.package = Some(package.join("."));
+        }
+
+        // Only the values, since the columns are named by whoever annotates
+        for value in entity.annotations.values_mut() {
+            *value = self.token(value);
+        }
+    }
+
+    pub fn graph(&mut self, graph: &mut EntityGraph) {
+        for entity in graph.entities.values_mut() {
+            self.entity(entity);
+        }
+    }
+}
+
+/// 256 random bits as hex, drawn from the randomly seeded keys of the
+/// standard library's hasher.
+fn random_secret() -> String {
+    (0..4)
+        .map(|i| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_usize(i);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_keeps_depth() {
+        let mut anonymizer = Anonymizer::default();
+        let a = anonymizer.path("src/foo/Bar.java");
+        let b = anonymizer.path("src/baz/Bar.java");
+
+        assert_eq!(a.split('/').count(), 3);
+        assert!(a.ends_with(".java"));
+        assert_eq!(a.split('/').next(), b.split('/').next());
+        assert_eq!(anonymizer.reverse(a.split('/').next().unwrap()).unwrap(), "src");
+    }
+
+    #[test]
+    fn test_token_depends_on_secret() {
+        let mut a = Anonymizer::default();
+        let mut b = Anonymizer::default();
+        assert_ne!(a.secret, b.secret);
+        assert_ne!(a.token("main"), b.token("main"));
+
+        b.secret = a.secret.clone();
+        b.mapping.clear();
+        assert_eq!(a.token("main"), b.token("main"));
+        assert_eq!(a.token("main").len(), 12);
+        assert_eq!(a.stable_id(StableId(1)), b.stable_id(StableId(1)));
+        assert_ne!(a.stable_id(StableId(1)), Anonymizer::default().stable_id(StableId(1)));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::ir::{EntityGraph, NodeIndex};
+
+/// How large a rendered graph (e.g. a DOT file) is or would be.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputSize {
+    pub nodes: usize,
+    pub edges: usize,
+    pub bytes: usize,
+}
+
+impl Display for OutputSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} nodes, {} edges, {}", self.nodes, self.edges, format_bytes(self.bytes))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BudgetErr {
+    #[error("output would have {0} nodes, over the budget of {1}")]
+    Nodes(usize, usize),
+    #[error("output would have {0} edges, over the budget of {1}")]
+    Edges(usize, usize),
+    #[error("output would be {}, over the budget of {}", format_bytes(*.0), format_bytes(*.1))]
+    Bytes(usize, usize),
+}
+
+/// The largest output worth producing. Most renderers give up long before a
+/// graph gets as large as a big codebase, so it is better to refuse (or slice
+/// the graph down) than to write a file nothing can open.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+    pub max_nodes: Option<usize>,
+    pub max_edges: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Budget {
+    pub fn check(&self, size: &OutputSize) -> Result<(), BudgetErr> {
+        match (self.max_nodes, self.max_edges, self.max_bytes) {
+            (Some(max), _, _) if size.nodes > max => Err(BudgetErr::Nodes(size.nodes, max)),
+            (_, Some(max), _) if size.edges > max => Err(BudgetErr::Edges(size.edges, max)),
+            (_, _, Some(max)) if size.bytes > max => Err(BudgetErr::Bytes(size.bytes, max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The largest fraction of `size` which would fit within the budget.
+    fn ratio(&self, size: &OutputSize) -> f64 {
+        [(self.max_nodes, size.nodes), (self.max_edges, size.edges), (self.max_bytes, size.bytes)]
+            .into_iter()
+            .filter_map(|(max, actual)| Some(max? as f64 / actual.max(1) as f64))
+            .fold(1.0, f64::min)
+    }
+}
+
+/// The most estimates `render_within` will make while looking for a slice of
+/// the graph that fits, which bounds the time spent on an oversized graph.
+const MAX_SLICE_STEPS: usize = 24;
+
+/// Estimate the size of `graph` and check it against `budget`. If it does not
+/// fit and `auto_slice` is set, search for the largest slice of the graph (see
+/// `slice`) that does. Either way, only the graph which is kept is rendered.
+///
+/// The search is a bisection over the number of entities kept, so `estimate`
+/// should be cheap relative to `render` (see `estimate_size`).
+pub fn render_within<T, E, R>(
+    graph: &EntityGraph,
+    budget: &Budget,
+    auto_slice: bool,
+    estimate: E,
+    render: R,
+) -> Result<(T, OutputSize), BudgetErr>
+where
+    E: Fn(&EntityGraph) -> OutputSize,
+    R: FnOnce(&EntityGraph) -> T,
+{
+    let size = estimate(graph);
+    log::info!("Estimated output size is {}.", size);
+
+    match budget.check(&size) {
+        Ok(()) => return Ok((render(graph), size)),
+        Err(err) if !auto_slice => return Err(err),
+        Err(_) => (),
+    }
+
+    let ranked = rank(graph);
+
+    // The most entities known to fit (none, at worst) and the fewest known not
+    // to. The size only roughly grows with the number of entities, so this
+    // finds a good slice rather than the largest possible one.
+    let (mut fits, mut exceeds) = (0, ranked.len());
+    let mut best = None;
+    let mut guess = (ranked.len() as f64 * budget.ratio(&size)) as usize;
+
+    for _ in 0..MAX_SLICE_STEPS {
+        if exceeds - fits <= 1 {
+            break;
+        }
+
+        let n = guess.clamp(fits + 1, exceeds - 1);
+        let sliced = slice(graph, &ranked[..n]);
+        let size = estimate(&sliced);
+
+        match budget.check(&size) {
+            Ok(()) => {
+                fits = n;
+                best = Some((sliced, size));
+            }
+            Err(_) => exceeds = n,
+        }
+
+        guess = fits + (exceeds - fits) / 2;
+    }
+
+    let (sliced, size) = best.unwrap_or_else(|| {
+        let sliced = slice(graph, &[]);
+        let size = estimate(&sliced);
+        (sliced, size)
+    });
+
+    log::warn!(
+        "Sliced the graph down to the {} most connected of {} entities ({}).",
+        fits,
+        ranked.len(),
+        size
+    );
+    Ok((render(&sliced), size))
+}
+
+/// The most statements `estimate_size` renders of each kind.
+const SAMPLE_SIZE: usize = 1000;
+
+/// Estimate the size of an output with a statement for each of `nodes` and
+/// `edges` (plus `overhead` bytes) without rendering them all. The byte count
+/// is extrapolated from an evenly spaced sample of each.
+pub fn estimate_size<N, E>(
+    nodes: &[N],
+    edges: &[E],
+    node_len: impl Fn(&N) -> usize,
+    edge_len: impl Fn(&E) -> usize,
+    overhead: usize,
+) -> OutputSize {
+    OutputSize {
+        nodes: nodes.len(),
+        edges: edges.len(),
+        bytes: overhead + sampled_len(nodes, node_len) + sampled_len(edges, edge_len),
+    }
+}
+
+/// The total `len` of `items`, extrapolated from at most `SAMPLE_SIZE` of them.
+fn sampled_len<T>(items: &[T], len: impl Fn(&T) -> usize) -> usize {
+    if items.len() <= SAMPLE_SIZE {
+        return items.iter().map(len).sum();
+    }
+
+    let sample = items.iter().step_by(items.len() / SAMPLE_SIZE).take(SAMPLE_SIZE);
+    let mean = sample.map(len).sum::<usize>() as f64 / SAMPLE_SIZE as f64;
+    (mean * items.len() as f64) as usize
+}
+
+/// The entities of `graph`, most connected (by the total count of their deps)
+/// first.
+fn rank(graph: &EntityGraph) -> Vec<NodeIndex> {
+    let mut degrees: HashMap<NodeIndex, usize> = HashMap::new();
+
+    for dep in &graph.deps {
+        *degrees.entry(dep.src).or_default() += dep.count;
+        *degrees.entry(dep.tgt).or_default() += dep.count;
+    }
+
+    graph
+        .entities
+        .keys()
+        .copied()
+        .sorted_by_key(|id| (std::cmp::Reverse(degrees.get(id).copied().unwrap_or(0)), *id))
+        .collect()
+}
+
+/// The subgraph containing only the given entities and the deps between them.
+fn slice(graph: &EntityGraph, keep: &[NodeIndex]) -> EntityGraph {
+    let keep: HashSet<NodeIndex> = keep.iter().copied().collect();
+
+    EntityGraph {
+        entities: graph
+            .entities
+            .iter()
+            .filter(|(id, _)| keep.contains(id))
+            .map(|(id, entity)| (*id, entity.clone()))
+            .collect(),
+        deps: graph
+            .deps
+            .iter()
+            .filter(|dep| keep.contains(&dep.src) && keep.contains(&dep.tgt))
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Format a number of bytes for humans, e.g. "1.5 MiB".
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let budget = Budget { max_nodes: Some(10), max_edges: None, max_bytes: Some(1000) };
+        let size = OutputSize { nodes: 5, edges: 1_000_000, bytes: 4000 };
+
+        assert!(matches!(budget.check(&size), Err(BudgetErr::Bytes(4000, 1000))));
+        assert_eq!(budget.ratio(&size), 0.25);
+        assert!(budget.check(&OutputSize { bytes: 1000, ..size }).is_ok());
+        assert_eq!(format_bytes(1536 * 1024), "1.5 MiB");
+    }
+
+    #[test]
+    fn test_sampled_len() {
+        assert_eq!(sampled_len(&[1, 2, 3], |n| *n), 6);
+        assert_eq!(sampled_len(&vec![7; 10 * SAMPLE_SIZE], |n| *n), 70 * SAMPLE_SIZE);
+
+        let estimate = estimate_size(&["ab"; 3], &[(); 2], |s| s.len(), |_| 5, 10);
+        assert_eq!(estimate, OutputSize { nodes: 3, edges: 2, bytes: 26 });
+    }
+}
@@ -0,0 +1,313 @@
+//! Write entries as lines of JSON such that re-serializing an entry read from
+//! a line gives back that same line, byte for byte, except for the fields
+//! which were changed.
+//!
+//! Commands which only filter entries (e.g. `exclude`) can copy lines
+//! verbatim, but commands which transform entries have to re-serialize them.
+//! Serializing through serde would reorder keys, write `null` for missing
+//! fields, and re-escape strings, so every line would differ from the original
+//! even where nothing was changed. Instead, the original line is scanned for
+//! the order of its keys and the exact text of its values, and each value is
+//! reused unless the entry disagrees with it.
+//!
+//! Without an original line (or if it cannot be scanned), entries are written
+//! in canonical form: keys in the order of the fields of `kythe.proto.Entry`
+//! and `kythe.proto.VName`, missing fields omitted, and strings escaped as
+//! little as possible. Insignificant whitespace is never preserved.
+
+use std::borrow::Cow;
+
+use sha2::{Digest, Sha256};
+
+use crate::io::{EntryRef, TicketRef};
+
+/// Write `entry` as a line of JSON (including the trailing newline), reusing
+/// the key order and escaping of `original` where possible.
+pub fn to_json_line(entry: &EntryRef, original: Option<&str>) -> String {
+    let layout = original.and_then(|line| Scanner::new(line).object());
+    let mut out = String::new();
+    write_object(&mut out, &entry_fields(entry), layout.as_deref());
+    out.push('\n');
+    out
+}
+
+/// A SHA-256 digest of the canonical form of `entry`. The same entry gets the
+/// same hash whether it was read from JSON (in any key order) or protobuf, so
+/// it can serve as a key for deduplicating entries.
+pub fn content_hash(entry: &EntryRef) -> [u8; 32] {
+    let digest = Sha256::digest(to_json_line(entry, None).as_bytes());
+    digest[..].try_into().unwrap()
+}
+
+/// The value of a field of an entry.
+enum Field<'e> {
+    Str(Option<&'e str>),
+    Ticket(&'e TicketRef<'e>),
+}
+
+/// The fields of `entry` in canonical order.
+fn entry_fields<'e>(entry: &'e EntryRef) -> Vec<(&'static str, Field<'e>)> {
+    let fields = match entry {
+        EntryRef::Edge { src, tgt, edge_kind, fact_name, fact_value } => vec![
+            ("source", Field::Ticket(src)),
+            ("edge_kind", Field::Str(edge_kind.as_deref())),
+            ("target", Field::Ticket(tgt)),
+            ("fact_name", Field::Str(Some(fact_name))),
+            ("fact_value", Field::Str(fact_value.as_deref())),
+        ],
+        EntryRef::Node { src, fact_name, fact_value } => vec![
+            ("source", Field::Ticket(src)),
+            ("fact_name", Field::Str(Some(fact_name))),
+            ("fact_value", Field::Str(fact_value.as_deref())),
+        ],
+    };
+
+    // Entries always have a fact name, but edges often leave it empty
+    fields.into_iter().filter(|(name, field)| !is_blank_fact_name(name, field)).collect()
+}
+
+fn is_blank_fact_name(name: &str, field: &Field) -> bool {
+    matches!((name, field), ("fact_name", Field::Str(Some(""))))
+}
+
+/// The fields of `ticket` in canonical order.
+fn ticket_fields<'e>(ticket: &'e TicketRef) -> Vec<(&'static str, Field<'e>)> {
+    vec![
+        ("signature", Field::Str(ticket.signature.as_deref())),
+        ("corpus", Field::Str(ticket.corpus.as_deref())),
+        ("root", Field::Str(ticket.root.as_deref())),
+        ("path", Field::Str(ticket.path.as_deref())),
+        ("language", Field::Str(ticket.language.as_deref())),
+    ]
+}
+
+/// Write `fields` as an object. Keys found in `layout` are written first, in
+/// its order, followed by the rest in canonical order. Keys in `layout` which
+/// are not fields of an entry are copied as they were.
+fn write_object(out: &mut String, fields: &[(&str, Field)], layout: Option<&[(&str, Raw)]>) {
+    let mut members = Vec::new();
+    let layout = layout.unwrap_or_default();
+
+    for (key, raw) in layout {
+        let name = unquote(key);
+
+        match fields.iter().find(|(field_name, _)| *field_name == name) {
+            None => members.push(format!("{}:{}", key, raw.text())),
+            Some((_, field)) => {
+                if let Some(value) = write_value(field, Some(raw)) {
+                    members.push(format!("{}:{}", key, value));
+                }
+            }
+        }
+    }
+
+    for (name, field) in fields {
+        if layout.iter().any(|(key, _)| unquote(key) == *name) {
+            continue;
+        }
+
+        if let Some(value) = write_value(field, None) {
+            members.push(format!("\"{}\":{}", name, value));
+        }
+    }
+
+    out.push('{');
+    out.push_str(&members.join(","));
+    out.push('}');
+}
+
+/// The text of `field`, reusing the text of `raw` if it holds the same value.
+/// Missing fields are omitted (unless they were explicitly null).
+fn write_value(field: &Field, raw: Option<&Raw>) -> Option<String> {
+    match (field, raw) {
+        (Field::Str(None), Some(Raw::Null)) => Some("null".to_string()),
+        (Field::Str(None), _) => None,
+        (Field::Str(Some(value)), Some(Raw::Str(text))) if unquote(text) == *value => {
+            Some(text.to_string())
+        }
+        (Field::Str(Some(value)), _) => Some(serde_json::to_string(value).unwrap()),
+        (Field::Ticket(ticket), raw) => {
+            let layout = match raw {
+                Some(Raw::Object(members)) => Some(members.as_slice()),
+                _ => None,
+            };
+            let mut out = String::new();
+            write_object(&mut out, &ticket_fields(ticket), layout);
+            Some(out)
+        }
+    }
+}
+
+/// Decode the text of a JSON string (including its quotes).
+fn unquote(text: &str) -> Cow<'_, str> {
+    match text.contains('\\') {
+        false => Cow::Borrowed(text.trim_matches('"')),
+        true => Cow::Owned(serde_json::from_str(text).unwrap_or_default()),
+    }
+}
+
+/// A JSON value as it appeared in the original line.
+enum Raw<'a> {
+    /// The text of a string, including its quotes.
+    Str(&'a str),
+    Null,
+    /// The text of each key (including quotes) and value, in order.
+    Object(Vec<(&'a str, Raw<'a>)>),
+    /// Anything else (which Kythe does not write), kept as text.
+    Other(&'a str),
+}
+
+impl Raw<'_> {
+    fn text(&self) -> Cow<'_, str> {
+        match self {
+            Raw::Str(text) | Raw::Other(text) => Cow::Borrowed(text),
+            Raw::Null => Cow::Borrowed("null"),
+            Raw::Object(members) => {
+                let members = members.iter().map(|(key, raw)| format!("{}:{}", key, raw.text()));
+                Cow::Owned(format!("{{{}}}", members.collect::<Vec<_>>().join(",")))
+            }
+        }
+    }
+}
+
+/// Just enough of a JSON scanner to recover the layout of an entry. Gives up
+/// (returning `None`) on anything it does not expect rather than guessing.
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        let rest = &self.text.as_bytes()[self.pos..];
+        let skipped = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        self.pos += skipped;
+        rest.get(skipped).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        match self.peek()? == byte {
+            true => {
+                self.pos += 1;
+                Some(())
+            }
+            false => None,
+        }
+    }
+
+    fn object(&mut self) -> Option<Vec<(&'a str, Raw<'a>)>> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+
+        if self.peek()? == b'}' {
+            self.pos += 1;
+            return Some(members);
+        }
+
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+
+            match self.peek()? {
+                b',' => self.pos += 1,
+                b'}' => {
+                    self.pos += 1;
+                    return Some(members);
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        self.peek()?;
+        let start = self.pos;
+        self.expect(b'"')?;
+        let bytes = self.text.as_bytes();
+
+        while self.pos < bytes.len() {
+            match bytes[self.pos] {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return self.text.get(start..self.pos);
+                }
+                _ => self.pos += 1,
+            }
+        }
+
+        None
+    }
+
+    fn value(&mut self) -> Option<Raw<'a>> {
+        match self.peek()? {
+            b'"' => Some(Raw::Str(self.string()?)),
+            b'{' => Some(Raw::Object(self.object()?)),
+            b'n' if self.text[self.pos..].starts_with("null") => {
+                self.pos += 4;
+                Some(Raw::Null)
+            }
+            b'-' | b'0'..=b'9' | b't' | b'f' => {
+                let start = self.pos;
+                let rest = &self.text.as_bytes()[start..];
+                self.pos += rest.iter().take_while(|b| !b",}] \t\r\n".contains(b)).count();
+                Some(Raw::Other(&self.text[start..self.pos]))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_is_byte_stable() {
+        let line = r#"{"fact_value":"ZmlsZQ==","source":{"path":"a\/b.cc","corpus":"c","root":null},"fact_name":"/kythe/node/kind"}"#;
+        let entry = EntryRef::from_json(line).unwrap();
+        assert_eq!(to_json_line(&entry, Some(line)), format!("{}\n", line));
+    }
+
+    #[test]
+    fn test_changed_fields_are_rewritten() {
+        let line = r#"{"source":{"path":"a\/b.cc","corpus":"c"},"fact_name":"/kythe/node/kind"}"#;
+        let mut entry = EntryRef::from_json(line).unwrap();
+
+        if let EntryRef::Node { src, .. } = &mut entry {
+            src.corpus = None;
+            src.language = Some(Cow::Borrowed("c++"));
+        }
+
+        assert_eq!(
+            to_json_line(&entry, Some(line)),
+            "{\"source\":{\"path\":\"a\\/b.cc\",\"language\":\"c++\"},\"fact_name\":\"/kythe/node/kind\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_canonical_form() {
+        let line = r#"{"target":{"signature":"b"},"source":{"language":"go","signature":"a"},"edge_kind":"/kythe/edge/ref","fact_name":"/"}"#;
+        let entry = EntryRef::from_json(line).unwrap();
+
+        assert_eq!(
+            to_json_line(&entry, None),
+            "{\"source\":{\"signature\":\"a\",\"language\":\"go\"},\"edge_kind\":\"/kythe/edge/ref\",\"target\":{\"signature\":\"b\"},\"fact_name\":\"/\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_content_hash_ignores_layout() {
+        let a = r#"{"source":{"signature":"a","language":"go"},"fact_name":"/kythe/node/kind","fact_value":"ZmlsZQ=="}"#;
+        let b = r#"{ "fact_name": "/kythe/node/kind", "fact_value": "ZmlsZQ==", "source": {"language": "go", "signature": "a", "root": null} }"#;
+        let c = r#"{"source":{"signature":"b","language":"go"},"fact_name":"/kythe/node/kind","fact_value":"ZmlsZQ=="}"#;
+        let hash = |line| content_hash(&EntryRef::from_json(line).unwrap());
+        assert_eq!(hash(a), hash(b));
+        assert_ne!(hash(a), hash(c));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use sha2::{Digest, Sha256};
+
+use crate::ir::{EdgeKind, EntityGraph, NodeIndex, NodeKind};
+
+/// A hash of the shape of an entity: its own kind along with the kind of
+/// each of its outgoing deps and of the entity each one targets, counted as a
+/// bag. Names and paths are left out, so two copies of the same function (or
+/// class) in different places have the same fingerprint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint(pub u64);
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Entities with the same name and the same fingerprint, which are most
+/// likely mechanical duplicates of each other.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CloneGroup {
+    pub name: String,
+    pub kind: &'static str,
+    pub fingerprint: Fingerprint,
+    /// The number of outgoing deps of each member.
+    pub num_deps: usize,
+    pub members: Vec<NodeIndex>,
+}
+
+/// The outgoing deps of each entity, counted by their kind and the kind of
+/// their target. `graph` should have its anchors lifted (see
+/// `EntityGraph::lift_anchors`), or else the deps of a function body belong
+/// to its anchors rather than to the function.
+fn bags(graph: &EntityGraph) -> HashMap<NodeIndex, BTreeMap<(EdgeKind, &'static str), usize>> {
+    let mut bags: HashMap<_, BTreeMap<_, usize>> = HashMap::new();
+
+    for dep in &graph.deps {
+        if let Some(tgt) = graph.entities.get(&dep.tgt) {
+            let bag = bags.entry(dep.src).or_default();
+            *bag.entry((dep.kind, tgt.kind.name())).or_default() += dep.count;
+        }
+    }
+
+    bags
+}
+
+fn fingerprint(kind: &NodeKind, bag: &BTreeMap<(EdgeKind, &str), usize>) -> Fingerprint {
+    let mut hasher = Sha256::new();
+    hasher.update(kind.name().as_bytes());
+    hasher.update([0]);
+
+    for ((edge_kind, tgt_kind), count) in bag {
+        hasher.update(format!("{:?}\t{}\t{}", edge_kind, tgt_kind, count).as_bytes());
+        hasher.update([0]);
+    }
+
+    let digest = hasher.finalize();
+    Fingerprint(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+}
+
+/// Compute the fingerprint of every entity with at least one outgoing dep.
+pub fn fingerprints(graph: &EntityGraph) -> HashMap<NodeIndex, Fingerprint> {
+    bags(graph)
+        .into_iter()
+        .filter_map(|(id, bag)| Some((id, fingerprint(&graph.entities.get(&id)?.kind, &bag))))
+        .collect()
+}
+
+/// Group the entities which share both a name and a fingerprint, ignoring
+/// entities with fewer than `min_deps` outgoing deps (since e.g. every empty
+/// function of the same name looks alike). Groups are sorted largest first.
+pub fn clone_groups(graph: &EntityGraph, min_deps: usize) -> Vec<CloneGroup> {
+    let mut groups: BTreeMap<(&str, Fingerprint), CloneGroup> = BTreeMap::new();
+
+    for (id, bag) in bags(graph) {
+        let num_deps = bag.values().sum::<usize>();
+        let entity = match graph.entities.get(&id) {
+            Some(entity) if num_deps >= min_deps.max(1) => entity,
+            _ => continue,
+        };
+
+        let fingerprint = fingerprint(&entity.kind, &bag);
+        let group = groups.entry((&entity.name, fingerprint)).or_insert_with(|| CloneGroup {
+            name: entity.name.clone(),
+            kind: entity.kind.name(),
+            fingerprint,
+            num_deps,
+            members: Vec::new(),
+        });
+        group.members.push(id);
+    }
+
+    let mut groups = groups
+        .into_values()
+        .filter(|group| group.members.len() > 1)
+        .map(|mut group| {
+            group.members.sort_by_key(|id| &graph.entities[id].qualified_name);
+            group
+        })
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|group| {
+        (std::cmp::Reverse(group.members.len()), std::cmp::Reverse(group.num_deps))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Dep, Entity, NameSource, StableId};
+
+    fn entity(id: usize, name: &str, kind: NodeKind) -> Entity {
+        Entity {
+            id: NodeIndex(id),
+            stable_id: StableId(id as u64),
+            parent_ids: Vec::new(),
+            name: name.to_string(),
+            name_source: NameSource::Signature,
+            qualified_name: format!("ns{}.{}", id, name),
+            path: format!("{}.cc", id),
+            kind,
+            params: Vec::new(),
+            package: None,
+            annotations: Default::default(),
+        }
+    }
+
+    fn dep(src: usize, tgt: usize, kind: EdgeKind) -> Dep {
+        Dep { src: NodeIndex(src), tgt: NodeIndex(tgt), kind, count: 1, config: None }
+    }
+
+    #[test]
+    fn test_clone_groups() {
+        // 0, 1, and 2 are all "f", but only 0 and 1 use the same kinds of
+        // things. 3 has the same shape as 0 and 1 but a different name.
+        let entities = [
+            entity(0, "f", NodeKind::Macro),
+            entity(1, "f", NodeKind::Macro),
+            entity(2, "f", NodeKind::Macro),
+            entity(3, "g", NodeKind::Macro),
+            entity(4, "m", NodeKind::Package),
+            entity(5, "p", NodeKind::Package),
+        ];
+        let deps = vec![
+            dep(0, 4, EdgeKind::RefCall),
+            dep(0, 5, EdgeKind::Ref),
+            dep(1, 4, EdgeKind::Ref),
+            dep(1, 5, EdgeKind::RefCall),
+            dep(2, 4, EdgeKind::RefCall),
+            dep(3, 5, EdgeKind::RefCall),
+            dep(3, 4, EdgeKind::Ref),
+        ];
+        let graph = EntityGraph { entities: entities.map(|e| (e.id, e)).into(), deps };
+
+        let groups = clone_groups(&graph, 1);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "f");
+        assert_eq!(groups[0].members, vec![NodeIndex(0), NodeIndex(1)]);
+        assert_eq!(groups[0].num_deps, 2);
+
+        let fingerprints = fingerprints(&graph);
+        assert_eq!(fingerprints[&NodeIndex(0)], fingerprints[&NodeIndex(3)]);
+        assert_ne!(fingerprints[&NodeIndex(0)], fingerprints[&NodeIndex(2)]);
+
+        assert!(clone_groups(&graph, 3).is_empty());
+    }
+}
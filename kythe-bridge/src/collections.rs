@@ -68,55 +68,113 @@ impl<N: Eq + Hash> IntoIterator for IdMap<N> {
     }
 }
 
+/// The overlay of an `EdgeBag` is always allowed to hold this many edges
+/// before it is compacted.
+const MIN_OVERLAY: usize = 1024;
+
+/// A multiset of directed edges.
+///
+/// Edges are kept in a single vector sorted by source then target, along with
+/// the positions of those edges sorted by target then source, so that both the
+/// outgoing and incoming edges of a node are found by binary search and each
+/// count is stored once. New edges go into a small mutable overlay which is
+/// merged into the sorted vectors by `compact`. This happens on its own once
+/// the overlay outgrows the sorted edges, but the overlay is scanned by every
+/// lookup, so call `compact` once construction is done.
 #[derive(Debug, Default)]
 pub struct EdgeBag<N> {
-    outgoing: HashMap<N, HashMap<N, usize>>,
-    incoming: HashMap<N, HashMap<N, usize>>,
+    /// Sorted by `(src, tgt)`.
+    edges: Vec<(N, N, usize)>,
+    /// Positions in `edges`, sorted by `(tgt, src)`.
+    incoming: Vec<usize>,
+    /// Edges not yet in `edges`.
+    overlay: HashMap<(N, N), usize>,
 }
 
-impl<N: Copy + Eq + Hash> EdgeBag<N> {
+impl<N: Copy + Ord + Hash> EdgeBag<N> {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
-            outgoing: HashMap::new(),
-            incoming: HashMap::new(),
+            edges: Vec::new(),
+            incoming: Vec::new(),
+            overlay: HashMap::new(),
         }
     }
 
     pub fn insert(&mut self, src: N, tgt: N) -> usize {
-        // Outgoing
-        let inner = self.outgoing.entry(src).or_default();
-        let count = inner.entry(tgt).or_default();
-        *count += 1;
+        self.insert_count(src, tgt, 1)
+    }
+
+    pub fn insert_count(&mut self, src: N, tgt: N, n: usize) -> usize {
+        if let Ok(i) = self.edges.binary_search_by_key(&(src, tgt), |(src, tgt, _)| (*src, *tgt)) {
+            self.edges[i].2 += n;
+            return self.edges[i].2;
+        }
+
+        let count = self.overlay.entry((src, tgt)).or_default();
+        *count += n;
+        let count = *count;
+
+        if self.overlay.len() > self.edges.len().max(MIN_OVERLAY) {
+            self.compact();
+        }
+
+        count
+    }
+
+    /// Merge the overlay into the sorted edges.
+    pub fn compact(&mut self) {
+        if self.overlay.is_empty() {
+            return;
+        }
 
-        // Incoming
-        let inner = self.incoming.entry(tgt).or_default();
-        let count = inner.entry(src).or_default();
-        *count += 1;
+        // The overlay never holds an edge which is already sorted
+        let overlay = std::mem::take(&mut self.overlay);
+        self.edges.extend(overlay.into_iter().map(|((src, tgt), count)| (src, tgt, count)));
+        self.edges.sort_unstable_by_key(|(src, tgt, _)| (*src, *tgt));
+        self.edges.shrink_to_fit();
 
-        *count
+        let edges = &self.edges;
+        self.incoming = (0..edges.len()).collect();
+        self.incoming.sort_unstable_by_key(|i| (edges[*i].1, edges[*i].0));
     }
 
     pub fn outgoing(&self, src: &N) -> impl Iterator<Item = (N, usize)> + '_ {
-        self.outgoing
-            .get(src)
-            .map(|inner| inner.iter().map(move |(tgt, count)| (*tgt, *count)))
-            .into_iter()
-            .flatten()
+        let start = self.edges.partition_point(|(other, _, _)| other < src);
+        let end = self.edges.partition_point(|(other, _, _)| other <= src);
+        let src = *src;
+        let overlay = self.overlay.iter().filter(move |((other, _), _)| *other == src);
+
+        self.edges[start..end]
+            .iter()
+            .map(|(_, tgt, count)| (*tgt, *count))
+            .chain(overlay.map(|((_, tgt), count)| (*tgt, *count)))
     }
 
     pub fn incoming(&self, tgt: &N) -> impl Iterator<Item = (N, usize)> + '_ {
-        self.incoming
-            .get(tgt)
-            .map(|inner| inner.iter().map(move |(src, count)| (*src, *count)))
-            .into_iter()
-            .flatten()
+        let start = self.incoming.partition_point(|i| self.edges[*i].1 < *tgt);
+        let end = self.incoming.partition_point(|i| self.edges[*i].1 <= *tgt);
+        let tgt = *tgt;
+        let overlay = self.overlay.iter().filter(move |((_, other), _)| *other == tgt);
+
+        self.incoming[start..end]
+            .iter()
+            .map(|i| (self.edges[*i].0, self.edges[*i].2))
+            .chain(overlay.map(|((src, _), count)| (*src, *count)))
+    }
+
+    pub fn count(&self, src: &N, tgt: &N) -> Option<usize> {
+        match self.edges.binary_search_by(|(s, t, _)| (s, t).cmp(&(src, tgt))) {
+            Ok(i) => Some(self.edges[i].2),
+            Err(_) => self.overlay.get(&(*src, *tgt)).copied(),
+        }
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (N, N, usize)> + '_ {
-        self.outgoing
+        self.edges
             .iter()
-            .flat_map(|(src, tgts)| tgts.iter().map(move |(tgt, count)| (*src, *tgt, *count)))
+            .copied()
+            .chain(self.overlay.iter().map(|((src, tgt), count)| (*src, *tgt, *count)))
     }
 }
 
@@ -128,7 +186,7 @@ pub struct KindedEdgeBag<K, N> {
 impl<K, N> KindedEdgeBag<K, N>
 where
     K: Copy + Eq + Hash,
-    N: Copy + Default + Eq + Hash,
+    N: Copy + Default + Ord + Hash,
 {
     pub fn new() -> Self {
         Self {
@@ -140,6 +198,15 @@ where
         self.bags.entry(kind).or_default().insert(src, tgt)
     }
 
+    pub fn insert_count(&mut self, kind: K, src: N, tgt: N, n: usize) -> usize {
+        self.bags.entry(kind).or_default().insert_count(src, tgt, n)
+    }
+
+    /// Compact the bag of each kind (see `EdgeBag::compact`).
+    pub fn compact(&mut self) {
+        self.bags.values_mut().for_each(EdgeBag::compact);
+    }
+
     pub fn outgoing(&self, kind: &K, src: &N) -> impl Iterator<Item = (N, usize)> + '_ {
         self.bags.get(&kind).map(|m| m.outgoing(src)).into_iter().flatten()
     }
@@ -148,6 +215,13 @@ where
         self.bags.get(&kind).map(|m| m.incoming(tgt)).into_iter().flatten()
     }
 
+    /// Every outgoing edge of `src`, regardless of kind.
+    pub fn outgoing_all<'a>(&'a self, src: &'a N) -> impl Iterator<Item = (K, N, usize)> + 'a {
+        self.bags
+            .iter()
+            .flat_map(move |(kind, m)| m.outgoing(src).map(move |(tgt, count)| (*kind, tgt, count)))
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (K, N, N, usize)> + '_ {
         self.bags.iter().flat_map(|(kind, edge_set)| {
             edge_set
@@ -155,6 +229,27 @@ where
                 .map(move |(src, tgt, count)| (*kind, src, tgt, count))
         })
     }
+
+    pub fn iter_kind(&self, kind: &K) -> impl Iterator<Item = (N, N, usize)> + '_ {
+        self.bags.get(kind).map(|m| m.iter()).into_iter().flatten()
+    }
+
+    /// Iterate over the edges of every kind which satisfies `pred`.
+    pub fn iter_where<'a, F>(&'a self, pred: F) -> impl Iterator<Item = (K, N, N, usize)> + 'a
+    where
+        F: Fn(&K) -> bool + 'a,
+    {
+        self.bags.iter().filter(move |(kind, _)| pred(*kind)).flat_map(|(kind, edge_set)| {
+            edge_set
+                .iter()
+                .map(move |(src, tgt, count)| (*kind, src, tgt, count))
+        })
+    }
+
+    /// Every kind of edge from `src` to `tgt` along with its count.
+    pub fn between<'a>(&'a self, src: &'a N, tgt: &'a N) -> impl Iterator<Item = (K, usize)> + 'a {
+        self.bags.iter().filter_map(move |(kind, m)| m.count(src, tgt).map(|count| (*kind, count)))
+    }
 }
 
 #[cfg(test)]
@@ -172,4 +267,27 @@ mod tests {
         let set: HashSet<(usize, usize, usize)> = bag.iter().collect();
         assert!(set.contains(&(3, 4, 1)));
     }
+
+    #[test]
+    fn test_compact() {
+        let mut bag: EdgeBag<usize> = EdgeBag::new();
+        bag.insert(1, 2);
+        bag.insert(1, 3);
+        bag.insert(2, 3);
+        bag.compact();
+
+        // Both before and after the new edges are compacted
+        assert_eq!(bag.insert(1, 2), 2);
+        assert_eq!(bag.insert_count(3, 1, 5), 5);
+
+        for _ in 0..2 {
+            assert_eq!(bag.outgoing(&1).collect::<Vec<_>>(), vec![(2, 2), (3, 1)]);
+            assert_eq!(bag.incoming(&3).collect::<Vec<_>>(), vec![(1, 1), (2, 1)]);
+            assert_eq!(bag.incoming(&1).collect::<Vec<_>>(), vec![(3, 5)]);
+            assert_eq!(bag.count(&3, &1), Some(5));
+            assert_eq!(bag.count(&3, &2), None);
+            assert_eq!(bag.iter().count(), 4);
+            bag.compact();
+        }
+    }
 }
@@ -0,0 +1,53 @@
+use crate::budget::Budget;
+
+/// Limits on the size of a rendered graph, shared by every command that draws
+/// one.
+#[derive(clap::Args)]
+pub struct CliBudgetArgs {
+    /// Refuse to write output with more than this many nodes. Use 0 for no
+    /// limit.
+    #[clap(
+        help_heading = "BUDGET OPTIONS",
+        value_name = "N",
+        long,
+        default_value_t = 20_000,
+        display_order = 60
+    )]
+    max_output_nodes: usize,
+    /// Refuse to write output with more than this many edges. Use 0 for no
+    /// limit.
+    #[clap(
+        help_heading = "BUDGET OPTIONS",
+        value_name = "N",
+        long,
+        default_value_t = 100_000,
+        display_order = 61
+    )]
+    max_output_edges: usize,
+    /// Refuse to write output larger than this many megabytes. Use 0 for no
+    /// limit.
+    #[clap(
+        help_heading = "BUDGET OPTIONS",
+        value_name = "MB",
+        long,
+        default_value_t = 64,
+        display_order = 62
+    )]
+    max_output_megabytes: usize,
+    /// Instead of refusing, keep only the most connected entities (and the
+    /// deps between them) so that the output fits within the budget.
+    #[clap(help_heading = "BUDGET OPTIONS", long, display_order = 63)]
+    pub auto_slice: bool,
+}
+
+impl CliBudgetArgs {
+    pub fn to_budget(&self) -> Budget {
+        let limit = |max: usize| Some(max).filter(|max| *max > 0);
+
+        Budget {
+            max_nodes: limit(self.max_output_nodes),
+            max_edges: limit(self.max_output_edges),
+            max_bytes: limit(self.max_output_megabytes).map(|max| max * 1024 * 1024),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::snapshot::Snapshot;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Build caches which other subcommands can load with --from-cache.
+#[derive(clap::Args)]
+pub struct CliCacheCommand {
+    #[clap(subcommand)]
+    command: CliCacheSubCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum CliCacheSubCommand {
+    Build(CliCacheBuildCommand),
+}
+
+impl CliCommand for CliCacheCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        match &self.command {
+            CliCacheSubCommand::Build(com) => com.execute(),
+        }
+    }
+}
+
+/// Load entries once and save the deduplicated graph as a binary cache.
+///
+/// Loading a cache skips reading and decoding entries entirely, so running
+/// several subcommands over the same entries takes seconds rather than
+/// minutes. The load options given here (e.g. --strip-facts or --dedup-files)
+/// are baked into the cache. The cache is a snapshot, so `snapshot info` also
+/// works on it.
+#[derive(clap::Args)]
+pub struct CliCacheBuildCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write the cache to.
+    #[clap(value_name = "CACHE")]
+    cache: PathBuf,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliCacheBuildCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let graph = self.load.load(&self.input)?;
+
+        let start = Instant::now();
+        Snapshot::from(graph).write(&self.cache)?;
+        log::info!("Wrote cache in {} secs.", start.elapsed().as_secs_f32());
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use crate::clones::clone_groups;
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Find groups of entities which look mechanically duplicated.
+///
+/// Each entity is fingerprinted by the kinds of its outgoing deps and the
+/// kinds of the entities they target (but not which entities those are).
+/// Entities with the same name and the same fingerprint are listed together,
+/// largest group first, since they are most likely copies of one another
+/// (e.g. a class pasted into several modules).
+#[derive(clap::Args)]
+pub struct CliClonesCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Ignore entities with fewer than this many outgoing deps, which are too
+    /// small to tell apart.
+    #[clap(value_name = "N", long, default_value_t = 3, display_order = 3)]
+    min_deps: usize,
+    /// Only list this many groups.
+    #[clap(value_name = "N", long, display_order = 4)]
+    limit: Option<usize>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliClonesCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let mut entity_graph = self.load.entities(&spec_graph)?;
+        entity_graph.lift_anchors(&spec_graph);
+
+        let groups = clone_groups(&entity_graph, self.min_deps);
+        log::info!(
+            "Found {} group(s) of clones with {} entities in total.",
+            groups.len(),
+            groups.iter().map(|group| group.members.len()).sum::<usize>()
+        );
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+
+        for (i, group) in groups.iter().take(self.limit.unwrap_or(usize::MAX)).enumerate() {
+            writeln!(
+                writer,
+                "Group {} ({} {} named {}, {} deps each, fingerprint {}):",
+                i + 1,
+                group.members.len(),
+                group.kind,
+                group.name,
+                group.num_deps,
+                group.fingerprint
+            )?;
+
+            for id in &group.members {
+                let entity = &entity_graph.entities[id];
+                writeln!(writer, "  {} ({})", entity.qualified_name, entity.path)?;
+            }
+
+            writeln!(writer)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
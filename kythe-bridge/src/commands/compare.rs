@@ -0,0 +1,90 @@
+use crate::compare::{compare, Agreement};
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use tabled::{Style, Table, Tabled};
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Compare the graphs produced by two indexers (or two versions of one) over
+/// the same code.
+///
+/// Nodes are aligned by ticket. For each edge kind, report how many edges
+/// appear in both graphs and how many are missing from either one, which helps
+/// to quantify indexer regressions.
+#[derive(clap::Args)]
+pub struct CliCompareCommand {
+    /// Path of the first file of entries.
+    #[clap(value_name = "A")]
+    a: PathBuf,
+    /// Path of the second file of entries.
+    #[clap(value_name = "B")]
+    b: PathBuf,
+    /// Path of the file to write the table to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 1)]
+    output: Option<PathBuf>,
+    /// Path of a file to write every edge which only appears in one of the
+    /// graphs to (as newline-delimited JSON).
+    #[clap(value_name = "PATH", long, display_order = 2)]
+    diff_out: Option<PathBuf>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Tabled)]
+struct Row {
+    #[tabled(rename = "Edge Kind")]
+    kind: String,
+
+    #[tabled(rename = "Both")]
+    both: usize,
+
+    #[tabled(rename = "Only A")]
+    only_a: usize,
+
+    #[tabled(rename = "Only B")]
+    only_b: usize,
+
+    #[tabled(rename = "Agreement")]
+    agreement: String,
+}
+
+impl Row {
+    fn new(kind: String, agreement: &Agreement) -> Self {
+        Self {
+            kind,
+            both: agreement.both,
+            only_a: agreement.only_a,
+            only_b: agreement.only_b,
+            agreement: format!("{:.1}%", agreement.ratio() * 100.0),
+        }
+    }
+}
+
+impl CliCommand for CliCompareCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let a = self.load.load(&[self.a.display().to_string()])?;
+        let b = self.load.load(&[self.b.display().to_string()])?;
+        let comparison = compare(&a, &b);
+
+        let mut rows = vec![Row::new("(nodes)".to_string(), &comparison.nodes)];
+        rows.extend(comparison.edges.iter().map(|(kind, a)| Row::new(format!("{:?}", kind), a)));
+        let table = Table::new(rows).with(Style::psql()).to_string();
+        open_bufwriter(self.output.clone())?.write_all(table.as_bytes())?;
+
+        if let Some(path) = &self.diff_out {
+            let mut writer = open_bufwriter(Some(path.clone()))?;
+
+            for edge in &comparison.missing {
+                serde_json::to_writer(&mut writer, edge)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use crate::coverage::{coverage_map, test_matcher, DEFAULT_TEST_PATTERNS};
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Report which test files refer to each function and type, as CSV.
+///
+/// A file is a test if its path matches a test pattern. An entity is "covered"
+/// by a test file if any entity in that file depends on it, so this is only a
+/// cheap structural proxy for real test coverage. Entities no test refers to
+/// are listed with zero test files.
+#[derive(clap::Args)]
+pub struct CliCoverageMapCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Glob matching the paths of test files. May be repeated. If ommitted,
+    /// common conventions are used (e.g. "**/tests/**" or "**/*_test.*").
+    #[clap(value_name = "GLOB", long, display_order = 3)]
+    test_pattern: Vec<String>,
+    /// Only list entities which no test file refers to.
+    #[clap(long, display_order = 4)]
+    untested_only: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliCoverageMapCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let tests = match self.test_pattern.is_empty() {
+            true => test_matcher(&DEFAULT_TEST_PATTERNS)?,
+            false => test_matcher(&self.test_pattern)?,
+        };
+
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+        let rows = coverage_map(&entity_graph, &tests);
+
+        let num_untested = rows.iter().filter(|row| row.num_test_files == 0).count();
+        log::info!("Found {} of {} entities without any tests.", num_untested, rows.len());
+
+        let mut writer = csv::Writer::from_writer(open_bufwriter(self.output.clone())?);
+
+        for row in rows.into_iter().filter(|row| !self.untested_only || row.num_test_files == 0) {
+            writer.serialize(row)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
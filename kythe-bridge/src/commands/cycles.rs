@@ -0,0 +1,97 @@
+use crate::cycles::{entity_cycles, file_cycles, Cycle};
+use crate::io::open_bufwriter;
+use crate::ir::{EdgeKind, FileKey, NodeIndex};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Find cycles of dependencies between files (or entities).
+///
+/// Each cycle is a strongly connected component: every member depends on
+/// every other member, possibly indirectly. Cycles are printed largest first,
+/// along with the edges inside them and the kinds and counts of the deps which
+/// form each edge.
+#[derive(clap::Args)]
+pub struct CliCyclesCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Find cycles between entities rather than between files.
+    #[clap(long, display_order = 3)]
+    entities: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+fn format_counts(counts: &BTreeMap<EdgeKind, usize>) -> String {
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{:?}: {}", kind, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write each cycle, naming its members with `label`.
+fn format_cycles<N, F: Fn(&N) -> String>(cycles: &[Cycle<N>], unit: &str, label: F) -> String {
+    let mut text = String::new();
+
+    for (i, cycle) in cycles.iter().enumerate() {
+        writeln!(text, "Cycle {} ({} {}):", i + 1, cycle.members.len(), unit).unwrap();
+
+        for edge in &cycle.edges {
+            let (src, tgt, counts) = (label(&edge.src), label(&edge.tgt), &edge.counts);
+            writeln!(text, "  {} -> {} [{}]", src, tgt, format_counts(counts)).unwrap();
+        }
+
+        writeln!(text).unwrap();
+    }
+
+    text
+}
+
+impl CliCommand for CliCyclesCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let (text, num_cycles) = match self.entities {
+            true => {
+                let cycles = entity_cycles(&entity_graph);
+                let label = |id: &NodeIndex| match entity_graph.entities.get(id) {
+                    Some(entity) => format!("{} ({})", entity.qualified_name, entity.path),
+                    None => id.to_string(),
+                };
+                (format_cycles(&cycles, "entities", label), cycles.len())
+            }
+            false => {
+                let cycles = file_cycles(&entity_graph, &spec_graph);
+                // Taken from the file entity, so that paths are aliased (and
+                // anonymized) like everywhere else
+                let file = |key: FileKey| entity_graph.entities.get(&spec_graph.get_file(key)?);
+                let label = |key: &FileKey| match file(*key) {
+                    Some(file) => file.path.clone(),
+                    None => "???".to_string(),
+                };
+                (format_cycles(&cycles, "files", label), cycles.len())
+            }
+        };
+
+        log::info!("Found {} cycle(s).", num_cycles);
+        let mut writer = open_bufwriter(self.output.clone())?;
+        writer.write_all(text.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
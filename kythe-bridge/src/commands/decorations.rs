@@ -0,0 +1,49 @@
+use crate::decorations::decorations;
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Produce every anchor of a single file along with its span, edge kind, and
+/// target entity (as JSON).
+///
+/// This is similar to Kythe's decorations API and is meant as a building block
+/// for editor plugins.
+#[derive(clap::Args)]
+pub struct CliDecorationsCommand {
+    /// Path of the file to decorate, as it appears in the output of `format`.
+    #[clap(value_name = "FILE")]
+    file: String,
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliDecorationsCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+        let decorations = decorations(&spec_graph, &entity_graph, &self.file);
+
+        if decorations.is_empty() {
+            log::warn!("Found no anchors in \"{}\".", self.file);
+        }
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+        serde_json::to_writer_pretty(&mut writer, &decorations)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
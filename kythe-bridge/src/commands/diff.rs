@@ -0,0 +1,144 @@
+use crate::diff::{diff, DepChange, EntityChange, GraphDiff};
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use tabled::{Style, Table, Tabled};
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Report the entities and deps which were added or removed between two
+/// versions of a codebase.
+///
+/// Entities are matched by their stable id (derived from the corpus, path, and
+/// signature of their ticket), so the two graphs may be loaded from entirely
+/// separate indexer runs. This is useful for tracking architectural drift
+/// between commits.
+#[derive(clap::Args)]
+pub struct CliDiffCommand {
+    /// Path (or glob) of the entries of the earlier version. May be repeated.
+    #[clap(value_name = "PATH", long, required = true, display_order = 1)]
+    before: Vec<String>,
+    /// Path (or glob) of the entries of the later version. May be repeated.
+    #[clap(value_name = "PATH", long, required = true, display_order = 2)]
+    after: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 3)]
+    output: Option<PathBuf>,
+    /// Format of the report.
+    #[clap(
+        short = 'f',
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "table",
+        display_order = 4
+    )]
+    format: CliDiffFormat,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliDiffFormat {
+    /// A table of entity changes followed by a table of dep changes
+    Table,
+    /// A single JSON object with a list for each kind of change
+    Json,
+}
+
+#[derive(Tabled)]
+struct EntityRow<'a> {
+    #[tabled(rename = "Change")]
+    change: &'static str,
+
+    #[tabled(rename = "Kind")]
+    kind: &'static str,
+
+    #[tabled(rename = "Path")]
+    path: &'a str,
+
+    #[tabled(rename = "Name")]
+    name: &'a str,
+}
+
+impl<'a> EntityRow<'a> {
+    fn new(change: &'static str, entity: &'a EntityChange) -> Self {
+        Self { change, kind: entity.kind, path: &entity.path, name: &entity.qualified_name }
+    }
+}
+
+#[derive(Tabled)]
+struct DepRow<'a> {
+    #[tabled(rename = "Change")]
+    change: &'static str,
+
+    #[tabled(rename = "Source")]
+    src: &'a str,
+
+    #[tabled(rename = "Edge Kind")]
+    kind: String,
+
+    #[tabled(rename = "Target")]
+    tgt: &'a str,
+}
+
+impl<'a> DepRow<'a> {
+    fn new(change: &'static str, dep: &'a DepChange) -> Self {
+        Self { change, src: &dep.src, kind: format!("{:?}", dep.kind), tgt: &dep.tgt }
+    }
+}
+
+fn to_tables(diff: &GraphDiff) -> String {
+    let entities = diff
+        .added_entities
+        .iter()
+        .map(|e| EntityRow::new("+", e))
+        .chain(diff.removed_entities.iter().map(|e| EntityRow::new("-", e)));
+    let deps = diff
+        .added_deps
+        .iter()
+        .map(|d| DepRow::new("+", d))
+        .chain(diff.removed_deps.iter().map(|d| DepRow::new("-", d)));
+
+    format!(
+        "{}\n\n{}\n",
+        Table::new(entities).with(Style::psql()),
+        Table::new(deps).with(Style::psql())
+    )
+}
+
+impl CliCommand for CliDiffCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let before_spec = self.load.spec(self.load.load(&self.before)?)?;
+        let before = self.load.entities(&before_spec)?;
+        let after_spec = self.load.spec(self.load.load(&self.after)?)?;
+        let after = self.load.entities(&after_spec)?;
+        let diff = diff(&before, &after);
+
+        log::info!(
+            "Found {} added and {} removed entities, and {} added and {} removed deps.",
+            diff.added_entities.len(),
+            diff.removed_entities.len(),
+            diff.added_deps.len(),
+            diff.removed_deps.len()
+        );
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+
+        match self.format {
+            CliDiffFormat::Table => writer.write_all(to_tables(&diff).as_bytes())?,
+            CliDiffFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, &diff)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
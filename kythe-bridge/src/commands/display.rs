@@ -1,20 +1,28 @@
-use dot_writer::{Attributes, DotWriter};
+use itertools::Itertools;
+use rayon::prelude::*;
 
-use crate::io::{EntryReader, open_bufwriter};
-use crate::ir::{Dep, Entity, EntityGraph, SpecGraph, RawGraph, NodeKind};
+use crate::budget::{estimate_size, render_within, OutputSize};
+use crate::io::open_bufwriter;
+use crate::ir::{Dep, Entity, EntityGraph, GroupBy, NodeIndex, NodeKind};
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::io::Write;
 use std::path::PathBuf;
 use std::time::Instant;
 
+use super::budget::CliBudgetArgs;
+use super::load::{parse_group_by, CliGroupBy, CliLoadArgs};
 use super::CliCommand;
 
 /// Produce a DOT file that can be rendered with Graphviz.
 ///
 /// Reads a stream of newline-delimited entries in and writes out a DOT file. It
 /// is recommended to use the `exclude` subcommand to filter down the graph to a
-/// legible size.
+/// legible size. The size of the output is checked against a budget before
+/// anything is written (see --max-output-nodes, --max-output-edges, and
+/// --max-output-megabytes), and oversized output is refused unless
+/// --auto-slice is given.
 ///
 /// For more info on Kythe's entry format, see https://kythe.io/docs/kythe-storage.html.
 ///
@@ -23,64 +31,389 @@ use super::CliCommand;
 /// console does not support UTF-8).
 #[derive(clap::Args)]
 pub struct CliDisplayCommand {
-    /// Path of the file to read entries from. If ommitted, read from stdin.
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
     #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
-    input: Option<PathBuf>,
+    input: Vec<String>,
     /// Path of the file to write DOT file to. If ommitted, write to stdout.
     #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
     output: Option<PathBuf>,
+    /// Truncate names and text within labels to at most this many characters.
+    #[clap(value_name = "N", long, default_value_t = 32, display_order = 4)]
+    max_label_len: usize,
+    /// Draw a single edge between each pair of nodes, labeled with the count
+    /// of each kind of edge between them, rather than one edge per kind.
+    #[clap(long, display_order = 5)]
+    collapse_edges: bool,
+    /// Hide edges with a count below this. If --collapse-edges is given, this
+    /// applies to the total count between each pair of nodes.
+    #[clap(value_name = "N", long, default_value_t = 1, display_order = 6)]
+    min_count: usize,
+    /// Draw a box (cluster) around the nodes in each file, package, or
+    /// directory (e.g. "dir:2").
+    #[clap(value_name = "BY", long, value_parser = parse_group_by, display_order = 7)]
+    group_by: Option<CliGroupBy>,
+    /// Draw each top-level directory as a single node instead of drawing
+    /// entities, with edges weighted by the number of deps between them.
+    #[clap(long, conflicts_with_all = &["group-by", "collapse-edges"], display_order = 8)]
+    dirs: bool,
+    /// With --dirs, draw the children (directories and files) of the given
+    /// directory instead of the directory itself. May be repeated, including
+    /// for directories within other expanded directories.
+    #[clap(value_name = "DIR", long, requires = "dirs", display_order = 9)]
+    expand: Vec<String>,
+
+    #[clap(flatten)]
+    budget: CliBudgetArgs,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+/// The statements of a DOT file along with the number of nodes they draw
+/// (which may be less than the number of node statements due to clusters).
+struct DotStmts {
+    nodes: Vec<String>,
+    edges: Vec<String>,
+    n_nodes: usize,
+}
+
+const DOT_HEADER: &[u8] = b"digraph {\n";
+const DOT_FOOTER: &[u8] = b"}\n";
+
+impl DotStmts {
+    fn size(&self) -> OutputSize {
+        let stmts = self.nodes.iter().chain(self.edges.iter());
+        let bytes =
+            DOT_HEADER.len() + stmts.map(|stmt| stmt.len()).sum::<usize>() + DOT_FOOTER.len();
+        OutputSize { nodes: self.n_nodes, edges: self.edges.len(), bytes }
+    }
 }
 
 impl CliCommand for CliDisplayCommand {
     fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let graph = self.load.load(&self.input)?;
         let start = Instant::now();
-        let reader = EntryReader::open(self.input.clone())?;
-        let graph = RawGraph::try_from(reader)?;
-        log::debug!("Loaded raw graph in {} secs.", start.elapsed().as_secs_f32());
-        let start = Instant::now();
-        let graph = SpecGraph::try_from(graph)?;
+        let graph = self.load.spec(graph)?;
         log::debug!("Loaded spec graph in {} secs.", start.elapsed().as_secs_f32());
-        let graph = EntityGraph::try_from(graph)?;
-
-        // Setup graphviz stuff
-        let mut output_bytes: Vec<u8> = Vec::new();
-        {
-            let mut dot_writer = DotWriter::from(&mut output_bytes);
-            let mut digraph = dot_writer.digraph();
-    
-            // Add nodes to DOT graph
-            for entity in graph.entities.values() {
-                let mut node = digraph.node_named(entity.id.to_string());
-                node.set_label(&to_node_label(entity));
+        let graph = self.load.entities(&graph)?;
+
+        let start = Instant::now();
+        let estimate = |graph: &EntityGraph| match self.dirs {
+            true => to_dir_graph(graph, &self.expand, self.min_count).estimate(),
+            false => self.estimate_entity_stmts(graph),
+        };
+        let render = |graph: &EntityGraph| match self.dirs {
+            true => to_dir_graph(graph, &self.expand, self.min_count).to_stmts(),
+            false => self.to_entity_stmts(graph),
+        };
+        let budget = self.budget.to_budget();
+        let (stmts, _) = render_within(&graph, &budget, self.budget.auto_slice, estimate, render)?;
+        log::debug!(
+            "Generated DOT statements ({}) in {} secs.",
+            stmts.size(),
+            start.elapsed().as_secs_f32()
+        );
+
+        // Write output
+        let mut writer = open_bufwriter(self.output.clone())?;
+        writer.write_all(DOT_HEADER)?;
+
+        for stmt in stmts.nodes.iter().chain(stmts.edges.iter()) {
+            writer.write_all(stmt.as_bytes())?;
+        }
+
+        writer.write_all(DOT_FOOTER)?;
+        Ok(())
+    }
+}
+
+impl CliDisplayCommand {
+    fn to_entity_stmts(&self, graph: &EntityGraph) -> DotStmts {
+        // Generate DOT statements in parallel
+        let max_len = self.max_label_len;
+        let entities = graph.entities.values().sorted_by_key(|e| e.id).collect_vec();
+        let nodes: Vec<String> = match &self.group_by {
+            None => entities.par_iter().map(|e| to_node_stmt(e, max_len)).collect(),
+            Some(group_by) => {
+                let group_by: GroupBy = group_by.into();
+                let clusters = entities
+                    .iter()
+                    .into_group_map_by(|e| group_by.key(**e))
+                    .into_iter()
+                    .sorted_by_key(|(key, _)| *key)
+                    .collect_vec();
+                clusters
+                    .par_iter()
+                    .enumerate()
+                    .map(|(i, (key, entities))| to_cluster_stmt(i, key, entities, max_len))
+                    .collect()
+            }
+        };
+        let min_count = self.min_count;
+
+        let edges: Vec<String> = match self.collapse_edges {
+            false => {
+                graph.deps.par_iter().filter(|d| d.count >= min_count).map(to_edge_stmt).collect()
             }
-    
-            // Add edges to DOT graph
-            for dep in &graph.deps {
-                let edge = digraph.edge(dep.src.to_string(), dep.tgt.to_string());
-                edge.attributes().set_label(&to_edge_label(dep));
+            true => {
+                let pairs = self.to_collapsed_pairs(graph);
+                pairs.par_iter().map(|(pair, deps)| to_collapsed_edge_stmt(*pair, deps)).collect()
+            }
+        };
+
+        DotStmts { nodes, edges, n_nodes: entities.len() }
+    }
+
+    /// Estimate the size of `to_entity_stmts` from the number of entities and
+    /// edges, ignoring the few bytes each cluster adds.
+    fn estimate_entity_stmts(&self, graph: &EntityGraph) -> OutputSize {
+        let max_len = self.max_label_len;
+        let entities = graph.entities.values().sorted_by_key(|e| e.id).collect_vec();
+        let node_len = |e: &&Entity| to_node_stmt(e, max_len).len();
+        let overhead = DOT_HEADER.len() + DOT_FOOTER.len();
+
+        match self.collapse_edges {
+            false => {
+                let deps = graph.deps.iter().filter(|d| d.count >= self.min_count).collect_vec();
+                estimate_size(&entities, &deps, node_len, |d| to_edge_stmt(d).len(), overhead)
+            }
+            true => {
+                let pairs = self.to_collapsed_pairs(graph);
+                let edge_len =
+                    |(pair, deps): &(_, Vec<&Dep>)| to_collapsed_edge_stmt(*pair, deps).len();
+                estimate_size(&entities, &pairs, node_len, edge_len, overhead)
             }
         }
+    }
 
-        // Write output
-        open_bufwriter(self.output.clone())?.write_all(&output_bytes)?;
-        Ok(())
+    /// The deps between each pair of entities, if their total count is at
+    /// least --min-count.
+    fn to_collapsed_pairs<'a>(
+        &self,
+        graph: &'a EntityGraph,
+    ) -> Vec<((NodeIndex, NodeIndex), Vec<&'a Dep>)> {
+        graph
+            .deps
+            .iter()
+            .into_group_map_by(|d| (d.src, d.tgt))
+            .into_iter()
+            .filter(|(_, deps)| deps.iter().map(|d| d.count).sum::<usize>() >= self.min_count)
+            .sorted_by_key(|(pair, _)| *pair)
+            .collect_vec()
     }
 }
 
-fn clean(text: String) -> String {
-    text.replace("\"", "'")
+/// The unit an entity at `path` is drawn as in the directory view: the
+/// top-level directory of `path`, unless that directory is expanded, in which
+/// case its child directory (or file) containing `path`, and so on. Also
+/// returns the innermost expanded directory, if any, as its cluster.
+fn to_dir_unit<'a>(path: &'a str, expand: &HashSet<&str>) -> (&'a str, Option<&'a str>) {
+    let mut cluster = None;
+    let mut end = 0;
+
+    loop {
+        end = match path[end..].find('/') {
+            Some(i) if end + i > 0 => end + i,
+            // A file directly within the last expanded directory (or root)
+            _ => return (path, cluster),
+        };
+
+        if !expand.contains(&path[..end]) {
+            return (&path[..end], cluster);
+        }
+
+        cluster = Some(&path[..end]);
+        end += 1;
+    }
+}
+
+/// The directory view of a graph: each (top-level or expanded) directory as a
+/// single unit, with the deps between each pair of units added up.
+struct DirGraph<'a> {
+    /// The number of entities within each unit, keyed by its cluster and name.
+    sizes: Vec<((Option<&'a str>, &'a str), usize)>,
+    /// The total count of the deps between each pair of units, if at least the
+    /// minimum count.
+    counts: Vec<((&'a str, &'a str), usize)>,
 }
 
-fn to_node_label(entity: &Entity) -> String {
+fn to_dir_graph<'a>(graph: &'a EntityGraph, expand: &[String], min_count: usize) -> DirGraph<'a> {
+    let expand: HashSet<&str> = expand.iter().map(|dir| dir.trim_end_matches('/')).collect();
+    let units: HashMap<NodeIndex, (&str, Option<&str>)> =
+        graph.entities.values().map(|e| (e.id, to_dir_unit(&e.path, &expand))).collect();
+
+    let mut sizes: BTreeMap<(Option<&str>, &str), usize> = BTreeMap::new();
+
+    for (unit, cluster) in units.values() {
+        *sizes.entry((*cluster, *unit)).or_default() += 1;
+    }
+
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+
+    for dep in &graph.deps {
+        let (_, src, tgt) = dep.normalized();
+
+        if let (Some((src, _)), Some((tgt, _))) = (units.get(&src), units.get(&tgt)) {
+            if src != tgt {
+                *counts.entry((*src, *tgt)).or_default() += dep.count;
+            }
+        }
+    }
+
+    DirGraph {
+        sizes: sizes.into_iter().collect(),
+        counts: counts.into_iter().filter(|(_, count)| *count >= min_count).collect(),
+    }
+}
+
+impl DirGraph<'_> {
+    /// Estimate the size of `to_stmts`, ignoring the few bytes each cluster
+    /// adds.
+    fn estimate(&self) -> OutputSize {
+        estimate_size(
+            &self.sizes,
+            &self.counts,
+            |((_, unit), size)| to_dir_node_stmt(unit, *size).len() + 1,
+            |((src, tgt), count)| to_dir_edge_stmt(src, tgt, *count).len(),
+            DOT_HEADER.len() + DOT_FOOTER.len(),
+        )
+    }
+
+    /// Draw each unit as a single node, with a single edge between each pair
+    /// of units weighted by the deps between them.
+    fn to_stmts(self) -> DotStmts {
+        let n_nodes = self.sizes.len();
+        let nodes = self
+            .sizes
+            .into_iter()
+            .group_by(|((cluster, _), _)| *cluster)
+            .into_iter()
+            .enumerate()
+            .map(|(i, (cluster, units))| {
+                let indent = match cluster {
+                    None => "",
+                    Some(_) => "\t",
+                };
+                let stmts = units
+                    .map(|((_, unit), size)| format!("{}{}", indent, to_dir_node_stmt(unit, size)))
+                    .join("");
+
+                match cluster {
+                    None => stmts,
+                    Some(cluster) => format!(
+                        "\tsubgraph cluster_{} {{\n\t\tlabel=\"{}\";\n{}\t}}\n",
+                        i,
+                        escape(cluster),
+                        stmts
+                    ),
+                }
+            })
+            .collect_vec();
+
+        let edges = self
+            .counts
+            .into_iter()
+            .map(|((src, tgt), count)| to_dir_edge_stmt(src, tgt, count))
+            .collect_vec();
+
+        DotStmts { nodes, edges, n_nodes }
+    }
+}
+
+fn to_dir_node_stmt(unit: &str, size: usize) -> String {
+    let name = unit.rsplit('/').next().unwrap_or(unit);
+    format!(
+        "\t\"{}\" [label=\"{}\\n({} entities)\", tooltip=\"{}\"];\n",
+        escape(unit),
+        escape(name),
+        size,
+        escape(unit)
+    )
+}
+
+fn to_dir_edge_stmt(src: &str, tgt: &str, count: usize) -> String {
+    format!("\t\"{}\" -> \"{}\" [label=\"{}\"];\n", escape(src), escape(tgt), count)
+}
+
+/// Escape text for use within a double-quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Shorten text to at most `max_len` characters, marking any truncation with
+/// an ellipsis. Operates on characters rather than bytes so it never splits a
+/// multi-byte character.
+fn truncate(text: &str, max_len: usize) -> String {
+    if text.chars().count() <= max_len {
+        return text.to_string();
+    }
+
+    match max_len {
+        0..=3 => text.chars().take(max_len).collect(),
+        _ => text.chars().take(max_len - 3).chain("...".chars()).collect(),
+    }
+}
+
+fn to_node_label(entity: &Entity, max_len: usize) -> String {
     let kind = match &entity.kind {
-        NodeKind::Doc(text) => NodeKind::Doc(text[..18].to_string()),
-        NodeKind::File(text) => NodeKind::File(text[..18].to_string()),
+        NodeKind::Constant(text) => NodeKind::Constant(truncate(text, max_len)),
+        NodeKind::Diagnostic(text) => NodeKind::Diagnostic(truncate(text, max_len)),
+        NodeKind::Doc(text) => NodeKind::Doc(truncate(text, max_len)),
+        NodeKind::File(text) => NodeKind::File(truncate(text, max_len)),
+        NodeKind::Lookup(text) => NodeKind::Lookup(truncate(text, max_len)),
         kind => kind.clone(),
     };
-    
-    clean(format!("{}\n<{:?}>", entity.name, kind))
+
+    format!("{}\n<{:?}>", truncate(&entity.name, max_len), kind)
 }
 
 fn to_edge_label(dep: &Dep) -> String {
-    clean(format!("{:?} ({})", dep.kind, dep.count))
+    format!("{:?} ({})", dep.kind, dep.count)
+}
+
+fn to_node_stmt(entity: &&Entity, max_len: usize) -> String {
+    format!(
+        "\t{} [label=\"{}\", tooltip=\"{}\"];\n",
+        entity.id,
+        escape(&to_node_label(entity, max_len)),
+        escape(&entity.path)
+    )
+}
+
+fn to_cluster_stmt(index: usize, label: &str, entities: &[&&Entity], max_len: usize) -> String {
+    let nodes = entities.iter().map(|e| format!("\t{}", to_node_stmt(e, max_len))).join("");
+    format!("\tsubgraph cluster_{} {{\n\t\tlabel=\"{}\";\n{}\t}}\n", index, escape(label), nodes)
+}
+
+fn to_edge_stmt(dep: &Dep) -> String {
+    format!("\t{} -> {} [label=\"{}\"];\n", dep.src, dep.tgt, escape(&to_edge_label(dep)))
+}
+
+fn to_collapsed_edge_stmt((src, tgt): (NodeIndex, NodeIndex), deps: &[&Dep]) -> String {
+    let label = deps.iter().sorted_by_key(|d| d.kind).map(|d| to_edge_label(d)).join("\n");
+    format!("\t{} -> {} [label=\"{}\"];\n", src, tgt, escape(&label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 18), "short");
+        assert_eq!(truncate("abcdefgh", 6), "abc...");
+        assert_eq!(truncate("ééééé", 4), "é...");
+        assert_eq!(truncate("abcdefgh", 2), "ab");
+    }
+
+    #[test]
+    fn test_to_dir_unit() {
+        let expand = HashSet::from(["src", "src/db"]);
+        assert_eq!(to_dir_unit("lib/a.cc", &expand), ("lib", None));
+        assert_eq!(to_dir_unit("src/util/a.cc", &expand), ("src/util", Some("src")));
+        assert_eq!(to_dir_unit("src/a.cc", &expand), ("src/a.cc", Some("src")));
+        assert_eq!(to_dir_unit("src/db/x/a.cc", &expand), ("src/db/x", Some("src/db")));
+        assert_eq!(to_dir_unit("a.cc", &expand), ("a.cc", None));
+    }
 }
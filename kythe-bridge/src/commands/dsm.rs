@@ -1,51 +1,316 @@
-// use crate::dv8::{Dv8Graph, Dv8Matrix};
-// use crate::io::Writer;
-
-// use std::path::PathBuf;
-// use std::time::Instant;
-
-// use super::CliCommand;
-
-// /// Produce a JSON file that can be processed by DV8.
-// ///
-// /// Reads a stream of newline-delimited entries in and produces a file-level DSM
-// /// (Design Structure Matrix) in a format suitable for DV8 (https://archdia.com/).
-// ///
-// /// On Windows, it is recommended to use --input/--output rather than
-// /// stdin/stdout for both performance reasons and compatibility reasons (Windows
-// /// console does not support UTF-8).
-// #[derive(clap::Args)]
-// pub struct CliDsmCommand {
-//     /// Path of the file to read entries from. If ommitted, read from stdin.
-//     #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
-//     input: Option<PathBuf>,
-//     /// Path of the file to write JSON file to. If ommitted, write to stdout.
-//     #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
-//     output: Option<PathBuf>,
-//     /// Name of the output DSM. This is included in the JSON file.
-//     #[clap(short = 'n', long, display_order = 3)]
-//     name: String,
-// }
-
-// impl CliCommand for CliDsmCommand {
-//     fn execute(&self) {
-//         let input = self.input.as_ref().map(PathBuf::as_path);
-//         let output = self.output.as_ref().map(PathBuf::as_path);
-//         let mut writer = Writer::open(output).unwrap();
-
-//         let start = Instant::now();
-//         let graph = Dv8Graph::open(input).unwrap();
-//         log::debug!("Loaded graph in {} secs.", start.elapsed().as_secs_f32());
-
-//         let start = Instant::now();
-//         let mut matrix = Dv8Matrix::from(graph);
-//         matrix.set_name(self.name.clone());
-//         log::debug!("Converted to DV8 matrix in {} secs.", start.elapsed().as_secs_f32());
-
-//         let start = Instant::now();
-//         let serialized = serde_json::to_string_pretty(&matrix).unwrap();
-//         log::debug!("Serialized in {} secs.", start.elapsed().as_secs_f32());
-
-//         writer.write(serialized.as_bytes()).unwrap();
-//     }
-// }
+use crate::dv8::{Dv8Sink, Granularity, KindMap, MatrixDiff, MatrixFormat};
+use crate::io::open_bufwriter;
+use crate::manifest::ManifestWriter;
+use crate::seriation::Seriation;
+use crate::sink::write_graph;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use super::load::{parse_group_by, CliGroupBy, CliLoadArgs};
+use super::CliCommand;
+
+/// Produce a DSM that can be processed by DV8 (or by Lattix, Structure101, or
+/// as a plain table).
+///
+/// Reads a stream of newline-delimited entries in and produces a file-level DSM
+/// (Design Structure Matrix) in a format suitable for DV8 (https://archdia.com/).
+/// Use the `export` subcommand to write a DSM alongside other outputs (or
+/// alongside a clustering of the DSM) from a single load of the graph.
+///
+/// With --granularity entity, each function, class, field, etc. is a variable
+/// instead, with the deps of its anchors lifted to it.
+///
+/// Variables are in order of name unless reordered with --order. Partitioning
+/// (--order partition) is usually the most telling for finding layers and
+/// cycles, while clustering brings tightly coupled variables together.
+///
+/// Not everyone has DV8, so the matrix may instead be written as a plain CSV
+/// (or TSV) table of dep counts with --format, either dense (with the names of
+/// the variables as the first row and column) or, with --sparse, as one
+/// src/dest/count row per non-empty cell. Either loads directly into pandas or
+/// R.
+///
+/// Teams using Lattix or Structure101 instead may use --format ldi or --format
+/// s101, which roll up the same variables and dependency kinds as DV8.
+///
+/// DV8 shows variables named like "src.module.File.java" as a hierarchy, so
+/// --dotted names them that way, and --clsx also writes a matching clustering
+/// so that the DSM opens already grouped by directory.
+///
+/// With --diff, no entries are read. Instead, two DSMs previously written by
+/// this subcommand (in DV8's format) are compared, with variables matched by
+/// name, and the change in each cell is written as a DSM of its own. In DV8's
+/// format, each changed cell is "Added", "Removed", "Increased", or
+/// "Decreased" (by the change in its weight), while tables hold the signed
+/// change.
+///
+/// On Windows, it is recommended to use --input/--output rather than
+/// stdin/stdout for both performance reasons and compatibility reasons (Windows
+/// console does not support UTF-8).
+#[derive(clap::Args)]
+pub struct CliDsmCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Name of the output DSM. This is included in the JSON file. If ommitted,
+    /// the name of the output file (without its extension) is used.
+    #[clap(short = 'n', value_name = "NAME", long, display_order = 3)]
+    name: Option<String>,
+    /// Whether each variable of the DSM is a file, a package, or a directory
+    /// (e.g. "dir:2" for the first two levels of directories).
+    #[clap(
+        value_name = "BY",
+        long,
+        value_parser = parse_group_by,
+        default_value = "path",
+        display_order = 4
+    )]
+    group_by: CliGroupBy,
+    /// Whether each variable of the DSM is a group of entities (see
+    /// --group-by) or a single entity. Entities are named by their file and
+    /// their ancestors, e.g. "src/a.cc/Foo/bar".
+    #[clap(
+        value_name = "GRANULARITY",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "file",
+        display_order = 5
+    )]
+    granularity: CliGranularity,
+    /// Path of a JSON file which maps edge kinds to DV8 dependency kinds (or
+    /// to null, to drop them), overriding the built-in mapping for the edge
+    /// kinds it lists. Keys may omit the "/kythe/edge/" prefix, e.g.
+    /// {"ref/includes": "Use", "typed": "Type"}.
+    #[clap(value_name = "PATH", long, display_order = 6)]
+    kind_map: Option<PathBuf>,
+    /// How to order the variables of the DSM.
+    #[clap(
+        value_name = "ORDER",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "name",
+        display_order = 7
+    )]
+    order: CliOrder,
+    /// Format of the output.
+    #[clap(
+        short = 'f',
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "dv8",
+        display_order = 8
+    )]
+    format: CliMatrixFormat,
+    /// Write one row per non-empty cell rather than a dense table. Only
+    /// applies if the format is csv or tsv.
+    #[clap(long, display_order = 9)]
+    sparse: bool,
+    /// Paths of two DSMs (in DV8's format) to compare instead of reading
+    /// entries, e.g. of the same project before and after a refactoring.
+    #[clap(
+        long,
+        value_names = &["BEFORE", "AFTER"],
+        number_of_values = 2,
+        conflicts_with = "input",
+        display_order = 10
+    )]
+    diff: Option<Vec<PathBuf>>,
+    /// Name each variable by its path with "/" replaced by "." (e.g.
+    /// "src.module.File.java"), which DV8 shows as a hierarchy of
+    /// directories.
+    #[clap(long, display_order = 11)]
+    dotted: bool,
+    /// Path of the file to write a clustering of the variables by directory
+    /// (in DV8's .clsx JSON format) to, so that DV8 opens the DSM already
+    /// grouped by directory. Implies --dotted.
+    #[clap(value_name = "PATH", long, conflicts_with = "diff", display_order = 12)]
+    clsx: Option<PathBuf>,
+    /// Also write the size and checksum of the output (and of the clustering,
+    /// if any) to <PATH>.manifest.json, so that it can be checked with
+    /// `verify-export`.
+    #[clap(long, requires = "output", display_order = 14)]
+    manifest: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliGranularity {
+    File,
+    Entity,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliOrder {
+    /// In order of name
+    Name,
+    /// Reverse Cuthill-McKee, to minimize the distance of each dep from the
+    /// diagonal
+    Rcm,
+    /// By hierarchical clustering, to keep tightly coupled variables together
+    Cluster,
+    /// By DSM partitioning, to put every dep outside of a cycle below the
+    /// diagonal
+    Partition,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliMatrixFormat {
+    /// DV8's JSON format
+    Dv8,
+    /// A table of comma-separated values
+    Csv,
+    /// A table of tab-separated values
+    Tsv,
+    /// Lattix's LDI XML
+    Ldi,
+    /// Structure101's generic XML
+    S101,
+}
+
+impl From<&CliOrder> for Seriation {
+    fn from(order: &CliOrder) -> Self {
+        match order {
+            CliOrder::Name => Seriation::Name,
+            CliOrder::Rcm => Seriation::Rcm,
+            CliOrder::Cluster => Seriation::Cluster,
+            CliOrder::Partition => Seriation::Partition,
+        }
+    }
+}
+
+impl CliDsmCommand {
+    fn format(&self) -> MatrixFormat {
+        let delimiter = match self.format {
+            CliMatrixFormat::Dv8 => return MatrixFormat::Dv8,
+            CliMatrixFormat::Ldi => return MatrixFormat::Ldi,
+            CliMatrixFormat::S101 => return MatrixFormat::Structure101,
+            CliMatrixFormat::Csv => b',',
+            CliMatrixFormat::Tsv => b'\t',
+        };
+
+        match self.sparse {
+            true => MatrixFormat::Sparse(delimiter),
+            false => MatrixFormat::Dense(delimiter),
+        }
+    }
+
+    fn name(&self) -> String {
+        let stem = self.output.as_ref().and_then(|path| path.file_stem());
+
+        match (&self.name, stem) {
+            (Some(name), _) => name.clone(),
+            (None, Some(stem)) => stem.to_string_lossy().to_string(),
+            (None, None) => "dsm".to_string(),
+        }
+    }
+
+    fn execute_diff(&self, before: &Path, after: &Path) -> Result<(), Box<dyn Error>> {
+        let mut diff = MatrixDiff::read(before, after)?;
+        log::info!("Found {} changed cell(s).", diff.num_changed());
+
+        let seriation: Seriation = (&self.order).into();
+        let matrix = diff.to_matrix();
+        diff.reorder(&seriation.order(matrix.num_vars(), &matrix.weights()));
+
+        let writer = open_bufwriter(self.output.clone())?;
+        let (mut writer, manifest) = ManifestWriter::new(writer, self.manifest);
+
+        match self.format() {
+            MatrixFormat::Dense(delimiter) => diff.write_dense(&mut writer, delimiter)?,
+            MatrixFormat::Sparse(delimiter) => diff.write_sparse(&mut writer, delimiter)?,
+            format => {
+                let mut matrix = diff.to_matrix();
+                matrix.set_name(self.name());
+                matrix.write(&mut writer, format)?;
+            }
+        }
+
+        writer.flush()?;
+
+        if let (Some(manifest), Some(path)) = (manifest, &self.output) {
+            manifest.manifest()?.write_sidecar(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CliCommand for CliDsmCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(paths) = &self.diff {
+            return self.execute_diff(&paths[0], &paths[1]);
+        }
+
+        let kinds = match &self.kind_map {
+            Some(path) => KindMap::read(path)?,
+            None => KindMap::default(),
+        };
+
+        let graph = self.load.load(&self.input)?;
+        let start = Instant::now();
+        let graph = self.load.spec(graph)?;
+        log::debug!("Loaded spec graph in {} secs.", start.elapsed().as_secs_f32());
+        let mut entity_graph = self.load.entities(&graph)?;
+
+        let granularity = match self.granularity {
+            CliGranularity::File => Granularity::Group((&self.group_by).into()),
+            CliGranularity::Entity => {
+                let num_lifted = entity_graph.lift_anchors(&graph);
+                log::debug!("Lifted the deps of {} anchor(s).", num_lifted);
+                Granularity::Entity
+            }
+        };
+
+        let start = Instant::now();
+        let writer = open_bufwriter(self.output.clone())?;
+        let (writer, manifest) = ManifestWriter::new(writer, self.manifest);
+        let mut clsx_manifest = None;
+        let seriation: Seriation = (&self.order).into();
+        let mut sink = Dv8Sink::with_granularity(writer, Some(self.name()), granularity)
+            .kind_map(kinds)
+            .format(self.format())
+            .order_by(move |matrix| seriation.order(matrix.num_vars(), &matrix.weights()));
+
+        if self.dotted || self.clsx.is_some() {
+            let clsx = match &self.clsx {
+                Some(path) => {
+                    let writer = open_bufwriter(Some(path.clone()))?;
+                    let (writer, manifest) = ManifestWriter::new(writer, self.manifest);
+                    clsx_manifest = manifest.map(|manifest| (manifest, path));
+                    Some(Box::new(writer) as Box<dyn Write>)
+                }
+                None => None,
+            };
+            sink = sink.dotted(clsx);
+        }
+
+        write_graph(&entity_graph, &mut sink)?;
+        drop(sink);
+
+        // Only once the outputs are complete, so that a manifest never
+        // describes a partial file
+        if let (Some(manifest), Some(path)) = (manifest, &self.output) {
+            manifest.manifest()?.write_sidecar(path)?;
+        }
+
+        if let Some((manifest, path)) = clsx_manifest {
+            manifest.manifest()?.write_sidecar(path)?;
+        }
+
+        log::debug!("Wrote matrix in {} secs.", start.elapsed().as_secs_f32());
+        Ok(())
+    }
+}
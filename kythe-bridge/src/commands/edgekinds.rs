@@ -1,7 +1,7 @@
 use itertools::Itertools;
 
-use crate::io::{open_bufwriter, EntryReader};
-use crate::ir::{AnchorKind, EdgeKind, NodeIndex, NodeKind, RawGraph, SpecGraph};
+use crate::io::open_bufwriter;
+use crate::ir::{AnchorKind, EdgeKind, NodeIndex, NodeKind, SpecGraph};
 
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
@@ -9,6 +9,7 @@ use std::io::Write;
 use std::path::PathBuf;
 use tabled::{Style, Table, Tabled};
 
+use super::load::CliLoadArgs;
 use super::CliCommand;
 
 /// Produce a table of edge kinds and frequencies
@@ -25,6 +26,10 @@ use super::CliCommand;
 /// source" in, this means there are 3 nodes with the same node kind and each
 /// one has 2 outgoing edges all with the same edge kind and target kind.
 ///
+/// With --split-by, each bucket is also split by the language (or corpus) of
+/// the source of its edges, so that the edge kinds of each indexer in a mixed
+/// corpus can be told apart.
+///
 /// For more info on Kythe's entry format, see https://kythe.io/docs/kythe-storage.html.
 ///
 /// On Windows, it is recommended to use --input/--output rather than
@@ -33,15 +38,22 @@ use super::CliCommand;
 #[derive(clap::Args)]
 #[clap(verbatim_doc_comment)]
 pub struct CliEdgeKindsCommand {
-    /// Path of the file to read entries from. If ommitted, read from stdin.
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
     #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
-    input: Option<PathBuf>,
+    input: Vec<String>,
     /// Path of the file to write to. If ommitted, write to stdout.
     #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
     output: Option<PathBuf>,
     /// Group edges by this endpoint, then count.
     #[clap(short = 'c', value_name = "ENDPOINT", long, arg_enum, value_parser)]
     count_by: CountBy,
+    /// Split each row by the language or corpus of the source of its edges.
+    #[clap(short = 's', value_name = "BY", long, arg_enum, value_parser)]
+    split_by: Option<SplitBy>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
 }
 
 #[derive(Clone, clap::ValueEnum)]
@@ -50,12 +62,17 @@ pub enum CountBy {
     Target,
 }
 
+#[derive(Clone, clap::ValueEnum)]
+pub enum SplitBy {
+    Language,
+    Corpus,
+}
+
 impl CliCommand for CliEdgeKindsCommand {
     fn execute(&self) -> Result<(), Box<dyn Error>> {
         // Load graph
-        let reader = EntryReader::open(self.input.clone())?;
-        let raw_graph = RawGraph::try_from(reader)?;
-        let graph = SpecGraph::try_from(raw_graph)?;
+        let raw_graph = self.load.load(&self.input)?;
+        let graph = self.load.spec(raw_graph)?;
 
         // Select count by
         let count_by = match self.count_by {
@@ -67,8 +84,9 @@ impl CliCommand for CliEdgeKindsCommand {
         let edges: HashSet<Edge> = graph.iter().map_into().collect();
 
         // Group edges by their `TotalEdgeKind`
-        let edges: HashMap<TotalEdgeKind, Vec<Edge>> =
-            edges.into_iter().into_group_map_by(|e| TotalEdgeKind::from_graph_edge(&graph, e));
+        let edges: HashMap<TotalEdgeKind, Vec<Edge>> = edges.into_iter().into_group_map_by(|e| {
+            TotalEdgeKind::from_graph_edge(&graph, e, self.split_by.as_ref())
+        });
 
         // Subgroup the edges of each group according to their source or target, then
         // count the size of each subgroup. Store these counts as `Vec<usize>`.
@@ -94,7 +112,11 @@ impl CliCommand for CliEdgeKindsCommand {
         // Map these counts to a table and write it out
         let mut rows: Vec<Row> = edges.into_iter().map(Row::from_pair).collect();
         rows.sort();
-        let table = Table::new(rows).with(Style::psql()).to_string();
+        let table = match self.split_by {
+            None => to_table(rows),
+            Some(SplitBy::Language) => to_table(rows.into_iter().map(LanguageRow::from)),
+            Some(SplitBy::Corpus) => to_table(rows.into_iter().map(CorpusRow::from)),
+        };
         open_bufwriter(self.output.clone())?.write_all(table.as_bytes())?;
         Ok(())
     }
@@ -131,22 +153,32 @@ impl From<(EdgeKind, NodeIndex, NodeIndex, usize)> for Edge {
 
 #[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct TotalEdgeKind {
+    /// The language or corpus of the source (if splitting), otherwise empty.
+    split: String,
     src: String,
     tgt: String,
     edge: String,
 }
 
 impl TotalEdgeKind {
-    fn new(src: String, tgt: String, edge: String) -> Self {
-        Self { src, tgt, edge }
+    fn new(split: String, src: String, tgt: String, edge: String) -> Self {
+        Self { split, src, tgt, edge }
     }
 
-    fn from_graph_edge(graph: &SpecGraph, edge: &Edge) -> Self {
-        let src = to_nodekind_str(&graph.get_node(edge.src).kind);
+    fn from_graph_edge(graph: &SpecGraph, edge: &Edge, split_by: Option<&SplitBy>) -> Self {
+        let src_node = graph.get_node(edge.src);
+        let split = match split_by {
+            None => String::new(),
+            Some(SplitBy::Language) => src_node.lang.to_string(),
+            Some(SplitBy::Corpus) => {
+                graph.file_path(src_node).corpus.clone().unwrap_or_else(|| "<none>".to_string())
+            }
+        };
+        let src = to_nodekind_str(&src_node.kind);
         let tgt = to_nodekind_str(&graph.get_node(edge.tgt).kind);
         let edge = to_edgekind_str(&edge.kind);
 
-        Self::new(src, tgt, edge)
+        Self::new(split, src, tgt, edge)
     }
 }
 
@@ -154,9 +186,11 @@ fn to_nodekind_str(kind: &NodeKind) -> String {
     match kind {
         NodeKind::Anchor(AnchorKind::Explicit(_)) => "Anchor(Explicit(...))".to_owned(),
         NodeKind::Constant(_) => "Constant(...)".to_owned(),
+        NodeKind::Diagnostic(_) => "Diagnostic(...)".to_owned(),
         NodeKind::Doc(_) => "Doc(...)".to_owned(),
         NodeKind::File(_) => "File(...)".to_owned(),
         NodeKind::Lookup(_) => "Lookup(...)".to_owned(),
+        NodeKind::Vcs(_) => "Vcs(...)".to_owned(),
         _ => format!("{:?}", kind),
     }
 }
@@ -168,8 +202,15 @@ fn to_edgekind_str(kind: &EdgeKind) -> String {
     }
 }
 
+fn to_table<T: Tabled>(rows: impl IntoIterator<Item = T>) -> String {
+    Table::new(rows).with(Style::psql()).to_string()
+}
+
 #[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Tabled)]
 struct Row {
+    #[tabled(skip)]
+    split: String,
+
     #[tabled(rename = "Source Kind")]
     src: String,
 
@@ -198,6 +239,7 @@ struct Row {
 impl Row {
     fn from_pair((kind, counts): (TotalEdgeKind, HashMap<usize, usize>)) -> Self {
         let mut row = Self {
+            split: kind.split,
             src: kind.src,
             tgt: kind.tgt,
             edge: kind.edge,
@@ -222,3 +264,33 @@ impl Row {
         row
     }
 }
+
+#[derive(Tabled)]
+struct LanguageRow {
+    #[tabled(rename = "Language")]
+    language: String,
+
+    #[tabled(inline)]
+    row: Row,
+}
+
+impl From<Row> for LanguageRow {
+    fn from(mut row: Row) -> Self {
+        Self { language: std::mem::take(&mut row.split), row }
+    }
+}
+
+#[derive(Tabled)]
+struct CorpusRow {
+    #[tabled(rename = "Corpus")]
+    corpus: String,
+
+    #[tabled(inline)]
+    row: Row,
+}
+
+impl From<Row> for CorpusRow {
+    fn from(mut row: Row) -> Self {
+        Self { corpus: std::mem::take(&mut row.split), row }
+    }
+}
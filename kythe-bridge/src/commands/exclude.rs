@@ -1,16 +1,14 @@
-use crate::io::Entry;
-use crate::io::EntryLineReader;
-use crate::io::Ticket;
-use crate::io::open_bufwriter;
+use crate::io::{expand_inputs, BatchWriter, EntryFormat, EntryLineReader};
+use kythe_bridge::exclusion::{
+    read_rule_file, EdgeExclusionKind, ExclusionSet, PathKindBasedExclusion,
+    PathListBasedExclusion, PathPatternBasedExclusion,
+};
+use kythe_bridge::paths::PathKind;
 
 use log;
-use std::collections::HashSet;
 use std::error::Error;
-use std::fmt::Debug;
 use std::fs;
 
-use std::io::Write;
-use std::path::Path;
 use std::{path::PathBuf, time::Instant};
 
 use super::CliCommand;
@@ -33,13 +31,42 @@ use super::CliCommand;
 /// console does not support UTF-8).
 #[derive(clap::Args)]
 pub struct CliExcludeCommand {
-    /// Path of the file to read entries from. If ommitted, read from stdin.
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
     #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
-    input: Option<PathBuf>,
+    input: Vec<String>,
     /// Path of the file to write entries to. If ommitted, write to stdout.
     #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
     output: Option<PathBuf>,
+    /// Path of a file to write statistics about what was excluded to (as JSON).
+    #[clap(help_heading = "MISC", value_name = "PATH", long, display_order = 34)]
+    stats_out: Option<PathBuf>,
+    /// Number of leading directories used to group excluded paths in the
+    /// statistics.
+    #[clap(help_heading = "MISC", value_name = "N", long, default_value_t = 2, display_order = 35)]
+    stats_depth: usize,
+    /// Log and skip malformed entries instead of aborting. The number skipped
+    /// is reported at the end.
+    #[clap(help_heading = "MISC", long, display_order = 36)]
+    lenient: bool,
+    /// Write the output on a separate thread so that it overlaps with reading
+    /// the input.
+    #[clap(help_heading = "MISC", long, display_order = 37)]
+    async_write: bool,
+    /// Re-serialize each kept entry instead of copying its line verbatim. Keys
+    /// keep their original order and strings their original escaping, so the
+    /// output should still match the input line for line.
+    #[clap(help_heading = "MISC", long, display_order = 38)]
+    reserialize: bool,
+
+    #[clap(flatten)]
+    exclusion: CliExclusionArgs,
+}
 
+/// Options for excluding entries, shared by every command that filters an
+/// entry stream.
+#[derive(clap::Args)]
+pub struct CliExclusionArgs {
     /// Alias for --if-any-nilpathed.
     #[clap(
         help_heading = "EXCLUDE OPTIONS",
@@ -330,25 +357,34 @@ pub struct CliExcludeCommand {
     //     display_order = 31
     // )]
     // by_edgekind: Option<String>,
+    /// Read additional exclusion rules from a file. Each line of the file is
+    /// one of these options without its leading dashes (e.g.
+    /// "if-any-nilpathed" or "by-path src/**"). Lines starting with '#' are
+    /// ignored.
+    #[clap(
+        help_heading = "EXCLUDE OPTIONS",
+        value_name = "RULES_PATH",
+        long,
+        display_order = 32
+    )]
+    rules: Option<PathBuf>,
+
     /// Do not remove any nodes unless explicitly requested (e.g. with
     /// --by-node-factname).
     #[clap(help_heading = "MISC", short = 'k', long, display_order = 33)]
     keep_nodes: bool,
 }
 
-impl CliCommand for CliExcludeCommand {
-    fn execute(&self) -> Result<(), Box<dyn Error>> {
-        let mut writer = open_bufwriter(self.output.clone())?;
-
-        let mut rules: Vec<Box<dyn Exclusion>> = Vec::new();
+impl CliExclusionArgs {
+    /// Build the set of exclusion rules requested on the command line.
+    pub fn to_rules(&self) -> Result<ExclusionSet, Box<dyn Error>> {
+        let mut rules = ExclusionSet::new();
 
         let mut push_path_kind_exclusion =
             |exclusion_kind: Option<EdgeExclusionKind>, path_kind: PathKind| {
                 if let Some(exclusion_kind) = exclusion_kind {
                     let ticket_rule = Box::new(PathKindBasedExclusion::new(path_kind));
-                    let rule =
-                        TickedBasedExclusion::new(exclusion_kind, ticket_rule, self.keep_nodes);
-                    rules.push(Box::new(rule));
+                    rules.register_ticket_rule(exclusion_kind, ticket_rule, self.keep_nodes);
                 };
             };
 
@@ -357,7 +393,7 @@ impl CliCommand for CliExcludeCommand {
             self.if_all_nilpathed,
             self.if_src_nilpathed,
             self.if_tgt_nilpathed,
-        );
+        )?;
 
         push_path_kind_exclusion(nilpath_kind, PathKind::NilPathed);
 
@@ -366,7 +402,7 @@ impl CliCommand for CliExcludeCommand {
             self.if_all_abspathed,
             self.if_src_abspathed,
             self.if_tgt_abspathed,
-        );
+        )?;
 
         push_path_kind_exclusion(abspath_kind, PathKind::AbsPathed);
 
@@ -375,16 +411,14 @@ impl CliCommand for CliExcludeCommand {
             self.if_all_relpathed,
             self.if_src_relpathed,
             self.if_tgt_relpathed,
-        );
+        )?;
 
         push_path_kind_exclusion(relpath_kind, PathKind::RelPathed);
 
         if let Some(pattern) = &self.by_path {
             let matcher = globset::Glob::new(pattern)?.compile_matcher();
             let ticket_rule = Box::new(PathPatternBasedExclusion::new(matcher));
-            let rule =
-                TickedBasedExclusion::new(EdgeExclusionKind::Any, ticket_rule, self.keep_nodes);
-            rules.push(Box::new(rule));
+            rules.register_ticket_rule(EdgeExclusionKind::Any, ticket_rule, self.keep_nodes);
         }
 
         if let Some(pathlist) = &self.by_pathlist {
@@ -394,38 +428,47 @@ impl CliCommand for CliExcludeCommand {
                 Ok(text) => {
                     let rule = PathListBasedExclusion::new(text.lines().map(String::from));
                     let rule = Box::new(rule);
-                    let rule =
-                        TickedBasedExclusion::new(EdgeExclusionKind::Any, rule, self.keep_nodes);
-                    rules.push(Box::new(rule));
+                    rules.register_ticket_rule(EdgeExclusionKind::Any, rule, self.keep_nodes);
                 }
             }
         }
 
+        if let Some(path) = &self.rules {
+            log::debug!("Loading exclusion rules from {}...", path.display());
+            rules.extend(read_rule_file(path)?);
+        }
+
+        Ok(rules)
+    }
+}
+
+impl CliCommand for CliExcludeCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let mut writer = BatchWriter::create(self.output.clone(), self.async_write)?;
+        let rules = self.exclusion.to_rules()?.reserialize(self.reserialize);
+
         log::debug!(
             "Found the following {} exclusion rule(s) on the command line:",
             rules.len()
         );
-        for rule in &rules {
+        for rule in rules.iter() {
             log::debug!("{:#?}", rule);
         }
         log::info!("Starting exclusion process...");
 
         let start = Instant::now();
-        let mut num_lines = 0u128;
-        let mut num_excluded = 0u128;
-
-        'outer: for (line, entry) in EntryLineReader::open(self.input.clone())? {
-            num_lines = num_lines + 1;
-
-            for rule in &rules {
-                if rule.is_excluded(&entry) {
-                    num_excluded += 1;
-                    continue 'outer;
-                }
+        let reader = EntryLineReader::open_all(expand_inputs(&self.input)?, EntryFormat::Auto)?
+            .lenient(self.lenient)
+            .parallel(true);
+        let (num_lines, num_excluded) = match &self.stats_out {
+            None => rules.apply(reader, &mut writer)?,
+            Some(path) => {
+                let stats = rules.apply_with_stats(reader, &mut writer, self.stats_depth)?;
+                fs::write(path, serde_json::to_string_pretty(&stats)?)?;
+                (stats.num_entries as u128, stats.num_excluded as u128)
             }
-
-            writer.write_all(line.as_bytes())?;
-        }
+        };
+        writer.finish()?;
 
         log::info!(
             "Excluded {} out of {} entries in {} secs.",
@@ -437,206 +480,3 @@ impl CliCommand for CliExcludeCommand {
         Ok(())
     }
 }
-
-#[derive(Debug)]
-enum EdgeExclusionKind {
-    Any,
-    All,
-    Src,
-    Tgt,
-}
-
-impl EdgeExclusionKind {
-    fn from_bools(any: bool, all: bool, src: bool, tgt: bool) -> Option<Self> {
-        match (any, all, src, tgt) {
-            (false, false, false, false) => None,
-            (true, false, false, false) => Some(Self::Any),
-            (false, true, false, false) => Some(Self::All),
-            (false, false, true, false) => Some(Self::Src),
-            (false, false, false, true) => Some(Self::Tgt),
-            _ => panic!(),
-        }
-    }
-}
-
-trait Exclusion: Debug {
-    fn is_excluded(&self, entry: &Entry) -> bool;
-}
-
-#[allow(dead_code)]
-#[derive(Debug)]
-enum FactExclusionKind {
-    Both,
-    Edge,
-    Node,
-}
-
-#[allow(dead_code)]
-impl FactExclusionKind {
-    fn from_bools(both: bool, edge: bool, node: bool) -> Option<Self> {
-        match (both, edge, node) {
-            (false, false, false) => None,
-            (true, false, false) => Some(Self::Both),
-            (false, true, false) => Some(Self::Edge),
-            (false, false, true) => Some(Self::Node),
-            _ => panic!(),
-        }
-    }
-}
-
-#[derive(Debug)]
-struct FactBasedExclusion {
-    kind: FactExclusionKind,
-    matcher: globset::GlobMatcher,
-}
-
-#[allow(dead_code)]
-impl FactBasedExclusion {
-    fn new(kind: FactExclusionKind, matcher: globset::GlobMatcher) -> Self {
-        Self { kind, matcher }
-    }
-}
-
-impl Exclusion for FactBasedExclusion {
-    fn is_excluded(&self, entry: &Entry) -> bool {
-        match entry {
-            Entry::Edge { fact_name, .. } => match self.kind {
-                FactExclusionKind::Node => false,
-                _ => self.matcher.is_match(fact_name),
-            },
-            Entry::Node { fact_name, .. } => match self.kind {
-                FactExclusionKind::Edge => false,
-                _ => self.matcher.is_match(fact_name),
-            },
-        }
-    }
-}
-
-#[derive(Debug)]
-struct TickedBasedExclusion {
-    kind: EdgeExclusionKind,
-    ticket_rule: Box<dyn TicketExclusion>,
-    keep_nodes: bool,
-}
-
-impl TickedBasedExclusion {
-    fn new(
-        kind: EdgeExclusionKind,
-        ticket_rule: Box<dyn TicketExclusion>,
-        keep_nodes: bool,
-    ) -> Self {
-        Self {
-            kind,
-            ticket_rule,
-            keep_nodes,
-        }
-    }
-}
-
-impl Exclusion for TickedBasedExclusion {
-    fn is_excluded(&self, entry: &Entry) -> bool {
-        let is_excluded = |t: &Ticket| self.ticket_rule.is_excluded(t);
-
-        match entry {
-            Entry::Edge { src, tgt, .. } => match self.kind {
-                EdgeExclusionKind::Any => is_excluded(src) || is_excluded(tgt),
-                EdgeExclusionKind::All => is_excluded(src) && is_excluded(tgt),
-                EdgeExclusionKind::Src => is_excluded(src),
-                EdgeExclusionKind::Tgt => is_excluded(tgt),
-            },
-            Entry::Node { src, .. } => match self.kind {
-                EdgeExclusionKind::Any => !self.keep_nodes && is_excluded(src),
-                _ => false,
-            },
-        }
-    }
-}
-
-trait TicketExclusion: Debug {
-    fn is_excluded(&self, ticket: &Ticket) -> bool;
-}
-
-#[derive(Debug, PartialEq, Eq)]
-enum PathKind {
-    NilPathed,
-    RelPathed,
-    AbsPathed,
-}
-
-impl PathKind {
-    fn of(path: Option<&String>) -> Self {
-        match path {
-            None => Self::NilPathed,
-            Some(text) => match text.chars().next() {
-                Some('/') => Self::AbsPathed,
-                _ => Self::RelPathed,
-            },
-        }
-    }
-}
-
-#[derive(Debug)]
-struct PathKindBasedExclusion {
-    kind: PathKind,
-}
-
-impl PathKindBasedExclusion {
-    fn new(kind: PathKind) -> Self {
-        Self { kind }
-    }
-}
-
-impl TicketExclusion for PathKindBasedExclusion {
-    fn is_excluded(&self, ticket: &Ticket) -> bool {
-        self.kind == PathKind::of(ticket.path.as_ref())
-    }
-}
-
-#[derive(Debug)]
-struct PathPatternBasedExclusion {
-    matcher: globset::GlobMatcher,
-}
-
-impl PathPatternBasedExclusion {
-    fn new(matcher: globset::GlobMatcher) -> Self {
-        Self { matcher }
-    }
-}
-
-impl TicketExclusion for PathPatternBasedExclusion {
-    fn is_excluded(&self, ticket: &Ticket) -> bool {
-        match &ticket.path {
-            None => false,
-            Some(path) => !self.matcher.is_match(Path::new(path)),
-        }
-    }
-}
-
-struct PathListBasedExclusion {
-    paths: HashSet<String>,
-}
-
-impl Debug for PathListBasedExclusion {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PathListBasedExclusion")
-            .field("paths", &self.paths.len())
-            .finish()
-    }
-}
-
-impl PathListBasedExclusion {
-    fn new(paths: impl Iterator<Item = String>) -> Self {
-        Self {
-            paths: paths.collect(),
-        }
-    }
-}
-
-impl TicketExclusion for PathListBasedExclusion {
-    fn is_excluded(&self, ticket: &Ticket) -> bool {
-        match &ticket.path {
-            None => false,
-            Some(path) => !self.paths.contains(path),
-        }
-    }
-}
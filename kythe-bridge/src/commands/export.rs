@@ -0,0 +1,306 @@
+use itertools::Itertools;
+
+use crate::drh::ClsxSink;
+use crate::dv8::{Dv8Sink, KindMap, MatrixFormat};
+use crate::graphml::write_graphml;
+use crate::io::open_bufwriter;
+use crate::ir::{EntityGraph, GroupBy, SpecGraph};
+use crate::manifest::ManifestWriter;
+use crate::metrics::write_file_metrics;
+use crate::sink::{write_graph, NdjsonSink};
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::load::{parse_group_by, CliGroupBy, CliLoadArgs, Plan};
+use super::CliCommand;
+
+/// Write several outputs from a single load of the graph.
+///
+/// Loading a large graph can take minutes, so rather than running `format`,
+/// `metrics`, etc. one after another, this builds the graph once and then
+/// writes every requested output concurrently.
+///
+/// For more info on Kythe's entry format, see https://kythe.io/docs/kythe-storage.html.
+#[derive(clap::Args)]
+pub struct CliExportCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write a file-level DSM (in DV8's JSON format) to.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 2)]
+    dsm: Option<PathBuf>,
+    /// Name of the DSM. This is included in the DSM (and clustering) file.
+    #[clap(help_heading = "OUTPUTS", value_name = "NAME", long, display_order = 3)]
+    dsm_name: Option<String>,
+    /// Path of the file to write a Design Rule Hierarchy clustering of the DSM
+    /// (in DV8's .clsx JSON format) to, so that the DSM can be viewed already
+    /// clustered into layers and modules.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 4)]
+    clsx: Option<PathBuf>,
+    /// Path of the file to write the DSM to in Lattix's LDI XML format.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 5)]
+    ldi: Option<PathBuf>,
+    /// Path of the file to write the DSM to in Structure101's generic XML
+    /// format.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 6)]
+    s101: Option<PathBuf>,
+    /// Path of the file to write a GraphML document of entities and deps to.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 7)]
+    graphml: Option<PathBuf>,
+    /// Path of the file to write file-level metrics (as CSV) to.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 8)]
+    metrics: Option<PathBuf>,
+    /// Path of the file to write the output of the `format` subcommand to.
+    #[clap(help_heading = "OUTPUTS", value_name = "PATH", long, display_order = 9)]
+    json: Option<PathBuf>,
+    /// Whether the DSM and metrics are per file, per package, or per
+    /// directory (e.g. "dir:2" for the first two levels of directories).
+    #[clap(
+        value_name = "BY",
+        long,
+        alias = "granularity",
+        value_parser = parse_group_by,
+        default_value = "path",
+        display_order = 10
+    )]
+    group_by: CliGroupBy,
+    /// Path of a JSON file which maps edge kinds to DV8 dependency kinds (or
+    /// to null, to drop them), overriding the built-in mapping for the edge
+    /// kinds it lists. Applies to the DSM (in every format) and its
+    /// clustering.
+    #[clap(value_name = "PATH", long, display_order = 11)]
+    kind_map: Option<PathBuf>,
+    /// Also write the size and checksum of each output to
+    /// <PATH>.manifest.json, so that it can be checked with `verify-export`.
+    #[clap(long, display_order = 13)]
+    manifest: bool,
+    /// Print the stages that would be run, how many passes they make over the
+    /// input, and how they use memory, then exit without loading anything.
+    #[clap(long, display_order = 14)]
+    plan: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+enum Export {
+    Dsm(PathBuf),
+    Clsx(PathBuf),
+    Ldi(PathBuf),
+    Structure101(PathBuf),
+    GraphMl(PathBuf),
+    Metrics(PathBuf),
+    Json(PathBuf),
+}
+
+impl Export {
+    fn path(&self) -> &PathBuf {
+        match self {
+            Export::Dsm(path) => path,
+            Export::Clsx(path) => path,
+            Export::Ldi(path) => path,
+            Export::Structure101(path) => path,
+            Export::GraphMl(path) => path,
+            Export::Metrics(path) => path,
+            Export::Json(path) => path,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Export::Dsm(_) => "DSM",
+            Export::Clsx(_) => "DRH clustering",
+            Export::Ldi(_) => "Lattix LDI",
+            Export::Structure101(_) => "Structure101 XML",
+            Export::GraphMl(_) => "GraphML",
+            Export::Metrics(_) => "metrics",
+            Export::Json(_) => "JSON",
+        }
+    }
+
+    /// Whether the output is built up in memory before any of it is written,
+    /// rather than written while walking the graph.
+    fn is_buffered(&self) -> bool {
+        matches!(self, Export::Dsm(_) | Export::Clsx(_) | Export::Ldi(_) | Export::Structure101(_))
+    }
+
+    fn write(
+        &self,
+        spec: &SpecGraph,
+        graph: &EntityGraph,
+        dsm_name: Option<&String>,
+        group_by: GroupBy,
+        kinds: &KindMap,
+        manifest: bool,
+    ) -> Result<(), String> {
+        let start = Instant::now();
+        let writer = open_bufwriter(Some(self.path().clone())).map_err(|e| e.to_string())?;
+        let (writer, manifest) = ManifestWriter::new(writer, manifest);
+        self.write_to(writer, spec, graph, dsm_name, group_by, kinds).map_err(|e| e.to_string())?;
+
+        // Only once the output is complete, so that the manifest never
+        // describes a partial file
+        if let Some(manifest) = manifest {
+            let manifest = manifest.manifest().map_err(|e| e.to_string())?;
+            manifest.write_sidecar(self.path()).map_err(|e| e.to_string())?;
+        }
+
+        log::debug!(
+            "Wrote {} in {} secs.",
+            self.path().to_string_lossy(),
+            start.elapsed().as_secs_f32()
+        );
+
+        Ok(())
+    }
+
+    /// Takes ownership of `writer` so that it is flushed before returning.
+    fn write_to<W: Write + 'static>(
+        &self,
+        mut writer: W,
+        spec: &SpecGraph,
+        graph: &EntityGraph,
+        dsm_name: Option<&String>,
+        group_by: GroupBy,
+        kinds: &KindMap,
+    ) -> std::io::Result<()> {
+        match self {
+            Export::Dsm(_) => {
+                let mut sink =
+                    Dv8Sink::new(writer, dsm_name.cloned(), group_by).kind_map(kinds.clone());
+                write_graph(graph, &mut sink)
+            }
+            Export::Clsx(_) => {
+                let mut sink =
+                    ClsxSink::new(writer, dsm_name.cloned(), group_by).kind_map(kinds.clone());
+                write_graph(graph, &mut sink)
+            }
+            Export::Ldi(_) => {
+                let mut sink = Dv8Sink::new(writer, dsm_name.cloned(), group_by)
+                    .kind_map(kinds.clone())
+                    .format(MatrixFormat::Ldi);
+                write_graph(graph, &mut sink)
+            }
+            Export::Structure101(_) => {
+                let mut sink = Dv8Sink::new(writer, dsm_name.cloned(), group_by)
+                    .kind_map(kinds.clone())
+                    .format(MatrixFormat::Structure101);
+                write_graph(graph, &mut sink)
+            }
+            Export::GraphMl(_) => write_graphml(graph, &mut writer),
+            Export::Metrics(_) => {
+                write_file_metrics(spec, graph, group_by, &mut writer).map_err(std::io::Error::from)
+            }
+            Export::Json(_) => write_graph(graph, &mut NdjsonSink::new(writer)),
+        }
+    }
+}
+
+impl CliExportCommand {
+    fn exports(&self) -> Vec<Export> {
+        let exports = [
+            self.dsm.clone().map(Export::Dsm),
+            self.clsx.clone().map(Export::Clsx),
+            self.ldi.clone().map(Export::Ldi),
+            self.s101.clone().map(Export::Structure101),
+            self.graphml.clone().map(Export::GraphMl),
+            self.metrics.clone().map(Export::Metrics),
+            self.json.clone().map(Export::Json),
+        ];
+
+        exports.into_iter().flatten().collect_vec()
+    }
+
+    fn plan(&self, exports: &[Export]) -> Result<Plan, Box<dyn Error>> {
+        let mut plan = Plan::default();
+
+        if self.kind_map.is_some() {
+            plan.stage("Read the kind map");
+        }
+
+        self.load.plan(&self.input, &mut plan)?;
+
+        let manifest = match self.manifest {
+            true => ", each with a manifest",
+            false => "",
+        };
+        plan.stage(format!(
+            "Write {} output(s) concurrently, one thread each{}: {}",
+            exports.len(),
+            manifest,
+            exports
+                .iter()
+                .map(|export| format!("{} to {}", export.name(), export.path().to_string_lossy()))
+                .join(", ")
+        ));
+
+        let buffered = exports.iter().filter(|export| export.is_buffered()).collect_vec();
+
+        if !buffered.is_empty() {
+            plan.memory(format!(
+                "The {} each build their own matrix in memory before writing it",
+                buffered.iter().map(|export| export.name()).join(", ")
+            ));
+        }
+
+        Ok(plan)
+    }
+}
+
+impl CliCommand for CliExportCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let exports = self.exports();
+
+        if exports.is_empty() {
+            log::warn!("No outputs were requested.");
+            return Ok(());
+        }
+
+        if self.plan {
+            return Ok(self.plan(&exports)?.write(&mut open_bufwriter(None)?)?);
+        }
+
+        let kinds = match &self.kind_map {
+            Some(path) => KindMap::read(path)?,
+            None => KindMap::default(),
+        };
+
+        let graph = self.load.load(&self.input)?;
+        let spec = self.load.spec(graph)?;
+        let graph = self.load.entities(&spec)?;
+
+        // Fan out to one thread per output
+        let start = Instant::now();
+        let (spec, graph) = (&spec, &graph);
+        let dsm_name = self.dsm_name.as_ref();
+        let group_by = (&self.group_by).into();
+        let kinds = &kinds;
+        let manifest = self.manifest;
+
+        let results = std::thread::scope(|scope| {
+            let handles = exports
+                .iter()
+                .map(|export| {
+                    scope.spawn(move || {
+                        export.write(spec, graph, dsm_name, group_by, kinds, manifest)
+                    })
+                })
+                .collect_vec();
+
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect_vec()
+        });
+
+        for (export, res) in exports.iter().zip(results) {
+            if let Err(err) = res {
+                Err(format!("failed to write {}: {}", export.path().to_string_lossy(), err))?;
+            }
+        }
+
+        log::info!("Wrote {} output(s) in {} secs.", exports.len(), start.elapsed().as_secs_f32());
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use crate::externals::{by_corpus, externals, CorpusExternals, External};
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use tabled::{Style, Table, Tabled};
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// List the nodes which are depended on but never defined.
+///
+/// A node which is the target of a reference (or other dependency) but which
+/// no anchor defines is most likely part of an external library, so this
+/// quantifies how much of each third-party API a codebase uses. Each row is a
+/// target (or, with --by corpus, all of the targets in a corpus) along with
+/// the total count of references to it and the number of files which refer to
+/// it.
+#[derive(clap::Args)]
+pub struct CliExternalsCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Write one row per target or one row per corpus.
+    #[clap(
+        value_name = "BY",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "target",
+        display_order = 3
+    )]
+    by: CliExternalsBy,
+    /// Format of the report.
+    #[clap(
+        short = 'f',
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "table",
+        display_order = 4
+    )]
+    format: CliExternalsFormat,
+    /// Only list this many rows.
+    #[clap(value_name = "N", long, display_order = 5)]
+    limit: Option<usize>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliExternalsBy {
+    Target,
+    Corpus,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliExternalsFormat {
+    Table,
+    Csv,
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct TargetRow<'a> {
+    #[tabled(rename = "Corpus")]
+    corpus: &'a str,
+
+    #[tabled(rename = "Language")]
+    language: &'a str,
+
+    #[tabled(rename = "Signature")]
+    signature: &'a str,
+
+    #[tabled(rename = "Kind")]
+    kind: &'a str,
+
+    #[tabled(rename = "Refs")]
+    refs: usize,
+
+    #[tabled(rename = "Files")]
+    files: usize,
+
+    #[tabled(rename = "Edge Kinds")]
+    edge_kinds: String,
+}
+
+impl<'a> From<&'a External> for TargetRow<'a> {
+    fn from(external: &'a External) -> Self {
+        let ticket = &external.ticket;
+        let edge_kinds =
+            external.edge_kinds.iter().map(|(kind, count)| format!("{:?}: {}", kind, count));
+
+        Self {
+            corpus: ticket.corpus.as_deref().unwrap_or_default(),
+            language: ticket.language.as_deref().unwrap_or_default(),
+            signature: ticket.signature.as_deref().unwrap_or_default(),
+            kind: external.kind.as_deref().unwrap_or("none"),
+            refs: external.refs,
+            files: external.files.len(),
+            edge_kinds: edge_kinds.collect::<Vec<_>>().join(", "),
+        }
+    }
+}
+
+#[derive(Tabled, serde::Serialize)]
+struct CorpusRow<'a> {
+    #[tabled(rename = "Corpus")]
+    corpus: &'a str,
+
+    #[tabled(rename = "Targets")]
+    targets: usize,
+
+    #[tabled(rename = "Refs")]
+    refs: usize,
+
+    #[tabled(rename = "Files")]
+    files: usize,
+}
+
+impl<'a> From<&'a CorpusExternals> for CorpusRow<'a> {
+    fn from(corpus: &'a CorpusExternals) -> Self {
+        Self {
+            corpus: &corpus.corpus,
+            targets: corpus.targets,
+            refs: corpus.refs,
+            files: corpus.files.len(),
+        }
+    }
+}
+
+impl CliExternalsCommand {
+    fn write_rows<T, W>(&self, rows: Vec<T>, writer: &mut W) -> Result<(), Box<dyn Error>>
+    where
+        T: Tabled + serde::Serialize,
+        W: Write,
+    {
+        let rows = rows.into_iter().take(self.limit.unwrap_or(usize::MAX));
+
+        match self.format {
+            CliExternalsFormat::Table => {
+                writeln!(writer, "{}", Table::new(rows).with(Style::psql()))?;
+            }
+            CliExternalsFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+
+                writer.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CliCommand for CliExternalsCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let mut externals = externals(raw_graph);
+        self.load.anonymize(|anonymizer| {
+            for external in &mut externals {
+                anonymizer.ticket(&mut external.ticket);
+                external.files = external.files.iter().map(|path| anonymizer.path(path)).collect();
+            }
+        })?;
+        log::info!("Found {} external target(s).", externals.len());
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+
+        match self.by {
+            CliExternalsBy::Target => {
+                let rows = externals.iter().map(TargetRow::from).collect();
+                self.write_rows(rows, &mut writer)?;
+            }
+            CliExternalsBy::Corpus => {
+                let corpora = by_corpus(&externals);
+                let rows = corpora.iter().map(CorpusRow::from).collect();
+                self.write_rows(rows, &mut writer)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
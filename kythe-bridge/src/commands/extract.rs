@@ -0,0 +1,49 @@
+use crate::io::open_bufwriter;
+use crate::kzip;
+use kythe_bridge::canonical::to_json_line;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::CliCommand;
+
+/// Produce file entries (node kind and text facts) for the source files of a
+/// kzip.
+///
+/// A kzip only holds compilation units and their inputs, so this does not
+/// index anything. The output can be concatenated with the entries of an
+/// indexer to provide the text of each file. Other subcommands accept a path
+/// ending in ".kzip" as --input directly.
+///
+/// For more info on the kzip format, see https://kythe.io/docs/kythe-kzip.html.
+#[derive(clap::Args)]
+pub struct CliExtractCommand {
+    /// Path of the kzip to read.
+    #[clap(value_name = "KZIP")]
+    kzip: PathBuf,
+    /// Path of the file to write entries to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 1)]
+    output: Option<PathBuf>,
+}
+
+impl CliCommand for CliExtractCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let entries = kzip::read_file_entries(&self.kzip)?;
+        let mut writer = open_bufwriter(self.output.clone())?;
+
+        for entry in &entries {
+            writer.write_all(to_json_line(&entry.borrowed(), None).as_bytes())?;
+        }
+
+        log::info!(
+            "Extracted {} file(s) in {} secs.",
+            entries.len() / 2,
+            start.elapsed().as_secs_f32()
+        );
+
+        Ok(())
+    }
+}
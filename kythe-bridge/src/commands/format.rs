@@ -1,16 +1,22 @@
-use itertools::Itertools;
-
-use crate::io::{EntryReader, open_bufwriter};
-use crate::ir::{EntityGraph, RawGraph, SpecGraph};
+use crate::dv8::{Dv8Sink, KindMap};
+use crate::io::open_bufwriter;
+use crate::ir::GroupBy;
+use crate::manifest::ManifestWriter;
+#[cfg(feature = "parquet")]
+use crate::sink::ParquetSink;
+use crate::sink::{write_graph, CsvSink, NdjsonSink, OutputSink};
 
 use std::error::Error;
-use std::io::Write;
 use std::path::PathBuf;
 
+use super::load::{parse_group_by, CliGroupBy, CliLoadArgs};
 use super::CliCommand;
 
 /// Produce "human-readable" JSON nodes and edges for debugging purposes.
 ///
+/// Other formats may be chosen with --format. Most of these describe deps
+/// rather than entities, so they are better suited to further analysis.
+///
 /// For more info on Kythe's entry format, see https://kythe.io/docs/kythe-storage.html.
 ///
 /// On Windows, it is recommended to use --input/--output rather than
@@ -18,36 +24,106 @@ use super::CliCommand;
 /// console does not support UTF-8).
 #[derive(clap::Args)]
 pub struct CliFormatCommand {
-    /// Path of the file to read entries from. If ommitted, read from stdin.
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
     #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
-    input: Option<PathBuf>,
+    input: Vec<String>,
     /// Path of the file to write to. If ommitted, write to stdout.
     #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
     output: Option<PathBuf>,
+    /// Format of the output.
+    #[clap(
+        short = 'f',
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "json",
+        display_order = 4
+    )]
+    format: CliOutputFormat,
+    /// Name of the DSM. This is included in the output if the format is dsm.
+    #[clap(value_name = "NAME", long, display_order = 5)]
+    dsm_name: Option<String>,
+    /// Whether the variables of the DSM are files, packages, or directories
+    /// (e.g. "dir:2" for the first two levels of directories). Only applies
+    /// if the format is dsm.
+    #[clap(
+        value_name = "BY",
+        long,
+        alias = "granularity",
+        value_parser = parse_group_by,
+        default_value = "path",
+        display_order = 6
+    )]
+    group_by: CliGroupBy,
+    /// Path of a JSON file which maps edge kinds to DV8 dependency kinds (or
+    /// to null, to drop them), overriding the built-in mapping for the edge
+    /// kinds it lists. Only applies if the format is dsm.
+    #[clap(value_name = "PATH", long, display_order = 7)]
+    kind_map: Option<PathBuf>,
+    /// Also write the size and checksum of the output to
+    /// <OUTPUT>.manifest.json, so that it can be checked with `verify-export`.
+    #[clap(long, requires = "output", display_order = 8)]
+    manifest: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliOutputFormat {
+    /// One line of JSON per entity and per dep
+    Json,
+    /// One row per dep, including the path and name of both endpoints
+    Csv,
+    /// The same rows as csv, but as a Parquet file
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// A file-level DSM in DV8's JSON format
+    Dsm,
+}
+
+impl CliOutputFormat {
+    pub fn sink<W: std::io::Write + 'static>(
+        &self,
+        writer: W,
+        dsm_name: Option<String>,
+        group_by: GroupBy,
+        kinds: KindMap,
+    ) -> std::io::Result<Box<dyn OutputSink>> {
+        Ok(match self {
+            CliOutputFormat::Json => Box::new(NdjsonSink::new(writer)),
+            CliOutputFormat::Csv => Box::new(CsvSink::new(writer)),
+            #[cfg(feature = "parquet")]
+            CliOutputFormat::Parquet => Box::new(ParquetSink::new(writer)?),
+            CliOutputFormat::Dsm => {
+                Box::new(Dv8Sink::new(writer, dsm_name, group_by).kind_map(kinds))
+            }
+        })
+    }
 }
 
 impl CliCommand for CliFormatCommand {
     fn execute(&self) -> Result<(), Box<dyn Error>> {
-        let reader = EntryReader::open(self.input.clone())?;
-        let raw_graph = RawGraph::try_from(reader)?;
-        let spec_graph = SpecGraph::try_from(raw_graph)?;
-        let entity_graph = EntityGraph::try_from(spec_graph)?;
-
-        // Sort
-        let mut entities = entity_graph.entities.into_values().collect_vec();
-        entities.sort();
-        let mut deps = entity_graph.deps;
-        deps.sort();
-
-        // Output
-        let mut writer = open_bufwriter(self.output.clone())?;
-
-        for entity in entities {
-            write!(writer, "{}\n", serde_json::to_string(&entity)?)?;
-        }
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let kinds = match &self.kind_map {
+            Some(path) => KindMap::read(path)?,
+            None => KindMap::default(),
+        };
+
+        let writer = open_bufwriter(self.output.clone())?;
+        let (writer, manifest) = ManifestWriter::new(writer, self.manifest);
+        let dsm_name = self.dsm_name.clone();
+        let mut sink = self.format.sink(writer, dsm_name, (&self.group_by).into(), kinds)?;
+        write_graph(&entity_graph, &mut sink)?;
+        drop(sink);
 
-        for dep in deps {
-            write!(writer, "{}\n", serde_json::to_string(&dep)?)?;
+        if let (Some(manifest), Some(path)) = (manifest, &self.output) {
+            manifest.manifest()?.write_sidecar(path)?;
         }
 
         Ok(())
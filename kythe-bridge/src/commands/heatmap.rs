@@ -0,0 +1,95 @@
+use crate::heatmap::{heatmap, Metric};
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::budget::CliBudgetArgs;
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Render a source file as HTML with each line shaded by a metric.
+///
+/// Each line is shaded by the highest score of the entities (functions,
+/// classes, fields, etc.) whose definitions span it, with a legend for the
+/// scale. Hovering over a line lists those entities with their fan-in and
+/// fan-out. --lift-anchors is not supported, as the definitions are found
+/// through the anchors of the file. The page is refused if it would not fit
+/// within the budget (see --max-output-nodes and --max-output-megabytes).
+#[derive(clap::Args)]
+pub struct CliHeatmapCommand {
+    /// Path of the file to render, as it appears in the output of `format`.
+    #[clap(value_name = "FILE")]
+    file: String,
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the HTML file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// The score to shade each entity by. The hotspot score is fan-in times
+    /// fan-out.
+    #[clap(
+        value_name = "METRIC",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "hotspot",
+        display_order = 3
+    )]
+    metric: CliHeatmapMetric,
+
+    #[clap(flatten)]
+    budget: CliBudgetArgs,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliHeatmapMetric {
+    FanIn,
+    FanOut,
+    Hotspot,
+}
+
+impl From<&CliHeatmapMetric> for Metric {
+    fn from(metric: &CliHeatmapMetric) -> Self {
+        match metric {
+            CliHeatmapMetric::FanIn => Metric::FanIn,
+            CliHeatmapMetric::FanOut => Metric::FanOut,
+            CliHeatmapMetric::Hotspot => Metric::Hotspot,
+        }
+    }
+}
+
+impl CliCommand for CliHeatmapCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        if self.budget.auto_slice {
+            Err("--auto-slice is not supported by heatmap, which renders a single file")?;
+        }
+
+        if self.load.lifts_anchors() {
+            Err("--lift-anchors is not supported by heatmap, which needs the anchors of the file")?;
+        }
+
+        if self.load.anonymizes() {
+            Err("--anonymize is not supported by heatmap, which renders the text of the file")?;
+        }
+
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let budget = self.budget.to_budget();
+        let metric = (&self.metric).into();
+        let html = heatmap(&spec_graph, &entity_graph, &self.file, metric, &budget)?;
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+        writer.write_all(html.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
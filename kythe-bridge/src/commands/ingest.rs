@@ -0,0 +1,115 @@
+use crate::io::EntryReader;
+use crate::ir::RawGraph;
+use crate::snapshot::Snapshot;
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Instant;
+
+use super::exclude::CliExclusionArgs;
+use super::load::{log_fact_sizes, CliLoadArgs};
+use super::CliCommand;
+
+/// Run an indexer and write a snapshot of its output.
+///
+/// Spawns the given command through the shell and consumes its stdout as a
+/// stream of newline-delimited entries. Entries are filtered using the same
+/// options as the `exclude` subcommand before they are added to the graph, so
+/// the (often multi-GB) unfiltered output never needs to be written to disk.
+///
+/// For more info on Kythe's entry format, see https://kythe.io/docs/kythe-storage.html.
+#[derive(clap::Args)]
+pub struct CliIngestCommand {
+    /// Indexer command to run. It must write newline-delimited entries to
+    /// stdout.
+    #[clap(short = 'e', value_name = "COMMAND", long, display_order = 1)]
+    exec: String,
+    /// Path of the file to write the snapshot to.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: PathBuf,
+
+    #[clap(flatten)]
+    exclusion: CliExclusionArgs,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliIngestCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let rules = self.exclusion.to_rules()?;
+
+        log::info!("Starting `{}`...", self.exec);
+        let start = Instant::now();
+        let mut child = Reaper(shell(&self.exec).stdout(Stdio::piped()).spawn()?);
+        let stdout = child.0.stdout.take().ok_or("failed to capture stdout of indexer")?;
+
+        let mut num_entries = 0u128;
+        let mut num_excluded = 0u128;
+
+        let mut options = self.load.to_options()?;
+        let graph = itertools::process_results(EntryReader::from_read(stdout), |entries| {
+            let entries = entries.filter(|entry| {
+                num_entries += 1;
+                let is_excluded = rules.is_excluded(&entry.borrowed());
+                num_excluded += is_excluded as u128;
+                !is_excluded
+            });
+            RawGraph::from_entries_with(entries, &mut options)
+        })??;
+        let status = child.wait()?;
+
+        if !status.success() {
+            Err(format!("indexer exited with {}", status))?;
+        }
+
+        log::info!(
+            "Excluded {} out of {} entries in {} secs.",
+            num_excluded,
+            num_entries,
+            start.elapsed().as_secs_f32()
+        );
+        log_fact_sizes(&graph);
+        let graph = self.load.postprocess(graph);
+
+        let start = Instant::now();
+        Snapshot::from(graph).write(&self.output)?;
+        log::info!("Wrote snapshot in {} secs.", start.elapsed().as_secs_f32());
+        Ok(())
+    }
+}
+
+/// Kills and reaps the indexer if it is dropped before being waited on, for
+/// example when a malformed entry ends ingestion early.
+struct Reaper(Child);
+
+impl Reaper {
+    fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        self.0.wait()
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            log::debug!("Killing indexer (pid {}).", self.0.id());
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
@@ -0,0 +1,79 @@
+use kythe_bridge::listing::{EntityFilter, EntityListing, ListRequest, DEFAULT_PAGE_SIZE};
+
+use crate::io::open_bufwriter;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// List the entities of a graph a page at a time as JSON.
+///
+/// Writes a single JSON object with the matching entities (in order of id)
+/// under "entities" and, if there are more, a cursor under "next". Pass the
+/// cursor back with --after to get the next page. Cursors are only valid for
+/// the same input loaded with the same options.
+///
+/// Each entity is written as by the `format` subcommand, keeping only the
+/// fields given with --fields (if any).
+#[derive(clap::Args)]
+pub struct CliListCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Only list entities of this kind, without any subkind (e.g.
+    /// "function").
+    #[clap(value_name = "KIND", long, display_order = 3)]
+    kind: Option<String>,
+    /// Only list entities within this directory (or file).
+    #[clap(value_name = "DIR", long, display_order = 4)]
+    path_prefix: Option<String>,
+    /// Only list entities whose qualified name contains this text.
+    #[clap(value_name = "TEXT", long, display_order = 5)]
+    name: Option<String>,
+    /// The fields of each entity to write (e.g. "id,qualified_name"). If
+    /// ommitted, write every field.
+    #[clap(value_name = "FIELDS", long, value_delimiter = ',', display_order = 6)]
+    fields: Vec<String>,
+    /// The "next" cursor of the previous page, to list the page after it.
+    #[clap(value_name = "CURSOR", long, display_order = 7)]
+    after: Option<String>,
+    /// The number of entities in the page, up to 10000.
+    #[clap(value_name = "N", long, default_value_t = DEFAULT_PAGE_SIZE, display_order = 8)]
+    limit: usize,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliListCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let request = ListRequest {
+            filter: EntityFilter {
+                kind: self.kind.clone(),
+                path_prefix: self.path_prefix.clone(),
+                name: self.name.clone(),
+            },
+            fields: self.fields.iter().cloned().collect(),
+            after: self.after.clone(),
+            limit: self.limit,
+        };
+        let page = EntityListing::new(&entity_graph).page(&request)?;
+
+        let mut writer = open_bufwriter(self.output.clone())?;
+        serde_json::to_writer(&mut writer, &page)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
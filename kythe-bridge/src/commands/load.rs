@@ -0,0 +1,759 @@
+use itertools::Itertools;
+use kythe_bridge::files::FileStore;
+use kythe_bridge::generated::GeneratedSources;
+
+use crate::annotations::Annotations;
+use crate::anonymize::Anonymizer;
+use crate::budget::format_bytes;
+use crate::diagnostics;
+use crate::io::{expand_inputs, open_bufwriter, EntryFormat, EntryReader};
+use crate::ir::{
+    EntityGraph, EntityGraphOptions, FileDedup, GroupBy, NameSource, NonePolicy, RawGraph,
+    RawGraphOptions, RootAliases, SpecGraph,
+};
+use crate::kzip;
+use crate::progress::Monitor;
+use crate::remote::{self, FetchOptions};
+use crate::snapshot::Snapshot;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Options for loading entries into a graph, shared by every command that
+/// builds one.
+#[derive(clap::Args)]
+pub struct CliLoadArgs {
+    /// Encoding of the entries being read. By default, this is detected from
+    /// the first byte of the input.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "auto",
+        display_order = 39
+    )]
+    input_format: CliEntryFormat,
+    /// Comma-separated list of facts to drop while loading (e.g. "code,text").
+    /// Names without a leading slash are assumed to be under "/kythe/".
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "FACTS",
+        long,
+        value_delimiter = ',',
+        display_order = 40
+    )]
+    strip_facts: Vec<String>,
+    /// Path of a file to write stripped facts to as newline-delimited entries.
+    /// If ommitted, stripped facts are discarded.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 41)]
+    spill: Option<PathBuf>,
+    /// Unify file nodes that represent the same source file, e.g. because it
+    /// was indexed under several roots or build configurations.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "BY",
+        long,
+        arg_enum,
+        value_parser,
+        display_order = 42
+    )]
+    dedup_files: Option<CliFileDedup>,
+    /// What to do with nodes that have no facts at all (and so have no kind).
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "POLICY",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "keep",
+        display_order = 43
+    )]
+    none_nodes: CliNonePolicy,
+    /// Display paths under the given root (or path prefix) using a short alias
+    /// instead, e.g. "bazel-out/k8-fastbuild/bin=GEN". May be repeated.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "PREFIX=ALIAS",
+        long,
+        value_parser = parse_root_alias,
+        display_order = 44
+    )]
+    root_alias: Vec<(String, String)>,
+    /// Keep parameter edges as deps instead of folding them into the
+    /// parameter list of each function.
+    #[clap(help_heading = "LOAD OPTIONS", long, display_order = 45)]
+    keep_param_deps: bool,
+    /// Merge each declaration into the definition that completes it, so that
+    /// header/implementation pairs count as a single entity.
+    #[clap(help_heading = "LOAD OPTIONS", long, display_order = 51)]
+    merge_declarations: bool,
+    /// Comma-separated list of where to take entity names from, in order of
+    /// preference. If none of them has a name, "???" is used.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "SOURCES",
+        long,
+        arg_enum,
+        value_parser,
+        value_delimiter = ',',
+        default_value = "marked-source,binding,signature,ticket",
+        display_order = 46
+    )]
+    name_sources: Vec<CliNameSource>,
+    /// Comma-separated list of build configurations to keep. Nodes indexed
+    /// under any other configuration are dropped. If ommitted, keep all.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        value_name = "CONFIGS",
+        long,
+        value_delimiter = ',',
+        display_order = 47
+    )]
+    configs: Vec<String>,
+    /// Log and skip malformed entries instead of aborting, and load edges of
+    /// unknown kinds as "Other". Both are summarized at the end.
+    #[clap(help_heading = "LOAD OPTIONS", long, display_order = 48)]
+    lenient: bool,
+    /// Fail if any warnings are logged while loading. Same as --max-warnings=0.
+    #[clap(
+        help_heading = "LOAD OPTIONS",
+        long,
+        conflicts_with = "max-warnings",
+        display_order = 49
+    )]
+    strict: bool,
+    /// Fail if more than this many warnings are logged while loading. Combined
+    /// with --lenient, this tolerates a few bad entries but not a corrupt file.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "N", long, display_order = 50)]
+    max_warnings: Option<usize>,
+    /// Path of a cache (built with `cache build`) or snapshot to load instead
+    /// of reading entries. Options which only apply while reading entries are
+    /// ignored.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 52)]
+    from_cache: Option<PathBuf>,
+    /// Fail if the entries describe more than this many distinct nodes. With
+    /// --lenient, skip entries which would add more instead.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "N", long, display_order = 53)]
+    max_nodes: Option<usize>,
+    /// Fail if there are more than this many edge entries. With --lenient,
+    /// skip the rest instead.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "N", long, display_order = 54)]
+    max_edges: Option<usize>,
+    /// Fail if any fact value is larger than this many bytes. With --lenient,
+    /// strip such facts instead (see --spill).
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "BYTES", long, display_order = 55)]
+    max_fact_size: Option<usize>,
+    /// Attribute the deps of each anchor to the entity it belongs to (e.g.
+    /// the function whose body contains it) and remove anchors from the
+    /// output.
+    #[clap(help_heading = "LOAD OPTIONS", long, display_order = 56)]
+    lift_anchors: bool,
+    /// Directory to keep downloaded inputs (given as http://, https://, or
+    /// s3:// URLs) in. If ommitted, use ~/.cache/sft/downloads.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 57)]
+    download_cache: Option<PathBuf>,
+    /// Download inputs at no more than this many bytes per second.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "BYTES", long, display_order = 58)]
+    max_download_rate: Option<u64>,
+    /// Path of a CSV file of annotations (e.g. team or criticality) to join
+    /// onto entities. The first column is matched against the qualified name
+    /// of each entity, or failing that, its (aliased) path. The other columns
+    /// are carried through to the output of `format`, `metrics`, and
+    /// `heatmap`.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 59)]
+    annotate: Option<PathBuf>,
+    /// Path of a file of rules attributing generated files (e.g. "*.pb.cc")
+    /// to the logical sources they were generated from, applied after
+    /// --root-alias. Each line is a glob and a path, e.g.
+    /// "GEN/**/*.pb.{h,cc} {dir}/{stem}.proto".
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 60)]
+    generated_sources: Option<PathBuf>,
+    /// Attribute the entities of each file to the file which generates them,
+    /// following Kythe "generates" edges. Rules from --generated-sources take
+    /// precedence.
+    #[clap(help_heading = "LOAD OPTIONS", long, display_order = 61)]
+    follow_generates: bool,
+    /// Path of a scratch file to keep the text of large files in, rather than
+    /// keeping it in memory. Only the parts which are needed (e.g. the text of
+    /// anchors) are read back. The file is removed once done.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "PATH", long, display_order = 62)]
+    spill_texts: Option<PathBuf>,
+    /// Replace paths, names, and other identifying text in the output with
+    /// opaque tokens (e.g. to share the output outside of the organization),
+    /// once every other option has been applied. The mapping from tokens back
+    /// to the original text (and the secret they are keyed by) is loaded from
+    /// (if present) and saved to the given file.
+    #[clap(help_heading = "LOAD OPTIONS", value_name = "MAPPING_PATH", long, display_order = 63)]
+    anonymize: Option<PathBuf>,
+}
+
+/// The smallest text written to disk with --spill-texts. Smaller texts cost
+/// little more than their line index, which stays in memory anyway.
+const SPILL_TEXT_THRESHOLD: usize = 4096;
+
+fn parse_root_alias(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((prefix, alias)) if !prefix.is_empty() => Ok((prefix.to_string(), alias.to_string())),
+        _ => Err(format!("expected PREFIX=ALIAS but found \"{}\"", text)),
+    }
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliEntryFormat {
+    /// Detect the format from the input
+    Auto,
+    /// Newline-delimited JSON
+    Json,
+    /// Length-delimited protobuf (as written by entrystream)
+    Proto,
+}
+
+impl From<&CliEntryFormat> for EntryFormat {
+    fn from(format: &CliEntryFormat) -> Self {
+        match format {
+            CliEntryFormat::Auto => EntryFormat::Auto,
+            CliEntryFormat::Json => EntryFormat::Json,
+            CliEntryFormat::Proto => EntryFormat::Proto,
+        }
+    }
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliNonePolicy {
+    /// Remove these nodes and their edges
+    Drop,
+    /// Keep these nodes as entities
+    Keep,
+    /// Attribute their edges to the single node they point to
+    MergeIntoTarget,
+}
+
+impl From<&CliNonePolicy> for NonePolicy {
+    fn from(policy: &CliNonePolicy) -> Self {
+        match policy {
+            CliNonePolicy::Drop => NonePolicy::Drop,
+            CliNonePolicy::Keep => NonePolicy::Keep,
+            CliNonePolicy::MergeIntoTarget => NonePolicy::MergeIntoTarget,
+        }
+    }
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliNameSource {
+    /// The identifier in the "code" fact
+    MarkedSource,
+    /// The text of the binding anchor
+    Binding,
+    /// The signature of the ticket
+    Signature,
+    /// The entire ticket
+    Ticket,
+}
+
+impl From<&CliNameSource> for NameSource {
+    fn from(source: &CliNameSource) -> Self {
+        match source {
+            CliNameSource::MarkedSource => NameSource::MarkedSource,
+            CliNameSource::Binding => NameSource::Binding,
+            CliNameSource::Signature => NameSource::Signature,
+            CliNameSource::Ticket => NameSource::Ticket,
+        }
+    }
+}
+
+/// How to group entities: "path" (the file each entity is in), "package"
+/// (the package each entity is in, falling back to its file), or "dir:N" (the
+/// first N levels of the directory each entity is in).
+#[derive(Clone)]
+pub struct CliGroupBy(GroupBy);
+
+pub fn parse_group_by(text: &str) -> Result<CliGroupBy, String> {
+    match text {
+        "path" | "file" => Ok(CliGroupBy(GroupBy::Path)),
+        "package" => Ok(CliGroupBy(GroupBy::Package)),
+        _ => match text.strip_prefix("dir:").map(str::parse::<usize>) {
+            Some(Ok(depth)) if depth > 0 => Ok(CliGroupBy(GroupBy::Dir(depth))),
+            _ => Err(format!("expected path, package, or dir:N but found \"{}\"", text)),
+        },
+    }
+}
+
+impl From<&CliGroupBy> for GroupBy {
+    fn from(group_by: &CliGroupBy) -> Self {
+        group_by.0
+    }
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliFileDedup {
+    /// Same corpus and normalized path, regardless of root
+    Path,
+    /// Same text
+    Text,
+}
+
+impl From<&CliFileDedup> for FileDedup {
+    fn from(by: &CliFileDedup) -> Self {
+        match by {
+            CliFileDedup::Path => FileDedup::Path,
+            CliFileDedup::Text => FileDedup::Text,
+        }
+    }
+}
+
+/// What a command would do, printed by `--plan` instead of doing it.
+#[derive(Debug, Default)]
+pub struct Plan {
+    stages: Vec<String>,
+    /// The number of times the input is read from start to end.
+    passes: usize,
+    memory: Vec<String>,
+}
+
+impl Plan {
+    pub fn stage(&mut self, text: impl Into<String>) {
+        self.stages.push(text.into());
+    }
+
+    pub fn memory(&mut self, text: impl Into<String>) {
+        self.memory.push(text.into());
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "Stages:")?;
+
+        for (i, stage) in self.stages.iter().enumerate() {
+            writeln!(writer, "  {}. {}", i + 1, stage)?;
+        }
+
+        writeln!(writer, "Passes over the input: {}", self.passes)?;
+        writeln!(writer, "Memory:")?;
+
+        for line in &self.memory {
+            writeln!(writer, "  - {}", line)?;
+        }
+
+        writer.flush()
+    }
+}
+
+impl CliLoadArgs {
+    /// Describe how `load` and `entities` would build a graph from `inputs`,
+    /// without downloading or reading any of them.
+    pub fn plan(&self, inputs: &[String], plan: &mut Plan) -> Result<(), Box<dyn Error>> {
+        match &self.from_cache {
+            Some(path) => {
+                let size = fs::metadata(path).map(|m| format_bytes(m.len() as usize));
+                plan.stage(format!(
+                    "Read the cache {} ({})",
+                    path.to_string_lossy(),
+                    size.unwrap_or_else(|_| "missing".to_string())
+                ));
+                plan.memory("The cached graph is read into memory whole");
+
+                if !inputs.is_empty() {
+                    plan.stage(format!("Ignore {} input(s) because of --from-cache", inputs.len()));
+                }
+            }
+            None => self.plan_entries(inputs, plan)?,
+        }
+
+        if let Some(by) = &self.dedup_files {
+            let by = match by {
+                CliFileDedup::Path => "path",
+                CliFileDedup::Text => "text",
+            };
+            plan.stage(format!("Merge duplicate files by {}, copying the graph once", by));
+        }
+
+        plan.stage("Convert nodes into a spec graph, taking ownership of the raw graph");
+        match &self.spill_texts {
+            Some(path) => plan.memory(format!(
+                "File texts of at least {} are kept in {} for resolving anchors",
+                format_bytes(SPILL_TEXT_THRESHOLD),
+                path.to_string_lossy()
+            )),
+            None => plan.memory("File texts are kept in memory for resolving anchors"),
+        }
+
+        let mut steps = vec!["Build entities"];
+
+        if self.merge_declarations {
+            steps.push("merge declarations into definitions");
+        }
+
+        if !self.root_alias.is_empty() {
+            steps.push("alias roots");
+        }
+
+        if self.generated_sources.is_some() || self.follow_generates {
+            steps.push("move generated entities to their sources");
+        }
+
+        if !self.keep_param_deps {
+            steps.push("fold params");
+        }
+
+        if self.lift_anchors {
+            steps.push("lift the deps of anchors");
+        }
+
+        if self.annotate.is_some() {
+            steps.push("join annotations");
+        }
+
+        if self.anonymize.is_some() {
+            steps.push("replace paths and names with tokens (reading and saving the mapping)");
+        }
+
+        plan.stage(steps.join(", "));
+        plan.memory("The spec graph is kept alongside the entity graph until the end");
+        Ok(())
+    }
+
+    fn plan_entries(&self, inputs: &[String], plan: &mut Plan) -> Result<(), Box<dyn Error>> {
+        let (remote, local): (Vec<_>, Vec<_>) =
+            inputs.iter().cloned().partition(|input| remote::is_remote(input));
+
+        if !remote.is_empty() {
+            let cache_dir = match &self.download_cache {
+                Some(dir) => dir.clone(),
+                None => remote::default_cache_dir(),
+            };
+            plan.stage(format!(
+                "Download {} remote input(s) into {} (unless already there)",
+                remote.len(),
+                cache_dir.to_string_lossy()
+            ));
+        }
+
+        let (kzips, paths): (Vec<_>, Vec<_>) =
+            expand_inputs(&local)?.into_iter().partition(|path| is_kzip(path));
+        let num_remote_kzips = remote.iter().filter(|url| url.ends_with(".kzip")).count();
+        let num_kzips = kzips.len() + num_remote_kzips;
+        let num_paths = paths.len() + remote.len() - num_remote_kzips;
+
+        // The sizes of remote inputs are unknown until they are downloaded
+        let size_of = |paths: &[PathBuf], num_remote: usize| {
+            let bytes: u64 =
+                paths.iter().filter_map(|path| fs::metadata(path).ok()).map(|m| m.len()).sum();
+            match num_remote {
+                0 => format_bytes(bytes as usize),
+                _ => format!("{} plus {} download(s)", format_bytes(bytes as usize), num_remote),
+            }
+        };
+
+        if num_kzips > 0 {
+            plan.stage(format!(
+                "Read the files of {} kzip(s) ({})",
+                num_kzips,
+                size_of(&kzips, num_remote_kzips)
+            ));
+        }
+
+        let format = match self.input_format {
+            CliEntryFormat::Auto => "format detected",
+            CliEntryFormat::Json => "JSON",
+            CliEntryFormat::Proto => "protobuf",
+        };
+
+        if num_paths > 0 {
+            plan.stage(format!(
+                "Read entries ({}) from {} file(s) ({}), decoding in parallel",
+                format,
+                num_paths,
+                size_of(&paths, num_paths - paths.len())
+            ));
+        } else if num_kzips == 0 {
+            plan.stage(format!("Read entries ({}) from stdin", format));
+        }
+
+        plan.passes += 1;
+        plan.memory("Every node (with its facts) and edge is held in memory as a raw graph");
+
+        if !self.strip_facts.is_empty() {
+            let strip = self.strip_facts.join(", ");
+
+            match &self.spill {
+                Some(path) => plan.memory(format!(
+                    "Facts {} are written to {} instead",
+                    strip,
+                    path.to_string_lossy()
+                )),
+                None => plan.memory(format!("Facts {} are dropped while reading", strip)),
+            }
+        }
+
+        let limits = [
+            self.max_nodes.map(|n| format!("{} nodes", n)),
+            self.max_edges.map(|n| format!("{} edges", n)),
+            self.max_fact_size.map(|n| format!("facts of {}", format_bytes(n))),
+        ];
+        let limits = limits.into_iter().flatten().join(", ");
+
+        if !limits.is_empty() {
+            let action = match self.lenient {
+                true => "skipping anything larger",
+                false => "failing on anything larger",
+            };
+            plan.memory(format!("Limited to at most {}, {}", limits, action));
+        }
+
+        Ok(())
+    }
+
+    /// The full names of the facts given to `--strip-facts`.
+    fn strip_facts(&self) -> HashSet<String> {
+        self.strip_facts
+            .iter()
+            .map(|name| match name.starts_with('/') {
+                true => name.clone(),
+                false => format!("/kythe/{}", name),
+            })
+            .collect()
+    }
+
+    /// Whether the text of files is dropped while loading.
+    pub fn strips_text(&self) -> bool {
+        self.strip_facts().contains("/kythe/text")
+    }
+
+    pub fn to_options(&self) -> Result<RawGraphOptions, Box<dyn Error>> {
+        let strip_facts = self.strip_facts();
+
+        if matches!(self.dedup_files, Some(CliFileDedup::Text))
+            && strip_facts.contains("/kythe/text")
+        {
+            Err("--dedup-files text needs the text of files, which --strip-facts removes")?;
+        }
+
+        let spill: Option<Box<dyn Write>> = match &self.spill {
+            Some(path) => Some(Box::new(open_bufwriter(Some(path.clone()))?)),
+            None => None,
+        };
+
+        Ok(RawGraphOptions {
+            strip_facts,
+            spill,
+            lenient: self.lenient,
+            max_nodes: self.max_nodes,
+            max_edges: self.max_edges,
+            max_fact_size: self.max_fact_size,
+            monitor: Monitor::default(),
+        })
+    }
+
+    /// Download any inputs which are URLs, returning the local path of each
+    /// in their place.
+    pub fn fetch_remote(&self, inputs: &[String]) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut options = FetchOptions { max_rate: self.max_download_rate, ..Default::default() };
+
+        if let Some(dir) = &self.download_cache {
+            options.cache_dir = dir.clone();
+        }
+
+        let mut local = Vec::with_capacity(inputs.len());
+
+        for input in inputs {
+            match remote::is_remote(input) {
+                true => local.push(remote::fetch(input, &options)?.to_string_lossy().into_owned()),
+                false => local.push(input.clone()),
+            }
+        }
+
+        Ok(local)
+    }
+
+    /// Load every input (each may be a glob or a URL) as if they were one
+    /// stream of entries. If there are no inputs, read from stdin.
+    pub fn load(&self, inputs: &[String]) -> Result<RawGraph, Box<dyn Error>> {
+        if let Some(path) = &self.from_cache {
+            return self.load_cache(path, inputs);
+        }
+
+        let start = Instant::now();
+        let inputs = self.fetch_remote(inputs)?;
+        let (kzips, paths): (Vec<_>, Vec<_>) =
+            expand_inputs(&inputs)?.into_iter().partition(|path| is_kzip(path));
+
+        let mut file_entries = Vec::new();
+        for path in &kzips {
+            file_entries.extend(kzip::read_file_entries(path)?);
+        }
+
+        let graph = match paths.is_empty() && !kzips.is_empty() {
+            true => RawGraph::from_entries_with(file_entries, &mut self.to_options()?)?,
+            false => {
+                let reader = EntryReader::open_all(paths, (&self.input_format).into())?
+                    .lenient(self.lenient)
+                    .parallel(true);
+                let mut options = self.to_options()?;
+                let entries = file_entries.into_iter().map(Ok).chain(reader);
+                itertools::process_results(entries, |entries| {
+                    RawGraph::from_entries_with(entries, &mut options)
+                })??
+            }
+        };
+        log::debug!("Loaded raw graph in {} secs.", start.elapsed().as_secs_f32());
+        log_fact_sizes(&graph);
+        let graph = self.postprocess(graph);
+        self.check_warnings("loading entries")?;
+        Ok(graph)
+    }
+
+    /// Load a graph from a cache rather than from entries.
+    fn load_cache(&self, path: &Path, inputs: &[String]) -> Result<RawGraph, Box<dyn Error>> {
+        if !inputs.is_empty() {
+            log::warn!("Ignoring {} input(s) because --from-cache was given.", inputs.len());
+        }
+
+        let start = Instant::now();
+        let graph = RawGraph::from(Snapshot::read(path)?);
+        log::debug!("Loaded cached graph in {} secs.", start.elapsed().as_secs_f32());
+        let graph = self.postprocess(graph);
+        self.check_warnings("loading entries")?;
+        Ok(graph)
+    }
+
+    /// Apply the transformations that are run after all entries are loaded.
+    pub fn postprocess(&self, graph: RawGraph) -> RawGraph {
+        match &self.dedup_files {
+            None => graph,
+            Some(by) => {
+                let (graph, num_removed) = graph.dedup_files(by.into());
+                log::info!("Merged {} duplicate file(s).", num_removed);
+                graph
+            }
+        }
+    }
+
+    /// Convert a raw graph into a spec graph, keeping the text of large files
+    /// on disk if --spill-texts is given.
+    pub fn spec(&self, graph: RawGraph) -> Result<SpecGraph, Box<dyn Error>> {
+        let texts = match &self.spill_texts {
+            Some(path) => FileStore::spilling(path, SPILL_TEXT_THRESHOLD)?,
+            None => FileStore::new(),
+        };
+
+        Ok(SpecGraph::from_raw_into(graph, &mut Monitor::default(), texts)?)
+    }
+
+    /// Convert a spec graph into an entity graph, reporting any nodes without
+    /// a kind.
+    pub fn entities(&self, spec: &SpecGraph) -> Result<EntityGraph, Box<dyn Error>> {
+        for (corpus, count) in spec.count_none_by_corpus() {
+            log::warn!(
+                "Found {} node(s) without any facts in corpus {}.",
+                count,
+                corpus.as_deref().unwrap_or("<none>")
+            );
+        }
+
+        let options = EntityGraphOptions {
+            none_policy: (&self.none_nodes).into(),
+            name_sources: self.name_sources.iter().map_into().collect(),
+            configs: match self.configs.is_empty() {
+                true => None,
+                false => Some(self.configs.iter().cloned().collect()),
+            },
+            merge_declarations: self.merge_declarations,
+        };
+
+        let mut graph = EntityGraph::new(spec, &options)?;
+        let aliases = RootAliases::new(self.root_alias.iter().cloned());
+
+        if !aliases.is_empty() {
+            graph.alias_roots(spec, &aliases);
+        }
+
+        let generated = match &self.generated_sources {
+            Some(path) => GeneratedSources::read(path)?,
+            None => GeneratedSources::new(),
+        };
+        let generated = generated.follow_edges(self.follow_generates);
+
+        if !generated.is_empty() {
+            let num_moved = generated.apply(&mut graph, spec);
+            log::info!("Moved {} generated entities to their sources.", num_moved);
+        }
+
+        if !self.keep_param_deps {
+            graph.fold_params(spec);
+        }
+
+        if self.lift_anchors {
+            let num_lifted = graph.lift_anchors(spec);
+            log::debug!("Lifted the deps of {} anchor(s).", num_lifted);
+        }
+
+        if let Some(path) = &self.annotate {
+            let num_annotated = Annotations::read(path)?.apply(&mut graph);
+            log::info!("Annotated {} entities.", num_annotated);
+        }
+
+        self.anonymize(|anonymizer| anonymizer.graph(&mut graph))?;
+        self.check_warnings("building entities")?;
+        Ok(graph)
+    }
+
+    /// Whether the deps of anchors are lifted (see --lift-anchors).
+    pub fn lifts_anchors(&self) -> bool {
+        self.lift_anchors
+    }
+
+    /// Whether output is anonymized (see --anonymize).
+    pub fn anonymizes(&self) -> bool {
+        self.anonymize.is_some()
+    }
+
+    /// If --anonymize is given, call `f` with the anonymizer and then save
+    /// its mapping. Entities are anonymized as they are built, so this is
+    /// only needed for output which is not taken from an entity graph.
+    pub fn anonymize<T>(&self, f: impl FnOnce(&mut Anonymizer) -> T) -> io::Result<Option<T>> {
+        let path = match &self.anonymize {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut anonymizer = Anonymizer::open(path)?;
+        let res = f(&mut anonymizer);
+        anonymizer.save(path)?;
+        Ok(Some(res))
+    }
+
+    /// Fail if more warnings have been logged so far than are allowed by
+    /// --strict or --max-warnings.
+    pub fn check_warnings(&self, stage: &str) -> Result<(), Box<dyn Error>> {
+        let max_warnings = match self.strict {
+            true => Some(0),
+            false => self.max_warnings,
+        };
+
+        match max_warnings {
+            Some(max) if diagnostics::num_warnings() > max => Err(format!(
+                "found {} warning(s) while {} but at most {} are allowed",
+                diagnostics::num_warnings(),
+                stage,
+                max
+            ))?,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `path` is a kzip, in which case only its files are loaded.
+pub fn is_kzip(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "kzip")
+}
+
+pub fn log_fact_sizes(graph: &RawGraph) {
+    for (name, size) in graph.fact_sizes() {
+        log::debug!("Found {} \"{}\" fact(s) totaling {} bytes.", size.count, name, size.bytes);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::io::open_bufwriter;
+use crate::lsp::documents;
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Produce document symbols and references for each file in the JSON shapes
+/// used by the Language Server Protocol.
+///
+/// Each line of the output is a single document with a "uri" (the path of the
+/// file), hierarchical "symbols" (nested by childof edges), and "references"
+/// (locations which refer to some entity). Positions use UTF-16 characters as
+/// required by LSP.
+#[derive(clap::Args)]
+pub struct CliLspCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliLspCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+        let mut writer = open_bufwriter(self.output.clone())?;
+
+        for document in documents(&spec_graph, &entity_graph) {
+            serde_json::to_writer(&mut writer, &document)?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use crate::io::open_bufwriter;
+use crate::metrics::{write_entity_metrics, write_file_metrics, write_package_metrics};
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::load::{parse_group_by, CliGroupBy, CliLoadArgs};
+use super::CliCommand;
+
+/// Write size and coupling metrics for each file (or package) as CSV.
+///
+/// Fan-in and fan-out count distinct files while deps-in and deps-out count
+/// the underlying edges. Lines count the lines of text in each file, e.g. for
+/// coupling per KLOC.
+///
+/// With --entities, write the size of each function, class, etc. instead: the
+/// span of its definition (in lines and bytes), the number of local variables
+/// it declares, and its number of parameters.
+///
+/// With --martin, write Robert C. Martin's package metrics instead: afferent
+/// and efferent coupling (counted in entities), instability, abstractness (the
+/// fraction of types which are interfaces), and distance from the main
+/// sequence. These are usually grouped by package (--group-by package).
+#[derive(clap::Args)]
+pub struct CliMetricsCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Write one row per package or per directory (e.g. "dir:2") rather
+    /// than per file.
+    #[clap(
+        value_name = "BY",
+        long,
+        value_parser = parse_group_by,
+        default_value = "path",
+        display_order = 3
+    )]
+    group_by: CliGroupBy,
+    /// Write instability, abstractness, and distance from the main sequence
+    /// for each package (or file or directory) rather than size metrics.
+    #[clap(long, display_order = 4)]
+    martin: bool,
+    /// Write size metrics for each entity rather than for each file.
+    #[clap(long, conflicts_with = "martin", display_order = 5)]
+    entities: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliMetricsCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let writer = open_bufwriter(self.output.clone())?;
+        let group_by = (&self.group_by).into();
+
+        match (self.martin, self.entities) {
+            (true, _) => write_package_metrics(&entity_graph, group_by, writer)?,
+            (_, true) => write_entity_metrics(&spec_graph, &entity_graph, writer)?,
+            _ => write_file_metrics(&spec_graph, &entity_graph, group_by, writer)?,
+        }
+
+        Ok(())
+    }
+}
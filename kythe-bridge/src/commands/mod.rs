@@ -1,8 +1,31 @@
+pub mod budget;
+pub mod cache;
+pub mod clones;
+pub mod compare;
+pub mod coverage;
+pub mod cycles;
+pub mod decorations;
+pub mod diff;
 pub mod display;
 pub mod dsm;
+pub mod edgekinds;
 pub mod exclude;
+pub mod export;
+pub mod externals;
+pub mod extract;
 pub mod format;
-pub mod edgekinds;
+pub mod heatmap;
+pub mod ingest;
+pub mod list;
+pub mod load;
+pub mod lsp;
+pub mod metrics;
+pub mod serve;
+pub mod snapshot;
+pub mod staleness;
+pub mod tee;
+pub mod types;
+pub mod verify;
 
 pub trait CliCommand {
     fn execute(&self) -> Result<(), Box<dyn std::error::Error>>;
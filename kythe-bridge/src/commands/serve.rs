@@ -0,0 +1,51 @@
+#[cfg(feature = "flight")]
+use crate::sink::{write_graph, ArrowSink};
+
+use std::error::Error;
+use std::net::SocketAddr;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Serve the entities and deps over Arrow Flight until interrupted.
+///
+/// The graph is loaded once, up front. Then the "entities" and "deps" tables
+/// may be fetched with a ticket of their name (or listed and described by a
+/// descriptor path of their name) by any Flight client, e.g. pyarrow. The deps
+/// table has the same columns as `format --format csv`.
+///
+/// Only available if built with the `flight` feature.
+#[derive(clap::Args)]
+#[cfg_attr(not(feature = "flight"), allow(dead_code))]
+pub struct CliServeCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Address to listen on for gRPC.
+    #[clap(value_name = "ADDR", long, default_value = "127.0.0.1:50051", display_order = 2)]
+    address: SocketAddr,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliServeCommand {
+    #[cfg(feature = "flight")]
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+
+        let mut sink = ArrowSink::default();
+        write_graph(&entity_graph, &mut sink)?;
+
+        log::info!("Serving Arrow Flight on {}...", self.address);
+        crate::flight::serve(self.address, sink.into_tables())
+    }
+
+    #[cfg(not(feature = "flight"))]
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        Err("serve is not available (build with --features flight)".into())
+    }
+}
@@ -0,0 +1,134 @@
+use crate::algebra::{combine, Operation};
+use crate::io::open_bufwriter;
+use crate::ir::RawGraph;
+use crate::snapshot::{self, Snapshot};
+
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::CliCommand;
+
+/// Inspect, upgrade, and combine snapshots created by the `ingest` subcommand.
+#[derive(clap::Args)]
+pub struct CliSnapshotCommand {
+    #[clap(subcommand)]
+    command: CliSnapshotSubCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum CliSnapshotSubCommand {
+    Info(CliSnapshotInfoCommand),
+    Upgrade(CliSnapshotUpgradeCommand),
+    Union(CliSnapshotAlgebraCommand),
+    Intersect(CliSnapshotAlgebraCommand),
+    Subtract(CliSnapshotAlgebraCommand),
+}
+
+impl CliCommand for CliSnapshotCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        match &self.command {
+            CliSnapshotSubCommand::Info(com) => com.execute(),
+            CliSnapshotSubCommand::Upgrade(com) => com.execute(),
+            CliSnapshotSubCommand::Union(com) => com.execute(Operation::Union),
+            CliSnapshotSubCommand::Intersect(com) => com.execute(Operation::Intersect),
+            CliSnapshotSubCommand::Subtract(com) => com.execute(Operation::Subtract),
+        }
+    }
+}
+
+/// Print summary statistics of a snapshot (as JSON).
+///
+/// The statistics are stored in the header of the snapshot, so this is fast
+/// even for very large snapshots.
+#[derive(clap::Args)]
+pub struct CliSnapshotInfoCommand {
+    /// Path of the snapshot.
+    #[clap(value_name = "SNAPSHOT")]
+    snapshot: PathBuf,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 1)]
+    output: Option<PathBuf>,
+}
+
+impl CliCommand for CliSnapshotInfoCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let stats = Snapshot::read_stats(&self.snapshot)?;
+        let mut writer = open_bufwriter(self.output.clone())?;
+        serde_json::to_writer_pretty(&mut writer, &stats)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Rewrite snapshots from older versions of this tool in the current format.
+///
+/// Older snapshots are migrated in memory each time they are read, which is
+/// slower than reading the current format, so long-lived snapshots are worth
+/// upgrading once. Snapshots which are too old to migrate must be re-indexed.
+#[derive(clap::Args)]
+pub struct CliSnapshotUpgradeCommand {
+    /// Path of the snapshot.
+    #[clap(value_name = "SNAPSHOT")]
+    snapshot: PathBuf,
+    /// Path of the file to write the upgraded snapshot to. If ommitted, the
+    /// snapshot is upgraded in place.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 1)]
+    output: Option<PathBuf>,
+}
+
+impl CliCommand for CliSnapshotUpgradeCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let output = self.output.as_ref().unwrap_or(&self.snapshot);
+        let version = Snapshot::upgrade(&self.snapshot, output)?;
+
+        match version == snapshot::VERSION {
+            true => log::info!("Snapshot is already at version {}.", version),
+            false => log::info!(
+                "Upgraded snapshot from version {} to {} in {} secs.",
+                version,
+                snapshot::VERSION,
+                start.elapsed().as_secs_f32()
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+/// Combine two snapshots into a new snapshot.
+///
+/// Nodes are matched by stable id (their corpus, path, and signature, but not
+/// their root) and edges by their kind and endpoints. `union` keeps everything
+/// in either snapshot, `intersect` keeps only what is in both, and `subtract`
+/// keeps the edges of the first snapshot which are not in the second (e.g. the
+/// dependencies present in a release build but not in a debug build).
+#[derive(clap::Args)]
+pub struct CliSnapshotAlgebraCommand {
+    /// Path of the first snapshot.
+    #[clap(value_name = "A")]
+    a: PathBuf,
+    /// Path of the second snapshot.
+    #[clap(value_name = "B")]
+    b: PathBuf,
+    /// Path of the file to write the combined snapshot to.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 1)]
+    output: PathBuf,
+}
+
+impl CliSnapshotAlgebraCommand {
+    fn execute(&self, op: Operation) -> Result<(), Box<dyn Error>> {
+        let start = Instant::now();
+        let a: RawGraph = Snapshot::read(&self.a)?.into();
+        let b: RawGraph = Snapshot::read(&self.b)?.into();
+        log::info!("Read snapshots in {} secs.", start.elapsed().as_secs_f32());
+
+        let graph = combine(a, b, op);
+        log::info!("Found {} node(s) in the {:?} of the snapshots.", graph.tickets().count(), op);
+
+        Snapshot::from(graph).write(&self.output)?;
+        Ok(())
+    }
+}
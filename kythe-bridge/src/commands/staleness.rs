@@ -0,0 +1,84 @@
+use crate::io::open_bufwriter;
+use crate::staleness::{check_freshness, Freshness};
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Report which files have changed since they were indexed, as CSV.
+///
+/// The text of each file in the graph is hashed and compared with the same
+/// file in a checkout. A file is "stale" if its contents differ and "missing"
+/// if it no longer exists, in which case anything derived from the graph
+/// about that file (metrics, DSMs, etc.) may no longer reflect the code.
+#[derive(clap::Args)]
+pub struct CliStalenessCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Path of the checkout to compare against. The path of each file is
+    /// taken to be relative to it.
+    #[clap(value_name = "PATH", long, display_order = 3)]
+    repo: PathBuf,
+    /// Also check files with a root (usually generated files under a build
+    /// output directory), taking their root and path to be relative to the
+    /// checkout.
+    #[clap(long, display_order = 6)]
+    include_roots: bool,
+    /// Only list files which are stale or missing.
+    #[clap(long, display_order = 4)]
+    stale_only: bool,
+    /// Exit with an error if any file is stale or missing (e.g. to fail a CI
+    /// job whose index is out of date).
+    #[clap(long, display_order = 5)]
+    check: bool,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+impl CliCommand for CliStalenessCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        if self.load.strips_text() {
+            Err("staleness needs the text of files, which --strip-facts removes")?;
+        }
+
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let (texts, paths) = (spec_graph.texts(), spec_graph.file_paths());
+        let files = check_freshness(texts, paths, &self.repo, self.include_roots)?;
+
+        let count = |freshness| files.iter().filter(|file| file.freshness == freshness).count();
+        let (num_stale, num_missing) = (count(Freshness::Stale), count(Freshness::Missing));
+        log::info!(
+            "Found {} stale and {} missing file(s) out of {}.",
+            num_stale,
+            num_missing,
+            files.len()
+        );
+
+        let mut writer = csv::Writer::from_writer(open_bufwriter(self.output.clone())?);
+
+        for file in
+            files.iter().filter(|file| !self.stale_only || file.freshness != Freshness::Fresh)
+        {
+            writer.serialize(file)?;
+        }
+
+        writer.flush()?;
+
+        match self.check && num_stale + num_missing > 0 {
+            true => Err(format!(
+                "found {} file(s) which changed since they were indexed",
+                num_stale + num_missing
+            ))?,
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::io::{expand_inputs, BatchWriter, EntryFormat, EntryLineReader};
+use kythe_bridge::exclusion::{read_rule_file, tee, ExclusionSet};
+
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::CliCommand;
+
+/// Split one stream of entries into several filtered streams in a single pass.
+///
+/// Each tap pairs a file of exclusion rules (in the format accepted by
+/// `exclude --rules`) with the file its entries are written to. Every entry is
+/// read once and written to each tap whose rules do not exclude it, so e.g.
+/// per-team or per-language subsets of a large stream can be made without
+/// reading it once per subset.
+#[derive(clap::Args)]
+pub struct CliTeeCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// A file of exclusion rules and the file to write the entries it keeps
+    /// to, e.g. "team-a.rules=team-a.json". May be repeated. An empty rules
+    /// path (e.g. "=all.json") keeps every entry.
+    #[clap(
+        value_name = "RULES=PATH",
+        long,
+        required = true,
+        value_parser = parse_tap,
+        display_order = 2
+    )]
+    tap: Vec<(Option<PathBuf>, PathBuf)>,
+    /// Log and skip malformed entries instead of aborting. The number skipped
+    /// is reported at the end.
+    #[clap(help_heading = "MISC", long, display_order = 3)]
+    lenient: bool,
+    /// Write each output on a separate thread so that it overlaps with reading
+    /// the input.
+    #[clap(help_heading = "MISC", long, display_order = 4)]
+    async_write: bool,
+}
+
+fn parse_tap(text: &str) -> Result<(Option<PathBuf>, PathBuf), String> {
+    match text.split_once('=') {
+        Some((rules, output)) if !output.is_empty() => {
+            let rules = Some(PathBuf::from(rules)).filter(|rules| !rules.as_os_str().is_empty());
+            Ok((rules, PathBuf::from(output)))
+        }
+        _ => Err(format!("expected RULES=PATH but found \"{}\"", text)),
+    }
+}
+
+impl CliCommand for CliTeeCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let mut taps = Vec::new();
+
+        for (rules, output) in &self.tap {
+            let rules = match rules {
+                Some(path) => read_rule_file(path)?,
+                None => ExclusionSet::new(),
+            };
+            log::debug!("Loaded {} rule(s) for `{}`.", rules.len(), output.display());
+            taps.push((rules, BatchWriter::create(Some(output.clone()), self.async_write)?));
+        }
+
+        log::info!("Starting tee into {} output(s)...", taps.len());
+        let start = Instant::now();
+        let reader = EntryLineReader::open_all(expand_inputs(&self.input)?, EntryFormat::Auto)?
+            .lenient(self.lenient);
+        let (num_lines, num_excluded) = tee(reader, &mut taps)?;
+
+        for ((_, writer), ((_, output), num_excluded)) in
+            taps.into_iter().zip(self.tap.iter().zip(num_excluded))
+        {
+            writer.finish()?;
+            log::info!(
+                "Wrote {} out of {} entries to `{}`.",
+                num_lines - num_excluded,
+                num_lines,
+                output.display()
+            );
+        }
+
+        log::info!("Finished in {} secs.", start.elapsed().as_secs_f32());
+        Ok(())
+    }
+}
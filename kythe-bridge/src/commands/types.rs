@@ -0,0 +1,76 @@
+use crate::dv8::Dv8Matrix;
+use crate::io::open_bufwriter;
+use crate::typecoupling::type_uses;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::load::CliLoadArgs;
+use super::CliCommand;
+
+/// Report which files depend on which types through `typed` edges.
+///
+/// This complements the call and use based views of the other subcommands.
+/// Type applications (e.g. `List<Foo>`) count as a use of each of their
+/// parameters and aliases are followed to the type they alias.
+#[derive(clap::Args)]
+pub struct CliTypesCommand {
+    /// Path (or glob) of a file to read entries from. May be repeated. If
+    /// ommitted, read from stdin.
+    #[clap(short = 'i', value_name = "PATH", long, display_order = 1)]
+    input: Vec<String>,
+    /// Path of the file to write to. If ommitted, write to stdout.
+    #[clap(short = 'o', value_name = "PATH", long, display_order = 2)]
+    output: Option<PathBuf>,
+    /// Format of the output.
+    #[clap(
+        short = 'f',
+        value_name = "FORMAT",
+        long,
+        arg_enum,
+        value_parser,
+        default_value = "csv",
+        display_order = 3
+    )]
+    format: CliTypesFormat,
+
+    #[clap(flatten)]
+    load: CliLoadArgs,
+}
+
+#[derive(Clone, clap::ValueEnum)]
+pub enum CliTypesFormat {
+    /// One row per (file, type) pair
+    Csv,
+    /// A file-level DSM in DV8's JSON format
+    Dsm,
+}
+
+impl CliCommand for CliTypesCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let raw_graph = self.load.load(&self.input)?;
+        let spec_graph = self.load.spec(raw_graph)?;
+        let entity_graph = self.load.entities(&spec_graph)?;
+        let uses = type_uses(&spec_graph, &entity_graph);
+        let writer = open_bufwriter(self.output.clone())?;
+
+        match self.format {
+            CliTypesFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(writer);
+
+                for row in uses {
+                    writer.serialize(row)?;
+                }
+
+                writer.flush()?;
+            }
+            CliTypesFormat::Dsm => {
+                let pairs =
+                    uses.iter().map(|u| (u.path.as_str(), u.type_path.as_str(), "Type", u.count));
+                serde_json::to_writer_pretty(writer, &Dv8Matrix::from_pairs(pairs))?;
+            }
+        }
+
+        Ok(())
+    }
+}
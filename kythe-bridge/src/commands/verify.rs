@@ -0,0 +1,46 @@
+use crate::manifest::Manifest;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::CliCommand;
+
+/// Check outputs against the manifests written alongside them.
+///
+/// A manifest is written next to an output (as <PATH>.manifest.json) when
+/// `format` or `export` is given --manifest. It records the size, number of
+/// lines, and SHA-256 of the output, so a file which was truncated or
+/// corrupted in transfer can be detected.
+#[derive(clap::Args)]
+pub struct CliVerifyExportCommand {
+    /// Path of an output to verify. May be repeated.
+    #[clap(value_name = "PATH", required = true)]
+    paths: Vec<PathBuf>,
+}
+
+impl CliCommand for CliVerifyExportCommand {
+    fn execute(&self) -> Result<(), Box<dyn Error>> {
+        let mut num_failed = 0;
+
+        for path in &self.paths {
+            match Manifest::verify(path) {
+                Ok(manifest) => log::info!(
+                    "{}: OK ({} bytes, {} lines)",
+                    path.to_string_lossy(),
+                    manifest.bytes,
+                    manifest.lines
+                ),
+                Err(err) => {
+                    log::error!("{}: {}", path.to_string_lossy(), err);
+                    num_failed += 1;
+                }
+            }
+        }
+
+        if num_failed > 0 {
+            Err(format!("{} of {} output(s) failed verification", num_failed, self.paths.len()))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::io::Ticket;
+use crate::ir::{EdgeKind, RawGraph};
+
+/// How many items appear in both graphs or in only one of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Agreement {
+    pub both: usize,
+    pub only_a: usize,
+    pub only_b: usize,
+}
+
+impl Agreement {
+    /// The fraction of all items which appear in both graphs.
+    pub fn ratio(&self) -> f64 {
+        match self.both + self.only_a + self.only_b {
+            0 => 1.0,
+            total => self.both as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub enum Side {
+    A,
+    B,
+}
+
+/// An edge which only appears in the graph on one `side`.
+#[derive(Debug, serde::Serialize)]
+pub struct MissingEdge<'a> {
+    pub only_in: Side,
+    pub kind: EdgeKind,
+    pub source: &'a Ticket,
+    pub target: &'a Ticket,
+}
+
+#[derive(Debug, Default)]
+pub struct Comparison<'a> {
+    pub nodes: Agreement,
+    pub edges: BTreeMap<EdgeKind, Agreement>,
+    pub missing: Vec<MissingEdge<'a>>,
+}
+
+/// Compare two graphs of the same code (e.g. produced by two versions of an
+/// indexer), aligning nodes by ticket. Edges are compared as sets, so their
+/// counts are ignored.
+pub fn compare<'a>(a: &'a RawGraph, b: &'a RawGraph) -> Comparison<'a> {
+    let mut comparison = Comparison::default();
+
+    let a_tickets: HashSet<&Ticket> = a.tickets().collect();
+    let b_tickets: HashSet<&Ticket> = b.tickets().collect();
+    comparison.nodes = Agreement {
+        both: a_tickets.intersection(&b_tickets).count(),
+        only_a: a_tickets.difference(&b_tickets).count(),
+        only_b: b_tickets.difference(&a_tickets).count(),
+    };
+
+    let a_edges: HashSet<_> =
+        a.ticket_edges().map(|(kind, src, tgt, _)| (kind, src, tgt)).collect();
+    let b_edges: HashSet<_> =
+        b.ticket_edges().map(|(kind, src, tgt, _)| (kind, src, tgt)).collect();
+
+    for edge in &a_edges {
+        let agreement = comparison.edges.entry(edge.0).or_default();
+
+        match b_edges.contains(edge) {
+            true => agreement.both += 1,
+            false => {
+                agreement.only_a += 1;
+                comparison.missing.push(MissingEdge::new(Side::A, edge));
+            }
+        }
+    }
+
+    for edge in b_edges.difference(&a_edges) {
+        comparison.edges.entry(edge.0).or_default().only_b += 1;
+        comparison.missing.push(MissingEdge::new(Side::B, edge));
+    }
+
+    comparison
+}
+
+impl<'a> MissingEdge<'a> {
+    fn new(only_in: Side, (kind, source, target): &(EdgeKind, &'a Ticket, &'a Ticket)) -> Self {
+        Self { only_in, kind: *kind, source, target }
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::{BTreeSet, HashMap};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use itertools::Itertools;
+
+use crate::ir::{Entity, EntityGraph, NodeIndex, NodeKind, Relation};
+
+/// Paths which are usually tests, by the conventions of the supported
+/// languages.
+pub const DEFAULT_TEST_PATTERNS: [&str; 8] = [
+    "**/test/**",
+    "**/tests/**",
+    "**/*_test.*",
+    "**/*_unittest.*",
+    "**/*Test.java",
+    "**/*Tests.java",
+    "**/test_*.py",
+    "**/*.test.ts",
+];
+
+/// Which test files refer to a single (non-test) entity.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Coverage {
+    pub path: String,
+    pub name: String,
+    pub kind: &'static str,
+    pub id: NodeIndex,
+    pub num_test_files: usize,
+    /// The paths of the test files, separated by semicolons.
+    pub test_files: String,
+}
+
+pub fn test_matcher<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(Glob::new(pattern.as_ref())?);
+    }
+
+    builder.build()
+}
+
+/// Whether tests are likely to care about `entity` on its own.
+fn is_testable(entity: &Entity) -> bool {
+    matches!(
+        entity.kind,
+        NodeKind::Function(_, _)
+            | NodeKind::Interface
+            | NodeKind::Record(_, _)
+            | NodeKind::Sum(_, _)
+    )
+}
+
+/// For every testable entity outside of the test files matched by `tests`,
+/// find the test files that depend on it. Entities that no test depends on
+/// are included with no test files. Sorted by path and then name.
+pub fn coverage_map(graph: &EntityGraph, tests: &GlobSet) -> Vec<Coverage> {
+    let is_test = |entity: &Entity| tests.is_match(&entity.path);
+    let mut test_files: HashMap<NodeIndex, BTreeSet<&str>> = HashMap::new();
+
+    for dep in &graph.deps {
+        let (src, tgt) = match dep.normalized() {
+            (Relation::DependsOn, src, tgt) => (src, tgt),
+            _ => continue,
+        };
+
+        if let (Some(src), Some(tgt)) = (graph.entities.get(&src), graph.entities.get(&tgt)) {
+            if is_test(src) && !is_test(tgt) {
+                test_files.entry(tgt.id).or_default().insert(&src.path);
+            }
+        }
+    }
+
+    graph
+        .entities
+        .values()
+        .filter(|entity| is_testable(entity) && !is_test(entity))
+        .map(|entity| {
+            let files = test_files.remove(&entity.id).unwrap_or_default();
+
+            Coverage {
+                path: entity.path.clone(),
+                name: entity.name.clone(),
+                kind: entity.kind.name(),
+                id: entity.id,
+                num_test_files: files.len(),
+                test_files: files.into_iter().join(";"),
+            }
+        })
+        .sorted()
+        .collect()
+}
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::ir::{EdgeKind, EntityGraph, FileKey, NodeIndex, Relation, SpecGraph};
+
+/// A set of nodes which all (transitively) depend on each other, along with
+/// the edges between them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cycle<N> {
+    pub members: Vec<N>,
+    pub edges: Vec<CycleEdge<N>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CycleEdge<N> {
+    pub src: N,
+    pub tgt: N,
+    pub counts: BTreeMap<EdgeKind, usize>,
+}
+
+fn is_dependency(kind: &EdgeKind) -> bool {
+    kind.relation().0 == Relation::DependsOn
+}
+
+/// Find cycles of dependencies between files. Only edges which express a
+/// dependency count (see `Relation`), so e.g. a header and the file which
+/// defines what it declares do not form a cycle on their own.
+pub fn file_cycles(graph: &EntityGraph, spec: &SpecGraph) -> Vec<Cycle<FileKey>> {
+    let mut counts = BTreeMap::new();
+
+    for dep in graph.rollup_to_files(spec) {
+        let dep_counts: BTreeMap<_, _> =
+            dep.counts.into_iter().filter(|(kind, _)| is_dependency(kind)).collect();
+
+        if dep.src != dep.tgt && !dep_counts.is_empty() {
+            counts.insert((dep.src, dep.tgt), dep_counts);
+        }
+    }
+
+    cycles(counts)
+}
+
+/// Find cycles of dependencies between entities.
+pub fn entity_cycles(graph: &EntityGraph) -> Vec<Cycle<NodeIndex>> {
+    let mut counts: BTreeMap<(NodeIndex, NodeIndex), BTreeMap<EdgeKind, usize>> = BTreeMap::new();
+
+    for dep in &graph.deps {
+        let (relation, src, tgt) = dep.normalized();
+
+        if relation != Relation::DependsOn || src == tgt {
+            continue;
+        }
+
+        if graph.entities.contains_key(&src) && graph.entities.contains_key(&tgt) {
+            *counts.entry((src, tgt)).or_default().entry(dep.kind).or_default() += dep.count;
+        }
+    }
+
+    cycles(counts)
+}
+
+/// Group the strongly connected components of the graph described by `counts`
+/// with the edges inside each. Largest first.
+fn cycles<N: Copy + Eq + Hash + Ord>(
+    counts: BTreeMap<(N, N), BTreeMap<EdgeKind, usize>>,
+) -> Vec<Cycle<N>> {
+    let components = strongly_connected(counts.keys().copied());
+    let component_of: HashMap<N, usize> = components
+        .iter()
+        .enumerate()
+        .flat_map(|(i, members)| members.iter().map(move |member| (*member, i)))
+        .collect();
+
+    let mut cycles: Vec<Cycle<N>> =
+        components.into_iter().map(|members| Cycle { members, edges: Vec::new() }).collect();
+
+    for ((src, tgt), counts) in counts {
+        match (component_of.get(&src), component_of.get(&tgt)) {
+            (Some(a), Some(b)) if a == b => cycles[*a].edges.push(CycleEdge { src, tgt, counts }),
+            _ => continue,
+        }
+    }
+
+    cycles.sort_by(|a, b| b.members.len().cmp(&a.members.len()).then(a.members.cmp(&b.members)));
+    cycles
+}
+
+/// The strongly connected components (with more than one node) of the graph
+/// with the given edges, using Tarjan's algorithm. Each component is sorted.
+pub fn strongly_connected<N, I>(edges: I) -> Vec<Vec<N>>
+where
+    N: Copy + Eq + Hash + Ord,
+    I: IntoIterator<Item = (N, N)>,
+{
+    const UNVISITED: usize = usize::MAX;
+
+    let mut adjacency: BTreeMap<N, Vec<N>> = BTreeMap::new();
+
+    for (src, tgt) in edges {
+        adjacency.entry(src).or_default().push(tgt);
+        adjacency.entry(tgt).or_default();
+    }
+
+    let nodes: Vec<N> = adjacency.keys().copied().collect();
+    let positions: HashMap<N, usize> = nodes.iter().enumerate().map(|(i, n)| (*n, i)).collect();
+    let succ: Vec<Vec<usize>> =
+        adjacency.values().map(|tgts| tgts.iter().map(|tgt| positions[tgt]).collect()).collect();
+
+    let mut index = vec![UNVISITED; nodes.len()];
+    let mut low = vec![0; nodes.len()];
+    let mut on_stack = vec![false; nodes.len()];
+    let mut stack = Vec::new();
+    let mut next = 0;
+    let mut components = Vec::new();
+
+    for root in 0..nodes.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+
+        // Each item is a node and the position of the next successor to visit
+        let mut work = vec![(root, 0)];
+
+        while let Some((v, i)) = work.pop() {
+            if i == 0 {
+                index[v] = next;
+                low[v] = next;
+                next += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+
+            if let Some(&w) = succ[v].get(i) {
+                work.push((v, i + 1));
+
+                if index[w] == UNVISITED {
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+
+                continue;
+            }
+
+            if low[v] == index[v] {
+                let mut component = Vec::new();
+
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack[w] = false;
+                    component.push(nodes[w]);
+
+                    if w == v {
+                        break;
+                    }
+                }
+
+                if component.len() > 1 {
+                    component.sort();
+                    components.push(component);
+                }
+            }
+
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[v]);
+            }
+        }
+    }
+
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strongly_connected() {
+        let edges = [(1, 2), (2, 3), (3, 1), (3, 4), (4, 5), (5, 4), (6, 6), (7, 1)];
+        let mut components = strongly_connected(edges);
+        components.sort();
+        assert_eq!(components, vec![vec![1, 2, 3], vec![4, 5]]);
+    }
+}
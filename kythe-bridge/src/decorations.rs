@@ -0,0 +1,63 @@
+use crate::ir::{AnchorKind, EdgeKind, EntityGraph, NodeIndex, NodeKind, SpecGraph, StableId};
+
+/// A single edge out of an anchor in a file, along with where the anchor is
+/// and what it points to. This is similar to a reference in Kythe's
+/// decorations API.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Decoration {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+    pub anchor: NodeIndex,
+    pub kind: EdgeKind,
+    pub target: Target,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Target {
+    pub id: NodeIndex,
+    pub stable_id: StableId,
+    pub name: String,
+    pub qualified_name: String,
+    pub path: String,
+    pub kind: &'static str,
+}
+
+/// Find every explicit anchor in the file at `path` (as displayed, so after
+/// any root aliases are applied) and the edges leaving it, sorted by position.
+pub fn decorations(spec: &SpecGraph, graph: &EntityGraph, path: &str) -> Vec<Decoration> {
+    let mut decorations = Vec::new();
+
+    for anchor in graph.entities.values().filter(|e| e.path == path) {
+        let pos = match &anchor.kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+            _ => continue,
+        };
+
+        for (kind, tgt, _) in spec.outgoing_all(anchor.id) {
+            let target = match graph.entities.get(&tgt) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            decorations.push(Decoration {
+                start: pos.start,
+                end: pos.end,
+                text: anchor.name.clone(),
+                anchor: anchor.id,
+                kind,
+                target: Target {
+                    id: target.id,
+                    stable_id: target.stable_id,
+                    name: target.name.clone(),
+                    qualified_name: target.qualified_name.clone(),
+                    path: target.path.clone(),
+                    kind: target.kind.name(),
+                },
+            });
+        }
+    }
+
+    decorations.sort();
+    decorations
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use kythe_bridge::io;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+static NUM_WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of warnings logged so far by this crate, including any that
+/// were not shown because of the verbosity, plus every malformed entry
+/// skipped (whether or not it was logged).
+pub fn num_warnings() -> usize {
+    NUM_WARNINGS.load(Ordering::Relaxed) + io::num_skipped()
+}
+
+/// Whether a warning counts towards `num_warnings`. Warnings from other
+/// crates are left out, as are those about malformed entries, which are
+/// counted by `io::num_skipped` instead.
+fn is_counted(metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    let crate_name = env!("CARGO_CRATE_NAME");
+    let is_ours = target
+        .strip_prefix(crate_name)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"));
+
+    metadata.level() == Level::Warn && is_ours && target != io::SKIPPED_TARGET
+}
+
+/// Wraps a logger so that every warning is counted, even when `--quiet` is
+/// given.
+struct CountingLogger(stderrlog::StdErrLog);
+
+impl Log for CountingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() == Level::Warn || self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if is_counted(record.metadata()) {
+            NUM_WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if self.0.enabled(record.metadata()) {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Use `logger` as the global logger, counting warnings as they pass through.
+pub fn init(logger: stderrlog::StdErrLog) -> Result<(), log::SetLoggerError> {
+    let max_level = logger.log_level_filter().max(LevelFilter::Warn);
+    log::set_boxed_logger(Box::new(CountingLogger(logger)))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_counted() {
+        let warning = |target| Metadata::builder().level(Level::Warn).target(target).build();
+
+        assert!(is_counted(&warning("kythe_bridge")));
+        assert!(is_counted(&warning("kythe_bridge::ir")));
+        assert!(!is_counted(&warning("kythe_bridge_extra")));
+        assert!(!is_counted(&warning("globset")));
+        assert!(!is_counted(&warning(io::SKIPPED_TARGET)));
+        assert!(!is_counted(
+            &Metadata::builder().level(Level::Info).target("kythe_bridge").build()
+        ));
+    }
+}
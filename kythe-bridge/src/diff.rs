@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ir::{EdgeKind, Entity, EntityGraph, StableId};
+
+/// An entity which only appears in one of the graphs being diffed.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct EntityChange {
+    pub path: String,
+    pub qualified_name: String,
+    pub kind: &'static str,
+    pub stable_id: StableId,
+}
+
+impl From<&Entity> for EntityChange {
+    fn from(entity: &Entity) -> Self {
+        Self {
+            path: entity.path.clone(),
+            qualified_name: entity.qualified_name.clone(),
+            kind: entity.kind.name(),
+            stable_id: entity.stable_id,
+        }
+    }
+}
+
+/// A dep which only appears in one of the graphs being diffed. Deps are the
+/// same if they have the same kind and their endpoints have the same stable
+/// ids, regardless of count.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct DepChange {
+    pub src: String,
+    pub kind: EdgeKind,
+    pub tgt: String,
+    pub src_id: StableId,
+    pub tgt_id: StableId,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct GraphDiff {
+    pub added_entities: Vec<EntityChange>,
+    pub removed_entities: Vec<EntityChange>,
+    pub added_deps: Vec<DepChange>,
+    pub removed_deps: Vec<DepChange>,
+}
+
+type DepKey = (StableId, EdgeKind, StableId);
+
+/// The entities and deps of a graph, keyed by stable id.
+struct Keyed<'a> {
+    entities: BTreeMap<StableId, &'a Entity>,
+    deps: BTreeSet<DepKey>,
+}
+
+impl<'a> Keyed<'a> {
+    fn new(graph: &'a EntityGraph) -> Self {
+        let entities = graph.entities.values().map(|e| (e.stable_id, e)).collect();
+        let deps = graph
+            .deps
+            .iter()
+            .filter_map(|dep| {
+                let src = graph.entities.get(&dep.src)?;
+                let tgt = graph.entities.get(&dep.tgt)?;
+                Some((src.stable_id, dep.kind, tgt.stable_id))
+            })
+            .collect();
+
+        Self { entities, deps }
+    }
+
+    fn entity_changes(&self, other: &Keyed) -> Vec<EntityChange> {
+        let mut changes = self
+            .entities
+            .iter()
+            .filter(|(id, _)| !other.entities.contains_key(id))
+            .map(|(_, entity)| EntityChange::from(*entity))
+            .collect::<Vec<_>>();
+        changes.sort();
+        changes
+    }
+
+    fn dep_changes(&self, other: &Keyed) -> Vec<DepChange> {
+        let name = |id: &StableId| self.entities[id].qualified_name.clone();
+        let mut changes = self
+            .deps
+            .difference(&other.deps)
+            .map(|(src, kind, tgt)| DepChange {
+                src: name(src),
+                kind: *kind,
+                tgt: name(tgt),
+                src_id: *src,
+                tgt_id: *tgt,
+            })
+            .collect::<Vec<_>>();
+        changes.sort();
+        changes
+    }
+}
+
+/// Find the entities and deps which were added or removed between `before`
+/// and `after`, matching entities by stable id.
+pub fn diff(before: &EntityGraph, after: &EntityGraph) -> GraphDiff {
+    let (before, after) = (Keyed::new(before), Keyed::new(after));
+
+    GraphDiff {
+        added_entities: after.entity_changes(&before),
+        removed_entities: before.entity_changes(&after),
+        added_deps: after.dep_changes(&before),
+        removed_deps: before.dep_changes(&after),
+    }
+}
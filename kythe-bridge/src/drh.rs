@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use crate::cycles::strongly_connected;
+use crate::dv8::{Dv8Clustering, Dv8Node, KindMap};
+use crate::ir::{Dep, Entity, GroupBy, NodeIndex};
+use crate::sink::OutputSink;
+
+/// A Design Rule Hierarchy: the variables of a DSM arranged into layers, where
+/// each layer only depends on the layers before it, with each layer split
+/// into modules.
+///
+/// The first layer holds the design rules which everything else depends on
+/// (e.g. interfaces and utilities), each as its own module. Variables in a
+/// cycle always share a layer and module. Within later layers, variables are
+/// in the same module if they depend on exactly the same design rules in
+/// earlier layers, since such variables can only be changed independently of
+/// the rest of the layer together.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Drh {
+    /// The variables of each module of each layer.
+    pub layers: Vec<Vec<Vec<String>>>,
+}
+
+impl Drh {
+    /// Cluster `vars`, where each pair in `deps` means the variable at the
+    /// first index depends on the variable at the second.
+    pub fn new(vars: &[String], deps: &BTreeSet<(usize, usize)>) -> Self {
+        // Every variable in a cycle joins the same component
+        let mut component_of: Vec<usize> = (0..vars.len()).collect();
+
+        for (i, members) in strongly_connected(deps.iter().copied()).into_iter().enumerate() {
+            for member in members {
+                component_of[member] = vars.len() + i;
+            }
+        }
+
+        let mut succs: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        let mut preds: HashMap<usize, BTreeSet<usize>> = HashMap::new();
+
+        for &component in &component_of {
+            succs.entry(component).or_default();
+        }
+
+        for &(src, tgt) in deps {
+            let (src, tgt) = (component_of[src], component_of[tgt]);
+
+            if src != tgt {
+                succs.entry(src).or_default().insert(tgt);
+                preds.entry(tgt).or_default().insert(src);
+            }
+        }
+
+        // Assign layers from the design rules (which depend on nothing) down
+        let mut remaining: HashMap<usize, usize> =
+            succs.iter().map(|(component, succs)| (*component, succs.len())).collect();
+        let mut layer_of: HashMap<usize, usize> = HashMap::new();
+        let mut queue: VecDeque<usize> =
+            remaining.iter().filter(|(_, n)| **n == 0).map(|(component, _)| *component).collect();
+
+        while let Some(component) = queue.pop_front() {
+            let layer = succs[&component].iter().map(|succ| layer_of[succ] + 1).max().unwrap_or(0);
+            layer_of.insert(component, layer);
+
+            for pred in preds.get(&component).into_iter().flatten() {
+                let n = remaining.get_mut(pred).unwrap();
+                *n -= 1;
+
+                if *n == 0 {
+                    queue.push_back(*pred);
+                }
+            }
+        }
+
+        // Group the components of each layer by the design rules they use,
+        // except for the design rules themselves, which are each a module
+        let mut modules: BTreeMap<usize, BTreeMap<BTreeSet<usize>, Vec<String>>> = BTreeMap::new();
+
+        for (var, component) in component_of.iter().enumerate() {
+            let rules = match succs[component].is_empty() {
+                true => BTreeSet::from([*component]),
+                false => succs[component].clone(),
+            };
+            let module = modules.entry(layer_of[component]).or_default().entry(rules).or_default();
+            module.push(vars[var].clone());
+        }
+
+        let layers = modules
+            .into_values()
+            .map(|modules| {
+                modules
+                    .into_values()
+                    .map(|vars| vars.into_iter().sorted().collect_vec())
+                    .sorted()
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        Self { layers }
+    }
+
+    pub fn to_clsx(&self, name: Option<String>) -> Dv8Clustering {
+        let structure = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(i, modules)| {
+                let layer = format!("L{}", i + 1);
+                let modules = modules
+                    .iter()
+                    .enumerate()
+                    .map(|(j, vars)| {
+                        let items = vars.iter().map(|var| Dv8Node::Item { name: var.clone() });
+                        Dv8Node::Group {
+                            name: format!("{}/M{}", layer, j + 1),
+                            nested: items.collect(),
+                        }
+                    })
+                    .collect();
+                Dv8Node::Group { name: layer, nested: modules }
+            })
+            .collect();
+
+        Dv8Clustering::new(name, structure)
+    }
+}
+
+/// Collects entities and deps like `Dv8Sink`, but writes a Design Rule
+/// Hierarchy clustering of the DSM it would have written.
+pub struct ClsxSink<W: Write> {
+    writer: W,
+    name: Option<String>,
+    group_by: GroupBy,
+    kinds: KindMap,
+    groups: HashMap<NodeIndex, String>,
+    deps: Vec<(NodeIndex, NodeIndex)>,
+}
+
+impl<W: Write> ClsxSink<W> {
+    pub fn new(writer: W, name: Option<String>, group_by: GroupBy) -> Self {
+        Self {
+            writer,
+            name,
+            group_by,
+            kinds: KindMap::default(),
+            groups: HashMap::new(),
+            deps: Vec::new(),
+        }
+    }
+
+    /// Only count the deps which `kinds` maps to some dependency kind (see
+    /// `Dv8Sink::kind_map`).
+    pub fn kind_map(mut self, kinds: KindMap) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    fn to_drh(&self) -> Drh {
+        let vars = self.groups.values().cloned().sorted().dedup().collect_vec();
+        let indices: HashMap<&String, usize> =
+            vars.iter().enumerate().map(|(i, v)| (v, i)).collect();
+        let group_of = |id: &NodeIndex| self.groups.get(id).map(|group| indices[group]);
+
+        let deps = self
+            .deps
+            .iter()
+            .filter_map(|(src, tgt)| Some((group_of(src)?, group_of(tgt)?)))
+            .filter(|(src, tgt)| src != tgt)
+            .collect();
+
+        Drh::new(&vars, &deps)
+    }
+}
+
+impl<W: Write> OutputSink for ClsxSink<W> {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+        self.groups.insert(entity.id, self.group_by.key(entity).to_string());
+        Ok(())
+    }
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+        if self.kinds.get(&dep.kind).is_some() {
+            let (_, src, tgt) = dep.normalized();
+            self.deps.push((src, tgt));
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let drh = self.to_drh();
+        log::info!(
+            "Found {} layer(s) with {} module(s) in the design rule hierarchy.",
+            drh.layers.len(),
+            drh.layers.iter().map(|modules| modules.len()).sum::<usize>()
+        );
+        serde_json::to_writer_pretty(&mut self.writer, &drh.to_clsx(self.name.clone()))?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drh() {
+        let vars = ["api", "impl_a", "impl_b", "impl_c", "main", "x", "y"];
+        let vars = vars.iter().map(|v| v.to_string()).collect_vec();
+        // impl_a and impl_b both only use api, x and y form a cycle
+        let deps = [(1, 0), (2, 0), (3, 0), (3, 5), (5, 6), (6, 5), (4, 1), (4, 2), (4, 3)];
+        let drh = Drh::new(&vars, &deps.into_iter().collect());
+
+        let layers = drh
+            .layers
+            .iter()
+            .map(|modules| modules.iter().map(|vars| vars.join(",")).collect_vec())
+            .collect_vec();
+        assert_eq!(layers, vec![vec!["api", "x,y"], vec!["impl_a,impl_b", "impl_c"], vec!["main"]]);
+    }
+}
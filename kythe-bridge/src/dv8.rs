@@ -1,208 +1,1016 @@
-// use std::{collections::HashMap, io, path::Path};
-
-// use crate::{
-//     collections::{KindedEdgeBag, ItemId, IdMap},
-//     io::{Entry, EntryReader},
-// };
-
-// pub struct Dv8Graph {
-//     nodes: IdMap<String>,
-//     edges: KindedEdgeBag<String, ItemId>,
-// }
-
-// impl Dv8Graph {
-//     pub fn new() -> Self {
-//         Self {
-//             nodes: IdMap::new(),
-//             edges: KindedEdgeBag::new(),
-//         }
-//     }
-
-//     pub fn open(path: Option<&Path>) -> io::Result<Self> {
-//         Ok(Self::from(EntryReader::open(path)?))
-//     }
-
-//     pub fn insert_var(&mut self, filename: String) -> ItemId {
-//         self.nodes.insert(filename)
-//     }
-
-//     pub fn insert_dep(&mut self, edge_kind: String, src: ItemId, tgt: ItemId) {
-//         self.edges.insert(edge_kind, src, tgt);
-//     }
-// }
-
-// impl From<EntryReader> for Dv8Graph {
-//     fn from(reader: EntryReader) -> Self {
-//         let mut graph = Dv8Graph::new();
-
-//         for entry in reader {
-//             match entry {
-//                 Entry::Edge { src, tgt, edge_kind, .. } => {
-//                     if let Some(src_path) = src.path && let Some(tgt_path) = tgt.path {
-//                         let src_id = graph.insert_var(src_path);
-//                         let tgt_id = graph.insert_var(tgt_path);
-//                         graph.insert_dep(edge_kind, src_id, tgt_id);
-//                     }
-//                 },
-//                 _ => ()
-//             }
-//         }
-
-//         return graph;
-//     }
-// }
-
-// #[derive(serde::Serialize, Debug, PartialEq, Eq)]
-// pub struct Dv8Matrix {
-//     #[serde(rename = "schemaVersion")]
-//     schema_version: &'static str,
-
-//     #[serde(rename = "name")]
-//     name: Option<String>,
-
-//     #[serde(rename = "variables")]
-//     vars: Vec<String>,
-
-//     #[serde(rename = "cells")]
-//     cells: Vec<Dv8Cell>,
-// }
-
-// impl Dv8Matrix {
-//     fn new(vars: Vec<String>, cells: Vec<Dv8Cell>) -> Self {
-//         Self {
-//             schema_version: "1.0",
-//             name: None,
-//             vars,
-//             cells,
-//         }
-//     }
-
-//     pub fn set_name(&mut self, name: String) {
-//         self.name = Some(name);
-//     }
-// }
-
-// impl From<Dv8Graph> for Dv8Matrix {
-//     fn from(graph: Dv8Graph) -> Self {
-//         to_matrix(graph)
-//     }
-// }
-
-// #[derive(serde::Serialize, Debug, PartialEq, Eq)]
-// pub struct Dv8Cell {
-//     #[serde(rename = "src")]
-//     src: usize,
-
-//     #[serde(rename = "dest")]
-//     tgt: usize,
-
-//     #[serde(rename = "values")]
-//     values: HashMap<&'static str, usize>,
-// }
-
-// impl Dv8Cell {
-//     fn new(src: usize, tgt: usize, values: HashMap<&'static str, usize>) -> Self {
-//         Self { src, tgt, values }
-//     }
-// }
-
-// fn to_vars(keeper: IdMap<String>) -> Vec<String> {
-//     let mut node_pairs: Vec<(ItemId, String)> = keeper.into_iter().collect();
-//     node_pairs.sort_by(|&(a_id, _), &(b_id, _)| a_id.cmp(&b_id));
-
-//     // Confirm that there are no gaps in node ids
-//     if let Some(last) = node_pairs.last() {
-//         assert!(usize::from(last.0) == node_pairs.len() - 1);
-//     }
-
-//     node_pairs.into_iter().map(|(_, node)| node).collect()
-// }
-
-// fn to_dv8_edge_kind(edge_kind: &String) -> Option<&'static str> {
-//     let edge_kind = edge_kind.strip_prefix("/kythe/edge/")?;
-
-//     match edge_kind {
-//         "ref" => Some("Use"),
-//         "ref/call" => Some("Call"),
-//         "ref/call/implicit" => Some("Call"),
-//         "ref/expands" => Some("Use"),
-//         "ref/init" => Some("Create"),
-//         "ref/init/implicit" => Some("Create"),
-//         "ref/imports" => Some("Import"),
-//         "ref/id" => Some("Use"),
-//         "ref/implicit" => Some("Use"),
-//         "ref/includes" => Some("Include"),
-//         "ref/queries" => Some("Use"),
-//         "extends/private" => Some("Extend"),
-//         "extends/public" => Some("Extend"),
-//         "overrides" => Some("ImplLink"),
-//         "overrides/root" => Some("ImplLink"),
-//         "undefines" => Some("Use"),
-//         "satisfies" => Some("Implement"),
-//         "extends" => Some("Extend"),
-//         "childof" => Some("Contain"),
-//         "childof/context" => Some("Contain"),
-//         // "completedby" => Some("Contain"),
-//         _ => match edge_kind.starts_with("param.") {
-//             true => Some("Parameter"),
-//             false => None,
-//         },
-//     }
-// }
-
-// fn to_cells(edges: KindedEdgeBag<String, ItemId>, indices: Vec<usize>) -> Vec<Dv8Cell> {
-//     let mut pair_map: HashMap<(usize, usize), HashMap<&'static str, usize>> = HashMap::new();
-
-//     for (kind, &src, &tgt, &count) in edges.iter() {
-//         let kind = to_dv8_edge_kind(kind);
-
-//         if kind.is_none() {
-//             continue;
-//         }
-
-//         let new_src = *indices.get(usize::from(src)).unwrap();
-//         let new_tgt = *indices.get(usize::from(tgt)).unwrap();
-
-//         pair_map
-//             .entry((new_src, new_tgt))
-//             .or_default()
-//             .insert(kind.unwrap(), count);
-//     }
-
-//     pair_map
-//         .into_iter()
-//         .map(|((src, tgt), values)| Dv8Cell::new(src, tgt, values))
-//         .collect()
-// }
-
-// fn argsort<T: Ord>(data: &[T]) -> Vec<usize> {
-//     let mut indices = (0..data.len()).collect::<Vec<_>>();
-//     indices.sort_by_key(|&i| &data[i]);
-//     indices
-// }
-
-// fn to_matrix(graph: Dv8Graph) -> Dv8Matrix {
-//     let mut vars = to_vars(graph.nodes);
-//     let indices = argsort(&vars);
-//     vars.sort();
-//     Dv8Matrix::new(vars, to_cells(graph.edges, indices))
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn test() {
-//         let mut graph = Dv8Graph::new();
-//         let tgt = graph.insert_var("src/Provider.java".to_owned());
-//         let src = graph.insert_var("src/Client.java".to_owned());
-//         graph.insert_dep("/kythe/edge/ref/call".to_owned(), src, tgt);
-
-//         let mut matrix = Dv8Matrix::from(graph);
-//         matrix.set_name("my-test".to_owned());
-
-//         let serialized = serde_json::to_string_pretty(&matrix).unwrap();
-//         println!("{}", serialized);
-//     }
-// }
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::io::escape_xml;
+use crate::ir::{Dep, EdgeKind, Entity, GroupBy, NodeIndex, NodeKind, UnknownEdgeKind};
+use crate::sink::OutputSink;
+
+/// A DSM (Design Structure Matrix) in the JSON format used by DV8
+/// (https://archdia.com/).
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct Dv8Matrix {
+    #[serde(rename = "schemaVersion")]
+    schema_version: &'static str,
+
+    #[serde(rename = "name")]
+    name: Option<String>,
+
+    #[serde(rename = "variables")]
+    vars: Vec<String>,
+
+    #[serde(rename = "cells")]
+    cells: Vec<Dv8Cell>,
+}
+
+impl Dv8Matrix {
+    fn new(vars: Vec<String>, cells: Vec<Dv8Cell>) -> Self {
+        Self { schema_version: "1.0", name: None, vars, cells }
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    pub fn num_vars(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// The total number of deps (of any kind) from each variable to each
+    /// other variable, by index.
+    pub fn weights(&self) -> BTreeMap<(usize, usize), usize> {
+        let weights =
+            self.cells.iter().map(|cell| ((cell.src, cell.tgt), cell.values.values().sum()));
+        weights.collect()
+    }
+
+    /// Move the variables so that the variable at index `order[i]` is now at
+    /// index `i`. `order` must be a permutation of the indices of the
+    /// variables.
+    pub fn reorder(&mut self, order: &[usize]) {
+        assert_eq!(order.len(), self.vars.len(), "order must include every variable");
+        let mut position = vec![0; order.len()];
+
+        for (i, old) in order.iter().enumerate() {
+            position[*old] = i;
+        }
+
+        let vars = order.iter().map(|old| std::mem::take(&mut self.vars[*old])).collect();
+        self.vars = vars;
+
+        for cell in &mut self.cells {
+            cell.src = position[cell.src];
+            cell.tgt = position[cell.tgt];
+        }
+
+        self.cells.sort_by_key(|cell| (cell.src, cell.tgt));
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W, format: MatrixFormat) -> io::Result<()> {
+        match format {
+            MatrixFormat::Dv8 => serde_json::to_writer_pretty(writer, self)?,
+            MatrixFormat::Dense(delimiter) => self.write_dense(writer, delimiter)?,
+            MatrixFormat::Sparse(delimiter) => self.write_sparse(writer, delimiter)?,
+            MatrixFormat::Ldi => self.write_ldi(&mut writer)?,
+            MatrixFormat::Structure101 => self.write_structure101(&mut writer)?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the total number of deps between each pair of variables as a
+    /// dense table. The first row and the first column hold the names of the
+    /// variables, so each row depends on the columns with non-zero counts.
+    pub fn write_dense<W: Write>(&self, writer: W, delimiter: u8) -> csv::Result<()> {
+        let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+        let weights = self.weights();
+        writer.write_record(std::iter::once("").chain(self.vars.iter().map(String::as_str)))?;
+
+        for (src, var) in self.vars.iter().enumerate() {
+            let counts = (0..self.vars.len())
+                .map(|tgt| weights.get(&(src, tgt)).copied().unwrap_or_default().to_string());
+            writer.write_record(std::iter::once(var.clone()).chain(counts))?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write one row per non-empty cell with the names of its variables and
+    /// the total number of deps between them (i.e. as triplets).
+    pub fn write_sparse<W: Write>(&self, writer: W, delimiter: u8) -> csv::Result<()> {
+        let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+        writer.write_record(["src", "dest", "count"])?;
+
+        for ((src, tgt), count) in self.weights() {
+            let count = count.to_string();
+            writer.write_record([&self.vars[src], &self.vars[tgt], &count])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the matrix as Lattix LDI XML. Each variable is an element, and
+    /// each dependency kind of each cell is a `uses` of the dest variable
+    /// whose strength is the number of deps of that kind.
+    pub fn write_ldi<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, "<ldi>")?;
+        let mut cells = self.cells.iter().peekable();
+
+        for (i, var) in self.vars.iter().enumerate() {
+            writeln!(writer, r#"  <element name="{}" type="module">"#, escape_xml(var))?;
+
+            while let Some(cell) = cells.next_if(|cell| cell.src == i) {
+                for (kind, count) in &cell.values {
+                    writeln!(
+                        writer,
+                        r#"    <uses provider="{}" kind="{}" strength="{}"/>"#,
+                        escape_xml(&self.vars[cell.tgt]),
+                        escape_xml(kind),
+                        count
+                    )?;
+                }
+            }
+
+            writeln!(writer, "  </element>")?;
+        }
+
+        writeln!(writer, "</ldi>")
+    }
+
+    /// Write the matrix in Structure101's generic XML flavor. Each variable
+    /// is a module (with its index as its id), and each dependency kind of
+    /// each cell is a dependency of that type.
+    pub fn write_structure101<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, r#"<data flavor="com.headway.examples.generic">"#)?;
+        writeln!(writer, "  <modules>")?;
+
+        for (i, var) in self.vars.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"    <module id="{}" type="module" name="{}"/>"#,
+                i,
+                escape_xml(var)
+            )?;
+        }
+
+        writeln!(writer, "  </modules>")?;
+        writeln!(writer, "  <dependencies>")?;
+
+        for cell in &self.cells {
+            for (kind, count) in &cell.values {
+                writeln!(
+                    writer,
+                    r#"    <dependency from="{}" to="{}" type="{}" weight="{}"/>"#,
+                    cell.src,
+                    cell.tgt,
+                    escape_xml(kind),
+                    count
+                )?;
+            }
+        }
+
+        writeln!(writer, "  </dependencies>")?;
+        writeln!(writer, "</data>")
+    }
+}
+
+impl Dv8Matrix {
+    /// Build a matrix from the number of dependencies of each kind between
+    /// pairs of files. Only files which appear in some pair become variables.
+    pub fn from_pairs<'a, I>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a str, &'static str, usize)>,
+    {
+        let pairs = pairs.into_iter().filter(|(src, tgt, _, _)| src != tgt).collect_vec();
+        let vars = pairs
+            .iter()
+            .flat_map(|(src, tgt, _, _)| [src.to_string(), tgt.to_string()])
+            .sorted()
+            .dedup()
+            .collect_vec();
+        let indices: HashMap<&str, usize> =
+            vars.iter().enumerate().map(|(i, v)| (v.as_str(), i)).collect();
+
+        let mut pair_map: BTreeMap<(usize, usize), BTreeMap<&'static str, usize>> = BTreeMap::new();
+
+        for (src, tgt, kind, count) in pairs {
+            let key = (indices[src], indices[tgt]);
+            *pair_map.entry(key).or_default().entry(kind).or_default() += count;
+        }
+
+        let cells = pair_map
+            .into_iter()
+            .map(|((src, tgt), values)| Dv8Cell::new(src, tgt, values))
+            .collect_vec();
+
+        Dv8Matrix::new(vars, cells)
+    }
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct Dv8Cell {
+    #[serde(rename = "src")]
+    src: usize,
+
+    #[serde(rename = "dest")]
+    tgt: usize,
+
+    #[serde(rename = "values")]
+    values: BTreeMap<&'static str, usize>,
+}
+
+impl Dv8Cell {
+    fn new(src: usize, tgt: usize, values: BTreeMap<&'static str, usize>) -> Self {
+        Self { src, tgt, values }
+    }
+}
+
+/// A hierarchical clustering of the variables of a DSM in the JSON format used
+/// by DV8 for `.clsx` files.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct Dv8Clustering {
+    #[serde(rename = "@schemaVersion")]
+    schema_version: &'static str,
+
+    #[serde(rename = "name", skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+
+    #[serde(rename = "structure")]
+    structure: Vec<Dv8Node>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "@type", rename_all = "lowercase")]
+pub enum Dv8Node {
+    Group { name: String, nested: Vec<Dv8Node> },
+    Item { name: String },
+}
+
+impl Dv8Clustering {
+    pub fn new(name: Option<String>, structure: Vec<Dv8Node>) -> Self {
+        Self { schema_version: "1.0", name, structure }
+    }
+
+    /// Cluster variables named by path into one group per directory, nested
+    /// as the directories are. Groups and items are named as by
+    /// `dotted_name`, to match a matrix written by `Dv8Sink::dotted`.
+    pub fn by_directory(name: Option<String>, vars: &[String]) -> Self {
+        let mut root = DirTree::default();
+
+        for var in vars {
+            let mut segments = var.split('/').collect_vec();
+            segments.pop();
+            let dir = segments
+                .into_iter()
+                .fold(&mut root, |dir, segment| dir.dirs.entry(segment).or_default());
+            dir.items.push(var);
+        }
+
+        Self::new(name, root.into_nodes(""))
+    }
+}
+
+/// The variables within a directory, and its subdirectories.
+#[derive(Default)]
+struct DirTree<'a> {
+    dirs: BTreeMap<&'a str, DirTree<'a>>,
+    items: Vec<&'a str>,
+}
+
+impl DirTree<'_> {
+    fn into_nodes(self, prefix: &str) -> Vec<Dv8Node> {
+        let groups = self.dirs.into_iter().map(|(segment, dir)| {
+            let name = match prefix {
+                "" => segment.to_string(),
+                _ => format!("{}.{}", prefix, segment),
+            };
+            let nested = dir.into_nodes(&name);
+            Dv8Node::Group { name, nested }
+        });
+        let items = self.items.into_iter().map(|var| Dv8Node::Item { name: dotted_name(var) });
+        groups.chain(items).collect()
+    }
+}
+
+/// Name a variable by its path with each "/" replaced by "." (e.g.
+/// `src.module.File.java`), which DV8 shows as a hierarchy.
+pub fn dotted_name(path: &str) -> String {
+    path.replace('/', ".")
+}
+
+pub fn to_dv8_edge_kind(edge_kind: &EdgeKind) -> Option<&'static str> {
+    match edge_kind {
+        EdgeKind::Ref => Some("Use"),
+        EdgeKind::RefCall => Some("Call"),
+        EdgeKind::RefCallImplicit => Some("Call"),
+        EdgeKind::RefExpands => Some("Use"),
+        EdgeKind::RefInit => Some("Create"),
+        EdgeKind::RefInitImplicit => Some("Create"),
+        EdgeKind::RefId => Some("Use"),
+        EdgeKind::RefImplicit => Some("Use"),
+        EdgeKind::RefIncludes => Some("Include"),
+        EdgeKind::RefQueries => Some("Use"),
+        EdgeKind::ExtendsPrivate => Some("Extend"),
+        EdgeKind::ExtendsProtected => Some("Extend"),
+        EdgeKind::ExtendsPublic => Some("Extend"),
+        EdgeKind::ExtendsPublicVirtual => Some("Extend"),
+        EdgeKind::Overrides => Some("ImplLink"),
+        EdgeKind::OverridesRoot => Some("ImplLink"),
+        EdgeKind::Undefines => Some("Use"),
+        EdgeKind::Childof => Some("Contain"),
+        EdgeKind::ChildofContext => Some("Contain"),
+        EdgeKind::Param(_) => Some("Parameter"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MatrixDiffErr {
+    #[error("failed to read matrix")]
+    Io(#[from] io::Error),
+    #[error("not a DV8 matrix")]
+    Json(#[from] serde_json::Error),
+    #[error("found cell ({0}, {1}) outside of a matrix of {2} variable(s)")]
+    OutOfBounds(usize, usize, usize),
+}
+
+type MatrixDiffRes<T> = Result<T, MatrixDiffErr>;
+
+/// The parts of a DV8 matrix file needed to compare it with another. Values
+/// are read as floats since DV8 itself may write them that way.
+#[derive(serde::Deserialize)]
+struct Dv8MatrixFile {
+    variables: Vec<String>,
+    cells: Vec<Dv8CellFile>,
+}
+
+#[derive(serde::Deserialize)]
+struct Dv8CellFile {
+    src: usize,
+    dest: usize,
+    values: BTreeMap<String, f64>,
+}
+
+/// The total weight of each cell of a matrix, keyed by the names of its
+/// variables.
+type NamedWeights = BTreeMap<(String, String), usize>;
+
+/// The change in the total weight of each cell between two DSMs of the same
+/// project (e.g. before and after a refactoring), with variables matched by
+/// name. Variables from either matrix are kept, even if all of their cells
+/// are unchanged.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MatrixDiff {
+    vars: Vec<String>,
+    /// The weight before and after of each cell which changed.
+    cells: BTreeMap<(usize, usize), (usize, usize)>,
+}
+
+impl MatrixDiff {
+    pub fn new(before: &Dv8Matrix, after: &Dv8Matrix) -> Self {
+        let named = |matrix: &Dv8Matrix| -> NamedWeights {
+            let weights = matrix.weights().into_iter().map(|((src, tgt), weight)| {
+                ((matrix.vars[src].clone(), matrix.vars[tgt].clone()), weight)
+            });
+            weights.collect()
+        };
+
+        Self::from_named(
+            before.vars.iter().chain(&after.vars).cloned().collect(),
+            named(before),
+            named(after),
+        )
+    }
+
+    /// Compare two DV8 matrix files, e.g. as written by `Dv8Sink`.
+    pub fn read(before: &Path, after: &Path) -> MatrixDiffRes<Self> {
+        let (before_vars, before) = read_named_weights(before)?;
+        let (after_vars, after) = read_named_weights(after)?;
+        let vars = before_vars.into_iter().chain(after_vars).collect();
+        Ok(Self::from_named(vars, before, after))
+    }
+
+    fn from_named(vars: Vec<String>, before: NamedWeights, after: NamedWeights) -> Self {
+        let vars = vars.into_iter().sorted().dedup().collect_vec();
+        let indices: HashMap<&str, usize> =
+            vars.iter().enumerate().map(|(i, v)| (v.as_str(), i)).collect();
+        let mut cells = BTreeMap::new();
+
+        for (src, tgt) in before.keys().chain(after.keys()) {
+            let weights = (
+                before.get(&(src.clone(), tgt.clone())).copied().unwrap_or_default(),
+                after.get(&(src.clone(), tgt.clone())).copied().unwrap_or_default(),
+            );
+
+            if weights.0 != weights.1 {
+                cells.insert((indices[src.as_str()], indices[tgt.as_str()]), weights);
+            }
+        }
+
+        Self { vars, cells }
+    }
+
+    /// The number of cells whose weight changed.
+    pub fn num_changed(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// A matrix with a single value per changed cell, for viewing in DV8:
+    /// "Added" or "Removed" with the weight of a cell which only appears in
+    /// one matrix, or otherwise "Increased" or "Decreased" by how much the
+    /// weight changed.
+    pub fn to_matrix(&self) -> Dv8Matrix {
+        let cells = self
+            .cells
+            .iter()
+            .map(|(&(src, tgt), &(before, after))| {
+                let value = match (before, after) {
+                    (0, after) => ("Added", after),
+                    (before, 0) => ("Removed", before),
+                    (before, after) if after > before => ("Increased", after - before),
+                    (before, after) => ("Decreased", before - after),
+                };
+                Dv8Cell::new(src, tgt, BTreeMap::from([value]))
+            })
+            .collect();
+
+        Dv8Matrix::new(self.vars.clone(), cells)
+    }
+
+    /// Move the variables as in `Dv8Matrix::reorder`.
+    pub fn reorder(&mut self, order: &[usize]) {
+        assert_eq!(order.len(), self.vars.len(), "order must include every variable");
+        let mut position = vec![0; order.len()];
+
+        for (i, old) in order.iter().enumerate() {
+            position[*old] = i;
+        }
+
+        let vars = order.iter().map(|old| std::mem::take(&mut self.vars[*old])).collect();
+        self.vars = vars;
+        let cells = std::mem::take(&mut self.cells).into_iter();
+        self.cells =
+            cells.map(|((src, tgt), cell)| ((position[src], position[tgt]), cell)).collect();
+    }
+
+    /// Write the change in weight (after minus before) of each cell as a dense
+    /// table, laid out as in `Dv8Matrix::write_dense`.
+    pub fn write_dense<W: Write>(&self, writer: W, delimiter: u8) -> csv::Result<()> {
+        let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+        writer.write_record(std::iter::once("").chain(self.vars.iter().map(String::as_str)))?;
+
+        for (src, var) in self.vars.iter().enumerate() {
+            let changes = (0..self.vars.len()).map(|tgt| match self.cells.get(&(src, tgt)) {
+                Some(&(before, after)) => (after as i64 - before as i64).to_string(),
+                None => "0".to_string(),
+            });
+            writer.write_record(std::iter::once(var.clone()).chain(changes))?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write one row per changed cell with the names of its variables, its
+    /// weight before and after, and the change in weight.
+    pub fn write_sparse<W: Write>(&self, writer: W, delimiter: u8) -> csv::Result<()> {
+        let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(writer);
+        writer.write_record(["src", "dest", "before", "after", "change"])?;
+
+        for (&(src, tgt), &(before, after)) in &self.cells {
+            writer.write_record([
+                self.vars[src].clone(),
+                self.vars[tgt].clone(),
+                before.to_string(),
+                after.to_string(),
+                (after as i64 - before as i64).to_string(),
+            ])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the variables of a DV8 matrix file and the total weight of each of its
+/// cells.
+fn read_named_weights(path: &Path) -> MatrixDiffRes<(Vec<String>, NamedWeights)> {
+    let file: Dv8MatrixFile = serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
+    let num_vars = file.variables.len();
+    let mut weights = NamedWeights::new();
+
+    for cell in file.cells {
+        let (src, tgt) = match (file.variables.get(cell.src), file.variables.get(cell.dest)) {
+            (Some(src), Some(tgt)) => (src.clone(), tgt.clone()),
+            _ => return Err(MatrixDiffErr::OutOfBounds(cell.src, cell.dest, num_vars)),
+        };
+        let weight = cell.values.values().sum::<f64>().round() as usize;
+        *weights.entry((src, tgt)).or_default() += weight;
+    }
+
+    Ok((file.variables, weights))
+}
+
+#[derive(Debug, Error)]
+pub enum KindMapErr {
+    #[error("failed to read kind map")]
+    Io(#[from] io::Error),
+    #[error("kind map must be a JSON object of edge kinds to dependency kinds (or null)")]
+    Json(#[from] serde_json::Error),
+    #[error("found invalid edge kind in kind map, \"{0}\"")]
+    InvalidEdgeKind(String),
+}
+
+type KindMapRes<T> = Result<T, KindMapErr>;
+
+/// How a `Dv8Sink` writes its matrix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatrixFormat {
+    /// DV8's JSON format.
+    #[default]
+    Dv8,
+    /// A dense table (see `Dv8Matrix::write_dense`) with the given delimiter.
+    Dense(u8),
+    /// A sparse table (see `Dv8Matrix::write_sparse`) with the given
+    /// delimiter.
+    Sparse(u8),
+    /// Lattix's LDI XML (see `Dv8Matrix::write_ldi`).
+    Ldi,
+    /// Structure101's generic XML (see `Dv8Matrix::write_structure101`).
+    Structure101,
+}
+
+/// Which DV8 dependency kind (if any) each edge kind becomes. Any edge kind
+/// which is not overridden keeps its mapping from `to_dv8_edge_kind`.
+#[derive(Clone, Debug, Default)]
+pub struct KindMap {
+    overrides: HashMap<EdgeKind, Option<&'static str>>,
+    /// Overrides every `Param` edge (unless overridden individually).
+    params: Option<Option<&'static str>>,
+}
+
+impl KindMap {
+    /// Read a kind map from a JSON object whose keys are edge kinds and whose
+    /// values are the DV8 dependency kinds they become, or null to drop them.
+    /// For example:
+    ///
+    /// ```text
+    /// {
+    ///     "ref/includes": "Use",
+    ///     "typed": "Type",
+    ///     "childof": null
+    /// }
+    /// ```
+    ///
+    /// Edge kinds may be given with or without the "/kythe/edge/" prefix. Use
+    /// "param" for every `param.N` edge. Edge kinds Kythe does not define (e.g.
+    /// "/kythe/edge/imports") are allowed, and only match when loading
+    /// leniently.
+    pub fn read(path: &Path) -> KindMapRes<Self> {
+        KindMap::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> KindMapRes<Self> {
+        let entries: BTreeMap<String, Option<String>> = serde_json::from_str(text)?;
+        let mut map = KindMap::default();
+
+        for (name, kind) in entries {
+            // Only a handful of names, and they are needed for as long as the
+            // sink is, so leak them like `UnknownEdgeKind` does
+            let kind = kind.map(|kind| -> &'static str { Box::leak(kind.into_boxed_str()) });
+            let name = match name.starts_with('/') {
+                true => name,
+                false => format!("/kythe/edge/{}", name),
+            };
+
+            if name == "/kythe/edge/param" {
+                map.params = Some(kind);
+                continue;
+            }
+
+            let edge_kind = match EdgeKind::try_from(name.as_str()) {
+                Ok(edge_kind) => edge_kind,
+                Err(_) if name.starts_with("/kythe/edge/param.") => {
+                    return Err(KindMapErr::InvalidEdgeKind(name))
+                }
+                Err(_) => EdgeKind::Other(UnknownEdgeKind::intern(&name)),
+            };
+
+            map.overrides.insert(edge_kind, kind);
+        }
+
+        Ok(map)
+    }
+
+    pub fn get(&self, edge_kind: &EdgeKind) -> Option<&'static str> {
+        match (self.overrides.get(edge_kind), edge_kind, self.params) {
+            (Some(kind), _, _) => *kind,
+            (None, EdgeKind::Param(_), Some(kind)) => kind,
+            (None, _, _) => to_dv8_edge_kind(edge_kind),
+        }
+    }
+}
+
+/// What each variable of a DSM stands for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Granularity {
+    /// The file, package, or directory of each entity (see `GroupBy`).
+    Group(GroupBy),
+    /// Each entity on its own. Variables are named by the path of the file
+    /// the entity is in followed by the names of its `childof` ancestors,
+    /// separated by slashes (e.g. `src/a.cc/Foo/bar`), so that DV8 can nest
+    /// them.
+    Entity,
+}
+
+/// Enough of an entity to give it a nested name.
+struct NestedNode {
+    parent: Option<NodeIndex>,
+    name: String,
+    path: String,
+    is_file: bool,
+}
+
+/// Collects entities and deps and then writes them as a single `Dv8Matrix`
+/// (pretty-printed, unless another `MatrixFormat` is given) once finished.
+/// Each variable of the matrix is a file, a package, a directory, or an
+/// entity, depending on `granularity`.
+pub struct Dv8Sink<W: Write> {
+    writer: W,
+    name: Option<String>,
+    granularity: Granularity,
+    kinds: KindMap,
+    groups: HashMap<NodeIndex, String>,
+    nodes: HashMap<NodeIndex, NestedNode>,
+    deps: Vec<(NodeIndex, NodeIndex, &'static str, usize)>,
+    order: Option<Box<dyn Fn(&Dv8Matrix) -> Vec<usize>>>,
+    format: MatrixFormat,
+    dotted: bool,
+    clsx: Option<Box<dyn Write>>,
+}
+
+impl<W: Write> Dv8Sink<W> {
+    pub fn new(writer: W, name: Option<String>, group_by: GroupBy) -> Self {
+        Self::with_granularity(writer, name, Granularity::Group(group_by))
+    }
+
+    pub fn with_granularity(writer: W, name: Option<String>, granularity: Granularity) -> Self {
+        Self {
+            writer,
+            name,
+            granularity,
+            kinds: KindMap::default(),
+            groups: HashMap::new(),
+            nodes: HashMap::new(),
+            deps: Vec::new(),
+            order: None,
+            format: MatrixFormat::default(),
+            dotted: false,
+            clsx: None,
+        }
+    }
+
+    /// Map edge kinds to dependency kinds with `kinds` rather than
+    /// `to_dv8_edge_kind` alone.
+    pub fn kind_map(mut self, kinds: KindMap) -> Self {
+        self.kinds = kinds;
+        self
+    }
+
+    /// Reorder the variables of the matrix (see `Dv8Matrix::reorder`) before
+    /// writing it. Otherwise they are in order of name.
+    pub fn order_by<F: Fn(&Dv8Matrix) -> Vec<usize> + 'static>(mut self, order: F) -> Self {
+        self.order = Some(Box::new(order));
+        self
+    }
+
+    /// Write the matrix as a plain table (e.g. for pandas or R) or in the
+    /// format of another tool rather than in DV8's format.
+    pub fn format(mut self, format: MatrixFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Name the variables as by `dotted_name` so that DV8 shows them grouped
+    /// by directory. If `clsx` is given, also write a matching clustering of
+    /// the variables by directory to it (see `Dv8Clustering::by_directory`).
+    pub fn dotted(mut self, clsx: Option<Box<dyn Write>>) -> Self {
+        self.dotted = true;
+        self.clsx = clsx;
+        self
+    }
+
+    fn to_matrix(&self) -> Dv8Matrix {
+        let groups = match self.granularity {
+            Granularity::Group(_) => Cow::Borrowed(&self.groups),
+            Granularity::Entity => Cow::Owned(nested_names(&self.nodes)),
+        };
+        let vars = groups.values().cloned().sorted().dedup().collect_vec();
+        let indices: HashMap<&String, usize> =
+            vars.iter().enumerate().map(|(i, v)| (v, i)).collect();
+        let group_of = |id: &NodeIndex| groups.get(id).map(|group| indices[group]);
+
+        let mut pair_map: BTreeMap<(usize, usize), BTreeMap<&'static str, usize>> = BTreeMap::new();
+
+        for &(src, tgt, kind, count) in &self.deps {
+            if let (Some(src), Some(tgt)) = (group_of(&src), group_of(&tgt)) {
+                if src != tgt {
+                    *pair_map.entry((src, tgt)).or_default().entry(kind).or_default() += count;
+                }
+            }
+        }
+
+        let cells = pair_map
+            .into_iter()
+            .map(|((src, tgt), values)| Dv8Cell::new(src, tgt, values))
+            .collect_vec();
+
+        let mut matrix = Dv8Matrix::new(vars, cells);
+
+        if let Some(name) = &self.name {
+            matrix.set_name(name.clone());
+        }
+
+        if let Some(order) = &self.order {
+            let order = order(&matrix);
+            matrix.reorder(&order);
+        }
+
+        matrix
+    }
+}
+
+impl<W: Write> OutputSink for Dv8Sink<W> {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+        match self.granularity {
+            Granularity::Group(group_by) => {
+                self.groups.insert(entity.id, group_by.key(entity).to_string());
+            }
+            Granularity::Entity => {
+                let node = NestedNode {
+                    parent: entity.parent_ids.iter().exactly_one().ok().copied(),
+                    name: entity.name.clone(),
+                    path: entity.path.clone(),
+                    is_file: matches!(entity.kind, NodeKind::File(_)),
+                };
+                self.nodes.insert(entity.id, node);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+        if let Some(kind) = self.kinds.get(&dep.kind) {
+            // Otherwise a child would appear to contain its parent
+            let (_, src, tgt) = dep.normalized();
+            self.deps.push((src, tgt, kind, dep.count));
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut matrix = self.to_matrix();
+
+        if let Some(clsx) = &mut self.clsx {
+            let clustering = Dv8Clustering::by_directory(self.name.clone(), &matrix.vars);
+            serde_json::to_writer_pretty(&mut *clsx, &clustering)?;
+            clsx.flush()?;
+        }
+
+        if self.dotted {
+            matrix.vars = matrix.vars.iter().map(|var| dotted_name(var)).collect();
+        }
+
+        matrix.write(&mut self.writer, self.format)?;
+        self.writer.flush()
+    }
+}
+
+/// Name each node by its path followed by the names of its ancestors, from
+/// the outermost in. An entity with several parents (or whose parent was not
+/// written) is named as if it were at the top of its file. Entities which
+/// would share a name (e.g. overloads) are told apart by a numeric suffix.
+fn nested_names(nodes: &HashMap<NodeIndex, NestedNode>) -> HashMap<NodeIndex, String> {
+    let mut by_name: BTreeMap<String, Vec<NodeIndex>> = BTreeMap::new();
+
+    for id in nodes.keys() {
+        by_name.entry(nested_name(nodes, *id)).or_default().push(*id);
+    }
+
+    let mut names = HashMap::new();
+
+    for (name, mut ids) in by_name {
+        ids.sort();
+
+        for (i, id) in ids.into_iter().enumerate() {
+            match i {
+                0 => names.insert(id, name.clone()),
+                _ => names.insert(id, format!("{} ({})", name, i + 1)),
+            };
+        }
+    }
+
+    names
+}
+
+fn nested_name(nodes: &HashMap<NodeIndex, NestedNode>, id: NodeIndex) -> String {
+    let mut segments = Vec::new();
+    let mut visited = HashSet::from([id]);
+    let mut node = &nodes[&id];
+
+    loop {
+        if node.is_file {
+            segments.push(node.path.as_str());
+            break;
+        }
+
+        segments.push(node.name.as_str());
+
+        match node.parent.filter(|parent| visited.insert(*parent)) {
+            Some(parent) if nodes.contains_key(&parent) => node = &nodes[&parent],
+            _ => {
+                if !node.path.is_empty() {
+                    segments.push(node.path.as_str());
+                }
+                break;
+            }
+        }
+    }
+
+    segments.reverse();
+    segments.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matrix_json() {
+        let mut matrix =
+            Dv8Matrix::from_pairs([("b.cc", "a.h", "Call", 2), ("b.cc", "a.h", "Use", 1)]);
+        matrix.set_name("test".to_string());
+
+        assert_eq!(
+            serde_json::to_value(&matrix).unwrap(),
+            serde_json::json!({
+                "schemaVersion": "1.0",
+                "name": "test",
+                "variables": ["a.h", "b.cc"],
+                "cells": [{ "src": 1, "dest": 0, "values": { "Call": 2, "Use": 1 } }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut matrix = Dv8Matrix::from_pairs([("a", "b", "Use", 1), ("c", "a", "Call", 2)]);
+        matrix.reorder(&[2, 0, 1]);
+
+        assert_eq!(matrix.vars, vec!["c", "a", "b"]);
+        assert_eq!(matrix.weights(), BTreeMap::from([((0, 1), 2), ((1, 2), 1)]));
+    }
+
+    #[test]
+    fn test_tables() {
+        let matrix = Dv8Matrix::from_pairs([("a", "b", "Use", 1), ("a", "b", "Call", 2)]);
+
+        let mut dense = Vec::new();
+        matrix.write_dense(&mut dense, b'\t').unwrap();
+        assert_eq!(String::from_utf8(dense).unwrap(), "\ta\tb\na\t0\t3\nb\t0\t0\n");
+
+        let mut sparse = Vec::new();
+        matrix.write_sparse(&mut sparse, b',').unwrap();
+        assert_eq!(String::from_utf8(sparse).unwrap(), "src,dest,count\na,b,3\n");
+    }
+
+    #[test]
+    fn test_xml() {
+        let matrix = Dv8Matrix::from_pairs([("a<b>", "c", "Use", 1), ("a<b>", "c", "Call", 2)]);
+
+        let mut ldi = Vec::new();
+        matrix.write_ldi(&mut ldi).unwrap();
+        let ldi = String::from_utf8(ldi).unwrap();
+        assert!(ldi.contains(r#"<element name="a&lt;b&gt;" type="module">"#));
+        assert!(ldi.contains(r#"<uses provider="c" kind="Call" strength="2"/>"#));
+        assert!(ldi.contains(r#"<uses provider="c" kind="Use" strength="1"/>"#));
+
+        let mut s101 = Vec::new();
+        matrix.write_structure101(&mut s101).unwrap();
+        let s101 = String::from_utf8(s101).unwrap();
+        assert!(s101.contains(r#"<module id="0" type="module" name="a&lt;b&gt;"/>"#));
+        assert!(s101.contains(r#"<dependency from="0" to="1" type="Call" weight="2"/>"#));
+    }
+
+    #[test]
+    fn test_matrix_diff() {
+        let before = Dv8Matrix::from_pairs([("a", "b", "Use", 2), ("b", "c", "Use", 1)]);
+        let after = Dv8Matrix::from_pairs([("a", "b", "Call", 3), ("c", "d", "Use", 1)]);
+        let diff = MatrixDiff::new(&before, &after);
+
+        assert_eq!(diff.num_changed(), 3);
+        assert_eq!(
+            serde_json::to_value(diff.to_matrix()).unwrap()["cells"],
+            serde_json::json!([
+                { "src": 0, "dest": 1, "values": { "Increased": 1 } },
+                { "src": 1, "dest": 2, "values": { "Removed": 1 } },
+                { "src": 2, "dest": 3, "values": { "Added": 1 } },
+            ])
+        );
+
+        let mut sparse = Vec::new();
+        diff.write_sparse(&mut sparse, b',').unwrap();
+        assert_eq!(
+            String::from_utf8(sparse).unwrap(),
+            "src,dest,before,after,change\na,b,2,3,1\nb,c,1,0,-1\nc,d,0,1,1\n"
+        );
+    }
+
+    #[test]
+    fn test_clustering_by_directory() {
+        let vars = ["src/a/x.java", "src/a/y.java", "src/b.java", "README"];
+        let vars = vars.iter().map(|var| var.to_string()).collect_vec();
+        let clustering = Dv8Clustering::by_directory(None, &vars);
+
+        assert_eq!(
+            serde_json::to_value(&clustering).unwrap()["structure"],
+            serde_json::json!([
+                {
+                    "@type": "group",
+                    "name": "src",
+                    "nested": [
+                        {
+                            "@type": "group",
+                            "name": "src.a",
+                            "nested": [
+                                { "@type": "item", "name": "src.a.x.java" },
+                                { "@type": "item", "name": "src.a.y.java" },
+                            ],
+                        },
+                        { "@type": "item", "name": "src.b.java" },
+                    ],
+                },
+                { "@type": "item", "name": "README" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_kind_map() {
+        let text = r#"{
+            "ref/includes": "Use",
+            "/kythe/edge/typed": "Type",
+            "childof": null,
+            "param": "Use"
+        }"#;
+        let kinds = KindMap::parse(text).unwrap();
+
+        assert_eq!(kinds.get(&EdgeKind::RefIncludes), Some("Use"));
+        assert_eq!(kinds.get(&EdgeKind::Typed), Some("Type"));
+        assert_eq!(kinds.get(&EdgeKind::Childof), None);
+        assert_eq!(kinds.get(&EdgeKind::Param(2)), Some("Use"));
+        assert_eq!(kinds.get(&EdgeKind::RefCall), Some("Call"));
+        assert!(KindMap::parse(r#"{"param.x": "Use"}"#).is_err());
+    }
+
+    #[test]
+    fn test_nested_names() {
+        let node = |parent: Option<usize>, name: &str, is_file| NestedNode {
+            parent: parent.map(NodeIndex),
+            name: name.to_string(),
+            path: "src/a.cc".to_string(),
+            is_file,
+        };
+        let nodes = HashMap::from([
+            (NodeIndex(0), node(None, "a.cc", true)),
+            (NodeIndex(1), node(Some(0), "Foo", false)),
+            (NodeIndex(2), node(Some(1), "bar", false)),
+            (NodeIndex(3), node(Some(1), "bar", false)),
+            (NodeIndex(4), node(None, "baz", false)),
+        ]);
+
+        let names = nested_names(&nodes);
+        let names = (0..5).map(|i| names[&NodeIndex(i)].as_str()).collect_vec();
+        assert_eq!(
+            names,
+            vec![
+                "src/a.cc",
+                "src/a.cc/Foo",
+                "src/a.cc/Foo/bar",
+                "src/a.cc/Foo/bar (2)",
+                "src/a.cc/baz"
+            ]
+        );
+    }
+}
@@ -0,0 +1,578 @@
+//! Rules for excluding entries from a stream.
+//!
+//! The `exclude` subcommand builds an [`ExclusionSet`] from its command line
+//! options, but any [`Exclusion`] or [`TicketExclusion`] may be registered, so
+//! project-specific rules can be added without touching the CLI. The same
+//! options may also be given as a rule file (see [`read_rule_file`]).
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::canonical::to_json_line;
+use crate::io::{EntryLineReader, EntryRef, TicketRef};
+use crate::paths::{dir_prefix, PathKind};
+
+/// An ordered collection of exclusion rules. An entry is excluded if any rule
+/// excludes it.
+#[derive(Debug, Default)]
+pub struct ExclusionSet {
+    rules: Vec<Box<dyn Exclusion>>,
+    reserialize: bool,
+}
+
+impl ExclusionSet {
+    pub fn new() -> Self {
+        Self { rules: Vec::new(), reserialize: false }
+    }
+
+    /// Write kept entries by re-serializing them (see `canonical`) rather than
+    /// by copying their lines. The output should be identical either way, so
+    /// this is a check that transformed entries will diff cleanly against
+    /// their originals.
+    pub fn reserialize(mut self, reserialize: bool) -> Self {
+        self.reserialize = reserialize;
+        self
+    }
+
+    fn write_entry<W: Write>(
+        &self,
+        writer: &mut W,
+        line: &str,
+        entry: &EntryRef,
+    ) -> std::io::Result<()> {
+        match self.reserialize {
+            true => writer.write_all(to_json_line(entry, Some(line)).as_bytes()),
+            false => writer.write_all(line.as_bytes()),
+        }
+    }
+
+    pub fn register(&mut self, rule: Box<dyn Exclusion>) {
+        self.rules.push(rule);
+    }
+
+    /// Register every rule of `other`.
+    pub fn extend(&mut self, other: ExclusionSet) {
+        self.rules.extend(other.rules);
+    }
+
+    /// Register a rule over tickets which is lifted to entries according to
+    /// `kind`.
+    pub fn register_ticket_rule(
+        &mut self,
+        kind: EdgeExclusionKind,
+        rule: Box<dyn TicketExclusion>,
+        keep_nodes: bool,
+    ) {
+        self.register(Box::new(TickedBasedExclusion::new(kind, rule, keep_nodes)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Exclusion> + '_ {
+        self.rules.iter().map(|rule| rule.as_ref())
+    }
+
+    pub fn is_excluded(&self, entry: &EntryRef) -> bool {
+        self.rules.iter().any(|rule| rule.is_excluded(entry))
+    }
+
+    /// Write every line of `reader` to `writer` unless its entry is excluded.
+    /// Returns the number of lines read and the number of lines excluded.
+    pub fn apply<W: Write>(
+        &self,
+        reader: EntryLineReader,
+        writer: &mut W,
+    ) -> std::io::Result<(u128, u128)> {
+        let mut num_lines = 0u128;
+        let mut num_excluded = 0u128;
+
+        reader.for_each_ref(|line, entry| {
+            num_lines += 1;
+
+            if self.is_excluded(entry) {
+                num_excluded += 1;
+                return Ok(());
+            }
+
+            self.write_entry(writer, line, entry)
+        })?;
+
+        Ok((num_lines, num_excluded))
+    }
+
+    /// Like `apply`, but also record which rules excluded which entries. Paths
+    /// are grouped into prefixes of at most `prefix_depth` directories.
+    pub fn apply_with_stats<W: Write>(
+        &self,
+        reader: EntryLineReader,
+        writer: &mut W,
+        prefix_depth: usize,
+    ) -> std::io::Result<ExclusionStats> {
+        let mut stats = ExclusionStats::new(self);
+
+        reader.for_each_ref(|line, entry| {
+            stats.num_entries += 1;
+            let (src, is_edge) = match entry {
+                EntryRef::Edge { src, .. } => (src, true),
+                EntryRef::Node { src, .. } => (src, false),
+            };
+
+            match self.rules.iter().position(|rule| rule.is_excluded(entry)) {
+                Some(index) => {
+                    stats.num_excluded += 1;
+                    stats.rules[index].excluded += 1;
+                    let prefix = path_prefix(src.path.as_deref(), prefix_depth);
+                    *stats.excluded_by_prefix.entry(prefix).or_default() += 1;
+                }
+                None => {
+                    let corpus = src.corpus.as_deref().unwrap_or("<none>");
+
+                    if !stats.kept_by_corpus.contains_key(corpus) {
+                        stats.kept_by_corpus.insert(corpus.to_string(), Composition::default());
+                    }
+
+                    let composition = stats.kept_by_corpus.get_mut(corpus).unwrap();
+
+                    match is_edge {
+                        true => composition.edges += 1,
+                        false => composition.nodes += 1,
+                    }
+
+                    self.write_entry(writer, line, entry)?;
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(stats)
+    }
+}
+
+/// A summary of what an `ExclusionSet` removed from (and left in) a stream.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ExclusionStats {
+    pub num_entries: u64,
+    pub num_excluded: u64,
+    /// Each entry is only counted against the first rule which excluded it.
+    pub rules: Vec<RuleStats>,
+    /// Excluded entries, grouped by the leading directories of their source.
+    pub excluded_by_prefix: BTreeMap<String, u64>,
+    /// Entries which were kept, grouped by the corpus of their source.
+    pub kept_by_corpus: BTreeMap<String, Composition>,
+}
+
+impl ExclusionStats {
+    fn new(rules: &ExclusionSet) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| RuleStats { rule: format!("{:?}", rule), excluded: 0 })
+            .collect();
+
+        Self { rules, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct RuleStats {
+    pub rule: String,
+    pub excluded: u64,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Composition {
+    pub nodes: u64,
+    pub edges: u64,
+}
+
+fn path_prefix(path: Option<&str>, depth: usize) -> String {
+    match path {
+        // Only consider directories, never the file name itself
+        Some(path) => dir_prefix(path, depth).to_string(),
+        None => "<none>".to_string(),
+    }
+}
+
+#[derive(Debug)]
+pub enum EdgeExclusionKind {
+    Any,
+    All,
+    Src,
+    Tgt,
+}
+
+/// More than one kind of exclusion was requested for the same rule.
+#[derive(Debug, Error)]
+#[error("expected at most one of {0} but found several")]
+pub struct ConflictingKinds(&'static str);
+
+impl EdgeExclusionKind {
+    pub fn from_bools(
+        any: bool,
+        all: bool,
+        src: bool,
+        tgt: bool,
+    ) -> Result<Option<Self>, ConflictingKinds> {
+        match (any, all, src, tgt) {
+            (false, false, false, false) => Ok(None),
+            (true, false, false, false) => Ok(Some(Self::Any)),
+            (false, true, false, false) => Ok(Some(Self::All)),
+            (false, false, true, false) => Ok(Some(Self::Src)),
+            (false, false, false, true) => Ok(Some(Self::Tgt)),
+            _ => Err(ConflictingKinds("any, all, src, or tgt")),
+        }
+    }
+}
+
+pub trait Exclusion: Debug + Send + Sync {
+    fn is_excluded(&self, entry: &EntryRef) -> bool;
+}
+
+#[derive(Debug)]
+pub enum FactExclusionKind {
+    Both,
+    Edge,
+    Node,
+}
+
+impl FactExclusionKind {
+    pub fn from_bools(
+        both: bool,
+        edge: bool,
+        node: bool,
+    ) -> Result<Option<Self>, ConflictingKinds> {
+        match (both, edge, node) {
+            (false, false, false) => Ok(None),
+            (true, false, false) => Ok(Some(Self::Both)),
+            (false, true, false) => Ok(Some(Self::Edge)),
+            (false, false, true) => Ok(Some(Self::Node)),
+            _ => Err(ConflictingKinds("both, edge, or node")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FactBasedExclusion {
+    kind: FactExclusionKind,
+    matcher: globset::GlobMatcher,
+}
+
+impl FactBasedExclusion {
+    pub fn new(kind: FactExclusionKind, matcher: globset::GlobMatcher) -> Self {
+        Self { kind, matcher }
+    }
+}
+
+impl Exclusion for FactBasedExclusion {
+    fn is_excluded(&self, entry: &EntryRef) -> bool {
+        match entry {
+            EntryRef::Edge { fact_name, .. } => match self.kind {
+                FactExclusionKind::Node => false,
+                _ => self.matcher.is_match(&**fact_name),
+            },
+            EntryRef::Node { fact_name, .. } => match self.kind {
+                FactExclusionKind::Edge => false,
+                _ => self.matcher.is_match(&**fact_name),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TickedBasedExclusion {
+    kind: EdgeExclusionKind,
+    ticket_rule: Box<dyn TicketExclusion>,
+    keep_nodes: bool,
+}
+
+impl TickedBasedExclusion {
+    pub fn new(
+        kind: EdgeExclusionKind,
+        ticket_rule: Box<dyn TicketExclusion>,
+        keep_nodes: bool,
+    ) -> Self {
+        Self {
+            kind,
+            ticket_rule,
+            keep_nodes,
+        }
+    }
+}
+
+impl Exclusion for TickedBasedExclusion {
+    fn is_excluded(&self, entry: &EntryRef) -> bool {
+        let is_excluded = |t: &TicketRef| self.ticket_rule.is_excluded(t);
+
+        match entry {
+            EntryRef::Edge { src, tgt, .. } => match self.kind {
+                EdgeExclusionKind::Any => is_excluded(src) || is_excluded(tgt),
+                EdgeExclusionKind::All => is_excluded(src) && is_excluded(tgt),
+                EdgeExclusionKind::Src => is_excluded(src),
+                EdgeExclusionKind::Tgt => is_excluded(tgt),
+            },
+            EntryRef::Node { src, .. } => match self.kind {
+                EdgeExclusionKind::Any => !self.keep_nodes && is_excluded(src),
+                _ => false,
+            },
+        }
+    }
+}
+
+pub trait TicketExclusion: Debug + Send + Sync {
+    fn is_excluded(&self, ticket: &TicketRef) -> bool;
+}
+
+#[derive(Debug)]
+pub struct PathKindBasedExclusion {
+    kind: PathKind,
+}
+
+impl PathKindBasedExclusion {
+    pub fn new(kind: PathKind) -> Self {
+        Self { kind }
+    }
+}
+
+impl TicketExclusion for PathKindBasedExclusion {
+    fn is_excluded(&self, ticket: &TicketRef) -> bool {
+        self.kind == PathKind::of(ticket.path.as_deref())
+    }
+}
+
+#[derive(Debug)]
+pub struct PathPatternBasedExclusion {
+    matcher: globset::GlobMatcher,
+}
+
+impl PathPatternBasedExclusion {
+    pub fn new(matcher: globset::GlobMatcher) -> Self {
+        Self { matcher }
+    }
+}
+
+impl TicketExclusion for PathPatternBasedExclusion {
+    fn is_excluded(&self, ticket: &TicketRef) -> bool {
+        match &ticket.path {
+            None => false,
+            Some(path) => !self.matcher.is_match(Path::new(&**path)),
+        }
+    }
+}
+
+pub struct PathListBasedExclusion {
+    paths: HashSet<String>,
+}
+
+impl Debug for PathListBasedExclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PathListBasedExclusion")
+            .field("paths", &self.paths.len())
+            .finish()
+    }
+}
+
+impl PathListBasedExclusion {
+    pub fn new(paths: impl Iterator<Item = String>) -> Self {
+        Self {
+            paths: paths.collect(),
+        }
+    }
+}
+
+impl TicketExclusion for PathListBasedExclusion {
+    fn is_excluded(&self, ticket: &TicketRef) -> bool {
+        match &ticket.path {
+            None => false,
+            Some(path) => !self.paths.contains(&**path),
+        }
+    }
+}
+
+/// Apply several sets of rules to `reader` in a single pass. Each line is
+/// written to the writer of every set which does not exclude it. Returns the
+/// number of lines read and the number of lines each set excluded.
+pub fn tee<W: Write>(
+    reader: EntryLineReader,
+    taps: &mut [(ExclusionSet, W)],
+) -> io::Result<(u128, Vec<u128>)> {
+    let mut num_lines = 0u128;
+    let mut num_excluded = vec![0u128; taps.len()];
+
+    reader.for_each_ref(|line, entry| {
+        num_lines += 1;
+
+        for ((rules, writer), num_excluded) in taps.iter_mut().zip(&mut num_excluded) {
+            match rules.is_excluded(entry) {
+                true => *num_excluded += 1,
+                false => rules.write_entry(writer, line, entry)?,
+            }
+        }
+
+        Ok(())
+    })?;
+
+    Ok((num_lines, num_excluded))
+}
+
+#[derive(Debug, Error)]
+pub enum RuleFileErr {
+    #[error("failed to read rule file")]
+    Io(#[from] io::Error),
+    #[error("unknown rule on line {0}: \"{1}\"")]
+    UnknownRule(usize, String),
+    #[error("rule on line {0} expects an argument")]
+    MissingArgument(usize),
+    #[error("invalid glob pattern on line {0}")]
+    InvalidGlob(usize, #[source] globset::Error),
+}
+
+/// Read a file of exclusion rules. Each line is one of the options of the
+/// `exclude` subcommand without its leading dashes, followed by its argument
+/// (if any). Blank lines and lines starting with `#` are ignored. For example:
+///
+/// ```text
+/// # Drop anything outside of the source tree
+/// if-any-nilpathed
+/// by-path src/**
+/// keep-nodes
+/// ```
+///
+/// Relative paths given to `by-pathlist` are relative to the rule file.
+pub fn read_rule_file(path: &Path) -> Result<ExclusionSet, RuleFileErr> {
+    let text = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_rules(&text, base)
+}
+
+/// Parse the contents of a rule file (see `read_rule_file`).
+pub fn parse_rules(text: &str, base: &Path) -> Result<ExclusionSet, RuleFileErr> {
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (i, name, Some(arg.trim())),
+            None => (i, line, None),
+        })
+        .collect::<Vec<_>>();
+
+    let keep_nodes = lines.iter().any(|(_, name, _)| *name == "keep-nodes");
+    let mut rules = ExclusionSet::new();
+
+    for (i, name, arg) in lines {
+        if name == "keep-nodes" {
+            continue;
+        }
+
+        if let Some((kind, path_kind)) = parse_path_kind_rule(name) {
+            let rule = Box::new(PathKindBasedExclusion::new(path_kind));
+            rules.register_ticket_rule(kind, rule, keep_nodes);
+            continue;
+        }
+
+        let arg = arg.ok_or(RuleFileErr::MissingArgument(i));
+
+        match name {
+            "by-path" => {
+                let glob = globset::Glob::new(arg?).map_err(|e| RuleFileErr::InvalidGlob(i, e))?;
+                let rule = Box::new(PathPatternBasedExclusion::new(glob.compile_matcher()));
+                rules.register_ticket_rule(EdgeExclusionKind::Any, rule, keep_nodes);
+            }
+            "by-pathlist" => {
+                let text = fs::read_to_string(base.join(arg?))?;
+                let rule = Box::new(PathListBasedExclusion::new(text.lines().map(String::from)));
+                rules.register_ticket_rule(EdgeExclusionKind::Any, rule, keep_nodes);
+            }
+            _ => return Err(RuleFileErr::UnknownRule(i, name.to_string())),
+        }
+    }
+
+    Ok(rules)
+}
+
+/// Parse a rule such as "if-src-abspathed" (or "if-abspathed", which is the
+/// same as "if-any-abspathed").
+fn parse_path_kind_rule(name: &str) -> Option<(EdgeExclusionKind, PathKind)> {
+    let name = name.strip_prefix("if-")?.strip_suffix("pathed")?;
+    let (kind, path_kind) = match name.split_once('-') {
+        Some((kind, path_kind)) => (kind, path_kind),
+        None => ("any", name),
+    };
+
+    let kind = match kind {
+        "any" => EdgeExclusionKind::Any,
+        "all" => EdgeExclusionKind::All,
+        "src" => EdgeExclusionKind::Src,
+        "tgt" => EdgeExclusionKind::Tgt,
+        _ => return None,
+    };
+
+    let path_kind = match path_kind {
+        "nil" => PathKind::NilPathed,
+        "abs" => PathKind::AbsPathed,
+        "rel" => PathKind::RelPathed,
+        _ => return None,
+    };
+
+    Some((kind, path_kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let text = "# comment\n\nif-nilpathed\nif-src-abspathed\nby-path src/**\nkeep-nodes\n";
+        let rules = parse_rules(text, Path::new("")).unwrap();
+        assert_eq!(rules.len(), 3);
+
+        assert!(matches!(
+            parse_rules("if-any-oddpathed", Path::new("")),
+            Err(RuleFileErr::UnknownRule(1, _))
+        ));
+        assert!(matches!(
+            parse_rules("\nby-path", Path::new("")),
+            Err(RuleFileErr::MissingArgument(2))
+        ));
+    }
+
+    #[test]
+    fn test_from_bools() {
+        assert!(matches!(EdgeExclusionKind::from_bools(false, false, true, false), Ok(Some(_))));
+        assert!(matches!(EdgeExclusionKind::from_bools(false, false, false, false), Ok(None)));
+        assert!(EdgeExclusionKind::from_bools(true, false, false, true).is_err());
+        assert!(FactExclusionKind::from_bools(true, true, false).is_err());
+    }
+
+    #[test]
+    fn test_tee() {
+        let text = concat!(
+            r#"{"source":{"path":"src/a.cc"},"fact_name":"/kythe/node/kind"}"#,
+            "\n",
+            r#"{"source":{"path":"lib/b.cc"},"fact_name":"/kythe/node/kind"}"#,
+            "\n",
+        );
+        let reader = EntryLineReader::from_read(io::Cursor::new(text));
+        let src_only = parse_rules("by-path src/**", Path::new("")).unwrap();
+        let mut taps = vec![(src_only, Vec::new()), (ExclusionSet::new(), Vec::new())];
+
+        let (num_lines, num_excluded) = tee(reader, &mut taps).unwrap();
+        assert_eq!((num_lines, num_excluded), (2, vec![1, 0]));
+        let first_line = format!("{}\n", text.lines().next().unwrap());
+        assert_eq!(taps[0].1, first_line.as_bytes());
+        assert_eq!(taps[1].1, text.as_bytes());
+    }
+}
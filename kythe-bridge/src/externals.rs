@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::io::Ticket;
+use crate::ir::{EdgeKind, NodeIndex, RawGraph, Relation};
+
+/// Node kinds which an indexer would emit (along with a defining anchor) for
+/// a node in its corpus, as opposed to e.g. builtin types or names, which are
+/// never defined anywhere.
+const DEFINABLE_KINDS: [&str; 11] = [
+    "abs",
+    "absvar",
+    "constant",
+    "function",
+    "interface",
+    "macro",
+    "package",
+    "record",
+    "sum",
+    "talias",
+    "variable",
+];
+
+/// A node which is depended on but never defined in the graph, and so most
+/// likely belongs to an external library.
+#[derive(Debug, PartialEq, Eq)]
+pub struct External {
+    pub ticket: Ticket,
+    /// The node kind, if the node has any facts at all.
+    pub kind: Option<String>,
+    /// The total count of deps on this node.
+    pub refs: usize,
+    /// The paths of the files which depend on this node.
+    pub files: BTreeSet<String>,
+    pub edge_kinds: BTreeMap<EdgeKind, usize>,
+}
+
+/// The externals of a single corpus, taken together.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CorpusExternals {
+    pub corpus: String,
+    pub targets: usize,
+    pub refs: usize,
+    pub files: BTreeSet<String>,
+}
+
+/// Find every node which is the target of a dep but which no anchor defines
+/// (or completes), most referenced first.
+pub fn externals(graph: RawGraph) -> Vec<External> {
+    let (tickets, nodes, edges) = graph.into_parts();
+
+    let defined: HashSet<NodeIndex> = edges
+        .iter()
+        .filter(|(kind, _, _, _)| {
+            matches!(
+                kind,
+                EdgeKind::Defines
+                    | EdgeKind::DefinesBinding
+                    | EdgeKind::Completes
+                    | EdgeKind::CompletesUniquely
+            )
+        })
+        .map(|(_, _, tgt, _)| *tgt)
+        .collect();
+
+    let mut externals: BTreeMap<NodeIndex, External> = BTreeMap::new();
+
+    for (kind, src, tgt, count) in edges {
+        if kind.relation() != (Relation::DependsOn, false) || defined.contains(&tgt) {
+            continue;
+        }
+
+        let node_kind = nodes[tgt.0].node_kind();
+
+        if !node_kind.map_or(true, |kind| DEFINABLE_KINDS.contains(&kind)) {
+            continue;
+        }
+
+        let external = externals.entry(tgt).or_insert_with(|| External {
+            ticket: tickets[tgt.0].clone(),
+            kind: node_kind.map(String::from),
+            refs: 0,
+            files: BTreeSet::new(),
+            edge_kinds: BTreeMap::new(),
+        });
+
+        external.refs += count;
+        *external.edge_kinds.entry(kind).or_default() += count;
+
+        if let Some(path) = &tickets[src.0].path {
+            external.files.insert(path.clone());
+        }
+    }
+
+    let mut externals: Vec<External> = externals.into_values().collect();
+    externals.sort_by(|a, b| {
+        b.refs.cmp(&a.refs).then_with(|| a.ticket.signature.cmp(&b.ticket.signature))
+    });
+    externals
+}
+
+/// Total `externals` by the corpus of each target, most referenced first.
+pub fn by_corpus(externals: &[External]) -> Vec<CorpusExternals> {
+    let mut corpora: BTreeMap<&str, CorpusExternals> = BTreeMap::new();
+
+    for external in externals {
+        let corpus = external.ticket.corpus.as_deref().unwrap_or_default();
+        let row = corpora.entry(corpus).or_insert_with(|| CorpusExternals {
+            corpus: corpus.to_string(),
+            ..Default::default()
+        });
+        row.targets += 1;
+        row.refs += external.refs;
+        row.files.extend(external.files.iter().cloned());
+    }
+
+    let mut corpora: Vec<CorpusExternals> = corpora.into_values().collect();
+    corpora.sort_by(|a, b| b.refs.cmp(&a.refs));
+    corpora
+}
@@ -0,0 +1,242 @@
+//! The text of each file in a graph, along with the services built on it:
+//! slicing out the text of an anchor, and finding the line of an offset.
+//!
+//! A `SpecGraph` takes the text out of its file nodes and keeps it in a
+//! `FileStore`, so that everything which needs file text (anchor resolution,
+//! HTML reports, the language server, etc.) goes through one place rather than
+//! each matching on `NodeKind::File`. Texts are kept in memory unless the store
+//! was created with `FileStore::spilling`, in which case large texts are
+//! written to a file on disk and only the ranges asked for are read back.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::ir::{slice_text, slice_text_lossy, FileKey, LineIndex, Pos};
+
+/// The most bytes a multi-byte character can extend before or after an offset
+/// which lands inside of it.
+const MAX_CHAR_OVERHANG: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct FileStore {
+    files: HashMap<FileKey, StoredFile>,
+    spill: Option<Spill>,
+}
+
+#[derive(Debug)]
+struct StoredFile {
+    text: StoredText,
+    len: usize,
+    lines: LineIndex,
+}
+
+#[derive(Debug)]
+enum StoredText {
+    Memory(String),
+    /// The byte offset of the text within the spill file.
+    Spilled(u64),
+}
+
+/// A scratch file holding every text of at least `threshold` bytes. It is
+/// removed when the store is dropped.
+#[derive(Debug)]
+struct Spill {
+    path: PathBuf,
+    file: Mutex<File>,
+    threshold: usize,
+    end: u64,
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl FileStore {
+    /// A store which keeps every text in memory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store which writes each text of at least `threshold` bytes to a new
+    /// file at `path` instead of keeping it in memory. Only the line index of
+    /// such texts stays in memory.
+    pub fn spilling(path: &Path, threshold: usize) -> io::Result<Self> {
+        let file =
+            fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let spill = Spill { path: path.to_path_buf(), file: Mutex::new(file), threshold, end: 0 };
+        Ok(Self { files: HashMap::new(), spill: Some(spill) })
+    }
+
+    /// Store `text` as the text of `file_key`, replacing any previous text.
+    pub fn insert(&mut self, file_key: FileKey, text: String) -> io::Result<()> {
+        let len = text.len();
+        let lines = LineIndex::new(&text);
+        let text = match &mut self.spill {
+            Some(spill) if len >= spill.threshold && len > 0 => {
+                let offset = spill.end;
+                let mut file = spill.file.lock().unwrap();
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(text.as_bytes())?;
+                spill.end += len as u64;
+                StoredText::Spilled(offset)
+            }
+            _ => StoredText::Memory(text),
+        };
+
+        self.files.insert(file_key, StoredFile { text, len, lines });
+        Ok(())
+    }
+
+    pub fn contains(&self, file_key: FileKey) -> bool {
+        self.files.contains_key(&file_key)
+    }
+
+    /// The number of files with text.
+    pub fn num_files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The keys of every file with text, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = FileKey> + '_ {
+        self.files.keys().copied()
+    }
+
+    /// The number of files whose text was written to disk.
+    pub fn num_spilled(&self) -> usize {
+        self.files.values().filter(|file| matches!(file.text, StoredText::Spilled(_))).count()
+    }
+
+    /// The length in bytes of the text of `file_key`.
+    pub fn text_len(&self, file_key: FileKey) -> Option<usize> {
+        Some(self.files.get(&file_key)?.len)
+    }
+
+    /// The lines of the text of `file_key`.
+    pub fn lines(&self, file_key: FileKey) -> Option<&LineIndex> {
+        Some(&self.files.get(&file_key)?.lines)
+    }
+
+    /// The entire text of `file_key`. Spilled texts are read back from disk,
+    /// so prefer `slice` when only part of the text is needed.
+    pub fn text(&self, file_key: FileKey) -> Option<Cow<str>> {
+        let file = self.files.get(&file_key)?;
+        self.slice_lossy(file_key, &Pos { start: 0, end: file.len })
+    }
+
+    /// The text of `file_key` covered by `pos`, widened to whole characters.
+    /// Returns `None` if there is no such file or under the same conditions as
+    /// `ir::slice_text`.
+    pub fn slice(&self, file_key: FileKey, pos: &Pos) -> Option<Cow<str>> {
+        let file = self.files.get(&file_key)?;
+
+        if pos.start > pos.end || pos.end > file.len {
+            return None;
+        }
+
+        match &file.text {
+            StoredText::Memory(text) => slice_text(text, pos).map(Cow::Borrowed),
+            StoredText::Spilled(offset) => {
+                // Read a little extra on either side so that the slice can be
+                // widened to character boundaries
+                let start = pos.start.saturating_sub(MAX_CHAR_OVERHANG);
+                let end = (pos.end + MAX_CHAR_OVERHANG).min(file.len);
+                let bytes = self.read_spilled(offset + start as u64, end - start).ok()?;
+                let is_boundary =
+                    |i: usize| i == 0 || i == file.len || (bytes[i - start] as i8) >= -0x40;
+
+                let mut lo = pos.start;
+                let mut hi = pos.end;
+
+                while !is_boundary(lo) {
+                    lo -= 1;
+                }
+
+                while !is_boundary(hi) {
+                    hi += 1;
+                }
+
+                let slice = &bytes[lo - start..hi - start];
+                Some(Cow::Owned(String::from_utf8_lossy(slice).into_owned()))
+            }
+        }
+    }
+
+    /// The exact bytes of `file_key` covered by `pos` (clamped to the end of
+    /// the text), with partial characters replaced. See
+    /// `ir::slice_text_lossy`.
+    pub fn slice_lossy(&self, file_key: FileKey, pos: &Pos) -> Option<Cow<str>> {
+        let file = self.files.get(&file_key)?;
+
+        match &file.text {
+            StoredText::Memory(text) => Some(slice_text_lossy(text, pos)),
+            StoredText::Spilled(offset) => {
+                let end = pos.end.min(file.len);
+                let start = pos.start.min(end);
+                let bytes = self.read_spilled(offset + start as u64, end - start).ok()?;
+                Some(Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()))
+            }
+        }
+    }
+
+    /// The one-based line and column (in characters) of byte `offset` in the
+    /// text of `file_key`. Returns `None` if `offset` is past the end.
+    pub fn position(&self, file_key: FileKey, offset: usize) -> Option<(usize, usize)> {
+        let (line, start) = self.lines(file_key)?.line(offset);
+        let prefix = self.slice(file_key, &Pos { start, end: offset })?;
+        Some((line + 1, prefix.chars().count() + 1))
+    }
+
+    fn read_spilled(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let spill = self.spill.as_ref().expect("texts are only spilled with a spill file");
+        let mut file = spill.file.lock().unwrap();
+        let mut bytes = vec![0; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spilled_matches_memory() {
+        let path = std::env::temp_dir().join(format!("files-{}.spill", std::process::id()));
+        let mut memory = FileStore::new();
+        let mut spilled = FileStore::spilling(&path, 4).unwrap();
+
+        for (i, text) in ["ab", "x\u{e9}y\nz\u{e9}", "", "one\ntwo\n"].iter().enumerate() {
+            memory.insert(FileKey(i as u32), text.to_string()).unwrap();
+            spilled.insert(FileKey(i as u32), text.to_string()).unwrap();
+        }
+
+        assert_eq!(spilled.num_spilled(), 2);
+
+        for i in 0..4 {
+            let key = FileKey(i);
+            assert_eq!(memory.text(key), spilled.text(key));
+
+            for start in 0..10 {
+                for end in start..10 {
+                    let pos = Pos { start, end };
+                    assert_eq!(memory.slice(key, &pos), spilled.slice(key, &pos));
+                    assert_eq!(memory.slice_lossy(key, &pos), spilled.slice_lossy(key, &pos));
+                }
+            }
+        }
+
+        assert_eq!(spilled.slice(FileKey(1), &Pos { start: 2, end: 3 }).unwrap(), "\u{e9}");
+        assert_eq!(spilled.position(FileKey(1), 6), Some((2, 2)));
+        assert_eq!(spilled.position(FileKey(3), 5), Some((2, 2)));
+
+        drop(spilled);
+        assert!(!path.exists());
+    }
+}
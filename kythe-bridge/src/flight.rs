@@ -0,0 +1,167 @@
+//! An Arrow Flight (https://arrow.apache.org/docs/format/Flight.html) service
+//! which streams the tables of an `ArrowSink` over gRPC, so that remote
+//! analytics tools can read a graph without a file hand-off.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use arrow::error::ArrowError;
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow_flight::flight_descriptor::DescriptorType;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::utils::flight_data_from_arrow_batch;
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
+    HandshakeRequest, HandshakeResponse, IpcMessage, PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use futures::stream::{self, Stream};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::sink::Table;
+
+type BoxStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+/// Serves each table under a ticket of its name, and describes it under a
+/// descriptor whose path is just its name. Nothing can be written.
+pub struct TableService {
+    tables: Arc<Vec<Table>>,
+}
+
+impl TableService {
+    pub fn new(tables: Vec<Table>) -> Self {
+        Self { tables: Arc::new(tables) }
+    }
+
+    fn table(&self, name: &[u8]) -> Result<&Table, Status> {
+        let name = String::from_utf8_lossy(name);
+        let table = self.tables.iter().find(|table| table.name == name);
+        table.ok_or_else(|| Status::not_found(format!("no table named {:?}", name)))
+    }
+
+    fn describe(&self, descriptor: &FlightDescriptor) -> Result<&Table, Status> {
+        match descriptor.path.as_slice() {
+            [name] => self.table(name.as_bytes()),
+            _ => Err(Status::invalid_argument("expected a path of one table name")),
+        }
+    }
+
+    fn info(&self, table: &Table) -> Result<FlightInfo, Status> {
+        let options = IpcWriteOptions::default();
+        let schema = IpcMessage::try_from(SchemaAsIpc::new(&table.schema, &options));
+        let descriptor = FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            cmd: Vec::new(),
+            path: vec![table.name.to_string()],
+        };
+        let endpoint = FlightEndpoint {
+            ticket: Some(Ticket { ticket: table.name.as_bytes().to_vec() }),
+            location: Vec::new(),
+        };
+        let rows = table.batches.iter().map(|batch| batch.num_rows() as i64).sum();
+        Ok(FlightInfo::new(schema.map_err(to_status)?, Some(descriptor), vec![endpoint], rows, -1))
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for TableService {
+    type HandshakeStream = BoxStream<HandshakeResponse>;
+    type ListFlightsStream = BoxStream<FlightInfo>;
+    type DoGetStream = BoxStream<FlightData>;
+    type DoPutStream = BoxStream<PutResult>;
+    type DoActionStream = BoxStream<arrow_flight::Result>;
+    type ListActionsStream = BoxStream<ActionType>;
+    type DoExchangeStream = BoxStream<FlightData>;
+
+    async fn handshake(
+        &self,
+        _: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("no authentication is needed"))
+    }
+
+    async fn list_flights(
+        &self,
+        _: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let infos: Vec<_> = self.tables.iter().map(|table| self.info(table)).collect();
+        Ok(Response::new(Box::pin(stream::iter(infos))))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let table = self.describe(request.get_ref())?;
+        Ok(Response::new(self.info(table)?))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let table = self.describe(request.get_ref())?;
+        let options = IpcWriteOptions::default();
+        Ok(Response::new(SchemaAsIpc::new(&table.schema, &options).into()))
+    }
+
+    /// Streams the schema and then each batch, encoding batches only as they
+    /// are sent.
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let table = self.table(&request.get_ref().ticket)?;
+        let options = IpcWriteOptions::default();
+        let schema = FlightData::from(SchemaAsIpc::new(&table.schema, &options));
+        let batches = table.batches.clone().into_iter().flat_map(move |batch| {
+            let (dictionaries, batch) = flight_data_from_arrow_batch(&batch, &options);
+            dictionaries.into_iter().chain(std::iter::once(batch))
+        });
+
+        let data = std::iter::once(schema).chain(batches).map(Ok);
+        Ok(Response::new(Box::pin(stream::iter(data))))
+    }
+
+    async fn do_put(
+        &self,
+        _: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("tables are read-only"))
+    }
+
+    async fn do_action(
+        &self,
+        _: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("there are no actions"))
+    }
+
+    async fn list_actions(
+        &self,
+        _: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(Box::pin(stream::empty())))
+    }
+
+    async fn do_exchange(
+        &self,
+        _: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("tables are read-only"))
+    }
+}
+
+/// Serve `tables` on `addr` until the process is interrupted.
+pub fn serve(addr: SocketAddr, tables: Vec<Table>) -> Result<(), Box<dyn Error>> {
+    let service = FlightServiceServer::new(TableService::new(tables));
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(Server::builder().add_service(service).serve(addr))?;
+    Ok(())
+}
+
+fn to_status(err: ArrowError) -> Status {
+    Status::internal(err.to_string())
+}
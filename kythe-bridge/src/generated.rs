@@ -0,0 +1,201 @@
+//! Attributing the entities of generated files (e.g. from protobuf or thrift)
+//! to the sources they were generated from, so that metrics and DSMs count a
+//! `foo.pb.cc` as part of `foo.proto` rather than as a file of its own.
+//!
+//! Generated files are found either by matching their paths against rules
+//! (each a glob and the logical path of what it matches) or by following the
+//! Kythe `generates` edges which some indexers emit from a source node to the
+//! nodes generated from it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::ir::{EdgeKind, EntityGraph, SpecGraph};
+use crate::paths;
+
+#[derive(Debug, Error)]
+pub enum GeneratedErr {
+    #[error("failed to read generated source rules")]
+    Io(#[from] io::Error),
+    #[error("expected a glob and a path on line {0}")]
+    MissingPath(usize),
+    #[error("invalid glob pattern on line {0}")]
+    InvalidGlob(usize, #[source] globset::Error),
+}
+
+type GeneratedRes<T> = Result<T, GeneratedErr>;
+
+#[derive(Debug)]
+struct Rule {
+    matcher: globset::GlobMatcher,
+    /// The directories at the start of the glob which have no wildcards.
+    base: String,
+    path: String,
+}
+
+impl Rule {
+    fn new(glob: &str, path: &str) -> Result<Self, globset::Error> {
+        let literal = &glob[..glob.find(['*', '?', '[', '{']).unwrap_or(glob.len())];
+        let base = match literal.rsplit_once('/') {
+            Some((base, _)) => base.to_string(),
+            None => String::new(),
+        };
+        let matcher = globset::Glob::new(glob)?.compile_matcher();
+        Ok(Self { matcher, base, path: path.to_string() })
+    }
+}
+
+/// Rules for where the entities of generated files really belong.
+#[derive(Debug, Default)]
+pub struct GeneratedSources {
+    rules: Vec<Rule>,
+    follow_edges: bool,
+}
+
+impl GeneratedSources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a file of rules. Each line is a glob followed by the logical path
+    /// of the files it matches, separated by whitespace. Blank lines and lines
+    /// starting with `#` are ignored. For example:
+    ///
+    /// ```text
+    /// # Attribute generated protobuf code to its .proto
+    /// GEN/**/*.pb.{h,cc}    {dir}/{stem}.proto
+    /// GEN/**/*_types.thrift.*  {dir}/{stem}.thrift
+    /// ```
+    ///
+    /// See `logical_path` for the placeholders a path may contain.
+    pub fn read(path: &Path) -> GeneratedRes<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the contents of a rule file (see `read`).
+    pub fn parse(text: &str) -> GeneratedRes<Self> {
+        let mut sources = Self::new();
+
+        for (i, line) in text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (glob, path) = match line.split_once(char::is_whitespace) {
+                Some((glob, path)) if !path.trim().is_empty() => (glob, path.trim()),
+                _ => return Err(GeneratedErr::MissingPath(i)),
+            };
+
+            let rule = Rule::new(glob, path).map_err(|e| GeneratedErr::InvalidGlob(i, e))?;
+            sources.rules.push(rule);
+        }
+
+        Ok(sources)
+    }
+
+    /// Also follow `generates` edges. The entities of a file are attributed to
+    /// the file which generates most of them (unless a rule matches first).
+    pub fn follow_edges(mut self, follow_edges: bool) -> Self {
+        self.follow_edges = follow_edges;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && !self.follow_edges
+    }
+
+    /// The logical path of `path` according to the first rule which matches
+    /// it, if any. In the path of the rule, "{dir}" is replaced by the
+    /// directory of `path` below the leading directories of the glob which
+    /// have no wildcards (so "api/v1" for "GEN/api/v1/user.pb.h" matched by
+    /// "GEN/**/*.pb.h"), and "{stem}" by its file name up to the first ".".
+    pub fn logical_path(&self, path: &str) -> Option<String> {
+        let rule = self.rules.iter().find(|rule| rule.matcher.is_match(path))?;
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let dir = paths::strip_dir_prefix(dir, &rule.base).unwrap_or(dir);
+        let stem = name.split('.').next().unwrap_or(name);
+        let logical =
+            rule.path.replace("{dir}", dir.trim_start_matches('/')).replace("{stem}", stem);
+
+        Some(logical.trim_start_matches('/').to_string())
+    }
+
+    /// Replace the path of every entity of a generated file with the path of
+    /// its source. Returns the number of entities moved.
+    pub fn apply(&self, graph: &mut EntityGraph, spec: &SpecGraph) -> usize {
+        let by_edges = match self.follow_edges {
+            true => generated_paths(graph, spec),
+            false => HashMap::new(),
+        };
+
+        let mut num_moved = 0;
+
+        for entity in graph.entities.values_mut() {
+            let logical = match self.logical_path(&entity.path) {
+                Some(logical) => logical,
+                None => match by_edges.get(&entity.path) {
+                    Some(logical) => logical.clone(),
+                    None => continue,
+                },
+            };
+
+            if entity.path != logical {
+                entity.path = logical;
+                num_moved += 1;
+            }
+        }
+
+        num_moved
+    }
+}
+
+/// Map the path of each file with generated entities to the path of the file
+/// which generates most of them.
+fn generated_paths(graph: &EntityGraph, spec: &SpecGraph) -> HashMap<String, String> {
+    let mut counts: BTreeMap<&str, BTreeMap<&str, usize>> = BTreeMap::new();
+
+    for entity in graph.entities.values() {
+        for (kind, tgt, count) in spec.outgoing_all(entity.id) {
+            if kind != EdgeKind::Generates {
+                continue;
+            }
+
+            if let Some(generated) = graph.entities.get(&tgt) {
+                if generated.path != entity.path {
+                    let sources = counts.entry(&generated.path).or_default();
+                    *sources.entry(&entity.path).or_default() += count;
+                }
+            }
+        }
+    }
+
+    counts
+        .into_iter()
+        .filter_map(|(generated, sources)| {
+            // Ties go to the first path, so that the result is deterministic
+            let (source, _) = sources.into_iter().rev().max_by_key(|(_, count)| *count)?;
+            Some((generated.to_string(), source.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logical_path() {
+        let text = "# Comment\nGEN/**/*.pb.{h,cc}  {dir}/{stem}.proto\nthrift/*  idl/all.thrift\n";
+        let sources = GeneratedSources::parse(text).unwrap();
+
+        assert_eq!(sources.logical_path("GEN/api/v1/user.pb.h").unwrap(), "api/v1/user.proto");
+        assert_eq!(sources.logical_path("GEN/user.pb.cc").unwrap(), "user.proto");
+        assert_eq!(sources.logical_path("thrift/a_types.cpp").unwrap(), "idl/all.thrift");
+        assert_eq!(sources.logical_path("src/user.cc"), None);
+        assert!(matches!(GeneratedSources::parse("*.pb.h"), Err(GeneratedErr::MissingPath(1))));
+    }
+}
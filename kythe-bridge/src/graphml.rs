@@ -0,0 +1,53 @@
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use crate::io::escape_xml;
+use crate::ir::EntityGraph;
+
+/// Write entities and deps as a GraphML (http://graphml.graphdrawing.org/)
+/// document, which most graph tools (Gephi, yEd, NetworkX, etc.) can open.
+pub fn write_graphml<W: Write>(graph: &EntityGraph, writer: &mut W) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(writer, r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#)?;
+    writeln!(writer, r#"  <key id="path" for="node" attr.name="path" attr.type="string"/>"#)?;
+    writeln!(writer, r#"  <key id="kind" for="node" attr.name="kind" attr.type="string"/>"#)?;
+    writeln!(writer, r#"  <key id="dep_kind" for="edge" attr.name="kind" attr.type="string"/>"#)?;
+    writeln!(writer, r#"  <key id="count" for="edge" attr.name="count" attr.type="int"/>"#)?;
+    writeln!(writer, r#"  <key id="config" for="edge" attr.name="config" attr.type="string"/>"#)?;
+    writeln!(writer, r#"  <graph id="G" edgedefault="directed">"#)?;
+
+    for entity in graph.entities.values().sorted_by_key(|e| e.id) {
+        writeln!(
+            writer,
+            r#"    <node id="n{}"><data key="name">{}</data><data key="path">{}</data><data key="kind">{}</data></node>"#,
+            entity.id,
+            escape_xml(&entity.name),
+            escape_xml(&entity.path),
+            entity.kind.name()
+        )?;
+    }
+
+    // Deps on nodes which are not entities (e.g. dropped `NodeKind::None`
+    // nodes) would point at missing nodes, which most tools reject
+    let deps = graph.deps.iter().filter(|dep| {
+        graph.entities.contains_key(&dep.src) && graph.entities.contains_key(&dep.tgt)
+    });
+
+    for dep in deps.sorted() {
+        let config = match &dep.config {
+            Some(config) => format!(r#"<data key="config">{}</data>"#, escape_xml(config)),
+            None => String::new(),
+        };
+
+        writeln!(
+            writer,
+            r#"    <edge source="n{}" target="n{}"><data key="dep_kind">{:?}</data><data key="count">{}</data>{}</edge>"#,
+            dep.src, dep.tgt, dep.kind, dep.count, config
+        )?;
+    }
+
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")
+}
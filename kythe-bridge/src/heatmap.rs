@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use thiserror::Error;
+use tinytemplate::TinyTemplate;
+
+use crate::budget::{Budget, BudgetErr, OutputSize};
+use crate::ir::{AnchorKind, EdgeKind, EntityGraph, NodeIndex, NodeKind, Relation, SpecGraph};
+
+const TEMPLATE: &str = include_str!("heatmap.html");
+
+#[derive(Debug, Error)]
+pub enum HeatmapErr {
+    #[error("found no text for \"{0}\" (was it indexed?)")]
+    NoText(String),
+    #[error("failed to render page")]
+    Template(#[from] tinytemplate::error::Error),
+    #[error("page is too large")]
+    OverBudget(#[from] BudgetErr),
+}
+
+type HeatmapRes<T> = Result<T, HeatmapErr>;
+
+/// The score each entity is colored by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// The number of distinct entities which depend on the entity.
+    FanIn,
+    /// The number of distinct entities the entity depends on.
+    FanOut,
+    /// Fan-in times fan-out, as in Henry and Kafura's information flow
+    /// complexity. Entities which are both widely used and widely dependent
+    /// are the riskiest to change.
+    Hotspot,
+}
+
+impl Metric {
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::FanIn => "fan-in",
+            Metric::FanOut => "fan-out",
+            Metric::Hotspot => "hotspot",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Fan {
+    fan_in: usize,
+    fan_out: usize,
+}
+
+impl Fan {
+    fn score(&self, metric: Metric) -> usize {
+        match metric {
+            Metric::FanIn => self.fan_in,
+            Metric::FanOut => self.fan_out,
+            Metric::Hotspot => self.fan_in * self.fan_out,
+        }
+    }
+}
+
+/// An entity defined in the file being rendered, and the lines it spans.
+struct Definition {
+    name: String,
+    fan: Fan,
+    /// The annotations of the entity (see `Entity::annotations`), if any.
+    annotations: String,
+    first_line: usize,
+    last_line: usize,
+}
+
+/// Render the file at `path` as a standalone HTML page in which each line is
+/// shaded by the highest score (per `metric`) of the entities defined over
+/// it. Hovering over a line lists those entities with their fan-in and
+/// fan-out (and annotations, if any).
+///
+/// `graph` must still have its anchors (see `EntityGraph::lift_anchors`). The
+/// deps of anchors are lifted to their entities before fans are counted. The
+/// page is refused before it is rendered if it would not fit within `budget`.
+pub fn heatmap(
+    spec: &SpecGraph,
+    graph: &EntityGraph,
+    path: &str,
+    metric: Metric,
+    budget: &Budget,
+) -> HeatmapRes<String> {
+    let (text, lines) = graph
+        .entities
+        .values()
+        .filter(|entity| entity.path == path)
+        .find_map(|entity| {
+            let file_key = spec.get_node(entity.id).file_key;
+            Some((spec.get_file_text(file_key)?, spec.line_index(file_key)?))
+        })
+        .ok_or_else(|| HeatmapErr::NoText(path.to_string()))?;
+    let fans = fans(spec, graph);
+
+    // The full extent of each entity comes from its `defines` anchor, if any,
+    // and otherwise from its `defines/binding` anchor
+    let mut spans: HashMap<NodeIndex, (EdgeKind, usize, usize)> = HashMap::new();
+
+    for anchor in graph.entities.values().filter(|e| e.path == path) {
+        let pos = match &anchor.kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+            _ => continue,
+        };
+
+        for (kind, tgt, _) in spec.outgoing_all(anchor.id) {
+            if !matches!(kind, EdgeKind::Defines | EdgeKind::DefinesBinding) {
+                continue;
+            }
+
+            if !matches!(spans.get(&tgt), Some((EdgeKind::Defines, _, _))) {
+                spans.insert(tgt, (kind, pos.start, pos.end));
+            }
+        }
+    }
+
+    let definitions = spans
+        .into_iter()
+        .filter_map(|(id, (_, start, end))| {
+            let entity = graph.entities.get(&id)?;
+            Some(Definition {
+                name: entity.qualified_name.clone(),
+                fan: fans.get(&id).copied().unwrap_or_default(),
+                annotations: entity
+                    .annotations
+                    .iter()
+                    .map(|(column, value)| format!("{}: {}", column, value))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                first_line: lines.line(start).0,
+                last_line: lines.line(end.saturating_sub(1).max(start)).0,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let size = estimate(&text, &definitions);
+    log::info!("Estimated output size is {}.", size);
+    budget.check(&size)?;
+
+    render(path, &text, &definitions, metric)
+}
+
+/// Roughly the bytes each row of the page adds beyond its code and title.
+const ROW_OVERHEAD: usize = 100;
+
+/// Roughly the bytes each definition adds to the title of a row beyond its
+/// name and annotations.
+const TITLE_OVERHEAD: usize = 40;
+
+/// Estimate the size of the page `render` would produce, counting each
+/// definition as a node (and nothing as an edge).
+fn estimate(text: &str, definitions: &[Definition]) -> OutputSize {
+    let titles: usize = definitions
+        .iter()
+        .map(|d| {
+            let lines = d.last_line.saturating_sub(d.first_line) + 1;
+            (d.name.len() + d.annotations.len() + TITLE_OVERHEAD) * lines
+        })
+        .sum();
+    let rows = text.lines().count() * ROW_OVERHEAD;
+
+    OutputSize {
+        nodes: definitions.len(),
+        edges: 0,
+        bytes: TEMPLATE.len() + text.len() + rows + titles,
+    }
+}
+
+/// Count the fan-in and fan-out of every entity, with the deps of anchors
+/// attributed to the entities which own them (as by `lift_anchors`, but
+/// without copying the graph).
+fn fans(spec: &SpecGraph, graph: &EntityGraph) -> HashMap<NodeIndex, Fan> {
+    let owners = graph.anchor_owners(spec);
+    let lift = |index: NodeIndex| match owners.get(&index) {
+        None => Some(index),
+        Some(owner) => *owner,
+    };
+
+    let mut pairs = HashSet::new();
+    let mut fans: HashMap<NodeIndex, Fan> = HashMap::new();
+
+    for dep in &graph.deps {
+        let (src, tgt) = match (lift(dep.src), lift(dep.tgt)) {
+            (Some(src), Some(tgt)) => (src, tgt),
+            _ => continue,
+        };
+        let (relation, src, tgt) = dep.kind.normalize(src, tgt);
+
+        if relation == Relation::DependsOn && src != tgt && pairs.insert((src, tgt)) {
+            fans.entry(src).or_default().fan_out += 1;
+            fans.entry(tgt).or_default().fan_in += 1;
+        }
+    }
+
+    fans
+}
+
+#[derive(serde::Serialize)]
+struct Page<'a> {
+    path: &'a str,
+    metric: &'static str,
+    low: String,
+    high: String,
+    max: usize,
+    rows: Vec<Row<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct Row<'a> {
+    number: usize,
+    color: String,
+    title: String,
+    code: &'a str,
+}
+
+fn render(
+    path: &str,
+    text: &str,
+    definitions: &[Definition],
+    metric: Metric,
+) -> HeatmapRes<String> {
+    // The definitions over each line, outermost first
+    let mut by_line: BTreeMap<usize, Vec<&Definition>> = BTreeMap::new();
+
+    for definition in definitions {
+        for line in definition.first_line..=definition.last_line {
+            by_line.entry(line).or_default().push(definition);
+        }
+    }
+
+    for defs in by_line.values_mut() {
+        defs.sort_by_key(|d| (d.first_line, std::cmp::Reverse(d.last_line), d.name.clone()));
+    }
+
+    let max = definitions.iter().map(|d| d.fan.score(metric)).max().unwrap_or(0);
+    let rows = text
+        .lines()
+        .enumerate()
+        .map(|(i, code)| {
+            let defs = by_line.get(&i).map(Vec::as_slice).unwrap_or_default();
+            let score = defs.iter().map(|d| d.fan.score(metric)).max().unwrap_or(0);
+            let title = defs
+                .iter()
+                .map(|d| {
+                    let title =
+                        format!("{} (fan-in {}, fan-out {})", d.name, d.fan.fan_in, d.fan.fan_out);
+                    match d.annotations.is_empty() {
+                        true => title,
+                        false => format!("{} [{}]", title, d.annotations),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            Row { number: i + 1, color: color(score, max), title, code }
+        })
+        .collect();
+
+    let page =
+        Page { path, metric: metric.name(), low: color(0, max), high: color(max, max), max, rows };
+
+    let mut template = TinyTemplate::new();
+    template.add_template("heatmap", TEMPLATE)?;
+    Ok(template.render("heatmap", &page)?)
+}
+
+/// Shade from transparent (zero) to red (`max`) on a log scale, so that a
+/// few extreme entities do not wash out the rest of the file.
+fn color(score: usize, max: usize) -> String {
+    let intensity = match max {
+        0 => 0.0,
+        _ => (score as f64).ln_1p() / (max as f64).ln_1p(),
+    };
+
+    format!("rgba(220, 40, 40, {:.2})", intensity * 0.7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color() {
+        assert_eq!(color(0, 0), "rgba(220, 40, 40, 0.00)");
+        assert_eq!(color(0, 10), "rgba(220, 40, 40, 0.00)");
+        assert_eq!(color(10, 10), "rgba(220, 40, 40, 0.70)");
+        assert!(color(3, 10) > color(1, 10));
+    }
+}
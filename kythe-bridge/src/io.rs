@@ -1,7 +1,19 @@
 use std::{fs, io};
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::io::BufRead;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::canonical::to_json_line;
+use crate::proto::{self, Value};
 
 pub fn open_bufwriter(path: Option<PathBuf>) -> io::Result<io::BufWriter<Box<dyn io::Write>>> {
     Ok(io::BufWriter::new(match path {
@@ -10,93 +22,774 @@ pub fn open_bufwriter(path: Option<PathBuf>) -> io::Result<io::BufWriter<Box<dyn
     }))
 }
 
-pub struct Reader(io::BufReader<Box<dyn io::Read>>);
+/// Gzip everything written to `write`. Since readers decompress gzip on their
+/// own, the output can be read back like any other file. Call `finish` on the
+/// encoder to write the gzip trailer and learn whether that succeeded.
+pub fn compress<W: io::Write>(write: W) -> flate2::write::GzEncoder<W> {
+    flate2::write::GzEncoder::new(write, flate2::Compression::default())
+}
+
+/// Escape text for use within an XML element or attribute.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace are not allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Size of each buffer filled by a `BatchWriter` before it is written out.
+const WRITE_BATCH_BYTES: usize = 1 << 22;
+
+/// Number of full buffers which may be waiting on the background thread before
+/// a `BatchWriter` blocks.
+const WRITE_QUEUE_LEN: usize = 4;
+
+/// A writer for passing through a large number of small lines, such as the
+/// entries kept by `exclude`.
+///
+/// Writes are copied into large buffers rather than going to the underlying
+/// writer one line at a time. If threaded, full buffers are handed to a
+/// background thread, which writes every buffer it has waiting with a single
+/// vectored write, so that writing overlaps with reading and decoding.
+///
+/// `finish` must be called to learn whether the last writes succeeded.
+pub struct BatchWriter {
+    buf: Vec<u8>,
+    target: BatchTarget,
+}
+
+enum BatchTarget {
+    Direct(Box<dyn io::Write>),
+    Threaded {
+        sender: SyncSender<Vec<u8>>,
+        recycled: Receiver<Vec<u8>>,
+        handle: JoinHandle<io::Result<()>>,
+    },
+    Finished,
+}
+
+impl BatchWriter {
+    /// Create a file (or use stdout) to write to.
+    pub fn create(path: Option<PathBuf>, threaded: bool) -> io::Result<Self> {
+        let write: Box<dyn io::Write + Send> = match path {
+            None => Box::new(io::stdout()),
+            Some(path) => Box::new(fs::File::create(path)?),
+        };
+
+        Ok(Self::new(write, threaded))
+    }
+
+    pub fn new(write: Box<dyn io::Write + Send>, threaded: bool) -> Self {
+        let target = match threaded {
+            false => BatchTarget::Direct(write),
+            true => {
+                let (sender, receiver) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+                let (recycler, recycled) = mpsc::channel();
+                let handle = thread::spawn(move || write_batches(write, receiver, recycler));
+                BatchTarget::Threaded { sender, recycled, handle }
+            }
+        };
+
+        Self { buf: Vec::with_capacity(WRITE_BATCH_BYTES), target }
+    }
+
+    /// Hand the current buffer to the underlying writer (or background thread).
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        match &mut self.target {
+            BatchTarget::Direct(write) => {
+                write.write_all(&self.buf)?;
+                self.buf.clear();
+            }
+            BatchTarget::Threaded { sender, recycled, .. } => {
+                let next = match recycled.try_recv() {
+                    Ok(mut buf) => {
+                        buf.clear();
+                        buf
+                    }
+                    Err(_) => Vec::with_capacity(WRITE_BATCH_BYTES),
+                };
+
+                // The receiver is only dropped if the thread failed, in which
+                // case the error is returned by `finish`
+                if sender.send(std::mem::replace(&mut self.buf, next)).is_err() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "background writer stopped early",
+                    ));
+                }
+            }
+            BatchTarget::Finished => {
+                return Err(io::Error::new(io::ErrorKind::Other, "writer already finished"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write out anything still buffered and wait for every write to complete.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_mut()
+    }
+
+    fn finish_mut(&mut self) -> io::Result<()> {
+        self.write_batch()?;
+
+        match std::mem::replace(&mut self.target, BatchTarget::Finished) {
+            BatchTarget::Direct(mut write) => write.flush(),
+            BatchTarget::Threaded { sender, handle, .. } => {
+                drop(sender);
+                handle.join().unwrap_or_else(|_| {
+                    Err(io::Error::new(io::ErrorKind::Other, "background writer panicked"))
+                })
+            }
+            BatchTarget::Finished => Ok(()),
+        }
+    }
+}
+
+impl io::Write for BatchWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(buf);
+
+        if self.buf.len() >= WRITE_BATCH_BYTES {
+            self.write_batch()?;
+        }
+
+        Ok(())
+    }
+
+    /// Only hands the current buffer off; use `finish` to wait for it to be
+    /// written.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_batch()
+    }
+}
+
+impl Drop for BatchWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish_mut() {
+            log::error!("Failed to write output: {}", err);
+        }
+    }
+}
+
+/// Run on the background thread of a `BatchWriter`. Whatever buffers have
+/// queued up are written together, then sent back to be reused.
+fn write_batches(
+    mut write: Box<dyn io::Write + Send>,
+    receiver: Receiver<Vec<u8>>,
+    recycler: Sender<Vec<u8>>,
+) -> io::Result<()> {
+    while let Ok(first) = receiver.recv() {
+        let mut bufs = vec![first];
+        bufs.extend(receiver.try_iter().take(WRITE_QUEUE_LEN));
+        write_all_vectored(&mut write, &bufs)?;
+
+        for buf in bufs {
+            // Fine if the writer has already been dropped
+            let _ = recycler.send(buf);
+        }
+    }
+
+    write.flush()
+}
+
+/// Write every buffer in order, using as few vectored writes as possible.
+fn write_all_vectored(write: &mut impl io::Write, bufs: &[Vec<u8>]) -> io::Result<()> {
+    let mut index = 0;
+    let mut offset = 0;
+
+    while index < bufs.len() {
+        let slices = std::iter::once(&bufs[index][offset..])
+            .chain(bufs[index + 1..].iter().map(Vec::as_slice))
+            .map(io::IoSlice::new)
+            .collect::<Vec<_>>();
+
+        let mut written = match write.write_vectored(&slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => written,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        while index < bufs.len() && written >= bufs[index].len() - offset {
+            written -= bufs[index].len() - offset;
+            index += 1;
+            offset = 0;
+        }
+
+        offset += written;
+    }
+
+    Ok(())
+}
+
+pub struct Reader {
+    read: io::BufReader<Box<dyn io::Read>>,
+    /// Set once reading fails, since the rest of the stream (e.g. after a
+    /// truncated protobuf or corrupt gzip) cannot be trusted.
+    failed: bool,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 
 impl Reader {
+    /// Open a file (or stdin), transparently decompressing it if it is gzip or
+    /// zstd compressed.
     fn open(path: Option<PathBuf>) -> io::Result<Self> {
-        Ok(Self(io::BufReader::new(match path {
+        let read: Box<dyn io::Read> = match path {
             None => Box::new(io::stdin().lock()),
             Some(path) => Box::new(fs::File::open(path)?),
-        })))
+        };
+
+        Ok(Self::new(decompress(read)?))
+    }
+
+    fn from_read(read: impl io::Read + 'static) -> Self {
+        Self::new(Box::new(read))
     }
+
+    fn new(read: Box<dyn io::Read>) -> Self {
+        Self { read: io::BufReader::new(read), failed: false }
+    }
+
+    /// Guess the format from the first byte. JSON entries always start with
+    /// "{" (or whitespace) while a length-delimited protobuf starts with a
+    /// varint.
+    fn detect(&mut self) -> EntryFormat {
+        match self.read.fill_buf().ok().and_then(|buf| buf.first().copied()) {
+            Some(byte) if byte != b'{' && !byte.is_ascii_whitespace() => EntryFormat::Proto,
+            _ => EntryFormat::Json,
+        }
+    }
+
+    /// Read the next record without decoding it. A record which cannot be
+    /// read is returned as `Record::Invalid`, after which the reader is
+    /// exhausted unless only the text of a line was invalid.
+    fn next_record(&mut self, format: &EntryFormat) -> Option<Record> {
+        match format {
+            EntryFormat::Proto => Some(match self.read_proto()? {
+                Ok(bytes) => Record::Proto(bytes),
+                Err(err) => Record::Invalid(err),
+            }),
+            _ => {
+                let mut line = String::new();
+
+                Some(match self.read_line(&mut line)? {
+                    Ok(()) => Record::Json(line),
+                    Err(err) => Record::Invalid(err),
+                })
+            }
+        }
+    }
+
+    /// Read the next length-delimited protobuf record.
+    fn read_proto(&mut self) -> Option<Result<Vec<u8>, String>> {
+        if self.failed {
+            return None;
+        }
+
+        let mut bytes = Vec::new();
+
+        match proto::read_delimited(&mut self.read, &mut bytes) {
+            Ok(true) => Some(Ok(bytes)),
+            Ok(false) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(format!("unreadable protobuf entry ({})", err)))
+            }
+        }
+    }
+
+    /// Read the next line into `line` (replacing its contents).
+    fn read_line(&mut self, line: &mut String) -> Option<Result<(), String>> {
+        if self.failed {
+            return None;
+        }
+
+        let mut bytes = std::mem::take(line).into_bytes();
+        bytes.clear();
+
+        match self.read.read_until(b'\n', &mut bytes) {
+            Ok(0) => None,
+            Ok(_) => Some(match String::from_utf8(bytes) {
+                Ok(text) => {
+                    *line = text;
+                    Ok(())
+                }
+                Err(err) => {
+                    let text = String::from_utf8_lossy(err.as_bytes());
+                    Err(format!("invalid UTF-8 in {:?}", text.trim_end()))
+                }
+            }),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(format!("unreadable entry ({})", err)))
+            }
+        }
+    }
+}
+
+/// A single undecoded entry, so that reading and decoding can be done on
+/// different threads.
+enum Record {
+    Json(String),
+    Proto(Vec<u8>),
+    /// A record which could not be read, along with why.
+    Invalid(String),
+}
+
+impl Record {
+    /// Decode the entry along with the line it was read from. Protobuf entries
+    /// are converted into a line of JSON. A malformed entry is returned as an
+    /// error describing it.
+    fn decode(self) -> Result<(String, Entry), String> {
+        match self {
+            Record::Proto(bytes) => match Entry::from_proto(&bytes) {
+                Some(entry) => Ok((to_json_line(&entry.borrowed(), None), entry)),
+                None => Err(format!("malformed protobuf entry ({} bytes)", bytes.len())),
+            },
+            Record::Json(line) => match Entry::from_json(&line) {
+                Ok(entry) => Ok((line, entry)),
+                Err(err) => Err(format!("{} in {:?}", err, line.trim_end())),
+            },
+            Record::Invalid(err) => Err(err),
+        }
+    }
+}
+
+/// Wrap `read` in a decompressor if it starts with the magic bytes of gzip or
+/// zstd. The magic bytes are checked rather than the file extension so that
+/// compressed stdin works too.
+fn decompress(read: Box<dyn io::Read>) -> io::Result<Box<dyn io::Read>> {
+    let mut read = io::BufReader::new(read);
+    let magic = read.fill_buf()?;
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        return Ok(Box::new(flate2::bufread::MultiGzDecoder::new(read)));
+    }
+
+    if magic.starts_with(&ZSTD_MAGIC) {
+        return Ok(Box::new(zstd::stream::read::Decoder::with_buffer(read)?));
+    }
+
+    Ok(Box::new(read))
+}
+
+/// The encoding of an entry stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EntryFormat {
+    /// Decide based on the first byte of the stream.
+    #[default]
+    Auto,
+    /// Newline-delimited JSON (as written by `entrystream --write_format=json`).
+    Json,
+    /// Varint-length-delimited protobuf (as written by `entrystream`).
+    Proto,
 }
 
-pub struct EntryReader(Reader);
+/// Expand each pattern into the paths it matches, in sorted order. Patterns
+/// without any glob syntax are kept as-is so that opening a missing file still
+/// reports the usual error.
+pub fn expand_inputs(patterns: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for pattern in patterns {
+        if !pattern.contains(|c| matches!(c, '*' | '?' | '[')) {
+            paths.push(PathBuf::from(pattern));
+            continue;
+        }
+
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let mut matches = glob::glob(pattern)
+            .map_err(|e| invalid(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(e.to_string()))?;
+
+        if matches.is_empty() {
+            return Err(invalid(format!("no files match \"{}\"", pattern)));
+        }
+
+        matches.sort();
+        paths.extend(matches);
+    }
+
+    Ok(paths)
+}
+
+pub struct EntryReader(EntryLineReader);
 
 impl EntryReader {
     pub fn open(path: Option<PathBuf>) -> io::Result<Self> {
-        Ok(Self(Reader::open(path)?))
+        Ok(Self(EntryLineReader::open(path)?))
+    }
+
+    pub fn open_as(path: Option<PathBuf>, format: EntryFormat) -> io::Result<Self> {
+        Ok(Self(EntryLineReader::open_as(path, format)?))
+    }
+
+    /// Read the files one after another as a single stream. If there are no
+    /// files, read from stdin.
+    pub fn open_all(paths: Vec<PathBuf>, format: EntryFormat) -> io::Result<Self> {
+        Ok(Self(EntryLineReader::open_all(paths, format)?))
+    }
+
+    /// Read entries from an arbitrary source, such as the stdout of a child
+    /// process.
+    pub fn from_read(read: impl io::Read + 'static) -> Self {
+        Self(EntryLineReader::from_read(read))
+    }
+
+    /// See `EntryLineReader::lenient`.
+    pub fn lenient(self, lenient: bool) -> Self {
+        Self(self.0.lenient(lenient))
+    }
+
+    /// See `EntryLineReader::parallel`.
+    pub fn parallel(self, parallel: bool) -> Self {
+        Self(self.0.parallel(parallel))
     }
 }
 
 impl IntoIterator for EntryReader {
     type IntoIter = EntryIter;
-    type Item = Entry;
+    type Item = io::Result<Entry>;
 
     fn into_iter(self) -> Self::IntoIter {
-        EntryIter { reader: self.0, buffer: String::new() }
+        EntryIter(self.0.into_iter())
     }
 }
 
-pub struct EntryIter {
-    reader: Reader,
-    buffer: String,
-}
+pub struct EntryIter(EntryLineIter);
 
 impl Iterator for EntryIter {
-    type Item = Entry;
+    type Item = io::Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.reader.0.read_line(&mut self.buffer).unwrap() {
-            0 => None,
-            _ => {
-                let entry = Entry::from_json(&self.buffer).unwrap();
-                self.buffer.clear();
-                Some(entry)
-            }
-        }
+        self.0.next().map(|res| res.map(|(_, entry)| entry))
     }
 }
 
-pub struct EntryLineReader(Reader);
+pub struct EntryLineReader {
+    readers: Vec<Reader>,
+    format: EntryFormat,
+    lenient: bool,
+    parallel: bool,
+}
 
 impl EntryLineReader {
+    fn new(readers: Vec<Reader>, format: EntryFormat) -> Self {
+        Self { readers, format, lenient: false, parallel: false }
+    }
+
     pub fn open(path: Option<PathBuf>) -> io::Result<Self> {
-        Ok(Self(Reader::open(path)?))
+        Self::open_as(path, EntryFormat::Auto)
+    }
+
+    pub fn open_as(path: Option<PathBuf>, format: EntryFormat) -> io::Result<Self> {
+        Ok(Self::new(vec![Reader::open(path)?], format))
+    }
+
+    /// Read the files one after another as a single stream. If there are no
+    /// files, read from stdin.
+    pub fn open_all(paths: Vec<PathBuf>, format: EntryFormat) -> io::Result<Self> {
+        if paths.is_empty() {
+            return Self::open_as(None, format);
+        }
+
+        let readers =
+            paths.into_iter().map(|path| Reader::open(Some(path))).collect::<io::Result<_>>()?;
+        Ok(Self::new(readers, format))
+    }
+
+    /// Read entries from an arbitrary source, such as the stdout of a child
+    /// process.
+    pub fn from_read(read: impl io::Read + 'static) -> Self {
+        Self::new(vec![Reader::from_read(read)], EntryFormat::Auto)
+    }
+
+    /// If lenient, log and skip malformed entries instead of failing. The
+    /// number skipped is reported once the stream is exhausted. Otherwise the
+    /// first malformed entry ends the stream with an error.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Call `f` with each entry (and the line it was read from) borrowed
+    /// rather than allocating a new `Entry` per line. This is the cheapest way
+    /// to stream entries which are only inspected. If parallel, entries are
+    /// instead decoded in batches on the rayon thread pool (see `parallel`)
+    /// and borrowed from there. Returns the number of malformed entries
+    /// skipped (see `lenient`).
+    pub fn for_each_ref<F>(self, mut f: F) -> io::Result<usize>
+    where
+        F: FnMut(&str, &EntryRef) -> io::Result<()>,
+    {
+        let mut iter = self.into_iter();
+
+        // Decoding dominates once it is spread across threads, so the
+        // allocations saved by borrowing are not worth giving that up
+        if iter.batch_size > 1 {
+            for res in &mut iter {
+                let (line, entry) = res?;
+                f(&line, &entry.borrowed())?;
+            }
+
+            return Ok(iter.num_skipped);
+        }
+
+        let mut line = String::new();
+        let mut num_skipped = 0;
+
+        while iter.advance() {
+            let (reader, format) = iter.current.as_mut().unwrap();
+
+            // None once the reader is exhausted
+            let res = match format {
+                EntryFormat::Proto => match reader.next_record(format) {
+                    None => None,
+                    Some(record) => Some(match record.decode() {
+                        Ok((line, entry)) => {
+                            f(&line, &entry.borrowed())?;
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }),
+                },
+                _ => match reader.read_line(&mut line) {
+                    None => None,
+                    Some(Err(err)) => Some(Err(err)),
+                    Some(Ok(())) => Some(match EntryRef::from_json(&line) {
+                        Ok(entry) => {
+                            f(&line, &entry)?;
+                            Ok(())
+                        }
+                        Err(err) => Err(format!("{} in {:?}", err, line.trim_end())),
+                    }),
+                },
+            };
+
+            match res {
+                None => iter.current = None,
+                Some(res) => {
+                    iter.num_read += 1;
+
+                    if let Err(err) = res {
+                        iter.skip(err)?;
+                        num_skipped += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(num_skipped)
+    }
+
+    /// If parallel, read entries in batches on the current thread and decode
+    /// each batch on the rayon thread pool. Entries are still yielded in the
+    /// order they were read.
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
     }
 }
 
 impl IntoIterator for EntryLineReader {
     type IntoIter = EntryLineIter;
-    type Item = (String, Entry);
+    type Item = io::Result<(String, Entry)>;
 
     fn into_iter(self) -> Self::IntoIter {
-        EntryLineIter { reader: self.0, buffer: String::new() }
+        EntryLineIter {
+            readers: self.readers.into_iter(),
+            current: None,
+            format: self.format,
+            batch: VecDeque::new(),
+            batch_size: if self.parallel { BATCH_SIZE } else { 1 },
+            lenient: self.lenient,
+            num_read: 0,
+            num_skipped: 0,
+        }
     }
 }
 
+/// Yields each entry along with the line of JSON it was read from. If the
+/// stream is protobuf, the line is the entry re-encoded as JSON. Unless
+/// lenient, a malformed entry is yielded as an error which ends the stream.
 pub struct EntryLineIter {
-    reader: Reader,
-    buffer: String,
+    readers: std::vec::IntoIter<Reader>,
+    current: Option<(Reader, EntryFormat)>,
+    format: EntryFormat,
+    batch: VecDeque<Result<(String, Entry), String>>,
+    batch_size: usize,
+    lenient: bool,
+    num_read: usize,
+    num_skipped: usize,
+}
+
+/// Number of entries decoded at once when decoding in parallel.
+const BATCH_SIZE: usize = 1 << 14;
+
+/// Number of malformed entries to log individually before going quiet.
+const MAX_REPORTED: usize = 10;
+
+/// The target of every log message about malformed entries, so that loggers
+/// which count warnings can leave them out in favor of `num_skipped`.
+pub const SKIPPED_TARGET: &str = "kythe_bridge::io::skipped";
+
+static NUM_SKIPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of malformed entries skipped so far by every reader, including
+/// those which were not logged.
+pub fn num_skipped() -> usize {
+    NUM_SKIPPED.load(AtomicOrdering::Relaxed)
+}
+
+impl EntryLineIter {
+    /// Log and count a malformed entry, or fail unless lenient.
+    fn skip(&mut self, err: String) -> io::Result<()> {
+        if !self.lenient {
+            // Nothing more is read once the stream has failed
+            self.readers = Vec::new().into_iter();
+            self.current = None;
+            self.batch.clear();
+            let msg = format!("found malformed entry #{}: {}", self.num_read, err);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+
+        self.num_skipped += 1;
+        NUM_SKIPPED.fetch_add(1, AtomicOrdering::Relaxed);
+
+        match self.num_skipped.cmp(&MAX_REPORTED) {
+            Ordering::Less => log::warn!(
+                target: SKIPPED_TARGET,
+                "Skipping malformed entry #{}: {}",
+                self.num_read,
+                err
+            ),
+            Ordering::Equal => log::warn!(
+                target: SKIPPED_TARGET,
+                "Skipping malformed entry #{}: {} (further malformed entries will not be logged)",
+                self.num_read,
+                err
+            ),
+            Ordering::Greater => log::debug!(
+                target: SKIPPED_TARGET,
+                "Skipping malformed entry #{}: {}",
+                self.num_read,
+                err
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Move on to the next reader if the current one is exhausted. Returns
+    /// false once every reader is exhausted.
+    fn advance(&mut self) -> bool {
+        if self.current.is_some() {
+            return true;
+        }
+
+        let mut reader = match self.readers.next() {
+            Some(reader) => reader,
+            None => {
+                self.report();
+                return false;
+            }
+        };
+
+        // Each file may be in a different format
+        let format = match self.format {
+            EntryFormat::Auto => reader.detect(),
+            format => format,
+        };
+
+        self.current = Some((reader, format));
+        true
+    }
+
+    /// Read up to a batch of records from the current reader and decode them,
+    /// in parallel if there is more than one. Leaves the batch empty and moves
+    /// on from the current reader once it is exhausted.
+    fn fill_batch(&mut self) {
+        let (reader, format) = self.current.as_mut().unwrap();
+        let records =
+            std::iter::from_fn(|| reader.next_record(format)).take(self.batch_size).collect_vec();
+
+        if records.is_empty() {
+            self.current = None;
+        } else if records.len() == 1 {
+            self.batch.extend(records.into_iter().map(Record::decode));
+        } else {
+            let decoded: Vec<_> = records.into_par_iter().map(Record::decode).collect();
+            self.batch.extend(decoded);
+        }
+    }
+
+    /// Log how many entries were skipped (at most once).
+    fn report(&mut self) {
+        if self.num_skipped > 0 {
+            log::warn!(target: SKIPPED_TARGET, "Skipped {} malformed entries.", self.num_skipped);
+            self.num_skipped = 0;
+        }
+    }
 }
 
 impl Iterator for EntryLineIter {
-    type Item = (String, Entry);
+    type Item = io::Result<(String, Entry)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.reader.0.read_line(&mut self.buffer).unwrap() {
-            0 => None,
-            _ => {
-                let entry = Entry::from_json(&self.buffer).unwrap();
-                let line = self.buffer.clone();
-                self.buffer.clear();
-                Some((line, entry))
+        loop {
+            if !self.advance() {
+                return None;
+            }
+
+            if self.batch.is_empty() {
+                self.fill_batch();
+            }
+
+            match self.batch.pop_front() {
+                Some(Ok(item)) => {
+                    self.num_read += 1;
+                    return Some(Ok(item));
+                }
+                Some(Err(err)) => {
+                    self.num_read += 1;
+
+                    if let Err(err) = self.skip(err) {
+                        return Some(Err(err));
+                    }
+                }
+                None => self.current = None,
             }
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq, Eq, Hash, Clone)]
 pub struct Ticket {
     pub corpus: Option<String>,
     pub language: Option<String>,
@@ -105,6 +798,61 @@ pub struct Ticket {
     pub signature: Option<String>,
 }
 
+impl Ticket {
+    /// Decode a `kythe.proto.VName` message.
+    pub fn from_proto(bytes: &[u8]) -> Option<Self> {
+        let mut ticket = Ticket::default();
+
+        for (field, value) in proto::fields(bytes)? {
+            if let Value::Bytes(bytes) = value {
+                let value = Some(to_string(bytes));
+
+                match field {
+                    1 => ticket.signature = value,
+                    2 => ticket.corpus = value,
+                    3 => ticket.root = value,
+                    4 => ticket.path = value,
+                    5 => ticket.language = value,
+                    _ => {}
+                }
+            }
+        }
+
+        Some(ticket)
+    }
+}
+
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+impl std::fmt::Display for Ticket {
+    /// Format as a Kythe URI (e.g. "kythe://corpus?lang=java?path=a/B.java#sig").
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kythe://{}", self.corpus.as_deref().unwrap_or_default())?;
+
+        if let Some(language) = &self.language {
+            write!(f, "?lang={}", language)?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(f, "?path={}", path)?;
+        }
+
+        if let Some(root) = &self.root {
+            write!(f, "?root={}", root)?;
+        }
+
+        if let Some(signature) = &self.signature {
+            write!(f, "#{}", signature)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single Kythe entry. Any entry with a target is an edge, even if its edge
+/// kind is missing or empty (as happens with some reverse-edge fact entries).
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
 #[serde(untagged)]
 pub enum Entry {
@@ -113,7 +861,9 @@ pub enum Entry {
         src: Ticket,
         #[serde(rename = "target")]
         tgt: Ticket,
-        edge_kind: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        edge_kind: Option<String>,
+        #[serde(default)]
         fact_name: String,
         fact_value: Option<String>,
     },
@@ -129,4 +879,281 @@ impl Entry {
     pub fn from_json(json: &String) -> serde_json::Result<Self> {
         serde_json::from_str(json)
     }
+
+    /// Decode a `kythe.proto.Entry` message. As in JSON, the fact value is
+    /// kept base64-encoded.
+    pub fn from_proto(bytes: &[u8]) -> Option<Self> {
+        let mut src = None;
+        let mut tgt = None;
+        let mut edge_kind = None;
+        let mut fact_name = String::new();
+        let mut fact_value = None;
+
+        for (field, value) in proto::fields(bytes)? {
+            match (field, value) {
+                (1, Value::Bytes(bytes)) => src = Some(Ticket::from_proto(bytes)?),
+                (2, Value::Bytes(bytes)) => edge_kind = Some(to_string(bytes)),
+                (3, Value::Bytes(bytes)) => tgt = Some(Ticket::from_proto(bytes)?),
+                (4, Value::Bytes(bytes)) => fact_name = to_string(bytes),
+                (5, Value::Bytes(bytes)) => fact_value = Some(base64::encode(bytes)),
+                _ => {}
+            }
+        }
+
+        let src = src.unwrap_or_default();
+
+        Some(match tgt {
+            Some(tgt) => Entry::Edge { src, tgt, edge_kind, fact_name, fact_value },
+            None => Entry::Node { src, fact_name, fact_value },
+        })
+    }
+
+    /// The edge kind of this entry, if it is an edge with a non-empty kind.
+    pub fn edge_kind(&self) -> Option<&str> {
+        match self {
+            Entry::Edge { edge_kind: Some(kind), .. } if !kind.is_empty() => Some(kind),
+            _ => None,
+        }
+    }
+}
+
+/// A borrowed version of `Entry`. When decoded from a line of JSON, strings
+/// which contain no escapes borrow from the line instead of being allocated,
+/// which is much cheaper for commands that only need to inspect each entry.
+#[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EntryRef<'a> {
+    Edge {
+        #[serde(rename = "source", borrow)]
+        src: TicketRef<'a>,
+        #[serde(rename = "target", borrow)]
+        tgt: TicketRef<'a>,
+        #[serde(default, borrow, deserialize_with = "borrow_opt")]
+        edge_kind: Option<Cow<'a, str>>,
+        #[serde(default, borrow)]
+        fact_name: Cow<'a, str>,
+        #[serde(default, borrow, deserialize_with = "borrow_opt")]
+        fact_value: Option<Cow<'a, str>>,
+    },
+    Node {
+        #[serde(rename = "source", borrow)]
+        src: TicketRef<'a>,
+        #[serde(borrow)]
+        fact_name: Cow<'a, str>,
+        #[serde(default, borrow, deserialize_with = "borrow_opt")]
+        fact_value: Option<Cow<'a, str>>,
+    },
+}
+
+impl<'a> EntryRef<'a> {
+    pub fn from_json(json: &'a str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// The edge kind of this entry, if it is an edge with a non-empty kind.
+    pub fn edge_kind(&self) -> Option<&str> {
+        match self {
+            EntryRef::Edge { edge_kind: Some(kind), .. } if !kind.is_empty() => Some(kind),
+            _ => None,
+        }
+    }
+
+    pub fn src(&self) -> &TicketRef<'a> {
+        match self {
+            EntryRef::Edge { src, .. } => src,
+            EntryRef::Node { src, .. } => src,
+        }
+    }
+}
+
+/// A borrowed version of `Ticket`.
+#[derive(serde::Deserialize, Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct TicketRef<'a> {
+    #[serde(default, borrow, deserialize_with = "borrow_opt")]
+    pub corpus: Option<Cow<'a, str>>,
+    #[serde(default, borrow, deserialize_with = "borrow_opt")]
+    pub language: Option<Cow<'a, str>>,
+    #[serde(default, borrow, deserialize_with = "borrow_opt")]
+    pub path: Option<Cow<'a, str>>,
+    #[serde(default, borrow, deserialize_with = "borrow_opt")]
+    pub root: Option<Cow<'a, str>>,
+    #[serde(default, borrow, deserialize_with = "borrow_opt")]
+    pub signature: Option<Cow<'a, str>>,
+}
+
+/// Serde only borrows a `Cow` directly, not one wrapped in an `Option`.
+fn borrow_opt<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    struct Borrowed<'a>(#[serde(borrow)] Cow<'a, str>);
+
+    let value: Option<Borrowed<'a>> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(value.map(|value| value.0))
+}
+
+impl Ticket {
+    pub fn borrowed(&self) -> TicketRef<'_> {
+        let borrow = |field: &Option<String>| field.as_deref().map(Cow::Borrowed);
+
+        TicketRef {
+            corpus: borrow(&self.corpus),
+            language: borrow(&self.language),
+            path: borrow(&self.path),
+            root: borrow(&self.root),
+            signature: borrow(&self.signature),
+        }
+    }
+}
+
+impl Entry {
+    pub fn borrowed(&self) -> EntryRef<'_> {
+        let borrow = |field: &Option<String>| field.as_deref().map(Cow::Borrowed);
+
+        match self {
+            Entry::Edge { src, tgt, edge_kind, fact_name, fact_value } => EntryRef::Edge {
+                src: src.borrowed(),
+                tgt: tgt.borrowed(),
+                edge_kind: borrow(edge_kind),
+                fact_name: Cow::Borrowed(fact_name),
+                fact_value: borrow(fact_value),
+            },
+            Entry::Node { src, fact_name, fact_value } => EntryRef::Node {
+                src: src.borrowed(),
+                fact_name: Cow::Borrowed(fact_name),
+                fact_value: borrow(fact_value),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_without_edge_kind() {
+        let json = r#"{"source":{"signature":"a"},"target":{"signature":"b"},"fact_name":"/"}"#;
+        let entry = Entry::from_json(&json.to_string()).unwrap();
+
+        assert!(matches!(entry, Entry::Edge { edge_kind: None, .. }));
+        assert_eq!(entry.edge_kind(), None);
+    }
+
+    #[test]
+    fn test_edge_with_empty_edge_kind() {
+        let json = r#"{"source":{"signature":"a"},"target":{"signature":"b"},"edge_kind":""}"#;
+        let entry = Entry::from_json(&json.to_string()).unwrap();
+
+        assert!(matches!(entry, Entry::Edge { .. }));
+        assert_eq!(entry.edge_kind(), None);
+    }
+
+    #[test]
+    fn test_edge_from_proto() {
+        let bytes = [
+            0x0a, 0x03, 0x0a, 0x01, b'a', // source { signature: "a" }
+            0x12, 0x01, b'k', // edge_kind: "k"
+            0x1a, 0x03, 0x0a, 0x01, b'b', // target { signature: "b" }
+        ];
+        let entry = Entry::from_proto(&bytes).unwrap();
+
+        assert_eq!(entry.edge_kind(), Some("k"));
+        assert!(matches!(entry, Entry::Edge { tgt: Ticket { signature: Some(_), .. }, .. }));
+    }
+
+    #[test]
+    fn test_node_is_not_edge() {
+        let json = r#"{"source":{"signature":"a"},"fact_name":"/kythe/node/kind","fact_value":"ZmlsZQ=="}"#;
+        let entry = Entry::from_json(&json.to_string()).unwrap();
+
+        assert!(matches!(entry, Entry::Node { .. }));
+        assert_eq!(entry.edge_kind(), None);
+    }
+
+    #[test]
+    fn test_lenient_skips_malformed_lines() {
+        let text = concat!(
+            r#"{"source":{"signature":"a"},"edge_kind":"k","target":{"signature":"b"}}"#,
+            "\nnot json\n",
+            r#"{"source":{"signature":"c"},"edge_kind":"k","target":{"signature":"d"}}"#,
+            "\n"
+        );
+        let reader = EntryReader::from_read(io::Cursor::new(text)).lenient(true);
+
+        assert_eq!(reader.into_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_malformed_entries_are_errors() {
+        let text = b"{\"source\":{},\"fact_name\":\"a\"}\n\xff\n{not json\n";
+        let entries = EntryReader::from_read(io::Cursor::new(&text[..])).into_iter().collect_vec();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_ok());
+        assert_eq!(entries[1].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let reader = EntryReader::from_read(io::Cursor::new(&text[..])).lenient(true);
+        assert_eq!(reader.into_iter().filter(Result::is_ok).count(), 1);
+
+        // A record cut short ends the stream rather than panicking
+        let truncated = [0x05, 0x0a];
+        let reader = EntryReader::from_read(io::Cursor::new(truncated)).lenient(true);
+        assert_eq!(reader.into_iter().count(), 0);
+        let reader = EntryReader::from_read(io::Cursor::new(truncated));
+        assert!(reader.into_iter().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parallel_preserves_order() {
+        let text = (0..100)
+            .map(|i| {
+                format!(r#"{{"source":{{"signature":"{}"}},"edge_kind":"k","target":{{}}}}"#, i)
+            })
+            .join("\n");
+        let reader = EntryReader::from_read(io::Cursor::new(text)).parallel(true);
+        let signatures = reader
+            .into_iter()
+            .map(|entry| match entry.unwrap() {
+                Entry::Edge { src, .. } => src.signature.unwrap(),
+                _ => panic!("expected an edge"),
+            })
+            .collect_vec();
+
+        assert_eq!(signatures, (0..100).map(|i| i.to_string()).collect_vec());
+    }
+
+    #[test]
+    fn test_entry_ref_borrows_unescaped_strings() {
+        let json = r#"{"source":{"path":"a.cc","signature":"f\u003cint\u003e"},"fact_name":"/kythe/node/kind"}"#;
+        let entry = EntryRef::from_json(json).unwrap();
+
+        assert!(matches!(entry.src().path, Some(Cow::Borrowed("a.cc"))));
+        assert_eq!(entry.src().signature.as_deref(), Some("f<int>"));
+        assert!(matches!(entry, EntryRef::Node { .. }));
+    }
+
+    /// Accepts at most three bytes per write, like a slow pipe.
+    struct ShortWriter(Vec<u8>);
+
+    impl io::Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_vectored_handles_short_writes() {
+        let bufs = vec![b"abcd".to_vec(), b"e".to_vec(), b"fghij".to_vec()];
+        let mut writer = ShortWriter(Vec::new());
+        write_all_vectored(&mut writer, &bufs).unwrap();
+
+        assert_eq!(writer.0, b"abcdefghij");
+    }
 }
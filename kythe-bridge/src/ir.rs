@@ -1,17 +1,25 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Display;
 use std::hash::Hash;
+use std::io::Write;
 use std::num::ParseIntError;
+use std::sync::Mutex;
 
 use bimap::BiHashMap;
 use itertools::Itertools;
+use sha2::{Digest, Sha256};
 
 use std::result::Result;
 
 use thiserror::Error;
 
 use crate::collections::KindedEdgeBag;
+use crate::files::FileStore;
 use crate::io::{Entry, EntryReader, Ticket};
+use crate::markedsource::MarkedSource;
+use crate::paths;
+use crate::progress::{Cancelled, Monitor, Stage};
 
 #[derive(Debug, Error)]
 pub enum IntoSpecErr {
@@ -43,11 +51,37 @@ pub enum IntoSpecErr {
     ExpectedInt(#[from] ParseIntError),
     #[error("failed to add node with ticket {0:?} and raw values {1:?}")]
     GraphBuildFailed(Ticket, RawNodeValue, #[source] Box<IntoSpecErr>),
+    #[error("failed to spill stripped fact")]
+    SpillFailed(#[from] std::io::Error),
+    #[error("failed to store file text")]
+    StoreFailed(#[source] std::io::Error),
+    #[error("found more than {1} {0} (see --max-{0})")]
+    LimitExceeded(&'static str, usize),
+    #[error("found a \"{0}\" fact of {1} bytes but at most {2} are allowed (see --max-fact-size)")]
+    FactTooLarge(String, usize, usize),
+    #[error("found a \"{0}\" fact whose value is not valid base64")]
+    InvalidFactValue(String, #[source] base64::DecodeError),
+    #[error("failed to read entries")]
+    ReadFailed(#[source] std::io::Error),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
 }
 
 type IntoSpecRes<T> = Result<T, IntoSpecErr>;
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 pub enum EdgeKind {
     Aliases,
     AliasesRoot,
@@ -87,6 +121,165 @@ pub enum EdgeKind {
     SpecializesSpeculative,
     Typed,
     Undefines,
+    /// An edge kind which is not recognized, only produced when loading
+    /// leniently.
+    Other(UnknownEdgeKind),
+    /// From a source node to a node generated from it (e.g. from a protobuf
+    /// message to the class generated for it). Comes after `Other` so that
+    /// the snapshot encoding of the kinds before it is unchanged.
+    Generates,
+}
+
+/// The name of an unrecognized edge kind (e.g. "/kythe/edge/imports").
+///
+/// Names are interned for the life of the process so that `EdgeKind` can stay
+/// `Copy`. There are only ever a handful of distinct unknown kinds.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnknownEdgeKind(&'static str);
+
+static UNKNOWN_EDGE_KINDS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+impl UnknownEdgeKind {
+    pub fn intern(name: &str) -> Self {
+        let mut names = UNKNOWN_EDGE_KINDS.lock().unwrap();
+
+        match names.iter().find(|known| **known == name) {
+            Some(known) => Self(known),
+            None => {
+                let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+                names.push(name);
+                Self(name)
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for UnknownEdgeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl serde::Serialize for UnknownEdgeKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for UnknownEdgeKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::intern(&String::deserialize(deserializer)?))
+    }
+}
+
+impl EdgeKind {
+    /// Whether this is one of the `/kythe/edge/ref` family of edges.
+    pub fn is_ref(&self) -> bool {
+        matches!(
+            self,
+            EdgeKind::Ref
+                | EdgeKind::RefCall
+                | EdgeKind::RefCallImplicit
+                | EdgeKind::RefDoc
+                | EdgeKind::RefExpands
+                | EdgeKind::RefExpandsTransitive
+                | EdgeKind::RefId
+                | EdgeKind::RefImplicit
+                | EdgeKind::RefIncludes
+                | EdgeKind::RefInit
+                | EdgeKind::RefInitImplicit
+                | EdgeKind::RefQueries
+                | EdgeKind::RefWrites
+                | EdgeKind::RefWritesImplicit
+        )
+    }
+
+    /// Whether this edge describes structure (containment or definition)
+    /// rather than a dependency.
+    pub fn is_structure(&self) -> bool {
+        matches!(
+            self,
+            EdgeKind::Childof
+                | EdgeKind::ChildofContext
+                | EdgeKind::Defines
+                | EdgeKind::DefinesBinding
+        )
+    }
+}
+
+/// What an edge means, independent of which way Kythe happens to point it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub enum Relation {
+    /// The source structurally contains the target (e.g. a class contains its
+    /// methods, or a function its parameters).
+    Contains,
+    /// The source is defined by the target, which is an anchor.
+    DefinedBy,
+    /// The source depends on the target.
+    DependsOn,
+    /// Anything else, such as documentation or completion.
+    Other,
+}
+
+impl EdgeKind {
+    /// The relation this edge expresses, and whether the raw edge points
+    /// against it. For instance, `Childof` points from child to parent, so it
+    /// is a reversed `Contains`.
+    pub fn relation(&self) -> (Relation, bool) {
+        match self {
+            EdgeKind::Childof | EdgeKind::ChildofContext => (Relation::Contains, true),
+            EdgeKind::Param(_) => (Relation::Contains, false),
+            EdgeKind::Defines | EdgeKind::DefinesBinding => (Relation::DefinedBy, true),
+            EdgeKind::Completedby
+            | EdgeKind::Completes
+            | EdgeKind::CompletesUniquely
+            | EdgeKind::Documents
+            | EdgeKind::Generates
+            | EdgeKind::RefDoc
+            | EdgeKind::Other(_) => (Relation::Other, false),
+            _ => (Relation::DependsOn, false),
+        }
+    }
+
+    /// Orient the endpoints of an edge of this kind so that `src` has the
+    /// returned relation to `tgt`.
+    pub fn normalize<T>(&self, src: T, tgt: T) -> (Relation, T, T) {
+        match self.relation() {
+            (relation, true) => (relation, tgt, src),
+            (relation, false) => (relation, src, tgt),
+        }
+    }
+}
+
+impl EdgeKind {
+    /// Parse an edge kind which may be a reverse edge (e.g. "%/kythe/edge/ref"
+    /// as found in serving data). Also returns whether it was reversed.
+    pub fn parse_directed(value: &str) -> IntoSpecRes<(Self, bool)> {
+        match value.strip_prefix('%') {
+            Some(value) => Ok((EdgeKind::try_from(value)?, true)),
+            None => Ok((EdgeKind::try_from(value)?, false)),
+        }
+    }
+
+    /// Like `parse_directed`, but an unknown edge kind becomes `Other` rather
+    /// than an error.
+    pub fn parse_directed_lenient(value: &str) -> IntoSpecRes<(Self, bool)> {
+        let (name, reversed) = match value.strip_prefix('%') {
+            Some(name) => (name, true),
+            None => (value, false),
+        };
+
+        match EdgeKind::try_from(name) {
+            Err(IntoSpecErr::UnknownEdgeKind(_)) => {
+                Ok((EdgeKind::Other(UnknownEdgeKind::intern(name)), reversed))
+            }
+            res => Ok((res?, reversed)),
+        }
+    }
 }
 
 impl TryFrom<&str> for EdgeKind {
@@ -108,6 +301,7 @@ impl TryFrom<&str> for EdgeKind {
             "/kythe/edge/extends/protected" => EdgeKind::ExtendsProtected,
             "/kythe/edge/extends/public" => EdgeKind::ExtendsPublic,
             "/kythe/edge/extends/public/virtual" => EdgeKind::ExtendsPublicVirtual,
+            "/kythe/edge/generates" => EdgeKind::Generates,
             "/kythe/edge/instantiates" => EdgeKind::Instantiates,
             "/kythe/edge/instantiates/speculative" => EdgeKind::InstantiatesSpeculative,
             "/kythe/edge/overrides" => EdgeKind::Overrides,
@@ -138,44 +332,65 @@ impl TryFrom<&str> for EdgeKind {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct RawNodeValue {
-    code: Option<String>,
-    complete: Option<String>,
-    loc_end: Option<String>,
-    loc_start: Option<String>,
-    node_kind: Option<String>,
-    param_default: Option<String>,
-    subkind: Option<String>,
-    tag_deprecated: Option<String>,
-    tag_static: Option<String>,
-    text: Option<String>,
+    pub(crate) build_config: Option<String>,
+    pub(crate) code: Option<String>,
+    pub(crate) complete: Option<String>,
+    pub(crate) context_url: Option<String>,
+    pub(crate) details: Option<String>,
+    pub(crate) loc_end: Option<String>,
+    pub(crate) loc_start: Option<String>,
+    pub(crate) message: Option<String>,
+    pub(crate) node_kind: Option<String>,
+    pub(crate) param_default: Option<String>,
+    pub(crate) subkind: Option<String>,
+    pub(crate) tag_deprecated: Option<String>,
+    pub(crate) tag_static: Option<String>,
+    pub(crate) text: Option<String>,
+    pub(crate) vcs_id: Option<String>,
+    pub(crate) vcs_type: Option<String>,
+    pub(crate) vcs_uri: Option<String>,
 }
 
+const FACT_BUILD_CONFIG: &'static str = "/kythe/build/config";
 const FACT_CODE: &'static str = "/kythe/code";
 const FACT_COMPLETE: &'static str = "/kythe/complete";
+const FACT_CONTEXT_URL: &'static str = "/kythe/context/url";
+const FACT_DETAILS: &'static str = "/kythe/details";
 const FACT_LOC_END: &'static str = "/kythe/loc/end";
 const FACT_LOC_START: &'static str = "/kythe/loc/start";
+const FACT_MESSAGE: &'static str = "/kythe/message";
 const FACT_NODE_KIND: &'static str = "/kythe/node/kind";
 const FACT_PARAM_DEFAULT: &'static str = "/kythe/param/default";
 const FACT_SUBKIND: &'static str = "/kythe/subkind";
 const FACT_TAG_DEPRECATED: &'static str = "/kythe/tag/deprecated";
 const FACT_TAG_STATIC: &'static str = "/kythe/tag/static";
 const FACT_TEXT: &'static str = "/kythe/text";
+const FACT_VCS_ID: &'static str = "/kythe/vcs/id";
+const FACT_VCS_TYPE: &'static str = "/kythe/vcs/type";
+const FACT_VCS_URI: &'static str = "/kythe/vcs/uri";
 
 impl RawNodeValue {
     fn get_mut(&mut self, fact_name: &str) -> IntoSpecRes<&mut Option<String>> {
         Ok(match fact_name {
+            FACT_BUILD_CONFIG => &mut self.build_config,
             FACT_CODE => &mut self.code,
             FACT_COMPLETE => &mut self.complete,
+            FACT_CONTEXT_URL => &mut self.context_url,
+            FACT_DETAILS => &mut self.details,
             FACT_LOC_END => &mut self.loc_end,
             FACT_LOC_START => &mut self.loc_start,
+            FACT_MESSAGE => &mut self.message,
             FACT_NODE_KIND => &mut self.node_kind,
             FACT_PARAM_DEFAULT => &mut self.param_default,
             FACT_SUBKIND => &mut self.subkind,
             FACT_TAG_DEPRECATED => &mut self.tag_deprecated,
             FACT_TAG_STATIC => &mut self.tag_static,
             FACT_TEXT => &mut self.text,
+            FACT_VCS_ID => &mut self.vcs_id,
+            FACT_VCS_TYPE => &mut self.vcs_type,
+            FACT_VCS_URI => &mut self.vcs_uri,
             _ => Err(IntoSpecErr::UnknownFactName(fact_name.to_string()))?,
         })
     }
@@ -184,21 +399,60 @@ impl RawNodeValue {
         Ok(self.get_mut(fact_name)?.replace(fact_value).is_none())
     }
 
+    /// The value of the `/kythe/node/kind` fact, if any.
+    pub fn node_kind(&self) -> Option<&str> {
+        self.node_kind.as_deref()
+    }
+
     fn to_text(self) -> IntoSpecRes<String> {
         self.text.ok_or(IntoSpecErr::MissingFact(FACT_TEXT))
     }
 
+    /// Fill in any facts missing from `self` with those from `other`.
+    pub(crate) fn merge(&mut self, other: RawNodeValue) {
+        fn fill(this: &mut Option<String>, that: Option<String>) {
+            if this.is_none() {
+                *this = that;
+            }
+        }
+
+        fill(&mut self.build_config, other.build_config);
+        fill(&mut self.code, other.code);
+        fill(&mut self.complete, other.complete);
+        fill(&mut self.context_url, other.context_url);
+        fill(&mut self.details, other.details);
+        fill(&mut self.loc_end, other.loc_end);
+        fill(&mut self.loc_start, other.loc_start);
+        fill(&mut self.message, other.message);
+        fill(&mut self.node_kind, other.node_kind);
+        fill(&mut self.param_default, other.param_default);
+        fill(&mut self.subkind, other.subkind);
+        fill(&mut self.tag_deprecated, other.tag_deprecated);
+        fill(&mut self.tag_static, other.tag_static);
+        fill(&mut self.text, other.text);
+        fill(&mut self.vcs_id, other.vcs_id);
+        fill(&mut self.vcs_type, other.vcs_type);
+        fill(&mut self.vcs_uri, other.vcs_uri);
+    }
+
     fn is_none(&self) -> bool {
-        self.code.is_none()
+        self.build_config.is_none()
+            && self.code.is_none()
             && self.complete.is_none()
+            && self.context_url.is_none()
+            && self.details.is_none()
             && self.loc_end.is_none()
             && self.loc_start.is_none()
+            && self.message.is_none()
             && self.node_kind.is_none()
             && self.param_default.is_none()
             && self.subkind.is_none()
             && self.tag_deprecated.is_none()
             && self.tag_static.is_none()
             && self.text.is_none()
+            && self.vcs_id.is_none()
+            && self.vcs_type.is_none()
+            && self.vcs_uri.is_none()
     }
 }
 
@@ -321,7 +575,11 @@ impl TryFrom<Option<&str>> for FunctionKind {
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
 pub enum Lang {
     Cpp,
+    Go,
     Java,
+    Python,
+    Rust,
+    TypeScript,
     Unspecified,
 }
 
@@ -331,7 +589,11 @@ impl TryFrom<Option<&str>> for Lang {
     fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
         match value {
             Some("c++") => Ok(Lang::Cpp),
+            Some("go") => Ok(Lang::Go),
             Some("java") => Ok(Lang::Java),
+            Some("python") => Ok(Lang::Python),
+            Some("rust") => Ok(Lang::Rust),
+            Some("typescript") => Ok(Lang::TypeScript),
             Some(str) => Err(IntoSpecErr::UnknownLang(str.to_string())),
             None => Ok(Lang::Unspecified),
         }
@@ -342,16 +604,49 @@ impl Display for Lang {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Lang::Cpp => write!(f, "c++"),
+            Lang::Go => write!(f, "go"),
             Lang::Java => write!(f, "java"),
+            Lang::Python => write!(f, "python"),
+            Lang::Rust => write!(f, "rust"),
+            Lang::TypeScript => write!(f, "typescript"),
             Lang::Unspecified => write!(f, "unspecified"),
         }
     }
 }
 
+impl Lang {
+    /// Guess the language of a file from the extension of its path.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, ext) = path.rsplit_once('.')?;
+
+        match ext {
+            "c" | "cc" | "cpp" | "cxx" | "h" | "hh" | "hpp" | "hxx" | "inc" => Some(Lang::Cpp),
+            "go" => Some(Lang::Go),
+            "java" => Some(Lang::Java),
+            "py" | "pyi" => Some(Lang::Python),
+            "rs" => Some(Lang::Rust),
+            "ts" | "tsx" => Some(Lang::TypeScript),
+            _ => None,
+        }
+    }
+
+    /// What separates the components of a qualified name in this language.
+    pub fn separator(&self) -> &'static str {
+        match self {
+            Lang::Cpp | Lang::Rust => "::",
+            _ => ".",
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
 pub enum RecordKind {
     Cpp(CppRecordKind),
+    Go(GoRecordKind),
     Java(JavaRecordKind),
+    Python(PythonRecordKind),
+    Rust(RustRecordKind),
+    TypeScript(TypeScriptRecordKind),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
@@ -392,13 +687,87 @@ impl TryFrom<Option<&str>> for JavaRecordKind {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum GoRecordKind {
+    Struct,
+}
+
+impl TryFrom<Option<&str>> for GoRecordKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("struct") => Ok(GoRecordKind::Struct),
+            Some(str) => Err(IntoSpecErr::UnknownRecordKind(Lang::Go, str.to_string()))?,
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum PythonRecordKind {
+    Class,
+}
+
+impl TryFrom<Option<&str>> for PythonRecordKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("class") => Ok(PythonRecordKind::Class),
+            Some(str) => Err(IntoSpecErr::UnknownRecordKind(Lang::Python, str.to_string()))?,
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum RustRecordKind {
+    Struct,
+    Union,
+}
+
+impl TryFrom<Option<&str>> for RustRecordKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("struct") => Ok(RustRecordKind::Struct),
+            Some("union") => Ok(RustRecordKind::Union),
+            Some(str) => Err(IntoSpecErr::UnknownRecordKind(Lang::Rust, str.to_string()))?,
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum TypeScriptRecordKind {
+    Class,
+}
+
+impl TryFrom<Option<&str>> for TypeScriptRecordKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("class") => Ok(TypeScriptRecordKind::Class),
+            Some(str) => Err(IntoSpecErr::UnknownRecordKind(Lang::TypeScript, str.to_string()))?,
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
 impl TryFrom<(Option<&str>, &Lang)> for RecordKind {
     type Error = IntoSpecErr;
 
     fn try_from((value, lang): (Option<&str>, &Lang)) -> IntoSpecRes<Self> {
         match lang {
             Lang::Cpp => Ok(RecordKind::Cpp(CppRecordKind::try_from(value)?)),
+            Lang::Go => Ok(RecordKind::Go(GoRecordKind::try_from(value)?)),
             Lang::Java => Ok(RecordKind::Java(JavaRecordKind::try_from(value)?)),
+            Lang::Python => Ok(RecordKind::Python(PythonRecordKind::try_from(value)?)),
+            Lang::Rust => Ok(RecordKind::Rust(RustRecordKind::try_from(value)?)),
+            Lang::TypeScript => Ok(RecordKind::TypeScript(TypeScriptRecordKind::try_from(value)?)),
             Lang::Unspecified => Err(IntoSpecErr::MissingLang),
         }
     }
@@ -408,6 +777,8 @@ impl TryFrom<(Option<&str>, &Lang)> for RecordKind {
 pub enum SumKind {
     Cpp(CppSumKind),
     Java(JavaSumKind),
+    Rust(RustSumKind),
+    TypeScript(TypeScriptSumKind),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
@@ -446,6 +817,40 @@ impl TryFrom<Option<&str>> for JavaSumKind {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum RustSumKind {
+    Enum,
+}
+
+impl TryFrom<Option<&str>> for RustSumKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("enum") => Ok(RustSumKind::Enum),
+            Some(str) => Err(IntoSpecErr::UnknownSumKind(Lang::Rust, str.to_string())),
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub enum TypeScriptSumKind {
+    Enum,
+}
+
+impl TryFrom<Option<&str>> for TypeScriptSumKind {
+    type Error = IntoSpecErr;
+
+    fn try_from(value: Option<&str>) -> IntoSpecRes<Self> {
+        match value {
+            Some("enum") => Ok(TypeScriptSumKind::Enum),
+            Some(str) => Err(IntoSpecErr::UnknownSumKind(Lang::TypeScript, str.to_string())),
+            None => Err(IntoSpecErr::MissingFact(FACT_SUBKIND)),
+        }
+    }
+}
+
 impl TryFrom<(Option<&str>, &Lang)> for SumKind {
     type Error = IntoSpecErr;
 
@@ -453,6 +858,13 @@ impl TryFrom<(Option<&str>, &Lang)> for SumKind {
         match lang {
             Lang::Cpp => Ok(SumKind::Cpp(CppSumKind::try_from(value)?)),
             Lang::Java => Ok(SumKind::Java(JavaSumKind::try_from(value)?)),
+            Lang::Rust => Ok(SumKind::Rust(RustSumKind::try_from(value)?)),
+            Lang::TypeScript => Ok(SumKind::TypeScript(TypeScriptSumKind::try_from(value)?)),
+            // Neither language has sum types
+            Lang::Go | Lang::Python => Err(IntoSpecErr::UnknownSumKind(
+                lang.clone(),
+                value.unwrap_or_default().to_string(),
+            )),
             Lang::Unspecified => Err(IntoSpecErr::MissingLang)?,
         }
     }
@@ -466,31 +878,68 @@ pub enum NodeKind {
     Absvar,
     Anchor(AnchorKind),
     Constant(String),
-    // Diagnostic(String),
+    /// The message of the diagnostic.
+    Diagnostic(String),
     Doc(String),
     File(String),
-    // Interface,
+    Interface,
     Function(CompleteStatus, FunctionKind),
     Lookup(String),
     Macro,
     Meta,
-    // Name,
+    Name,
     Package,
-    // Process,
+    Process,
     Record(CompleteStatus, RecordKind),
     Sum(CompleteStatus, SumKind),
-    // Symbol,
+    Symbol,
     Talias,
     Tapp,
     Tbuiltin,
     Tnominal,
     Tsigma,
-    // Tvar,
+    Tvar,
     Variable(CompleteStatus, VariableKind),
-    // Vcs,
+    /// The URI of the repository, if known.
+    Vcs(Option<String>),
     None, // Technically not allowed by spec but appears anyway.
 }
 
+impl NodeKind {
+    /// The Kythe name of this kind (e.g. "function"), without any subkind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Abs => "abs",
+            NodeKind::Absvar => "absvar",
+            NodeKind::Anchor(_) => "anchor",
+            NodeKind::Constant(_) => "constant",
+            NodeKind::Diagnostic(_) => "diagnostic",
+            NodeKind::Doc(_) => "doc",
+            NodeKind::File(_) => "file",
+            NodeKind::Function(_, _) => "function",
+            NodeKind::Interface => "interface",
+            NodeKind::Lookup(_) => "lookup",
+            NodeKind::Macro => "macro",
+            NodeKind::Meta => "meta",
+            NodeKind::Name => "name",
+            NodeKind::Package => "package",
+            NodeKind::Process => "process",
+            NodeKind::Record(_, _) => "record",
+            NodeKind::Sum(_, _) => "sum",
+            NodeKind::Symbol => "symbol",
+            NodeKind::Talias => "talias",
+            NodeKind::Tapp => "tapp",
+            NodeKind::Tbuiltin => "tbuiltin",
+            NodeKind::Tnominal => "tnominal",
+            NodeKind::Tsigma => "tsigma",
+            NodeKind::Tvar => "tvar",
+            NodeKind::Variable(_, _) => "variable",
+            NodeKind::Vcs(_) => "vcs",
+            NodeKind::None => "none",
+        }
+    }
+}
+
 impl TryFrom<(RawNodeValue, &Lang)> for NodeKind {
     type Error = IntoSpecErr;
 
@@ -504,16 +953,22 @@ impl TryFrom<(RawNodeValue, &Lang)> for NodeKind {
             Some("absvar") => Ok(NodeKind::Absvar),
             Some("anchor") => Ok(NodeKind::Anchor(AnchorKind::try_from(&value)?)),
             Some("constant") => Ok(NodeKind::Constant(value.to_text()?)),
+            Some("diagnostic") => Ok(NodeKind::Diagnostic(
+                value.message.ok_or(IntoSpecErr::MissingFact(FACT_MESSAGE))?,
+            )),
             Some("doc") => Ok(NodeKind::Doc(value.to_text()?)),
             Some("file") => Ok(NodeKind::File(value.to_text()?)),
             Some("function") => Ok(NodeKind::Function(
                 CompleteStatus::try_from(value.complete.as_deref())?,
                 FunctionKind::try_from(value.subkind.as_deref())?,
             )),
+            Some("interface") => Ok(NodeKind::Interface),
             Some("lookup") => Ok(NodeKind::Lookup(value.to_text()?)),
             Some("macro") => Ok(NodeKind::Macro),
             Some("meta") => Ok(NodeKind::Meta),
+            Some("name") => Ok(NodeKind::Name),
             Some("package") => Ok(NodeKind::Package),
+            Some("process") => Ok(NodeKind::Process),
             Some("record") => Ok(NodeKind::Record(
                 CompleteStatus::try_from(value.complete.as_deref())?,
                 RecordKind::try_from((value.subkind.as_deref(), lang))?,
@@ -526,27 +981,31 @@ impl TryFrom<(RawNodeValue, &Lang)> for NodeKind {
             Some("tapp") => Ok(NodeKind::Tapp),
             Some("tbuiltin") => Ok(NodeKind::Tbuiltin),
             Some("tnominal") => Ok(NodeKind::Tnominal),
+            Some("symbol") => Ok(NodeKind::Symbol),
             Some("tsigma") => Ok(NodeKind::Tsigma),
+            Some("tvar") => Ok(NodeKind::Tvar),
             Some("variable") => Ok(NodeKind::Variable(
                 CompleteStatus::try_from(value.complete.as_deref())?,
                 VariableKind::try_from(value.subkind.as_deref())?,
             )),
+            Some("vcs") => Ok(NodeKind::Vcs(value.vcs_uri)),
             Some(str) => Err(IntoSpecErr::UnknownNodeKind(str.to_string())),
             None => Err(IntoSpecErr::MissingFact(FACT_NODE_KIND)),
         }
     }
 }
 
+/// The corpus, root, and path of a ticket, which together identify a file.
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
-pub struct FileKey {
+pub struct FilePath {
     pub corpus: Option<String>,
     pub path: Option<String>,
     pub root: Option<String>,
 }
 
-impl From<&Ticket> for FileKey {
+impl From<&Ticket> for FilePath {
     fn from(ticket: &Ticket) -> Self {
-        FileKey {
+        FilePath {
             corpus: ticket.corpus.clone(),
             path: ticket.path.clone(),
             root: ticket.root.clone(),
@@ -554,29 +1013,182 @@ impl From<&Ticket> for FileKey {
     }
 }
 
+/// An interned `FilePath`, resolved through the `FileTable` of a `SpecGraph`.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct FileKey(pub u32);
+
+/// Every distinct `FilePath` of a graph, each assigned a `FileKey`.
+#[derive(Debug, Default)]
+pub struct FileTable {
+    paths: Vec<FilePath>,
+    keys: HashMap<FilePath, FileKey>,
+}
+
+impl FileTable {
+    /// The key of the file path of `ticket`, adding it if not yet present.
+    pub fn intern(&mut self, ticket: &Ticket) -> FileKey {
+        let path = FilePath::from(ticket);
+
+        if let Some(key) = self.keys.get(&path) {
+            return *key;
+        }
+
+        let key = FileKey(self.paths.len() as u32);
+        self.paths.push(path.clone());
+        self.keys.insert(path, key);
+        key
+    }
+
+    pub fn get(&self, path: &FilePath) -> Option<FileKey> {
+        self.keys.get(path).copied()
+    }
+
+    pub fn resolve(&self, key: FileKey) -> &FilePath {
+        &self.paths[key.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// Short names for (usually generated) roots, e.g. "bazel-out/k8-fastbuild/bin"
+/// displayed as "GEN".
+#[derive(Clone, Debug, Default)]
+pub struct RootAliases {
+    aliases: Vec<(String, String)>,
+}
+
+impl RootAliases {
+    pub fn new(aliases: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut aliases = aliases
+            .into_iter()
+            .map(|(prefix, alias)| (prefix.trim_end_matches('/').to_string(), alias))
+            .collect_vec();
+
+        // Prefer the most specific prefix
+        aliases.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Self { aliases }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The display path of a file. The root (if any) is prepended to the path
+    /// and then the longest matching prefix is replaced by its alias. Returns
+    /// `None` if no alias applies.
+    pub fn apply(&self, file_path: &FilePath) -> Option<String> {
+        let path = file_path.path.as_deref().unwrap_or_default();
+        let full = match file_path.root.as_deref() {
+            Some(root) if !root.is_empty() => format!("{}/{}", root, path),
+            _ => path.to_string(),
+        };
+
+        self.aliases.iter().find_map(|(prefix, alias)| {
+            let rest = paths::strip_dir_prefix(&full, prefix)?;
+            Some(format!("{}{}", alias, rest))
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
 pub struct Node {
     pub index: NodeIndex,
     pub signature: Option<String>,
     pub lang: Lang,
+    /// Whether `lang` was guessed because the ticket had no language.
+    pub lang_inferred: bool,
     pub file_key: FileKey,
     pub kind: NodeKind,
+    /// The identifier found in the `/kythe/code` fact, if any.
+    pub marked_name: Option<String>,
+    /// The build configuration this node was indexed under, if any.
+    pub build_config: Option<String>,
+}
+
+impl Node {
+    /// Rebuild the ticket this node was created from, given the path that its
+    /// `file_key` resolves to.
+    pub fn ticket(&self, file_path: &FilePath) -> Ticket {
+        Ticket {
+            corpus: file_path.corpus.clone(),
+            language: match (&self.lang, self.lang_inferred) {
+                (Lang::Unspecified, _) | (_, true) => None,
+                _ => Some(self.lang.to_string()),
+            },
+            path: file_path.path.clone(),
+            root: file_path.root.clone(),
+            signature: self.signature.clone(),
+        }
+    }
 }
 
-impl TryFrom<(NodeIndex, RawNodeValue, &Ticket)> for Node {
+/// A node's raw value and ticket, along with a guess at its language in case
+/// the ticket has none.
+type NodeParts<'a> = (NodeIndex, RawNodeValue, &'a Ticket, FileKey, Option<Lang>);
+
+impl TryFrom<NodeParts<'_>> for Node {
     type Error = IntoSpecErr;
 
-    fn try_from((index, raw, ticket): (NodeIndex, RawNodeValue, &Ticket)) -> IntoSpecRes<Self> {
+    fn try_from((index, mut raw, ticket, file_key, guess): NodeParts) -> IntoSpecRes<Self> {
         let signature = ticket.signature.clone();
-        let lang = Lang::try_from(ticket.language.as_deref())?;
-        let file_key = FileKey::from(ticket);
+        let (lang, lang_inferred) = match (Lang::try_from(ticket.language.as_deref())?, guess) {
+            (Lang::Unspecified, Some(guess)) => (guess, true),
+            (lang, _) => (lang, false),
+        };
+        let marked_name = raw
+            .code
+            .as_deref()
+            .and_then(|code| base64::decode(code).ok())
+            .and_then(|code| MarkedSource::decode(&code))
+            .and_then(|code| code.identifier());
+        // Taken first so that a node known only by its build config is `NodeKind::None`
+        let build_config = raw.build_config.take();
         let kind = NodeKind::try_from((raw, &lang))?;
 
-        Ok(Node { index, signature, lang, file_key, kind })
+        Ok(Node {
+            index,
+            signature,
+            lang,
+            lang_inferred,
+            file_key,
+            kind,
+            marked_name,
+            build_config,
+        })
     }
 }
 
-#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 pub struct NodeIndex(pub usize);
 
 impl Display for NodeIndex {
@@ -585,11 +1197,68 @@ impl Display for NodeIndex {
     }
 }
 
+/// An edge as a (kind, source, target, count) quadruple.
+pub type RawEdge = (EdgeKind, NodeIndex, NodeIndex, usize);
+
+/// The number of facts with a given name and the total size of their decoded
+/// values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FactSize {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+/// Options controlling how entries are loaded into a `RawGraph`.
+#[derive(Default)]
+pub struct RawGraphOptions {
+    /// Facts (e.g. "/kythe/text") whose values are replaced with an empty
+    /// string while loading.
+    pub strip_facts: HashSet<String>,
+    /// If present, stripped facts are written here as newline-delimited
+    /// entries.
+    pub spill: Option<Box<dyn std::io::Write>>,
+    /// Load unknown edge kinds as `EdgeKind::Other` instead of failing. Also
+    /// skip entries beyond the limits below rather than failing.
+    pub lenient: bool,
+    /// The most distinct nodes to load.
+    pub max_nodes: Option<usize>,
+    /// The most edge entries to load.
+    pub max_edges: Option<usize>,
+    /// The largest (decoded) fact value to load, in bytes. When lenient,
+    /// larger facts are stripped.
+    pub max_fact_size: Option<usize>,
+    /// Checked once per batch of entries.
+    pub monitor: Monitor,
+}
+
+/// How many entries were skipped for exceeding the limits of a
+/// `RawGraphOptions` (or for being invalid).
+#[derive(Debug, Default)]
+struct Truncation {
+    nodes: usize,
+    edges: usize,
+    facts: usize,
+    invalid: usize,
+}
+
+impl RawGraphOptions {
+    /// Fail unless lenient, in which case `skipped` is incremented instead.
+    fn exceed(&self, skipped: &mut usize, err: IntoSpecErr) -> IntoSpecRes<()> {
+        if !self.lenient {
+            return Err(err);
+        }
+
+        *skipped += 1;
+        Ok(())
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct RawGraph {
     nodes: Vec<RawNodeValue>,
     edges: KindedEdgeBag<EdgeKind, NodeIndex>,
     tickets: BiHashMap<Ticket, NodeIndex>,
+    fact_sizes: BTreeMap<String, FactSize>,
 }
 
 impl RawGraph {
@@ -605,50 +1274,426 @@ impl RawGraph {
         }
     }
 
+    /// Like `reserve`, but returns `None` rather than adding a node beyond
+    /// `max_nodes`.
+    fn try_reserve(&mut self, ticket: &Ticket, max_nodes: Option<usize>) -> Option<NodeIndex> {
+        if let Some(index) = self.tickets.get_by_left(ticket) {
+            return Some(*index);
+        }
+
+        match max_nodes {
+            Some(max) if self.nodes.len() >= max => None,
+            _ => Some(self.reserve(ticket.clone())),
+        }
+    }
+
+    /// Like `try_reserve` for both ends of an edge, but only adds either node
+    /// if there is room for both.
+    fn try_reserve_pair(
+        &mut self,
+        src: &Ticket,
+        tgt: &Ticket,
+        max_nodes: Option<usize>,
+    ) -> Option<(NodeIndex, NodeIndex)> {
+        if let Some(max) = max_nodes {
+            let is_new = |ticket: &Ticket| !self.tickets.contains_left(ticket);
+            let num_new = match src == tgt {
+                true => is_new(src) as usize,
+                false => is_new(src) as usize + is_new(tgt) as usize,
+            };
+
+            if self.nodes.len() + num_new > max {
+                return None;
+            }
+        }
+
+        Some((self.reserve(src.clone()), self.reserve(tgt.clone())))
+    }
+
     fn put_fact(&mut self, index: NodeIndex, name: String, value: String) -> IntoSpecRes<bool> {
         self.nodes[index.0].set(&name, value)
     }
 
-    fn put_edge(&mut self, kind: String, src: NodeIndex, tgt: NodeIndex) -> IntoSpecRes<usize> {
-        Ok(self.edges.insert(EdgeKind::try_from(kind.as_str())?, src, tgt))
+    fn put_edge(&mut self, kind: EdgeKind, src: NodeIndex, tgt: NodeIndex) -> usize {
+        self.edges.insert(kind, src, tgt)
     }
-}
 
-impl TryFrom<EntryReader> for RawGraph {
-    type Error = IntoSpecErr;
+    fn count_fact(&mut self, name: &str, bytes: usize) {
+        if !self.fact_sizes.contains_key(name) {
+            self.fact_sizes.insert(name.to_string(), FactSize::default());
+        }
 
-    fn try_from(reader: EntryReader) -> IntoSpecRes<Self> {
+        let size = self.fact_sizes.get_mut(name).unwrap();
+        size.count += 1;
+        size.bytes += bytes;
+    }
+
+    /// The sizes of all facts seen while loading, including stripped facts.
+    pub fn fact_sizes(&self) -> &BTreeMap<String, FactSize> {
+        &self.fact_sizes
+    }
+
+    /// Decompose into tickets and raw values (both ordered by `NodeIndex`) and
+    /// a list of edges.
+    pub fn into_parts(self) -> (Vec<Ticket>, Vec<RawNodeValue>, Vec<RawEdge>) {
+        let tickets = self
+            .tickets
+            .into_iter()
+            .sorted_by_key(|(_, index)| *index)
+            .map(|(ticket, _)| ticket)
+            .collect_vec();
+        let edges = self.edges.iter().collect_vec();
+        (tickets, self.nodes, edges)
+    }
+
+    pub fn tickets(&self) -> impl Iterator<Item = &Ticket> + '_ {
+        self.tickets.left_values()
+    }
+
+    /// Every edge, with its endpoints given as tickets rather than indices.
+    pub fn ticket_edges(&self) -> impl Iterator<Item = (EdgeKind, &Ticket, &Ticket, usize)> + '_ {
+        self.edges.iter().map(|(kind, src, tgt, count)| {
+            let src = self.tickets.get_by_right(&src).unwrap();
+            let tgt = self.tickets.get_by_right(&tgt).unwrap();
+            (kind, src, tgt, count)
+        })
+    }
+
+    /// The inverse of `into_parts`.
+    pub fn from_parts(tickets: Vec<Ticket>, nodes: Vec<RawNodeValue>, edges: Vec<RawEdge>) -> Self {
+        let mut graph = RawGraph::default();
+        graph.nodes = nodes;
+
+        for (i, ticket) in tickets.into_iter().enumerate() {
+            graph.tickets.insert(ticket, NodeIndex(i));
+        }
+
+        for (kind, src, tgt, count) in edges {
+            graph.edges.insert_count(kind, src, tgt, count);
+        }
+
+        graph.edges.compact();
+        graph
+    }
+
+    pub fn from_entries<I: IntoIterator<Item = Entry>>(entries: I) -> IntoSpecRes<Self> {
+        RawGraph::from_entries_with(entries, &mut RawGraphOptions::default())
+    }
+
+    pub fn from_entries_with<I: IntoIterator<Item = Entry>>(
+        entries: I,
+        options: &mut RawGraphOptions,
+    ) -> IntoSpecRes<Self> {
         let mut graph = RawGraph::default();
+        let mut num_kindless = 0;
+        let mut num_edges = 0;
+        let mut truncation = Truncation::default();
+        let mut num_unknown: BTreeMap<UnknownEdgeKind, usize> = BTreeMap::new();
+        let parse = match options.lenient {
+            true => EdgeKind::parse_directed_lenient,
+            false => EdgeKind::parse_directed,
+        };
+
+        // Reverse edges are flipped, then only added if the forward edge was
+        // not also seen (serving data usually contains both)
+        let mut reversed: HashMap<(EdgeKind, NodeIndex, NodeIndex), usize> = HashMap::new();
+        let mut num_entries = 0;
+
+        for entry in entries {
+            options.monitor.tick(Stage::Entries, num_entries, None)?;
+            num_entries += 1;
 
-        for entry in reader {
             match entry {
                 Entry::Edge { src, tgt, edge_kind, .. } => {
-                    let src_idx = graph.reserve(src);
-                    let tgt_idx = graph.reserve(tgt);
-                    graph.put_edge(edge_kind, src_idx, tgt_idx)?;
+                    if let Some(max) = options.max_edges.filter(|max| num_edges >= *max) {
+                        options.exceed(
+                            &mut truncation.edges,
+                            IntoSpecErr::LimitExceeded("edges", max),
+                        )?;
+                        continue;
+                    }
+
+                    let max_nodes = options.max_nodes;
+                    let (src_idx, tgt_idx) = match graph.try_reserve_pair(&src, &tgt, max_nodes) {
+                        Some(pair) => pair,
+                        None => {
+                            let err = IntoSpecErr::LimitExceeded("nodes", max_nodes.unwrap());
+                            options.exceed(&mut truncation.nodes, err)?;
+                            continue;
+                        }
+                    };
+
+                    num_edges += 1;
+
+                    match edge_kind.filter(|kind| !kind.is_empty()) {
+                        Some(edge_kind) => {
+                            let (kind, is_reversed) = parse(&edge_kind)?;
+
+                            if let EdgeKind::Other(name) = kind {
+                                *num_unknown.entry(name).or_default() += 1;
+                            }
+
+                            match is_reversed {
+                                true => *reversed.entry((kind, tgt_idx, src_idx)).or_default() += 1,
+                                false => {
+                                    graph.put_edge(kind, src_idx, tgt_idx);
+                                }
+                            }
+                        }
+                        None => num_kindless += 1,
+                    }
                 }
                 Entry::Node { src, fact_name, fact_value } => {
-                    let idx = graph.reserve(src);
-                    let decoded = base64::decode(fact_value.unwrap_or_default()).unwrap();
-                    let fact_value = String::from_utf8_lossy(&decoded).to_string();
-                    graph.put_fact(idx, fact_name, fact_value)?;
+                    // Checked against the encoded length so that oversized facts
+                    // are never decoded
+                    let encoded = fact_value.as_deref().unwrap_or_default();
+                    let size = decoded_len(encoded);
+                    let too_large = match options.max_fact_size {
+                        Some(max) if size > max => {
+                            let err = IntoSpecErr::FactTooLarge(fact_name.clone(), size, max);
+                            options.exceed(&mut truncation.facts, err)?;
+                            true
+                        }
+                        _ => false,
+                    };
+                    let decoded = match too_large {
+                        true => Vec::new(),
+                        false => match base64::decode(encoded) {
+                            Ok(decoded) => decoded,
+                            Err(err) => {
+                                let err = IntoSpecErr::InvalidFactValue(fact_name, err);
+                                options.exceed(&mut truncation.invalid, err)?;
+                                continue;
+                            }
+                        },
+                    };
+                    graph.count_fact(&fact_name, size);
+
+                    let idx = match graph.try_reserve(&src, options.max_nodes) {
+                        Some(idx) => idx,
+                        None => {
+                            let err =
+                                IntoSpecErr::LimitExceeded("nodes", options.max_nodes.unwrap());
+                            options.exceed(&mut truncation.nodes, err)?;
+                            continue;
+                        }
+                    };
+
+                    if !too_large && !options.strip_facts.contains(&fact_name) {
+                        // MarkedSource is a binary protobuf, so leave it encoded
+                        let fact_value = match fact_name.as_str() {
+                            FACT_CODE => fact_value.unwrap_or_default(),
+                            _ => String::from_utf8_lossy(&decoded).to_string(),
+                        };
+
+                        graph.put_fact(idx, fact_name, fact_value)?;
+                        continue;
+                    }
+
+                    if let Some(spill) = &mut options.spill {
+                        let entry = serde_json::json!({
+                            "source": &src,
+                            "fact_name": &fact_name,
+                            "fact_value": &fact_value,
+                        });
+                        serde_json::to_writer(&mut *spill, &entry).map_err(std::io::Error::from)?;
+                        spill.write_all(b"\n")?;
+                    }
+
+                    // Keep the fact (empty) so the node kind can still be determined
+                    graph.put_fact(idx, fact_name, String::new())?;
                 }
             }
         }
 
-        Ok(graph)
-    }
-}
+        options.monitor.finish(Stage::Entries, num_entries)?;
 
-pub enum NodeIndices {
-    None,
-    Sole(NodeIndex),
-    Many(Vec<NodeIndex>),
-}
+        // Otherwise errors writing the tail of the spill would be lost when it
+        // is dropped, leaving it silently truncated
+        if let Some(spill) = &mut options.spill {
+            spill.flush()?;
+        }
 
-impl From<Vec<NodeIndex>> for NodeIndices {
-    fn from(indices: Vec<NodeIndex>) -> Self {
-        match indices.len() {
+        graph.edges.compact();
+
+        for ((kind, src, tgt), count) in reversed {
+            if graph.edges.between(&src, &tgt).all(|(other, _)| other != kind) {
+                graph.edges.insert_count(kind, src, tgt, count);
+            }
+        }
+
+        graph.edges.compact();
+
+        if num_kindless > 0 {
+            log::warn!("Skipped {} edge(s) without an edge kind.", num_kindless);
+        }
+
+        if truncation.nodes > 0 {
+            log::warn!(
+                "Skipped {} entries which would have added more than {} nodes.",
+                truncation.nodes,
+                options.max_nodes.unwrap()
+            );
+        }
+
+        if truncation.edges > 0 {
+            log::warn!(
+                "Skipped {} edge(s) after reaching the limit of {}.",
+                truncation.edges,
+                options.max_edges.unwrap()
+            );
+        }
+
+        if truncation.facts > 0 {
+            log::warn!(
+                "Stripped {} fact(s) larger than {} bytes.",
+                truncation.facts,
+                options.max_fact_size.unwrap()
+            );
+        }
+
+        if truncation.invalid > 0 {
+            log::warn!("Skipped {} fact(s) with invalid base64 values.", truncation.invalid);
+        }
+
+        for (name, count) in num_unknown {
+            log::warn!("Loaded {} edge(s) of unknown kind \"{}\".", count, name.as_str());
+        }
+
+        Ok(graph)
+    }
+}
+
+/// How to decide that two file nodes are really the same file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileDedup {
+    /// Same corpus and same normalized path, regardless of root.
+    Path,
+    /// Same text, regardless of corpus, root, or path. Files without text
+    /// (e.g. because it was stripped) are left alone.
+    Text,
+}
+
+impl RawGraph {
+    /// Unify file nodes which represent the same source file (e.g. because it
+    /// was indexed under several roots or build variants). Every node
+    /// belonging to a duplicate file is moved to the canonical file, nodes
+    /// which then share a ticket are merged, and their edges are combined.
+    /// Returns the new graph and the number of file nodes removed.
+    pub fn dedup_files(self, by: FileDedup) -> (Self, usize) {
+        let (tickets, nodes, edges) = self.into_parts();
+
+        // Group file nodes by their identity
+        let mut groups: HashMap<String, Vec<FilePath>> = HashMap::new();
+
+        for (ticket, node) in tickets.iter().zip(&nodes) {
+            if node.node_kind.as_deref() != Some("file") {
+                continue;
+            }
+
+            let key = match by {
+                FileDedup::Path => format!(
+                    "{}\0{}",
+                    ticket.corpus.as_deref().unwrap_or_default(),
+                    paths::normalize(ticket.path.as_deref().unwrap_or_default())
+                ),
+                FileDedup::Text => match node.text.as_deref() {
+                    None | Some("") => continue,
+                    Some(text) => {
+                        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                        text.hash(&mut hasher);
+                        format!("{:x}", std::hash::Hasher::finish(&hasher))
+                    }
+                },
+            };
+
+            groups.entry(key).or_default().push(FilePath::from(ticket));
+        }
+
+        // Point every duplicate at the smallest key of its group
+        let mut remap: HashMap<FilePath, FilePath> = HashMap::new();
+
+        for file_paths in groups.into_values().filter(|paths| paths.len() > 1) {
+            let canonical = file_paths.iter().min().unwrap().clone();
+
+            for file_path in file_paths {
+                if file_path != canonical {
+                    remap.insert(file_path, canonical.clone());
+                }
+            }
+        }
+
+        let num_removed = remap.len();
+
+        if num_removed == 0 {
+            return (RawGraph::from_parts(tickets, nodes, edges), 0);
+        }
+
+        // Rewrite tickets and merge nodes which now collide
+        let mut new_tickets: Vec<Ticket> = Vec::new();
+        let mut new_nodes: Vec<RawNodeValue> = Vec::new();
+        let mut indices: HashMap<Ticket, NodeIndex> = HashMap::new();
+        let mut old_to_new: Vec<NodeIndex> = Vec::with_capacity(tickets.len());
+
+        for (ticket, node) in tickets.into_iter().zip(nodes) {
+            let ticket = match remap.get(&FilePath::from(&ticket)) {
+                None => ticket,
+                Some(canonical) => Ticket {
+                    corpus: canonical.corpus.clone(),
+                    path: canonical.path.clone(),
+                    root: canonical.root.clone(),
+                    ..ticket
+                },
+            };
+
+            match indices.get(&ticket) {
+                Some(index) => {
+                    new_nodes[index.0].merge(node);
+                    old_to_new.push(*index);
+                }
+                None => {
+                    let index = NodeIndex(new_nodes.len());
+                    indices.insert(ticket.clone(), index);
+                    new_tickets.push(ticket);
+                    new_nodes.push(node);
+                    old_to_new.push(index);
+                }
+            }
+        }
+
+        let edges = edges
+            .into_iter()
+            .map(|(kind, src, tgt, count)| (kind, old_to_new[src.0], old_to_new[tgt.0], count))
+            .collect_vec();
+
+        (RawGraph::from_parts(new_tickets, new_nodes, edges), num_removed)
+    }
+}
+
+/// The number of bytes that a base64 value decodes to, without decoding it.
+/// Exact when the value is valid (padded or not).
+fn decoded_len(encoded: &str) -> usize {
+    encoded.trim_end_matches('=').len() * 3 / 4
+}
+
+impl TryFrom<EntryReader> for RawGraph {
+    type Error = IntoSpecErr;
+
+    fn try_from(reader: EntryReader) -> IntoSpecRes<Self> {
+        itertools::process_results(reader, |entries| RawGraph::from_entries(entries))
+            .map_err(IntoSpecErr::ReadFailed)?
+    }
+}
+
+pub enum NodeIndices {
+    None,
+    Sole(NodeIndex),
+    Many(Vec<NodeIndex>),
+}
+
+impl From<Vec<NodeIndex>> for NodeIndices {
+    fn from(indices: Vec<NodeIndex>) -> Self {
+        match indices.len() {
             0 => NodeIndices::None,
             1 => NodeIndices::Sole(indices[0]),
             _ => NodeIndices::Many(indices),
@@ -678,11 +1723,98 @@ pub enum ResolveAnchorErr {
     OutOfBounds,
 }
 
-type ResolveAnchorRes<'a> = Result<&'a str, ResolveAnchorErr>;
+type ResolveAnchorRes<'a> = Result<Cow<'a, str>, ResolveAnchorErr>;
+
+/// Slice `text` by the byte offsets of `pos`. If either offset lands inside a
+/// multi-byte character, the slice is widened to include that character.
+/// Returns `None` if `pos` is backwards or extends past the end of `text`.
+pub fn slice_text<'a>(text: &'a str, pos: &Pos) -> Option<&'a str> {
+    if pos.start > pos.end || pos.end > text.len() {
+        return None;
+    }
+
+    let mut start = pos.start;
+    let mut end = pos.end;
+
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    Some(&text[start..end])
+}
+
+/// Decode exactly the bytes of `text` covered by `pos` (clamped to the end of
+/// `text`), replacing any partial characters with U+FFFD.
+pub fn slice_text_lossy<'a>(text: &'a str, pos: &Pos) -> Cow<'a, str> {
+    let end = pos.end.min(text.len());
+    let start = pos.start.min(end);
+    String::from_utf8_lossy(&text.as_bytes()[start..end])
+}
+
+/// A file whose text does not contain all of its anchors. Such anchors cannot
+/// be resolved, so any names taken from them are lost.
+#[derive(Debug, Default)]
+pub struct TextMismatch {
+    pub text_len: usize,
+    /// The number of anchors which are out of bounds or backwards.
+    pub num_anchors: usize,
+    /// The furthest end of any of these anchors.
+    pub max_end: usize,
+    pub samples: Vec<Pos>,
+}
+
+/// The byte offset at which each line of a file's text starts.
+#[derive(Clone, Debug, Default)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts =
+            std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+
+        Self { starts }
+    }
+
+    /// The zero-based line containing byte `offset`, along with the offset at
+    /// which that line starts.
+    pub fn line(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|start| *start <= offset) - 1;
+        (line, self.starts[line])
+    }
+
+    pub fn num_lines(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The number of lines in a text of `len` bytes as counted by
+    /// `str::lines`, i.e. without an empty line after a trailing newline.
+    pub fn count_lines(&self, len: usize) -> usize {
+        match self.starts.last() {
+            Some(start) if *start == len => self.starts.len() - 1,
+            _ => self.starts.len(),
+        }
+    }
+}
+
+/// How many files with mismatched text are reported individually.
+const MAX_REPORTED_MISMATCHES: usize = 10;
+
+/// How many mismatched anchors are kept as examples for each file.
+const MAX_MISMATCH_SAMPLES: usize = 3;
 
 pub struct SpecGraph {
     nodes: Vec<Node>,
-    files: HashMap<FileKey, NodeIndex>,
+    file_paths: FileTable,
+    /// The file node of each `FileKey`, if any.
+    files: Vec<Option<NodeIndex>>,
+    /// The text of each file node, which is taken out of the node itself.
+    texts: FileStore,
     edges: KindedEdgeBag<EdgeKind, NodeIndex>,
 }
 
@@ -691,6 +1823,25 @@ impl SpecGraph {
         self.nodes.get(index.0).unwrap()
     }
 
+    /// The corpus, root, and path of the file `node` belongs to.
+    pub fn file_path(&self, node: &Node) -> &FilePath {
+        self.file_paths.resolve(node.file_key)
+    }
+
+    pub fn file_paths(&self) -> &FileTable {
+        &self.file_paths
+    }
+
+    /// Rebuild the ticket `node` was created from.
+    pub fn ticket(&self, node: &Node) -> Ticket {
+        node.ticket(self.file_path(node))
+    }
+
+    /// The file node of `file_key`, if any.
+    pub fn get_file(&self, file_key: FileKey) -> Option<NodeIndex> {
+        *self.files.get(file_key.0 as usize)?
+    }
+
     pub fn resolve_anchor(&self, node: &Node) -> ResolveAnchorRes {
         let pos = match &node.kind {
             NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
@@ -698,38 +1849,175 @@ impl SpecGraph {
             _ => Err(ResolveAnchorErr::NotAnchor)?,
         };
 
-        let file_index = match self.files.get(&node.file_key) {
-            Some(file_index) => file_index,
-            None => Err(ResolveAnchorErr::FileNotFound)?,
+        if !self.texts.contains(node.file_key) {
+            Err(ResolveAnchorErr::FileNotFound)?
+        }
+
+        self.texts.slice(node.file_key, pos).ok_or(ResolveAnchorErr::OutOfBounds)
+    }
+
+    /// Like `resolve_anchor`, but never fails because of the position of the
+    /// anchor. See `slice_text_lossy`.
+    pub fn resolve_anchor_lossy(&self, node: &Node) -> Result<Cow<str>, ResolveAnchorErr> {
+        let pos = match &node.kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+            NodeKind::Anchor(_) => Err(ResolveAnchorErr::NotExplicitAnchor)?,
+            _ => Err(ResolveAnchorErr::NotAnchor)?,
         };
 
-        let text = match &self.nodes[file_index.0].kind {
-            NodeKind::File(text) => text,
-            _ => unreachable!(),
+        self.texts.slice_lossy(node.file_key, pos).ok_or(ResolveAnchorErr::FileNotFound)
+    }
+
+    /// The lines of the file `file_key`, if it has a file node.
+    pub fn line_index(&self, file_key: FileKey) -> Option<&LineIndex> {
+        self.texts.lines(file_key)
+    }
+
+    /// The number of lines in the text of `file_key` (see
+    /// `LineIndex::count_lines`), without reading the text itself.
+    pub fn count_lines(&self, file_key: FileKey) -> Option<usize> {
+        Some(self.texts.lines(file_key)?.count_lines(self.texts.text_len(file_key)?))
+    }
+
+    /// The one-based line and column (in characters) at which the anchor
+    /// `index` starts.
+    pub fn resolve_position(&self, index: NodeIndex) -> Result<(usize, usize), ResolveAnchorErr> {
+        let node = self.get_node(index);
+        let pos = match &node.kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+            NodeKind::Anchor(_) => Err(ResolveAnchorErr::NotExplicitAnchor)?,
+            _ => Err(ResolveAnchorErr::NotAnchor)?,
         };
 
-        match text.get(pos.start..pos.end) {
-            Some(str) => Ok(str),
-            None => Err(ResolveAnchorErr::OutOfBounds),
+        if !self.texts.contains(node.file_key) {
+            Err(ResolveAnchorErr::FileNotFound)?
         }
+
+        self.texts.position(node.file_key, pos.start).ok_or(ResolveAnchorErr::OutOfBounds)
     }
 
-    pub fn get_file_text(&self, file_key: &FileKey) -> Option<&String> {
-        let file_index = self.files.get(file_key)?;
-        match &self.nodes[file_index.0].kind {
-            NodeKind::File(text) => Some(text),
-            _ => None,
+    /// Files whose text does not contain every one of their anchors, e.g.
+    /// because the text fact was truncated. Files with empty text are skipped
+    /// since that usually means the text was stripped on purpose.
+    pub fn text_mismatches(&self) -> BTreeMap<FileKey, TextMismatch> {
+        let mut mismatches: BTreeMap<FileKey, TextMismatch> = BTreeMap::new();
+
+        for node in &self.nodes {
+            let pos = match &node.kind {
+                NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+                _ => continue,
+            };
+
+            let text_len = match self.texts.text_len(node.file_key) {
+                Some(text_len) if text_len > 0 => text_len,
+                _ => continue,
+            };
+
+            // Widening to whole characters never fails, so only these can
+            if pos.start <= pos.end && pos.end <= text_len {
+                continue;
+            }
+
+            let mismatch = mismatches
+                .entry(node.file_key)
+                .or_insert_with(|| TextMismatch { text_len, ..Default::default() });
+            mismatch.num_anchors += 1;
+            mismatch.max_end = mismatch.max_end.max(pos.end);
+
+            if mismatch.samples.len() < MAX_MISMATCH_SAMPLES {
+                mismatch.samples.push(pos.clone());
+            }
+        }
+
+        mismatches
+    }
+
+    /// Warn about the first few files found by `text_mismatches`.
+    fn report_text_mismatches(&self) {
+        let mismatches = self.text_mismatches();
+
+        for (file_key, mismatch) in mismatches.iter().take(MAX_REPORTED_MISMATCHES) {
+            log::warn!(
+                "Found {} anchor(s) past the {} byte(s) of text of {} (up to byte {}), e.g. {}.",
+                mismatch.num_anchors,
+                mismatch.text_len,
+                self.file_paths.resolve(*file_key).path.as_deref().unwrap_or("<none>"),
+                mismatch.max_end,
+                mismatch.samples.iter().map(|pos| format!("{}..{}", pos.start, pos.end)).join(", ")
+            );
+        }
+
+        if mismatches.len() > MAX_REPORTED_MISMATCHES {
+            log::warn!(
+                "Found anchors outside of the text of {} more file(s).",
+                mismatches.len() - MAX_REPORTED_MISMATCHES
+            );
         }
     }
 
+    /// The text of the file `file_key`, if it has a file node. Use `texts` to
+    /// get at only part of it.
+    pub fn get_file_text(&self, file_key: FileKey) -> Option<Cow<str>> {
+        self.texts.text(file_key)
+    }
+
+    /// The text of every file node.
+    pub fn texts(&self) -> &FileStore {
+        &self.texts
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (EdgeKind, NodeIndex, NodeIndex, usize)> + '_ {
         self.edges.iter()
     }
 
+    /// Iterate over the edges of a single kind.
+    pub fn iter_kind(
+        &self,
+        kind: EdgeKind,
+    ) -> impl Iterator<Item = (NodeIndex, NodeIndex, usize)> + '_ {
+        self.edges.iter_kind(&kind)
+    }
+
+    /// Iterate over every `/kythe/edge/ref` edge (including subkinds).
+    pub fn iter_ref_edges(
+        &self,
+    ) -> impl Iterator<Item = (EdgeKind, NodeIndex, NodeIndex, usize)> + '_ {
+        self.edges.iter_where(EdgeKind::is_ref)
+    }
+
+    /// Iterate over every childof and defines edge (including subkinds).
+    pub fn iter_structure_edges(
+        &self,
+    ) -> impl Iterator<Item = (EdgeKind, NodeIndex, NodeIndex, usize)> + '_ {
+        self.edges.iter_where(EdgeKind::is_structure)
+    }
+
+    /// Every kind of edge from `src` to `tgt` along with its count.
+    pub fn edges_between(&self, src: NodeIndex, tgt: NodeIndex) -> Vec<(EdgeKind, usize)> {
+        self.edges.between(&src, &tgt).collect_vec()
+    }
+
     pub fn iter_nodes(&self) -> impl Iterator<Item = &Node> + '_ {
         self.nodes.iter()
     }
 
+    /// Every outgoing edge of `index`, regardless of kind.
+    pub fn outgoing_all(&self, index: NodeIndex) -> Vec<(EdgeKind, NodeIndex, usize)> {
+        self.edges.outgoing_all(&index).collect_vec()
+    }
+
+    /// The number of `NodeKind::None` nodes in each corpus. These nodes have
+    /// no facts at all, which usually indicates truncated input.
+    pub fn count_none_by_corpus(&self) -> BTreeMap<Option<String>, usize> {
+        self.nodes
+            .iter()
+            .filter(|node| node.kind == NodeKind::None)
+            .map(|node| self.file_path(node).corpus.clone())
+            .counts()
+            .into_iter()
+            .collect()
+    }
+
     pub fn incoming(&self, kind: EdgeKind, index: NodeIndex) -> NodeIndices {
         self.edges.incoming(&kind, &index).map(|(i, _)| i).collect_vec().into()
     }
@@ -737,32 +2025,158 @@ impl SpecGraph {
     pub fn outgoing(&self, kind: EdgeKind, index: NodeIndex) -> NodeIndices {
         self.edges.outgoing(&kind, &index).map(|(i, _)| i).collect_vec().into()
     }
+
+    /// The node(s) which structurally contain the node at `index`. Both
+    /// `Childof` and `ChildofContext` edges are considered, in this order:
+    ///
+    /// 1. A sole `Childof` parent.
+    /// 2. A sole `ChildofContext` parent. Members of C++ templates often have
+    ///    a `Childof` edge to each instantiation (or none at all), while the
+    ///    context edge points at the template they are declared in.
+    /// 3. The sole `Childof` parent which is also a `ChildofContext` parent.
+    /// 4. Otherwise, every `Childof` parent.
+    pub fn parents(&self, index: NodeIndex) -> NodeIndices {
+        let parents = match self.outgoing(EdgeKind::Childof, index) {
+            NodeIndices::Sole(parent) => return NodeIndices::Sole(parent),
+            parents => Vec::from(parents),
+        };
+
+        let contexts = match self.outgoing(EdgeKind::ChildofContext, index) {
+            NodeIndices::Sole(context) => return NodeIndices::Sole(context),
+            contexts => Vec::from(contexts),
+        };
+
+        match parents.iter().filter(|parent| contexts.contains(parent)).exactly_one() {
+            Ok(parent) => NodeIndices::Sole(*parent),
+            Err(_) => parents.into(),
+        }
+    }
+}
+
+/// Guess the language of each node whose ticket has none, first from the
+/// extension of its path and otherwise from the languages of its neighbors
+/// (the most common one wins). File nodes are skipped, since a file is not
+/// written in the language of the nodes it contains (e.g. a header included
+/// from both C and C++) and its path is already known.
+fn infer_langs(raw_graph: &RawGraph) -> HashMap<NodeIndex, Lang> {
+    let mut guesses = HashMap::new();
+    let mut unresolved = HashSet::new();
+
+    for (ticket, index) in raw_graph.tickets.iter() {
+        if ticket.language.is_some() || raw_graph.nodes[index.0].node_kind() == Some("file") {
+            continue;
+        }
+
+        if let Some(lang) = ticket.path.as_deref().and_then(Lang::from_path) {
+            guesses.insert(*index, lang);
+        } else {
+            unresolved.insert(*index);
+        }
+    }
+
+    let num_by_path = guesses.len();
+
+    if !unresolved.is_empty() {
+        let known = |index: NodeIndex| match raw_graph.tickets.get_by_right(&index) {
+            Some(Ticket { language: Some(language), .. }) => {
+                Lang::try_from(Some(language.as_str())).ok()
+            }
+            _ => guesses.get(&index).cloned(),
+        };
+
+        let mut votes: HashMap<NodeIndex, BTreeMap<Lang, usize>> = HashMap::new();
+
+        for (_, src, tgt, _) in raw_graph.edges.iter() {
+            for (index, neighbor) in [(src, tgt), (tgt, src)] {
+                if unresolved.contains(&index) {
+                    if let Some(lang) = known(neighbor) {
+                        *votes.entry(index).or_default().entry(lang).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        for (index, counts) in votes {
+            // Ties go to whichever language sorts first
+            let best = counts.into_iter().max_by(|(a, m), (b, n)| m.cmp(n).then(b.cmp(a)));
+
+            if let Some((lang, _)) = best {
+                guesses.insert(index, lang);
+            }
+        }
+    }
+
+    if !guesses.is_empty() {
+        log::info!(
+            "Inferred the language of {} node(s) ({} from their path and {} from their neighbors).",
+            guesses.len(),
+            num_by_path,
+            guesses.len() - num_by_path
+        );
+    }
+
+    guesses
 }
 
 impl TryFrom<RawGraph> for SpecGraph {
     type Error = IntoSpecErr;
 
     fn try_from(raw_graph: RawGraph) -> IntoSpecRes<Self> {
+        SpecGraph::from_raw_with(raw_graph, &mut Monitor::default())
+    }
+}
+
+impl SpecGraph {
+    /// Like `SpecGraph::try_from`, but check `monitor` once per batch of
+    /// nodes.
+    pub fn from_raw_with(raw_graph: RawGraph, monitor: &mut Monitor) -> IntoSpecRes<Self> {
+        SpecGraph::from_raw_into(raw_graph, monitor, FileStore::new())
+    }
+
+    /// Like `SpecGraph::from_raw_with`, but keep the text of each file in
+    /// `texts` (e.g. a store created with `FileStore::spilling`).
+    pub fn from_raw_into(
+        raw_graph: RawGraph,
+        monitor: &mut Monitor,
+        mut texts: FileStore,
+    ) -> IntoSpecRes<Self> {
+        let mut guesses = infer_langs(&raw_graph);
         let edges = raw_graph.edges;
         let mut nodes = Vec::with_capacity(raw_graph.nodes.len());
-        let mut files = HashMap::new();
+        let mut file_paths = FileTable::default();
+        let mut files = Vec::new();
+        let num_nodes = raw_graph.nodes.len();
 
         for (i, raw_node) in raw_graph.nodes.into_iter().enumerate() {
+            monitor.tick(Stage::Nodes, i, Some(num_nodes))?;
             let index = NodeIndex(i);
             let ticket = raw_graph.tickets.get_by_right(&index).unwrap();
+            let file_key = file_paths.intern(ticket);
             let duplicate = raw_node.clone();
-            let node = Node::try_from((index, raw_node, ticket)).map_err(|e| {
-                IntoSpecErr::GraphBuildFailed(ticket.clone(), duplicate, Box::new(e))
-            })?;
-
-            if let NodeKind::File(_) = node.kind {
-                files.insert(node.file_key.clone(), index);
+            let guess = guesses.remove(&index);
+            let mut node =
+                Node::try_from((index, raw_node, ticket, file_key, guess)).map_err(|e| {
+                    IntoSpecErr::GraphBuildFailed(ticket.clone(), duplicate, Box::new(e))
+                })?;
+
+            if let NodeKind::File(text) = &mut node.kind {
+                files.resize(file_paths.len(), None);
+                files[file_key.0 as usize] = Some(index);
+                texts.insert(file_key, std::mem::take(text)).map_err(IntoSpecErr::StoreFailed)?;
             }
 
             nodes.push(node);
         }
 
-        Ok(SpecGraph { nodes, files, edges })
+        monitor.finish(Stage::Nodes, num_nodes)?;
+        files.resize(file_paths.len(), None);
+        if texts.num_spilled() > 0 {
+            log::info!("Spilled the text of {} file(s) to disk.", texts.num_spilled());
+        }
+
+        let graph = SpecGraph { nodes, file_paths, files, texts, edges };
+        graph.report_text_mismatches();
+        Ok(graph)
     }
 }
 
@@ -776,61 +2190,287 @@ pub enum IntoEntityErr {
     // FileNotRoot,
     #[error("failed to resolve anchor")]
     InvalidBinding(#[from] ResolveAnchorErr),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
 }
 
 type IntoEntityRes<T> = Result<T, IntoEntityErr>;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
 pub struct Entity {
     pub id: NodeIndex,
+    pub stable_id: StableId,
     pub parent_ids: Vec<NodeIndex>,
     pub name: String,
+    pub name_source: NameSource,
+    /// The names of the entity and its ancestors (see `SpecGraph::parents`), e.g.
+    /// `com.foo.Bar.baz`.
+    pub qualified_name: String,
     pub path: String,
 
     #[serde(flatten)]
     pub kind: NodeKind,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+
+    /// The name of the nearest enclosing package (e.g. a Java package), or of
+    /// the entity itself if it is a package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+
+    /// User-provided columns joined onto the entity (see
+    /// `annotations::Annotations`).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// A parameter of a function entity, folded in from a `Param` edge.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Param {
+    pub index: u8,
+    pub id: NodeIndex,
+    pub name: String,
+    pub type_id: Option<NodeIndex>,
+}
+
+/// Where the name of an entity was taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NameSource {
+    /// The identifier in the `/kythe/code` fact.
+    MarkedSource,
+    /// The text of the node itself (if an anchor) or of its binding anchor.
+    Binding,
+    /// The signature of the ticket.
+    Signature,
+    /// The entire ticket.
+    Ticket,
+    /// None of the requested sources were available.
+    Unknown,
+}
+
+impl NameSource {
+    /// The order in which sources are tried unless told otherwise.
+    pub const DEFAULT_PRIORITY: [NameSource; 4] =
+        [NameSource::MarkedSource, NameSource::Binding, NameSource::Signature, NameSource::Ticket];
 }
 
 impl Entity {
-    fn new(graph: &SpecGraph, id: NodeIndex) -> IntoEntityRes<Self> {
-        let parent_ids = graph.outgoing(EdgeKind::Childof, id).into();
+    fn new(graph: &SpecGraph, id: NodeIndex, name_sources: &[NameSource]) -> IntoEntityRes<Self> {
+        let parent_ids = graph.parents(id).into();
         let node = graph.get_node(id);
+        // File texts stay in the `FileStore`, so file entities carry none
         let kind = node.kind.clone();
-        let path = node.file_key.path.as_ref().unwrap().clone();
+        let file_path = graph.file_path(node);
+        let path = file_path.path.as_ref().unwrap().clone();
+        let stable_id = StableId::new(file_path, node.signature.as_deref());
+        let (name, name_source) = resolve_name(graph, node, name_sources)?;
+        let qualified_name = qualified_name(graph, id, &name, name_sources)?;
+        let package = match kind {
+            NodeKind::Package => Some(name.clone()),
+            _ => package_name(graph, id, name_sources)?,
+        };
 
-        if let Ok(name) = graph.resolve_anchor(node) {
-            return Ok(Entity { id, parent_ids, name: name.to_string(), path, kind });
+        Ok(Entity {
+            id,
+            stable_id,
+            parent_ids,
+            name,
+            name_source,
+            qualified_name,
+            path,
+            kind,
+            params: Vec::new(),
+            package,
+            annotations: BTreeMap::new(),
+        })
+    }
+}
+
+/// An identifier derived from the corpus, path, and signature of an entity's
+/// ticket. Unlike its `NodeIndex`, this does not depend on the order entries
+/// were loaded in, so it can be used to match entities across snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StableId(pub u64);
+
+impl StableId {
+    pub fn new(file_path: &FilePath, signature: Option<&str>) -> Self {
+        let mut hasher = Sha256::new();
+
+        for part in [file_path.corpus.as_deref(), file_path.path.as_deref(), signature] {
+            hasher.update(part.unwrap_or_default().as_bytes());
+            hasher.update([0]);
+        }
+
+        let digest = hasher.finalize();
+        StableId(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+    }
+}
+
+impl Display for StableId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Serialized as a hex string, since many JSON readers cannot represent every
+/// `u64` exactly.
+impl serde::Serialize for StableId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Follow parents up from `id` (stopping at its file, if any) and join
+/// the names along the way, outermost first.
+fn qualified_name(
+    graph: &SpecGraph,
+    id: NodeIndex,
+    name: &str,
+    name_sources: &[NameSource],
+) -> IntoEntityRes<String> {
+    let separator = graph.get_node(id).lang.separator();
+    let mut names = vec![name.to_string()];
+    let mut id = id;
+
+    for _ in 0..MAX_PACKAGE_DEPTH {
+        id = match graph.parents(id) {
+            NodeIndices::Sole(parent_id) => parent_id,
+            _ => break,
         };
 
-        let name = match graph.incoming(EdgeKind::DefinesBinding, id) {
-            NodeIndices::None => "???".to_string(),
-            NodeIndices::Sole(index) => match graph.resolve_anchor(graph.get_node(index)) {
-                Ok(name) => name.to_string(),
-                Err(ResolveAnchorErr::NotExplicitAnchor) => "?imp?".to_string(),
-                Err(err) => Err(IntoEntityErr::InvalidBinding(err))?,
-            },
-            NodeIndices::Many(_) => Err(IntoEntityErr::ManyBindingsFound)?,
+        let node = graph.get_node(id);
+
+        if let NodeKind::File(_) = node.kind {
+            break;
+        }
+
+        names.push(resolve_name(graph, node, name_sources)?.0);
+    }
+
+    names.reverse();
+    Ok(names.join(separator))
+}
+
+/// Follow parents up from `id` until reaching a package.
+fn package_name(
+    graph: &SpecGraph,
+    id: NodeIndex,
+    name_sources: &[NameSource],
+) -> IntoEntityRes<Option<String>> {
+    let mut id = id;
+
+    for _ in 0..MAX_PACKAGE_DEPTH {
+        id = match graph.parents(id) {
+            NodeIndices::Sole(parent_id) => parent_id,
+            _ => return Ok(None),
+        };
+
+        let node = graph.get_node(id);
+
+        if node.kind == NodeKind::Package {
+            return Ok(Some(resolve_name(graph, node, name_sources)?.0));
+        }
+    }
+
+    Ok(None)
+}
+
+/// How far up to look for a package before giving up (e.g. due to a cycle).
+const MAX_PACKAGE_DEPTH: usize = 32;
+
+/// How to group entities into coarser units, such as the rows of a DSM or
+/// metrics table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The path of the file each entity is in.
+    #[default]
+    Path,
+    /// The package each entity is in. Entities outside of any package (e.g.
+    /// because they are not Java) are grouped by path instead.
+    Package,
+    /// The directory of the file each entity is in, cut off after this many
+    /// levels. Files at the top level are grouped together as ".".
+    Dir(usize),
+}
+
+impl GroupBy {
+    pub fn key<'a>(&self, entity: &'a Entity) -> &'a str {
+        match (self, &entity.package) {
+            (GroupBy::Package, Some(package)) => package,
+            (GroupBy::Dir(depth), _) => paths::dir_prefix(&entity.path, *depth),
+            _ => &entity.path,
+        }
+    }
+}
+
+/// Take the name from the first source in `name_sources` that has one.
+fn resolve_name(
+    graph: &SpecGraph,
+    node: &Node,
+    name_sources: &[NameSource],
+) -> IntoEntityRes<(String, NameSource)> {
+    for source in name_sources {
+        let name = match source {
+            NameSource::MarkedSource => node.marked_name.clone(),
+            NameSource::Binding => binding_name(graph, node)?,
+            NameSource::Signature => node.signature.clone(),
+            NameSource::Ticket => Some(graph.ticket(node).to_string()),
+            NameSource::Unknown => None,
         };
 
-        Ok(Entity { id, parent_ids, name, path, kind })
+        if let Some(name) = name {
+            return Ok((name, *source));
+        }
     }
+
+    Ok(("???".to_string(), NameSource::Unknown))
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+fn binding_name(graph: &SpecGraph, node: &Node) -> IntoEntityRes<Option<String>> {
+    if let Ok(name) = graph.resolve_anchor(node) {
+        return Ok(Some(name.to_string()));
+    };
+
+    match graph.incoming(EdgeKind::DefinesBinding, node.index) {
+        NodeIndices::None => Ok(None),
+        NodeIndices::Sole(index) => match graph.resolve_anchor(graph.get_node(index)) {
+            Ok(name) => Ok(Some(name.to_string())),
+            Err(ResolveAnchorErr::NotExplicitAnchor) => Ok(None),
+            Err(err) => Err(IntoEntityErr::InvalidBinding(err)),
+        },
+        NodeIndices::Many(_) => Err(IntoEntityErr::ManyBindingsFound),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
 pub struct Dep {
     pub src: NodeIndex,
     pub tgt: NodeIndex,
     pub kind: EdgeKind,
     pub count: usize,
+
+    /// The build configuration of the source, so that deps from different
+    /// configurations can be told apart.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
 }
 
 impl Dep {
-    fn new(src: NodeIndex, tgt: NodeIndex, kind: EdgeKind, count: usize) -> Self {
-        Dep { src, tgt, kind, count }
+    /// The relation of this dep along with its endpoints, oriented so that
+    /// the source has that relation to the target.
+    pub fn normalized(&self) -> (Relation, NodeIndex, NodeIndex) {
+        self.kind.normalize(self.src, self.tgt)
+    }
+
+    fn new(spec: &SpecGraph, src: NodeIndex, tgt: NodeIndex, kind: EdgeKind, count: usize) -> Self {
+        let config = spec.get_node(src).build_config.clone();
+        Dep { src, tgt, kind, count, config }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct EntityGraph {
     pub entities: HashMap<NodeIndex, Entity>,
     pub deps: Vec<Dep>,
@@ -838,7 +2478,7 @@ pub struct EntityGraph {
 
 #[allow(dead_code)]
 fn ancestory(spec: &SpecGraph, id: NodeIndex) -> IntoEntityRes<Vec<NodeIndex>> {
-    let mut ancestory = match spec.outgoing(EdgeKind::Childof, id) {
+    let mut ancestory = match spec.parents(id) {
         NodeIndices::None => Vec::new(),
         NodeIndices::Sole(parent_id) => ancestory(spec, parent_id)?,
         NodeIndices::Many(_) => panic!(),
@@ -848,21 +2488,575 @@ fn ancestory(spec: &SpecGraph, id: NodeIndex) -> IntoEntityRes<Vec<NodeIndex>> {
     Ok(ancestory)
 }
 
+/// What to do with `NodeKind::None` nodes (nodes which only appear as the
+/// endpoint of an edge) when building entities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonePolicy {
+    /// Remove these nodes along with any edges touching them.
+    Drop,
+    /// Keep these nodes as entities.
+    #[default]
+    Keep,
+    /// Attribute the edges of each such node to the single node it has
+    /// outgoing edges to. If there is no such single node, drop it instead.
+    MergeIntoTarget,
+}
+
+/// Receives entities and deps incrementally as an `EntityGraph` is built.
+///
+/// Every entity is sent before any dep. If building fails part way through,
+/// the sink will have seen only part of the graph.
+pub trait EntitySink {
+    fn entity(&mut self, entity: Entity);
+
+    fn dep(&mut self, dep: Dep);
+
+    /// Called once after the last entity and dep have been sent.
+    fn finish(&mut self) {}
+}
+
+impl EntitySink for EntityGraph {
+    fn entity(&mut self, entity: Entity) {
+        self.entities.insert(entity.id, entity);
+    }
+
+    fn dep(&mut self, dep: Dep) {
+        self.deps.push(dep);
+    }
+}
+
+/// Options for building an `EntityGraph`.
+#[derive(Clone, Debug)]
+pub struct EntityGraphOptions {
+    pub none_policy: NonePolicy,
+    /// Where to take the name of each entity from, in order of preference.
+    pub name_sources: Vec<NameSource>,
+    /// If given, drop nodes indexed under any other build configuration (along
+    /// with their edges). Nodes without a configuration are always kept.
+    pub configs: Option<HashSet<String>>,
+    /// Merge each declaration into the definition that completes it (e.g. a
+    /// C++ function declared in a header and defined elsewhere), so the pair
+    /// counts as one entity.
+    pub merge_declarations: bool,
+}
+
+impl Default for EntityGraphOptions {
+    fn default() -> Self {
+        Self {
+            none_policy: NonePolicy::default(),
+            name_sources: NameSource::DEFAULT_PRIORITY.to_vec(),
+            configs: None,
+            merge_declarations: false,
+        }
+    }
+}
+
+impl EntityGraph {
+    pub fn new(spec: &SpecGraph, options: &EntityGraphOptions) -> IntoEntityRes<Self> {
+        EntityGraph::new_with(spec, options, &mut Monitor::default())
+    }
+
+    /// Like `EntityGraph::new`, but check `monitor` once per batch of nodes.
+    pub fn new_with(
+        spec: &SpecGraph,
+        options: &EntityGraphOptions,
+        monitor: &mut Monitor,
+    ) -> IntoEntityRes<Self> {
+        let mut graph = EntityGraph::default();
+        EntityGraph::stream_with(spec, options, &mut graph, monitor)?;
+        Ok(graph)
+    }
+
+    /// Like `EntityGraph::new`, but send each entity and dep to `sink` as soon
+    /// as it is ready instead of collecting them.
+    ///
+    /// Deps are only streamed one at a time when no nodes are dropped or merged
+    /// (by the none policy, configs, or declaration merging). Otherwise they
+    /// must first be combined, so they all arrive at the end.
+    pub fn stream<S: EntitySink + ?Sized>(
+        spec: &SpecGraph,
+        options: &EntityGraphOptions,
+        sink: &mut S,
+    ) -> IntoEntityRes<()> {
+        EntityGraph::stream_with(spec, options, sink, &mut Monitor::default())
+    }
+
+    /// Like `EntityGraph::stream`, but check `monitor` once per batch of
+    /// nodes. If cancelled, `sink` is left unfinished.
+    pub fn stream_with<S: EntitySink + ?Sized>(
+        spec: &SpecGraph,
+        options: &EntityGraphOptions,
+        sink: &mut S,
+        monitor: &mut Monitor,
+    ) -> IntoEntityRes<()> {
+        let none_policy = options.none_policy;
+        let mut redirects: HashMap<NodeIndex, Option<NodeIndex>> = HashMap::new();
+        let definitions = match options.merge_declarations {
+            true => definitions(spec, options),
+            false => HashMap::new(),
+        };
+
+        let num_nodes = spec.nodes.len();
+
+        for (i, node) in spec.iter_nodes().enumerate() {
+            monitor.tick(Stage::Entities, i, Some(num_nodes))?;
+
+            if !options.keeps_config(node) {
+                redirects.insert(node.index, None);
+                continue;
+            }
+
+            if let Some(definition) = definitions.get(&node.index) {
+                redirects.insert(node.index, Some(*definition));
+                continue;
+            }
+
+            if node.kind == NodeKind::None && none_policy != NonePolicy::Keep {
+                redirects.insert(
+                    node.index,
+                    match none_policy {
+                        NonePolicy::MergeIntoTarget => merge_target(spec, node.index),
+                        _ => None,
+                    },
+                );
+                continue;
+            }
+
+            let mut entity = Entity::new(spec, node.index, &options.name_sources)?;
+
+            for parent_id in entity.parent_ids.iter_mut() {
+                if let Some(definition) = definitions.get(parent_id) {
+                    *parent_id = *definition;
+                }
+            }
+
+            sink.entity(entity);
+        }
+
+        monitor.finish(Stage::Entities, num_nodes)?;
+
+        if redirects.is_empty() {
+            for (kind, src, tgt, count) in spec.iter() {
+                sink.dep(Dep::new(spec, src, tgt, kind, count));
+            }
+
+            sink.finish();
+            return Ok(());
+        }
+
+        // Follow each chain of redirects to its end, since the target of one
+        // may itself be redirected (e.g. a none node merged into a declaration
+        // which is merged into its definition). A cycle drops every node on it.
+        let resolved: HashMap<NodeIndex, Option<NodeIndex>> = redirects
+            .keys()
+            .map(|index| {
+                let mut target = Some(*index);
+
+                for _ in 0..=redirects.len() {
+                    match target.and_then(|target| redirects.get(&target)) {
+                        None => return (*index, target),
+                        Some(next) => target = *next,
+                    }
+                }
+
+                (*index, None)
+            })
+            .collect();
+        let redirect = |index: NodeIndex| match resolved.get(&index) {
+            None => Some(index),
+            Some(target) => *target,
+        };
+
+        // Redirect (or drop) the endpoints of each edge, then combine edges
+        // which have become identical. Only self-loops created by redirecting
+        // are dropped, not those which were already in the graph.
+        let mut counts: HashMap<(NodeIndex, NodeIndex, EdgeKind), usize> = HashMap::new();
+
+        for (kind, src, tgt, count) in spec.iter() {
+            if let (Some(new_src), Some(new_tgt)) = (redirect(src), redirect(tgt)) {
+                if new_src != new_tgt || src == tgt {
+                    *counts.entry((new_src, new_tgt, kind)).or_default() += count;
+                }
+            }
+        }
+
+        for ((src, tgt, kind), count) in counts {
+            sink.dep(Dep::new(spec, src, tgt, kind, count));
+        }
+
+        sink.finish();
+        Ok(())
+    }
+}
+
+impl EntityGraph {
+    /// Replace the path of every entity with its aliased display path.
+    pub fn alias_roots(&mut self, spec: &SpecGraph, aliases: &RootAliases) {
+        for entity in self.entities.values_mut() {
+            if let Some(path) = aliases.apply(spec.file_path(spec.get_node(entity.id))) {
+                entity.path = path;
+            }
+        }
+    }
+}
+
+impl EntityGraph {
+    /// Remove every `Param` dep and instead record it as a parameter of its
+    /// source entity, along with the type of the parameter (via `Typed`). The
+    /// parameter types are also appended to the qualified name of the source
+    /// (e.g. `com.foo.Bar.baz(int)`).
+    pub fn fold_params(&mut self, spec: &SpecGraph) {
+        let mut params: HashMap<NodeIndex, Vec<Param>> = HashMap::new();
+        let entities = &self.entities;
+
+        self.deps.retain(|dep| {
+            let index = match dep.kind {
+                EdgeKind::Param(index) => index,
+                _ => return true,
+            };
+
+            let name = match entities.get(&dep.tgt) {
+                Some(entity) => entity.name.clone(),
+                None => "???".to_string(),
+            };
+
+            let type_id = match spec.outgoing(EdgeKind::Typed, dep.tgt) {
+                NodeIndices::Sole(type_id) => Some(type_id),
+                _ => None,
+            };
+
+            let param = Param { index, id: dep.tgt, name, type_id };
+            params.entry(dep.src).or_default().push(param);
+            false
+        });
+
+        for (id, mut params) in params {
+            params.sort();
+
+            let types = params
+                .iter()
+                .map(|param| match param.type_id.and_then(|type_id| self.entities.get(&type_id)) {
+                    Some(entity) => entity.name.as_str(),
+                    None => "?",
+                })
+                .join(", ");
+
+            if let Some(entity) = self.entities.get_mut(&id) {
+                entity.qualified_name = format!("{}({})", entity.qualified_name, types);
+                entity.params = params;
+            }
+        }
+    }
+}
+
+impl EntityGraph {
+    /// The entity each anchor belongs to (see `lift_anchors`), or `None` if
+    /// it belongs to no entity.
+    pub fn anchor_owners(&self, spec: &SpecGraph) -> HashMap<NodeIndex, Option<NodeIndex>> {
+        self.entities
+            .values()
+            .filter(|entity| matches!(entity.kind, NodeKind::Anchor(_)))
+            .map(|entity| {
+                let owner =
+                    anchor_owner(spec, entity.id).filter(|owner| self.entities.contains_key(owner));
+                (entity.id, owner)
+            })
+            .collect()
+    }
+
+    /// Attribute every dep to or from an anchor to the entity the anchor
+    /// belongs to instead, then remove the anchors. So a `RefCall` from an
+    /// anchor in the body of `f` to `g` becomes a `RefCall` from `f` to `g`.
+    ///
+    /// Deps which become self-loops (such as `DefinesBinding` from an anchor
+    /// to its own entity) are dropped, as are deps of anchors which belong to
+    /// no entity. Returns the number of anchors removed.
+    pub fn lift_anchors(&mut self, spec: &SpecGraph) -> usize {
+        let owners = self.anchor_owners(spec);
+
+        if owners.is_empty() {
+            return 0;
+        }
+
+        let lift = |index: NodeIndex| match owners.get(&index) {
+            None => Some(index),
+            Some(owner) => *owner,
+        };
+
+        let mut counts: HashMap<(NodeIndex, NodeIndex, EdgeKind), usize> = HashMap::new();
+
+        for dep in self.deps.drain(..) {
+            if let (Some(src), Some(tgt)) = (lift(dep.src), lift(dep.tgt)) {
+                if src != tgt {
+                    *counts.entry((src, tgt, dep.kind)).or_default() += dep.count;
+                }
+            }
+        }
+
+        self.deps = counts
+            .into_iter()
+            .map(|((src, tgt, kind), count)| Dep::new(spec, src, tgt, kind, count))
+            .collect();
+        self.entities.retain(|id, _| !owners.contains_key(id));
+        owners.len()
+    }
+}
+
+/// The deps from one file to another, counted by kind.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileDep {
+    pub src: FileKey,
+    pub tgt: FileKey,
+    pub counts: BTreeMap<EdgeKind, usize>,
+}
+
+impl FileDep {
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+impl EntityGraph {
+    /// Collapse every dep between two entities into a dep between their
+    /// files, in the semantic direction of the dep (see `Relation`). Deps
+    /// within a single file are kept. Sorted by source and then target.
+    pub fn rollup_to_files(&self, spec: &SpecGraph) -> Vec<FileDep> {
+        let mut rollup: BTreeMap<(FileKey, FileKey), BTreeMap<EdgeKind, usize>> = BTreeMap::new();
+
+        for dep in &self.deps {
+            let (_, src, tgt) = dep.normalized();
+
+            if !self.entities.contains_key(&src) || !self.entities.contains_key(&tgt) {
+                continue;
+            }
+
+            let key = (spec.get_node(src).file_key, spec.get_node(tgt).file_key);
+            *rollup.entry(key).or_default().entry(dep.kind).or_default() += dep.count;
+        }
+
+        rollup.into_iter().map(|((src, tgt), counts)| FileDep { src, tgt, counts }).collect()
+    }
+}
+
+/// The nearest non-anchor that `anchor` belongs to, found by following
+/// `DefinesBinding`, then `Childof`, then `Defines` edges.
+fn anchor_owner(spec: &SpecGraph, anchor: NodeIndex) -> Option<NodeIndex> {
+    let mut index = anchor;
+
+    for _ in 0..MAX_LIFT_DEPTH {
+        index = [EdgeKind::DefinesBinding, EdgeKind::Childof, EdgeKind::Defines]
+            .into_iter()
+            .find_map(|kind| match spec.outgoing(kind, index) {
+                NodeIndices::Sole(owner) => Some(owner),
+                _ => None,
+            })?;
+
+        if !matches!(spec.get_node(index).kind, NodeKind::Anchor(_)) {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+/// How many anchors to pass through when looking for the owner of an anchor.
+const MAX_LIFT_DEPTH: usize = 8;
+
+impl EntityGraphOptions {
+    /// Whether `node` survives the build configuration filter.
+    fn keeps_config(&self, node: &Node) -> bool {
+        match (&self.configs, &node.build_config) {
+            (Some(configs), Some(config)) => configs.contains(config),
+            _ => true,
+        }
+    }
+}
+
+/// Map each declaration to the definition that completes it. A `Completes`
+/// edge points from the binding anchor of the definition to the declaration.
+/// Declarations completed by more than one (kept) definition are left alone.
+fn definitions(spec: &SpecGraph, options: &EntityGraphOptions) -> HashMap<NodeIndex, NodeIndex> {
+    let mut candidates: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
+
+    for (kind, anchor, declaration, _) in spec.iter() {
+        if !matches!(kind, EdgeKind::Completes | EdgeKind::CompletesUniquely) {
+            continue;
+        }
+
+        if let NodeIndices::Sole(definition) = spec.outgoing(EdgeKind::DefinesBinding, anchor) {
+            if definition != declaration && options.keeps_config(spec.get_node(definition)) {
+                candidates.entry(declaration).or_default().insert(definition);
+            }
+        }
+    }
+
+    let definitions: HashMap<_, _> = candidates
+        .into_iter()
+        .filter_map(|(declaration, definitions)| match definitions.len() {
+            1 => Some((declaration, definitions.into_iter().next().unwrap())),
+            _ => None,
+        })
+        .collect();
+
+    log::debug!("Found {} declaration(s) with a single definition.", definitions.len());
+    definitions
+}
+
+/// The single node (which is not itself `NodeKind::None`) that `index` has
+/// outgoing edges to, if any.
+fn merge_target(spec: &SpecGraph, index: NodeIndex) -> Option<NodeIndex> {
+    let targets = spec
+        .outgoing_all(index)
+        .into_iter()
+        .map(|(_, tgt, _)| tgt)
+        .filter(|tgt| spec.get_node(*tgt).kind != NodeKind::None)
+        .unique()
+        .collect_vec();
+
+    match targets.as_slice() {
+        [target] => Some(*target),
+        _ => None,
+    }
+}
+
 impl TryFrom<SpecGraph> for EntityGraph {
     type Error = IntoEntityErr;
 
     fn try_from(spec: SpecGraph) -> IntoEntityRes<Self> {
-        let mut entities = HashMap::new();
+        EntityGraph::new(&spec, &EntityGraphOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_text() {
+        // "é" is two bytes (1..3)
+        let text = "xéy";
+        assert_eq!(slice_text(text, &Pos { start: 1, end: 3 }), Some("é"));
+        assert_eq!(slice_text(text, &Pos { start: 2, end: 3 }), Some("é"));
+        assert_eq!(slice_text(text, &Pos { start: 0, end: 2 }), Some("xé"));
+        assert_eq!(slice_text(text, &Pos { start: 3, end: 5 }), None);
+        assert_eq!(slice_text(text, &Pos { start: 2, end: 1 }), None);
+    }
 
-        for node in spec.iter_nodes() {
-            entities.insert(node.index, Entity::new(&spec, node.index)?);
+    #[test]
+    fn test_slice_text_lossy() {
+        let text = "xéy";
+        assert_eq!(slice_text_lossy(text, &Pos { start: 0, end: 2 }), "x\u{FFFD}");
+        assert_eq!(slice_text_lossy(text, &Pos { start: 3, end: 9 }), "y");
+    }
+
+    #[test]
+    fn test_line_index() {
+        let lines = LineIndex::new("ab\ncd\n\ne");
+        assert_eq!(lines.num_lines(), 4);
+        assert_eq!(lines.line(0), (0, 0));
+        assert_eq!(lines.line(2), (0, 0));
+        assert_eq!(lines.line(3), (1, 3));
+        assert_eq!(lines.line(6), (2, 6));
+        assert_eq!(lines.line(9), (3, 7));
+
+        for text in ["", "a", "a\n", "a\n\nb", "a\r\nb\r\n"] {
+            assert_eq!(LineIndex::new(text).count_lines(text.len()), text.lines().count());
         }
+    }
 
-        let deps = spec
-            .iter()
-            .map(|(kind, src, tgt, count)| Dep::new(src, tgt, kind, count))
-            .collect_vec();
+    #[test]
+    fn test_stable_id_ignores_root() {
+        let path = |root: &str| FilePath {
+            corpus: Some("corpus".to_string()),
+            path: Some("a/b.cc".to_string()),
+            root: Some(root.to_string()),
+        };
+
+        let a = StableId::new(&path("bazel-out/k8-opt"), Some("sig"));
+        assert_eq!(a, StableId::new(&path("bazel-out/k8-dbg"), Some("sig")));
+        assert_ne!(a, StableId::new(&path("bazel-out/k8-opt"), Some("sig2")));
+        assert_eq!(a.to_string().len(), 16);
+    }
+
+    fn file(corpus: &str, root: &str, path: &str, text: Option<&str>) -> (Ticket, RawNodeValue) {
+        let ticket = Ticket {
+            corpus: Some(corpus.to_string()),
+            path: Some(path.to_string()),
+            root: Some(root.to_string()),
+            ..Default::default()
+        };
+        let node = RawNodeValue {
+            node_kind: Some("file".to_string()),
+            text: text.map(str::to_string),
+            ..Default::default()
+        };
+        (ticket, node)
+    }
+
+    #[test]
+    fn test_dedup_files() {
+        let (tickets, nodes): (Vec<_>, Vec<_>) = [
+            file("c", "bazel-out/k8-opt", "a/./b.cc", Some("int x;")),
+            file("c", "bazel-out/k8-dbg", "a/b.cc", Some("int x;")),
+            file("c", "", "a/c.cc", Some("int x;")),
+            file("c", "", "a/d.cc", Some("")),
+            file("c", "", "a/e.cc", None),
+        ]
+        .into_iter()
+        .unzip();
+        let edges = vec![(EdgeKind::Childof, NodeIndex(0), NodeIndex(2), 1)];
+        let graph = || RawGraph::from_parts(tickets.clone(), nodes.clone(), edges.clone());
+
+        let (by_path, num_removed) = graph().dedup_files(FileDedup::Path);
+        assert_eq!(num_removed, 1);
+        assert_eq!(by_path.nodes.len(), 4);
+
+        // Files without text are never considered duplicates of each other
+        let (by_text, num_removed) = graph().dedup_files(FileDedup::Text);
+        assert_eq!(num_removed, 2);
+        assert_eq!(by_text.nodes.len(), 3);
+        assert_eq!(
+            by_text.edges.iter().map(|(_, src, tgt, _)| (src, tgt)).collect_vec(),
+            [(NodeIndex(0), NodeIndex(0))]
+        );
+    }
+
+    #[test]
+    fn test_lang_from_path() {
+        assert_eq!(Lang::from_path("src/a/b.cc"), Some(Lang::Cpp));
+        assert_eq!(Lang::from_path("com/foo/Bar.java"), Some(Lang::Java));
+        assert_eq!(Lang::from_path("pkg/mod.tsx"), Some(Lang::TypeScript));
+        assert_eq!(Lang::from_path("BUILD"), None);
+        assert_eq!(Lang::from_path("README.md"), None);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        for value in ["", "a", "ab", "abc", "abcd", "\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}"] {
+            let encoded = base64::encode(value);
+            assert_eq!(decoded_len(&encoded), value.len());
+            assert_eq!(decoded_len(encoded.trim_end_matches('=')), value.len());
+        }
+    }
+
+    #[test]
+    fn test_try_reserve_pair() {
+        let ticket = |sig: &str| Ticket { signature: Some(sig.to_string()), ..Default::default() };
+        let mut graph = RawGraph::default();
+        assert!(graph.try_reserve_pair(&ticket("a"), &ticket("a"), Some(1)).is_some());
+        assert!(graph.try_reserve_pair(&ticket("a"), &ticket("b"), Some(1)).is_none());
+        assert_eq!(graph.nodes.len(), 1);
+        assert!(graph.try_reserve_pair(&ticket("b"), &ticket("c"), Some(2)).is_none());
+        assert_eq!(graph.nodes.len(), 1);
+        assert!(graph.try_reserve_pair(&ticket("b"), &ticket("c"), Some(3)).is_some());
+    }
 
-        Ok(EntityGraph { entities, deps })
+    #[test]
+    fn test_build_config_only_is_none() {
+        let ticket = Ticket { signature: Some("sig".to_string()), ..Default::default() };
+        let raw = RawNodeValue { build_config: Some("k8-opt".to_string()), ..Default::default() };
+        let node = Node::try_from((NodeIndex(0), raw, &ticket, FileKey(0), None)).unwrap();
+        assert_eq!(node.kind, NodeKind::None);
+        assert_eq!(node.build_config.as_deref(), Some("k8-opt"));
     }
 }
@@ -0,0 +1,174 @@
+//! Reading source files out of a `.kzip` archive.
+//!
+//! A kzip holds compilation units and their required inputs rather than
+//! entries, so only file nodes (with their text) can be read from it. These
+//! are enough to resolve anchors when the entries themselves come from an
+//! indexer run separately.
+//!
+//! See https://kythe.io/docs/kythe-kzip.html.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::io::{Entry, Ticket};
+use crate::proto::{self, Value};
+
+#[derive(Debug, Error)]
+pub enum KzipErr {
+    #[error("failed to read kzip")]
+    Io(#[from] io::Error),
+    #[error("failed to read kzip archive")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("failed to parse compilation unit \"{0}\"")]
+    MalformedUnit(String),
+    #[error("required input \"{0}\" is missing from the kzip")]
+    MissingFile(String),
+}
+
+type KzipRes<T> = Result<T, KzipErr>;
+
+#[derive(Default, serde::Deserialize)]
+struct IndexedCompilation {
+    #[serde(default)]
+    unit: CompilationUnit,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct CompilationUnit {
+    #[serde(default)]
+    required_input: Vec<FileInput>,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct FileInput {
+    #[serde(default)]
+    v_name: Ticket,
+    #[serde(default)]
+    info: FileInfo,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct FileInfo {
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    digest: String,
+}
+
+/// Read a node kind fact and a text fact for every distinct required input of
+/// every compilation unit (in either JSON or protobuf form) in the kzip.
+pub fn read_file_entries(path: &Path) -> KzipRes<Vec<Entry>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let names = archive.file_names().map(|name| name.to_string()).collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+
+    for name in &names {
+        let inputs = match unit_kind(name) {
+            Some(UnitKind::Json) => {
+                let unit: IndexedCompilation = serde_json::from_reader(archive.by_name(name)?)
+                    .map_err(|_| KzipErr::MalformedUnit(name.clone()))?;
+                unit.unit.required_input
+            }
+            Some(UnitKind::Proto) => {
+                let mut bytes = Vec::new();
+                archive.by_name(name)?.read_to_end(&mut bytes)?;
+                required_inputs(&bytes).ok_or_else(|| KzipErr::MalformedUnit(name.clone()))?
+            }
+            None => continue,
+        };
+
+        let root = name.split('/').next().unwrap_or_default();
+
+        for input in inputs {
+            let mut ticket = input.v_name;
+
+            if ticket.path.is_none() {
+                ticket.path = Some(input.info.path);
+            }
+
+            if !seen.insert(ticket.clone()) {
+                continue;
+            }
+
+            let file_name = format!("{}/files/{}", root, input.info.digest);
+            let mut text = Vec::new();
+            archive
+                .by_name(&file_name)
+                .map_err(|_| KzipErr::MissingFile(file_name.clone()))?
+                .read_to_end(&mut text)?;
+
+            entries.push(fact(ticket.clone(), "/kythe/node/kind", b"file"));
+            entries.push(fact(ticket, "/kythe/text", &text));
+        }
+    }
+
+    Ok(entries)
+}
+
+enum UnitKind {
+    Json,
+    Proto,
+}
+
+fn unit_kind(name: &str) -> Option<UnitKind> {
+    let mut parts = name.split('/').skip(1);
+
+    match (parts.next(), parts.next()) {
+        (Some("units"), Some(digest)) if !digest.is_empty() => Some(UnitKind::Json),
+        (Some("pbunits"), Some(digest)) if !digest.is_empty() => Some(UnitKind::Proto),
+        _ => None,
+    }
+}
+
+fn fact(src: Ticket, fact_name: &str, value: &[u8]) -> Entry {
+    let fact_name = fact_name.to_string();
+    Entry::Node { src, fact_name, fact_value: Some(base64::encode(value)) }
+}
+
+/// Decode the required inputs of a `kythe.proto.IndexedCompilation`.
+fn required_inputs(bytes: &[u8]) -> Option<Vec<FileInput>> {
+    let mut inputs = Vec::new();
+
+    for (field, value) in proto::fields(bytes)? {
+        if let (1, Value::Bytes(unit)) = (field, value) {
+            for (field, value) in proto::fields(unit)? {
+                if let (3, Value::Bytes(input)) = (field, value) {
+                    inputs.push(file_input(input)?);
+                }
+            }
+        }
+    }
+
+    Some(inputs)
+}
+
+fn file_input(bytes: &[u8]) -> Option<FileInput> {
+    let mut input = FileInput::default();
+
+    for (field, value) in proto::fields(bytes)? {
+        match (field, value) {
+            (1, Value::Bytes(v_name)) => input.v_name = Ticket::from_proto(v_name)?,
+            (2, Value::Bytes(info)) => {
+                for (field, value) in proto::fields(info)? {
+                    match (field, value) {
+                        (1, Value::Bytes(path)) => input.info.path = to_string(path),
+                        (2, Value::Bytes(digest)) => input.info.digest = to_string(digest),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(input)
+}
+
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
@@ -0,0 +1,30 @@
+//! Library for turning Kythe entries into dependency graphs.
+//!
+//! The main entry points are [`io::EntryReader`] for streaming entries,
+//! [`ir::RawGraph`], [`ir::SpecGraph`] and [`ir::EntityGraph`] for
+//! progressively higher-level views of those entries, and [`sink::OutputSink`]
+//! for writing an entity graph out in some format. Applications can follow
+//! (and cancel) long builds through a [`progress::Monitor`]. The
+//! `kythe-bridge` binary is a thin command line interface over this library.
+
+pub mod algebra;
+pub mod annotations;
+pub mod canonical;
+pub mod collections;
+pub mod dv8;
+pub mod exclusion;
+pub mod files;
+pub mod generated;
+pub mod io;
+pub mod ir;
+pub mod kzip;
+pub mod listing;
+pub mod manifest;
+pub mod markedsource;
+pub mod metrics;
+pub mod paths;
+pub mod progress;
+pub mod proto;
+pub mod remote;
+pub mod sink;
+pub mod snapshot;
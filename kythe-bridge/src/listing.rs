@@ -0,0 +1,199 @@
+//! Paged listings of the entities of a graph, for servers (and other
+//! long-lived embedders) which hand entities out a page at a time rather than
+//! sending millions of them to render a small view.
+//!
+//! An `EntityListing` sorts the entities once. Each request then filters them
+//! on the server, keeps only the requested fields, and returns a cursor which
+//! picks up where the page left off. Cursors are only meaningful for the graph
+//! they came from.
+
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use crate::ir::{Entity, EntityGraph, NodeIndex};
+use crate::paths::strip_dir_prefix;
+
+/// The number of entities in a page unless more (or fewer) are asked for.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// The most entities returned in a single page.
+pub const MAX_PAGE_SIZE: usize = 10_000;
+
+#[derive(Debug, Error)]
+pub enum ListingErr {
+    #[error("invalid cursor \"{0}\"")]
+    InvalidCursor(String),
+}
+
+type ListingRes<T> = Result<T, ListingErr>;
+
+/// Which entities to list. Every condition which is set must hold.
+#[derive(Clone, Debug, Default)]
+pub struct EntityFilter {
+    /// The Kythe name of the kind, without any subkind (e.g. "function").
+    pub kind: Option<String>,
+    /// A directory (or file) which the path must be in.
+    pub path_prefix: Option<String>,
+    /// Text which the qualified name must contain.
+    pub name: Option<String>,
+}
+
+impl EntityFilter {
+    pub fn matches(&self, entity: &Entity) -> bool {
+        self.kind.as_ref().map_or(true, |kind| entity.kind.name() == kind)
+            && self
+                .path_prefix
+                .as_ref()
+                .map_or(true, |prefix| strip_dir_prefix(&entity.path, prefix).is_some())
+            && self.name.as_ref().map_or(true, |name| entity.qualified_name.contains(name))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ListRequest {
+    pub filter: EntityFilter,
+    /// The fields of each entity to include (e.g. "id" and "qualified_name").
+    /// If empty, include every field.
+    pub fields: BTreeSet<String>,
+    /// The `next` cursor of the previous page, if any.
+    pub after: Option<String>,
+    /// The number of entities to return, up to `MAX_PAGE_SIZE`. If zero,
+    /// `DEFAULT_PAGE_SIZE` are returned.
+    pub limit: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Page {
+    pub entities: Vec<serde_json::Value>,
+    /// The cursor of the next page, or `None` if this is the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+pub struct EntityListing<'a> {
+    graph: &'a EntityGraph,
+    ids: Vec<NodeIndex>,
+}
+
+impl<'a> EntityListing<'a> {
+    pub fn new(graph: &'a EntityGraph) -> Self {
+        let mut ids = graph.entities.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        Self { graph, ids }
+    }
+
+    pub fn page(&self, request: &ListRequest) -> ListingRes<Page> {
+        let start = match &request.after {
+            None => 0,
+            Some(cursor) => {
+                let after = decode_cursor(cursor)?;
+                self.ids.partition_point(|id| *id <= after)
+            }
+        };
+
+        let limit = match request.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        };
+
+        let mut matches = self.ids[start..]
+            .iter()
+            .map(|id| &self.graph.entities[id])
+            .filter(|entity| request.filter.matches(entity));
+
+        let mut entities = Vec::new();
+        let mut last = None;
+
+        for entity in matches.by_ref().take(limit) {
+            entities.push(select_fields(entity, &request.fields));
+            last = Some(entity.id);
+        }
+
+        let next = match matches.next() {
+            Some(_) => last.map(encode_cursor),
+            None => None,
+        };
+
+        Ok(Page { entities, next })
+    }
+}
+
+fn encode_cursor(id: NodeIndex) -> String {
+    format!("e{}", id.0)
+}
+
+fn decode_cursor(cursor: &str) -> ListingRes<NodeIndex> {
+    match cursor.strip_prefix('e').map(str::parse) {
+        Some(Ok(index)) => Ok(NodeIndex(index)),
+        _ => Err(ListingErr::InvalidCursor(cursor.to_string())),
+    }
+}
+
+/// Serialize `entity` (as `format` would), keeping only `fields` unless it is
+/// empty. Fields which the entity does not have are left out.
+fn select_fields(entity: &Entity, fields: &BTreeSet<String>) -> serde_json::Value {
+    let mut value = serde_json::to_value(entity).unwrap();
+
+    if let (false, Some(object)) = (fields.is_empty(), value.as_object_mut()) {
+        object.retain(|key, _| fields.contains(key));
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{NameSource, NodeKind, StableId};
+
+    fn entity(id: usize, path: &str, kind: NodeKind) -> Entity {
+        Entity {
+            id: NodeIndex(id),
+            stable_id: StableId(id as u64),
+            parent_ids: Vec::new(),
+            name: format!("e{}", id),
+            name_source: NameSource::Signature,
+            qualified_name: format!("ns.e{}", id),
+            path: path.to_string(),
+            kind,
+            params: Vec::new(),
+            package: None,
+            annotations: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_paging() {
+        let entities = (0..5)
+            .map(|i| match i % 2 {
+                0 => entity(i, "src/a.cc", NodeKind::Macro),
+                _ => entity(i, "lib/b.cc", NodeKind::Package),
+            })
+            .map(|entity| (entity.id, entity));
+        let graph = EntityGraph { entities: entities.collect(), deps: Vec::new() };
+        let listing = EntityListing::new(&graph);
+
+        let mut request = ListRequest {
+            filter: EntityFilter { path_prefix: Some("src".to_string()), ..Default::default() },
+            fields: BTreeSet::from(["name".to_string()]),
+            limit: 2,
+            ..Default::default()
+        };
+
+        let page = listing.page(&request).unwrap();
+        assert_eq!(
+            page.entities,
+            vec![serde_json::json!({"name": "e0"}), serde_json::json!({"name": "e2"})]
+        );
+        assert_eq!(page.next.as_deref(), Some("e2"));
+
+        request.after = page.next;
+        let page = listing.page(&request).unwrap();
+        assert_eq!(page.entities, vec![serde_json::json!({"name": "e4"})]);
+        assert_eq!(page.next, None);
+
+        request.after = Some("bogus".to_string());
+        assert!(matches!(listing.page(&request), Err(ListingErr::InvalidCursor(_))));
+    }
+}
@@ -0,0 +1,223 @@
+//! Document symbols and references in the JSON shapes used by the Language
+//! Server Protocol (https://microsoft.github.io/language-server-protocol/), so
+//! a thin editor extension can offer offline code navigation.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::ir::{
+    AnchorKind, CppRecordKind, EdgeKind, Entity, EntityGraph, FunctionKind, GoRecordKind,
+    LineIndex, NodeIndex, NodeKind, Pos, RecordKind, RustRecordKind, SpecGraph, VariableKind,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub detail: String,
+    pub kind: u32,
+    pub range: Range,
+    pub selection_range: Range,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<DocumentSymbol>,
+}
+
+/// A location in this document which refers to some entity.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct Reference {
+    pub location: Location,
+    pub target: NodeIndex,
+    pub name: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Document {
+    pub uri: String,
+    pub symbols: Vec<DocumentSymbol>,
+    pub references: Vec<Reference>,
+}
+
+/// Build a document for every file with text, sorted by path.
+pub fn documents(spec: &SpecGraph, graph: &EntityGraph) -> Vec<Document> {
+    graph
+        .entities
+        .values()
+        .into_group_map_by(|e| e.path.as_str())
+        .into_iter()
+        .filter_map(|(path, entities)| document(spec, graph, path, &entities))
+        .sorted_by(|a, b| a.uri.cmp(&b.uri))
+        .collect()
+}
+
+fn document(
+    spec: &SpecGraph,
+    graph: &EntityGraph,
+    path: &str,
+    entities: &[&Entity],
+) -> Option<Document> {
+    let lines = entities.iter().find_map(|e| {
+        let file_key = spec.get_node(e.id).file_key;
+        Some(Utf16Lines { text: spec.get_file_text(file_key)?, lines: spec.line_index(file_key)? })
+    })?;
+    let location = |pos: &Pos| Location { uri: path.to_string(), range: lines.range(pos) };
+
+    let mut references = Vec::new();
+
+    for anchor in entities {
+        let pos = match &anchor.kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => pos,
+            _ => continue,
+        };
+
+        for (kind, tgt, _) in spec.outgoing_all(anchor.id) {
+            if let (true, Some(target)) = (kind.is_ref(), graph.entities.get(&tgt)) {
+                let name = target.name.clone();
+                references.push(Reference { location: location(pos), target: tgt, name });
+            }
+        }
+    }
+
+    references.sort();
+
+    // Create a flat symbol for each entity defined in this file, then nest
+    // them according to their parents
+    let mut symbols: HashMap<NodeIndex, DocumentSymbol> = HashMap::new();
+
+    for entity in entities {
+        let kind = match symbol_kind(&entity.kind) {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        let selection_range = match anchor_pos(spec, EdgeKind::DefinesBinding, entity.id) {
+            Some(pos) => lines.range(pos),
+            None => continue,
+        };
+
+        let range = match anchor_pos(spec, EdgeKind::Defines, entity.id) {
+            Some(pos) => lines.range(pos),
+            None => selection_range,
+        };
+
+        symbols.insert(
+            entity.id,
+            DocumentSymbol {
+                name: entity.name.clone(),
+                detail: entity.kind.name().to_string(),
+                kind,
+                range,
+                selection_range,
+                children: Vec::new(),
+            },
+        );
+    }
+
+    let mut children: HashMap<NodeIndex, Vec<NodeIndex>> = HashMap::new();
+    let mut roots = Vec::new();
+
+    for entity in entities.iter().filter(|e| symbols.contains_key(&e.id)) {
+        match entity.parent_ids.iter().find(|id| symbols.contains_key(id)) {
+            Some(parent_id) => children.entry(*parent_id).or_default().push(entity.id),
+            None => roots.push(entity.id),
+        }
+    }
+
+    let symbols = roots
+        .into_iter()
+        .filter_map(|id| nest(id, &mut symbols, &children))
+        .sorted_by_key(|symbol| symbol.range)
+        .collect();
+
+    Some(Document { uri: path.to_string(), symbols, references })
+}
+
+fn nest(
+    id: NodeIndex,
+    symbols: &mut HashMap<NodeIndex, DocumentSymbol>,
+    children: &HashMap<NodeIndex, Vec<NodeIndex>>,
+) -> Option<DocumentSymbol> {
+    let mut symbol = symbols.remove(&id)?;
+
+    for child_id in children.get(&id).into_iter().flatten() {
+        if let Some(child) = nest(*child_id, symbols, children) {
+            symbol.children.push(child);
+        }
+    }
+
+    symbol.children.sort_by_key(|child| child.range);
+    Some(symbol)
+}
+
+/// The span of the first explicit anchor with an edge of `kind` to `index`.
+fn anchor_pos(spec: &SpecGraph, kind: EdgeKind, index: NodeIndex) -> Option<&Pos> {
+    Vec::from(spec.incoming(kind, index)).into_iter().find_map(|anchor| {
+        match &spec.get_node(anchor).kind {
+            NodeKind::Anchor(AnchorKind::Explicit(pos)) => Some(pos),
+            _ => None,
+        }
+    })
+}
+
+/// The LSP `SymbolKind` of a node kind, if it is the kind of thing which
+/// should be listed as a symbol.
+fn symbol_kind(kind: &NodeKind) -> Option<u32> {
+    match kind {
+        NodeKind::Constant(_) => Some(14),
+        NodeKind::Function(_, FunctionKind::Constructor) => Some(9),
+        NodeKind::Function(_, _) => Some(12),
+        NodeKind::Interface => Some(11),
+        NodeKind::Macro => Some(14),
+        NodeKind::Package => Some(4),
+        NodeKind::Record(_, RecordKind::Cpp(CppRecordKind::Struct)) => Some(23),
+        NodeKind::Record(_, RecordKind::Go(GoRecordKind::Struct)) => Some(23),
+        NodeKind::Record(_, RecordKind::Rust(RustRecordKind::Struct)) => Some(23),
+        NodeKind::Record(_, _) => Some(5),
+        NodeKind::Sum(_, _) => Some(10),
+        NodeKind::Talias => Some(26),
+        NodeKind::Variable(_, VariableKind::Field) => Some(8),
+        NodeKind::Variable(_, _) => Some(13),
+        _ => None,
+    }
+}
+
+/// Converts byte offsets into LSP positions (with UTF-16 characters).
+struct Utf16Lines<'a> {
+    text: Cow<'a, str>,
+    lines: &'a LineIndex,
+}
+
+impl<'a> Utf16Lines<'a> {
+    fn position(&self, offset: usize) -> Position {
+        let (line, start) = self.lines.line(offset);
+        let character = match self.text.get(start..offset) {
+            Some(prefix) => prefix.encode_utf16().count(),
+            None => offset - start,
+        };
+
+        Position { line: line as u32, character: character as u32 }
+    }
+
+    fn range(&self, pos: &Pos) -> Range {
+        Range { start: self.position(pos.start), end: self.position(pos.end) }
+    }
+}
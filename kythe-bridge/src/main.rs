@@ -1,12 +1,30 @@
 #![feature(type_alias_impl_trait)]
-mod collections;
+mod anonymize;
+mod budget;
+mod clones;
 mod commands;
-mod dv8;
-mod io;
-mod ir;
+mod compare;
+mod coverage;
+mod cycles;
+mod decorations;
+mod diagnostics;
+mod diff;
+mod drh;
+mod externals;
+#[cfg(feature = "flight")]
+mod flight;
+mod graphml;
+mod heatmap;
+mod lsp;
+mod seriation;
+mod staleness;
+mod typecoupling;
 
 use clap::{Parser, Subcommand};
 use commands::CliCommand;
+use kythe_bridge::{
+    algebra, annotations, dv8, io, ir, kzip, manifest, metrics, progress, remote, sink, snapshot,
+};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -25,10 +43,32 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum CliSubCommand {
+    Cache(commands::cache::CliCacheCommand),
+    Clones(commands::clones::CliClonesCommand),
+    CompareIndexers(commands::compare::CliCompareCommand),
+    CoverageMap(commands::coverage::CliCoverageMapCommand),
+    Cycles(commands::cycles::CliCyclesCommand),
+    Decorations(commands::decorations::CliDecorationsCommand),
+    Diff(commands::diff::CliDiffCommand),
     Display(commands::display::CliDisplayCommand),
+    Dsm(commands::dsm::CliDsmCommand),
     Exclude(commands::exclude::CliExcludeCommand),
+    Extract(commands::extract::CliExtractCommand),
     EdgeKinds(commands::edgekinds::CliEdgeKindsCommand),
+    Export(commands::export::CliExportCommand),
+    Externals(commands::externals::CliExternalsCommand),
     Format(commands::format::CliFormatCommand),
+    Heatmap(commands::heatmap::CliHeatmapCommand),
+    Ingest(commands::ingest::CliIngestCommand),
+    List(commands::list::CliListCommand),
+    Lsp(commands::lsp::CliLspCommand),
+    Metrics(commands::metrics::CliMetricsCommand),
+    Serve(commands::serve::CliServeCommand),
+    Snapshot(commands::snapshot::CliSnapshotCommand),
+    Staleness(commands::staleness::CliStalenessCommand),
+    Tee(commands::tee::CliTeeCommand),
+    Types(commands::types::CliTypesCommand),
+    VerifyExport(commands::verify::CliVerifyExportCommand),
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -39,21 +79,56 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         false => stderrlog::LogLevelNum::Info,
     };
 
-    stderrlog::new()
+    let mut logger = stderrlog::new();
+    logger
         .module(module_path!())
         .quiet(cli.quiet)
         .verbosity(verbosity)
-        .timestamp(stderrlog::Timestamp::Millisecond)
-        .init()
-        .unwrap();
+        .timestamp(stderrlog::Timestamp::Millisecond);
+    diagnostics::init(logger).unwrap();
 
     match cli.command {
         None => std::process::exit(0),
         Some(command) => match command {
+            CliSubCommand::Cache(com) => com.execute(),
+            CliSubCommand::Clones(com) => com.execute(),
             CliSubCommand::Exclude(com) => com.execute(),
+            CliSubCommand::Extract(com) => com.execute(),
+            CliSubCommand::CompareIndexers(com) => com.execute(),
+            CliSubCommand::CoverageMap(com) => com.execute(),
+            CliSubCommand::Cycles(com) => com.execute(),
+            CliSubCommand::Decorations(com) => com.execute(),
+            CliSubCommand::Diff(com) => com.execute(),
             CliSubCommand::Display(com) => com.execute(),
+            CliSubCommand::Dsm(com) => com.execute(),
             CliSubCommand::EdgeKinds(com) => com.execute(),
+            CliSubCommand::Export(com) => com.execute(),
+            CliSubCommand::Externals(com) => com.execute(),
             CliSubCommand::Format(com) => com.execute(),
+            CliSubCommand::Heatmap(com) => com.execute(),
+            CliSubCommand::Ingest(com) => com.execute(),
+            CliSubCommand::List(com) => com.execute(),
+            CliSubCommand::Lsp(com) => com.execute(),
+            CliSubCommand::Metrics(com) => com.execute(),
+            CliSubCommand::Serve(com) => com.execute(),
+            CliSubCommand::Snapshot(com) => com.execute(),
+            CliSubCommand::Staleness(com) => com.execute(),
+            CliSubCommand::Tee(com) => com.execute(),
+            CliSubCommand::Types(com) => com.execute(),
+            CliSubCommand::VerifyExport(com) => com.execute(),
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        // Catches conflicting flags among flattened args, which clap only
+        // reports when the command is built
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,206 @@
+//! Sidecar files which record the size and checksum of an output.
+//!
+//! Result files can be large enough that copying them between machines
+//! occasionally truncates them. A manifest is written next to the output as it
+//! is produced, so the copy can later be checked with [`Manifest::verify`]
+//! without knowing anything about the format of the output itself.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Appended to the path of an output to get the path of its manifest.
+const SIDECAR_SUFFIX: &str = ".manifest.json";
+
+#[derive(Debug, Error)]
+pub enum ManifestErr {
+    #[error("failed to read file or manifest")]
+    Io(#[from] io::Error),
+    #[error("failed to parse manifest")]
+    Json(#[from] serde_json::Error),
+    #[error("expected {field} to be {expected} but found {actual}")]
+    Mismatch { field: &'static str, expected: String, actual: String },
+}
+
+type ManifestRes<T> = Result<T, ManifestErr>;
+
+/// The size and checksum of a file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub bytes: u64,
+    /// Number of newlines. For line-based formats this is the row count.
+    pub lines: u64,
+    /// Lowercase hex.
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Compute the manifest of an existing file.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = fs::File::open(path)?;
+        let mut digester = Digester::default();
+        let mut buf = vec![0; 1 << 16];
+
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => digester.update(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(digester.manifest())
+    }
+
+    /// The path of the manifest which describes `path`.
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(SIDECAR_SUFFIX);
+        PathBuf::from(sidecar)
+    }
+
+    /// Save this as the manifest of `path`.
+    pub fn write_sidecar(&self, path: &Path) -> io::Result<()> {
+        fs::write(Self::sidecar_path(path), serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Load the manifest of `path`.
+    pub fn read_sidecar(path: &Path) -> ManifestRes<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(Self::sidecar_path(path))?)?)
+    }
+
+    /// Check that `path` matches its manifest. Returns the manifest if so.
+    pub fn verify(path: &Path) -> ManifestRes<Self> {
+        let expected = Self::read_sidecar(path)?;
+        let actual = Self::of_file(path)?;
+
+        let check = |field, expected: String, actual: String| match expected == actual {
+            true => Ok(()),
+            false => Err(ManifestErr::Mismatch { field, expected, actual }),
+        };
+
+        check("bytes", expected.bytes.to_string(), actual.bytes.to_string())?;
+        check("lines", expected.lines.to_string(), actual.lines.to_string())?;
+        check("sha256", expected.sha256.clone(), actual.sha256)?;
+        Ok(expected)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Digester {
+    hasher: Sha256,
+    bytes: u64,
+    lines: u64,
+    /// Whether everything digested has since been flushed to the output.
+    unflushed: bool,
+}
+
+impl Digester {
+    fn update(&mut self, buf: &[u8]) {
+        self.hasher.update(buf);
+        self.bytes += buf.len() as u64;
+        self.lines += buf.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        self.unflushed |= !buf.is_empty();
+    }
+
+    fn manifest(self) -> Manifest {
+        let sha256 = self.hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+        Manifest { bytes: self.bytes, lines: self.lines, sha256 }
+    }
+}
+
+/// Computes the manifest of everything written through it.
+///
+/// The writer is usually moved into a sink, so the manifest is taken from the
+/// `ManifestHandle` returned alongside it once the sink is done. The writer is
+/// flushed when dropped, and the manifest is only available if every byte it
+/// describes was flushed without error.
+pub struct ManifestWriter<W: Write> {
+    inner: W,
+    digester: Option<Arc<Mutex<Digester>>>,
+}
+
+impl<W: Write> ManifestWriter<W> {
+    /// Wrap `inner`. If not `enabled`, writes pass straight through and there
+    /// is no handle.
+    pub fn new(inner: W, enabled: bool) -> (Self, Option<ManifestHandle>) {
+        let digester = enabled.then(|| Arc::new(Mutex::new(Digester::default())));
+        let handle = digester.clone().map(ManifestHandle);
+        (Self { inner, digester }, handle)
+    }
+}
+
+impl<W: Write> Write for ManifestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+
+        if let Some(digester) = &self.digester {
+            digester.lock().unwrap().update(&buf[..len]);
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+
+        if let Some(digester) = &self.digester {
+            digester.lock().unwrap().unflushed = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for ManifestWriter<W> {
+    fn drop(&mut self) {
+        // Errors are kept in the digester, to be reported by the handle
+        let _ = self.flush();
+    }
+}
+
+pub struct ManifestHandle(Arc<Mutex<Digester>>);
+
+impl ManifestHandle {
+    /// The manifest of everything written so far. Fails if any of it has not
+    /// been flushed (e.g. because flushing failed).
+    pub fn manifest(&self) -> io::Result<Manifest> {
+        let digester = self.0.lock().unwrap();
+
+        if digester.unflushed {
+            let msg = "output was not flushed, so it may be incomplete";
+            return Err(io::Error::new(io::ErrorKind::Other, msg));
+        }
+
+        Ok(digester.clone().manifest())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_of_written_bytes() {
+        let (mut writer, handle) = ManifestWriter::new(Vec::new(), true);
+        writer.write_all(b"abc\n").unwrap();
+        writer.write_all(b"def\n").unwrap();
+        let handle = handle.unwrap();
+        assert!(handle.manifest().is_err());
+
+        writer.flush().unwrap();
+        let manifest = handle.manifest().unwrap();
+
+        assert_eq!(manifest.bytes, 8);
+        assert_eq!(manifest.lines, 2);
+        assert_eq!(
+            manifest.sha256,
+            "924d391c158a46409fdff363063d718ea0bc00b14556f129984942af91233bbe"
+        );
+    }
+}
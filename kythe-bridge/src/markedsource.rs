@@ -0,0 +1,70 @@
+//! A minimal decoder for Kythe's `MarkedSource` protobuf message (the value of
+//! the `/kythe/code` fact). Only the fields needed to render the name of a node
+//! are kept.
+//!
+//! See https://github.com/kythe/kythe/blob/master/kythe/proto/common.proto.
+
+use crate::proto::{self, Value};
+
+const KIND_IDENTIFIER: u64 = 1;
+const KIND_CONTEXT: u64 = 2;
+const KIND_TYPE: u64 = 3;
+const KIND_PARAMETER: u64 = 4;
+
+const FIELD_KIND: u64 = 1;
+const FIELD_PRE_TEXT: u64 = 2;
+const FIELD_CHILD: u64 = 3;
+const FIELD_POST_CHILD_TEXT: u64 = 4;
+const FIELD_POST_TEXT: u64 = 5;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MarkedSource {
+    pub kind: u64,
+    pub pre_text: String,
+    pub children: Vec<MarkedSource>,
+    pub post_child_text: String,
+    pub post_text: String,
+}
+
+impl MarkedSource {
+    /// Decode a serialized `MarkedSource`. Returns `None` if it is malformed.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut source = MarkedSource::default();
+
+        for (field, value) in proto::fields(bytes)? {
+            match (field, value) {
+                (FIELD_KIND, Value::Varint(kind)) => source.kind = kind,
+                (FIELD_PRE_TEXT, Value::Bytes(text)) => source.pre_text = to_string(text),
+                (FIELD_CHILD, Value::Bytes(child)) => {
+                    source.children.push(MarkedSource::decode(child)?)
+                }
+                (FIELD_POST_CHILD_TEXT, Value::Bytes(text)) => {
+                    source.post_child_text = to_string(text)
+                }
+                (FIELD_POST_TEXT, Value::Bytes(text)) => source.post_text = to_string(text),
+                _ => {}
+            }
+        }
+
+        Some(source)
+    }
+
+    /// Render the first identifier which is not part of a context, type, or
+    /// parameter (e.g. "foo" rather than "ns::foo" or "void foo(int)").
+    pub fn identifier(&self) -> Option<String> {
+        match self.kind {
+            KIND_IDENTIFIER => Some(self.render()).filter(|text| !text.is_empty()),
+            KIND_CONTEXT | KIND_TYPE | KIND_PARAMETER => None,
+            _ => self.children.iter().find_map(|child| child.identifier()),
+        }
+    }
+
+    fn render(&self) -> String {
+        let children = self.children.iter().map(|child| child.render()).collect::<Vec<_>>();
+        format!("{}{}{}", self.pre_text, children.join(&self.post_child_text), self.post_text)
+    }
+}
+
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
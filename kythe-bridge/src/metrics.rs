@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::ir::{
+    AnchorKind, EdgeKind, EntityGraph, GroupBy, NodeIndex, NodeIndices, NodeKind, SpecGraph,
+    VariableKind,
+};
+
+/// How many ancestors to pass through when looking for the entity which
+/// declares a local variable.
+const MAX_LOCAL_DEPTH: usize = 16;
+
+/// Size and coupling metrics for a single file (or for a single package, in
+/// which case `path` holds the name of the package).
+///
+/// Fan-in and fan-out count distinct files, while the dep counts are weighted
+/// by the number of underlying edges. Deps are counted in their semantic
+/// direction (see `Relation`), so a parent's file fans out to its children's.
+/// Dependencies of a file on itself are not counted. Lines count the lines of
+/// text of the file (or of every file in the package), so that coupling can be
+/// compared per KLOC.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct FileMetrics {
+    pub path: String,
+    pub entities: usize,
+    pub lines: usize,
+    pub fan_in: usize,
+    pub fan_out: usize,
+    pub deps_in: usize,
+    pub deps_out: usize,
+}
+
+impl FileMetrics {
+    const COLUMNS: [&'static str; 7] =
+        ["path", "entities", "lines", "fan_in", "fan_out", "deps_in", "deps_out"];
+
+    fn record(&self) -> [String; 7] {
+        [
+            self.path.clone(),
+            self.entities.to_string(),
+            self.lines.to_string(),
+            self.fan_in.to_string(),
+            self.fan_out.to_string(),
+            self.deps_in.to_string(),
+            self.deps_out.to_string(),
+        ]
+    }
+}
+
+pub fn file_metrics(spec: &SpecGraph, graph: &EntityGraph, group_by: GroupBy) -> Vec<FileMetrics> {
+    let mut metrics: BTreeMap<&str, FileMetrics> = BTreeMap::new();
+    let mut pairs: HashSet<(&str, &str)> = HashSet::new();
+
+    for entity in graph.entities.values() {
+        let key = group_by.key(entity);
+        let row = metrics
+            .entry(key)
+            .or_insert_with(|| FileMetrics { path: key.to_string(), ..Default::default() });
+        row.entities += 1;
+
+        if let NodeKind::File(_) = &entity.kind {
+            let file_key = spec.get_node(entity.id).file_key;
+            row.lines += spec.count_lines(file_key).unwrap_or_default();
+        }
+    }
+
+    for dep in &graph.deps {
+        let (_, src, tgt) = dep.normalized();
+        let (src, tgt) = match (graph.entities.get(&src), graph.entities.get(&tgt)) {
+            (Some(src), Some(tgt)) if group_by.key(src) != group_by.key(tgt) => {
+                (group_by.key(src), group_by.key(tgt))
+            }
+            _ => continue,
+        };
+
+        metrics.get_mut(src).unwrap().deps_out += dep.count;
+        metrics.get_mut(tgt).unwrap().deps_in += dep.count;
+
+        if pairs.insert((src, tgt)) {
+            metrics.get_mut(src).unwrap().fan_out += 1;
+            metrics.get_mut(tgt).unwrap().fan_in += 1;
+        }
+    }
+
+    metrics.into_values().collect()
+}
+
+/// The names of every annotation (see `Entity::annotations`) in the graph,
+/// and the distinct values of each annotation within each group.
+fn group_annotations(
+    graph: &EntityGraph,
+    group_by: GroupBy,
+) -> (BTreeSet<&str>, BTreeMap<(&str, &str), BTreeSet<&str>>) {
+    let mut columns = BTreeSet::new();
+    let mut values: BTreeMap<(&str, &str), BTreeSet<&str>> = BTreeMap::new();
+
+    for entity in graph.entities.values() {
+        for (column, value) in &entity.annotations {
+            columns.insert(column.as_str());
+            values
+                .entry((group_by.key(entity), column.as_str()))
+                .or_default()
+                .insert(value.as_str());
+        }
+    }
+
+    (columns, values)
+}
+
+/// Write one row per file (or package) as CSV. Each annotation of the
+/// entities in a file becomes an extra column, holding its distinct values
+/// joined by "|".
+pub fn write_file_metrics<W: std::io::Write>(
+    spec: &SpecGraph,
+    graph: &EntityGraph,
+    group_by: GroupBy,
+    writer: W,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let (columns, values) = group_annotations(graph, group_by);
+    writer.write_record(FileMetrics::COLUMNS.into_iter().chain(columns.iter().copied()))?;
+
+    for row in file_metrics(spec, graph, group_by) {
+        let annotations = columns.iter().map(|column| match values.get(&(&*row.path, *column)) {
+            Some(values) => values.iter().join("|"),
+            None => String::new(),
+        });
+        writer.write_record(row.record().into_iter().chain(annotations))?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Size metrics for a single entity (e.g. a function or class) which is
+/// defined in the indexed source.
+///
+/// The span of an entity is the full extent of its `defines` anchor (or of
+/// its `defines/binding` anchor if it has no other), in lines and in bytes.
+/// Locals count the local variables (other than parameters) declared within
+/// the entity, each counted toward the nearest entity which contains it, so
+/// the locals of a lambda are not also counted toward its function.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct EntityMetrics {
+    pub path: String,
+    pub qualified_name: String,
+    pub kind: &'static str,
+    pub lines: usize,
+    pub bytes: usize,
+    pub locals: usize,
+    pub params: usize,
+}
+
+/// Compute `EntityMetrics` for every entity of `graph` with a definition
+/// (other than files and anchors).
+pub fn entity_metrics(spec: &SpecGraph, graph: &EntityGraph) -> Vec<EntityMetrics> {
+    // Prefer the full extent of a `defines` anchor, then the longest span
+    let mut spans: HashMap<NodeIndex, (bool, usize, usize, usize)> = HashMap::new();
+
+    for kind in [EdgeKind::DefinesBinding, EdgeKind::Defines] {
+        for (anchor, tgt, _) in spec.iter_kind(kind) {
+            let node = spec.get_node(anchor);
+            let (pos, lines) = match (&node.kind, spec.line_index(node.file_key)) {
+                (NodeKind::Anchor(AnchorKind::Explicit(pos)), Some(lines)) => (pos, lines),
+                _ => continue,
+            };
+
+            let first_line = lines.line(pos.start).0;
+            let last_line = lines.line(pos.end.saturating_sub(1).max(pos.start)).0;
+            let span = (kind == EdgeKind::Defines, pos.end - pos.start, first_line, last_line);
+
+            if spans.get(&tgt).map_or(true, |other| (span.0, span.1) > (other.0, other.1)) {
+                spans.insert(tgt, span);
+            }
+        }
+    }
+
+    let mut metrics: BTreeMap<NodeIndex, EntityMetrics> = graph
+        .entities
+        .values()
+        .filter(|entity| !matches!(entity.kind, NodeKind::File(_) | NodeKind::Anchor(_)))
+        .filter_map(|entity| {
+            let &(_, bytes, first_line, last_line) = spans.get(&entity.id)?;
+            let params = spec
+                .outgoing_all(entity.id)
+                .into_iter()
+                .filter(|(kind, _, _)| matches!(kind, EdgeKind::Param(_)))
+                .map(|(_, param, _)| param)
+                .unique()
+                .count();
+
+            let row = EntityMetrics {
+                path: entity.path.clone(),
+                qualified_name: entity.qualified_name.clone(),
+                kind: entity.kind.name(),
+                lines: last_line - first_line + 1,
+                bytes,
+                locals: 0,
+                params,
+            };
+            Some((entity.id, row))
+        })
+        .collect();
+
+    for node in spec.iter_nodes() {
+        if !matches!(
+            node.kind,
+            NodeKind::Variable(
+                _,
+                VariableKind::Local | VariableKind::LocalException | VariableKind::LocalResource
+            )
+        ) {
+            continue;
+        }
+
+        let mut index = node.index;
+
+        for _ in 0..MAX_LOCAL_DEPTH {
+            index = match spec.parents(index) {
+                NodeIndices::Sole(parent) => parent,
+                _ => break,
+            };
+
+            if let Some(row) = metrics.get_mut(&index) {
+                row.locals += 1;
+                break;
+            }
+        }
+    }
+
+    metrics.into_values().sorted().collect()
+}
+
+/// Write one row of `EntityMetrics` per entity as CSV.
+pub fn write_entity_metrics<W: std::io::Write>(
+    spec: &SpecGraph,
+    graph: &EntityGraph,
+    writer: W,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for row in entity_metrics(spec, graph) {
+        writer.serialize(row)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Robert C. Martin's package metrics for a single package (or file or
+/// directory, depending on the grouping).
+///
+/// Afferent coupling counts the entities outside of the package which depend
+/// on something inside it, and efferent coupling counts the entities inside
+/// the package which depend on something outside it. Instability is
+/// `efferent / (afferent + efferent)` (or zero if both are zero). Abstractness
+/// is the fraction of the types in the package which are interfaces rather
+/// than records (or zero if there are no types). Distance is how far the
+/// package lies from the "main sequence" where `abstractness + instability` is
+/// one.
+#[derive(Debug, Default, PartialEq, PartialOrd, serde::Serialize)]
+pub struct PackageMetrics {
+    pub package: String,
+    pub afferent: usize,
+    pub efferent: usize,
+    pub interfaces: usize,
+    pub records: usize,
+    pub instability: f64,
+    pub abstractness: f64,
+    pub distance: f64,
+}
+
+pub fn package_metrics(graph: &EntityGraph, group_by: GroupBy) -> Vec<PackageMetrics> {
+    let mut metrics: BTreeMap<&str, PackageMetrics> = BTreeMap::new();
+
+    for entity in graph.entities.values() {
+        let key = group_by.key(entity);
+        let row = metrics
+            .entry(key)
+            .or_insert_with(|| PackageMetrics { package: key.to_string(), ..Default::default() });
+
+        match entity.kind {
+            NodeKind::Interface => row.interfaces += 1,
+            NodeKind::Record(_, _) => row.records += 1,
+            _ => (),
+        }
+    }
+
+    let mut afferent = HashSet::new();
+    let mut efferent = HashSet::new();
+
+    for dep in &graph.deps {
+        let (_, src, tgt) = dep.normalized();
+        let (src_key, tgt_key) = match (graph.entities.get(&src), graph.entities.get(&tgt)) {
+            (Some(src), Some(tgt)) if group_by.key(src) != group_by.key(tgt) => {
+                (group_by.key(src), group_by.key(tgt))
+            }
+            _ => continue,
+        };
+
+        if afferent.insert((tgt_key, src)) {
+            metrics.get_mut(tgt_key).unwrap().afferent += 1;
+        }
+
+        if efferent.insert(src) {
+            metrics.get_mut(src_key).unwrap().efferent += 1;
+        }
+    }
+
+    let ratio = |a: usize, b: usize| match a + b {
+        0 => 0.0,
+        total => a as f64 / total as f64,
+    };
+
+    metrics
+        .into_values()
+        .map(|mut row| {
+            row.instability = ratio(row.efferent, row.afferent);
+            row.abstractness = ratio(row.interfaces, row.records);
+            row.distance = (row.abstractness + row.instability - 1.0).abs();
+            row
+        })
+        .collect()
+}
+
+/// Write one row of `PackageMetrics` per package (or file or directory) as
+/// CSV.
+pub fn write_package_metrics<W: std::io::Write>(
+    graph: &EntityGraph,
+    group_by: GroupBy,
+    writer: W,
+) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for row in package_metrics(graph, group_by) {
+        writer.serialize(row)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! Classifying and normalizing the paths of tickets.
+//!
+//! Kythe paths are usually relative and separated by "/", but indexers run on
+//! Windows (or fed absolute file names) emit paths like "C:\src\a.cc" or
+//! "\\server\share\a.cc" as well. Everything which inspects paths (exclusion
+//! rules, root aliases, file dedup, grouping by directory, and the runner's
+//! provenance) goes through these helpers so that such paths are treated the
+//! same everywhere.
+
+/// Whether a ticket has a path, and if so whether it is absolute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathKind {
+    NilPathed,
+    RelPathed,
+    AbsPathed,
+}
+
+impl PathKind {
+    pub fn of(path: Option<&str>) -> Self {
+        match path {
+            None => Self::NilPathed,
+            Some(path) if is_absolute(path) => Self::AbsPathed,
+            Some(_) => Self::RelPathed,
+        }
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/// The drive letter of a Windows path (e.g. "C:" of "C:\src"), if any.
+fn drive(path: &str) -> Option<&str> {
+    let bytes = path.as_bytes();
+
+    match bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        true => Some(&path[..2]),
+        false => None,
+    }
+}
+
+/// Whether `path` is absolute on either Unix or Windows, i.e. it starts with a
+/// separator (including UNC paths) or with a drive letter and a separator.
+pub fn is_absolute(path: &str) -> bool {
+    match drive(path) {
+        Some(drive) => path[drive.len()..].starts_with(is_separator),
+        None => path.starts_with(is_separator),
+    }
+}
+
+/// Use "/" as the only separator, remove redundant separators and "."
+/// components, and resolve ".." components where possible. A leading
+/// separator (or two, for UNC paths) and a drive letter (uppercased) are kept.
+pub fn normalize(path: &str) -> String {
+    let (prefix, rest) = match drive(path) {
+        Some(drive) => (drive.to_ascii_uppercase(), &path[drive.len()..]),
+        None => (String::new(), path),
+    };
+
+    let root = match rest.chars().take_while(|c| is_separator(*c)).count() {
+        0 => "",
+        2 if prefix.is_empty() && rest.contains('\\') => "//",
+        _ => "/",
+    };
+
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in rest.split(is_separator) {
+        match component {
+            "" | "." => continue,
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                // Nothing is above the root
+                None if !root.is_empty() => {}
+                _ => components.push(component),
+            },
+            _ => components.push(component),
+        }
+    }
+
+    format!("{}{}{}", prefix, root, components.join("/"))
+}
+
+/// The rest of `path` after `prefix`, but only if `prefix` ends on a component
+/// boundary. The rest is either empty or starts with "/". A trailing "/" on
+/// `prefix` is ignored.
+pub fn strip_dir_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    match path.strip_prefix(prefix.trim_end_matches('/'))? {
+        "" => Some(""),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+/// The first `depth` levels of the directory of `path`, or "." if `path` is
+/// not in any directory.
+pub fn dir_prefix(path: &str, depth: usize) -> &str {
+    let dir = match path.rsplit_once('/') {
+        Some((dir, _)) if !dir.is_empty() => dir,
+        _ => return ".",
+    };
+
+    match dir.match_indices('/').nth(depth.saturating_sub(1)) {
+        Some((i, _)) => &dir[..i],
+        None => dir,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_kind() {
+        assert_eq!(PathKind::of(None), PathKind::NilPathed);
+        assert_eq!(PathKind::of(Some("src/a.cc")), PathKind::RelPathed);
+        assert_eq!(PathKind::of(Some("/usr/include/stdio.h")), PathKind::AbsPathed);
+        assert_eq!(PathKind::of(Some("C:\\src\\a.cc")), PathKind::AbsPathed);
+        assert_eq!(PathKind::of(Some("c:/src/a.cc")), PathKind::AbsPathed);
+        assert_eq!(PathKind::of(Some("\\\\server\\share\\a.cc")), PathKind::AbsPathed);
+        assert_eq!(PathKind::of(Some("C:a.cc")), PathKind::RelPathed);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("a//b/./c/../d.cc"), "a/b/d.cc");
+        assert_eq!(normalize("../a/../../b"), "../../b");
+        assert_eq!(normalize("/a/../../b"), "/b");
+        assert_eq!(normalize("./"), "");
+        assert_eq!(normalize("c:\\src\\.\\lib\\..\\a.cc"), "C:/src/a.cc");
+        assert_eq!(normalize("\\\\server\\share\\a.cc"), "//server/share/a.cc");
+    }
+
+    #[test]
+    fn test_strip_dir_prefix() {
+        assert_eq!(strip_dir_prefix("bazel-out/bin/a.cc", "bazel-out/bin"), Some("/a.cc"));
+        assert_eq!(strip_dir_prefix("bazel-out/bin/a.cc", "bazel-out/bin/"), Some("/a.cc"));
+        assert_eq!(strip_dir_prefix("bazel-out/bin", "bazel-out/bin"), Some(""));
+        assert_eq!(strip_dir_prefix("bazel-out/binary/a.cc", "bazel-out/bin"), None);
+    }
+
+    #[test]
+    fn test_dir_prefix() {
+        assert_eq!(dir_prefix("a/b/c/d.cc", 1), "a");
+        assert_eq!(dir_prefix("a/b/c/d.cc", 2), "a/b");
+        assert_eq!(dir_prefix("a/b/c/d.cc", 5), "a/b/c");
+        assert_eq!(dir_prefix("d.cc", 2), ".");
+    }
+}
@@ -0,0 +1,166 @@
+//! Hooks for applications which embed this library to follow (and abort)
+//! long-running graph builds.
+//!
+//! A `Monitor` is passed to `RawGraph::from_entries_with` (through
+//! `RawGraphOptions`), `SpecGraph::from_raw_with`, and
+//! `EntityGraph::new_with`. Each checks its monitor once per batch of work
+//! rather than per item, so an idle monitor costs next to nothing.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+/// How many items are processed between checks of a `Monitor`.
+const BATCH_SIZE: usize = 1 << 14;
+
+/// A flag which asks any build holding a clone of it to stop at the end of its
+/// current batch. Cheap to clone and safe to cancel from another thread (e.g.
+/// a GUI's cancel button or a server's request timeout).
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("cancelled while {0}")]
+pub struct Cancelled(pub Stage);
+
+/// The part of a build which is underway.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading entries into a `RawGraph`. The total is unknown.
+    Entries,
+    /// Converting the nodes of a `RawGraph` into a `SpecGraph`.
+    Nodes,
+    /// Building entities from the nodes of a `SpecGraph`.
+    Entities,
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Entries => write!(f, "reading entries"),
+            Stage::Nodes => write!(f, "converting nodes"),
+            Stage::Entities => write!(f, "building entities"),
+        }
+    }
+}
+
+/// How far a build has come.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub stage: Stage,
+    /// The number of items (entries, nodes, etc.) processed so far.
+    pub done: usize,
+    /// The number of items in this stage, if known up front.
+    pub total: Option<usize>,
+}
+
+pub type ProgressFn = Box<dyn FnMut(Progress) + Send>;
+
+/// An optional cancellation token and an optional progress callback. The
+/// default monitor never cancels and reports nothing.
+#[derive(Default)]
+pub struct Monitor {
+    token: Option<CancellationToken>,
+    progress: Option<ProgressFn>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop with `Cancelled` once `token` is cancelled.
+    pub fn cancel_with(mut self, token: CancellationToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Call `f` once per batch and once at the end of each stage.
+    pub fn on_progress<F: FnMut(Progress) + Send + 'static>(mut self, f: F) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Report progress and check for cancellation, but only at the start of
+    /// each batch. Call with every item.
+    pub(crate) fn tick(
+        &mut self,
+        stage: Stage,
+        done: usize,
+        total: Option<usize>,
+    ) -> Result<(), Cancelled> {
+        match done % BATCH_SIZE {
+            0 => self.check(stage, done, total),
+            _ => Ok(()),
+        }
+    }
+
+    /// Report that `stage` is complete (or check for cancellation one last
+    /// time if it is not).
+    pub(crate) fn finish(&mut self, stage: Stage, done: usize) -> Result<(), Cancelled> {
+        self.check(stage, done, Some(done))
+    }
+
+    fn check(&mut self, stage: Stage, done: usize, total: Option<usize>) -> Result<(), Cancelled> {
+        if self.token.as_ref().map_or(false, CancellationToken::is_cancelled) {
+            return Err(Cancelled(stage));
+        }
+
+        if let Some(progress) = &mut self.progress {
+            progress(Progress { stage, done, total });
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Monitor")
+            .field("token", &self.token)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_monitor() {
+        let token = CancellationToken::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let mut monitor = Monitor::new()
+            .cancel_with(token.clone())
+            .on_progress(move |progress| sink.lock().unwrap().push(progress.done));
+
+        for done in 0..BATCH_SIZE + 1 {
+            monitor.tick(Stage::Nodes, done, None).unwrap();
+        }
+
+        monitor.finish(Stage::Nodes, BATCH_SIZE + 1).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![0, BATCH_SIZE, BATCH_SIZE + 1]);
+
+        token.cancel();
+        assert!(monitor.tick(Stage::Entities, 1, None).is_ok());
+        assert!(matches!(monitor.tick(Stage::Entities, 0, None), Err(Cancelled(Stage::Entities))));
+    }
+}
@@ -0,0 +1,134 @@
+//! Just enough of the protobuf wire format to decode Kythe's messages without
+//! generated code.
+//!
+//! See https://developers.google.com/protocol-buffers/docs/encoding.
+
+use std::io::{self, BufRead};
+
+/// The value of a single field. Groups (wire types 3 and 4) are not supported.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Decode every (field number, value) pair of a message. Returns `None` if the
+/// message is malformed.
+pub fn fields(bytes: &[u8]) -> Option<Vec<(u64, Value)>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let key = read_varint(bytes, &mut pos)?;
+
+        let value = match key & 0x7 {
+            0 => Value::Varint(read_varint(bytes, &mut pos)?),
+            1 => Value::Fixed64(u64::from_le_bytes(take(bytes, &mut pos, 8)?.try_into().ok()?)),
+            2 => {
+                let len = read_varint(bytes, &mut pos)? as usize;
+                Value::Bytes(take(bytes, &mut pos, len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(take(bytes, &mut pos, 4)?.try_into().ok()?)),
+            _ => return None,
+        };
+
+        fields.push((key >> 3, value));
+    }
+
+    Some(fields)
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let taken = bytes.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+    Some(taken)
+}
+
+pub fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// The largest message `read_delimited` accepts. Kythe entries are far
+/// smaller even with the text of a large file, so a longer length means the
+/// stream is corrupt.
+pub const MAX_MESSAGE_LEN: u64 = 1 << 30;
+
+/// Read the next varint-length-delimited message (as written by Kythe's
+/// `entrystream`) into `buffer`. Returns `false` at the end of the stream.
+pub fn read_delimited<R: BufRead>(reader: &mut R, buffer: &mut Vec<u8>) -> io::Result<bool> {
+    let mut len = 0u64;
+    let mut byte = [0u8];
+
+    for shift in (0..64).step_by(7) {
+        if reader.read(&mut byte)? == 0 {
+            return match shift {
+                0 => Ok(false),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+
+        len |= ((byte[0] & 0x7f) as u64) << shift;
+
+        if byte[0] & 0x80 == 0 {
+            if len > MAX_MESSAGE_LEN {
+                let msg = format!("message of {} bytes is too long", len);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+
+            // Only grow the buffer as bytes arrive, in case the stream is cut short
+            buffer.clear();
+
+            if reader.by_ref().take(len).read_to_end(buffer)? < len as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            return Ok(true);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        // field 1 = varint 150, field 2 = "hi"
+        let bytes = [0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i'];
+        let fields = fields(&bytes).unwrap();
+
+        assert_eq!(fields, vec![(1, Value::Varint(150)), (2, Value::Bytes(b"hi"))]);
+        assert_eq!(super::fields(&bytes[..4]), None);
+    }
+
+    #[test]
+    fn test_read_delimited() {
+        let mut buffer = Vec::new();
+        let mut reader = &[0x02, b'h', b'i', 0x03, b'x'][..];
+        assert!(read_delimited(&mut reader, &mut buffer).unwrap());
+        assert_eq!(buffer, b"hi");
+        let err = read_delimited(&mut reader, &mut buffer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A garbage length is refused rather than allocated
+        let mut reader = &[0xff, 0xff, 0xff, 0xff, 0x7f][..];
+        let err = read_delimited(&mut reader, &mut buffer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,297 @@
+//! Download inputs given as URLs (e.g. artifacts in CI object storage) so they
+//! can be read like any local file.
+//!
+//! Downloads are streamed into a local cache. An interrupted download is kept
+//! as a partial file and resumed (with an HTTP range request) on the next
+//! attempt, and a completed download is reused for as long as the server
+//! reports the same version of it. Versions are told apart by the ETag (or
+//! Last-Modified date) the server sent, which is kept next to the download.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FetchErr {
+    #[error("failed to write download")]
+    Io(#[from] io::Error),
+    #[error("failed to download \"{0}\": {1}")]
+    Http(String, String),
+    #[error("cannot download \"{0}\" (s3 support was not enabled at build time)")]
+    S3Disabled(String),
+}
+
+type FetchRes<T> = Result<T, FetchErr>;
+
+/// How many bytes to copy between checks of the rate limit.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Options controlling how remote inputs are downloaded.
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    /// Where downloads are kept.
+    pub cache_dir: PathBuf,
+    /// The most bytes to download per second, if limited.
+    pub max_rate: Option<u64>,
+    /// How many times to retry (resuming where it left off) after a failure.
+    pub retries: usize,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self { cache_dir: default_cache_dir(), max_rate: None, retries: 3 }
+    }
+}
+
+/// The cache used unless told otherwise, under `$XDG_CACHE_HOME` (or
+/// `~/.cache`) if either is available.
+pub fn default_cache_dir() -> PathBuf {
+    let base = match (std::env::var_os("XDG_CACHE_HOME"), std::env::var_os("HOME")) {
+        (Some(cache), _) => PathBuf::from(cache),
+        (None, Some(home)) => Path::new(&home).join(".cache"),
+        (None, None) => std::env::temp_dir(),
+    };
+
+    base.join("sft").join("downloads")
+}
+
+/// Whether `input` should be downloaded rather than opened as a local path.
+pub fn is_remote(input: &str) -> bool {
+    ["http://", "https://", "s3://"].iter().any(|scheme| input.starts_with(scheme))
+}
+
+/// Download `url` into the cache (unless already there) and return the path
+/// of the local copy.
+pub fn fetch(url: &str, options: &FetchOptions) -> FetchRes<PathBuf> {
+    let http_url = to_http_url(url)?;
+    let path = cache_path(&options.cache_dir, url);
+
+    if path.exists() {
+        if is_current(&http_url, &path) {
+            log::debug!("Using cached download of {}.", url);
+            return Ok(path);
+        }
+
+        log::info!("Cached download of {} is out of date.", url);
+        fs::remove_file(&path)?;
+        remove_if_exists(&validator_path(&path))?;
+    }
+
+    fs::create_dir_all(&options.cache_dir)?;
+    let mut partial = path.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let mut attempt = 0;
+
+    loop {
+        match download(&http_url, &partial, options.max_rate) {
+            Ok(()) => break,
+            Err(err) if attempt < options.retries => {
+                attempt += 1;
+                log::warn!(
+                    "Download of {} failed ({}). Retrying ({}/{})...",
+                    url,
+                    err,
+                    attempt,
+                    options.retries
+                );
+                thread::sleep(Duration::from_secs(1 << attempt.min(5)));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    fs::rename(&partial, &path)?;
+
+    match read_validator(&partial) {
+        Some(_) => fs::rename(validator_path(&partial), validator_path(&path))?,
+        None => remove_if_exists(&validator_path(&path))?,
+    }
+
+    log::info!("Downloaded {} to {}.", url, path.display());
+    Ok(path)
+}
+
+/// Where the validator (see `validator`) of the download at `path` is kept.
+fn validator_path(path: &Path) -> PathBuf {
+    let mut validator = path.as_os_str().to_owned();
+    validator.push(".validator");
+    PathBuf::from(validator)
+}
+
+/// What identifies the version of a remote file: its ETag or, failing that,
+/// its Last-Modified date. Either may be sent back in an If-Range header.
+fn validator(response: &ureq::Response) -> Option<String> {
+    response.header("ETag").or_else(|| response.header("Last-Modified")).map(str::to_string)
+}
+
+fn read_validator(path: &Path) -> Option<String> {
+    fs::read_to_string(validator_path(path)).ok().filter(|validator| !validator.is_empty())
+}
+
+/// Whether the cached download at `path` is still the current version of
+/// `url`. If that cannot be told (e.g. the server is unreachable or sent no
+/// validator), the cache is trusted.
+fn is_current(url: &str, path: &Path) -> bool {
+    let cached = match read_validator(path) {
+        Some(cached) => cached,
+        None => return true,
+    };
+
+    match ureq::head(url).call() {
+        Ok(response) => validator(&response).map_or(true, |current| current == cached),
+        Err(err) => {
+            log::warn!("Could not check whether the download of {} is current ({}).", url, err);
+            true
+        }
+    }
+}
+
+/// Where the download of `url` is kept. The name of the file is kept as a
+/// suffix so that formats can still be detected by extension (e.g. `.kzip`).
+fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let digest = Sha256::digest(url.as_bytes());
+    let hash: String = digest.iter().take(16).map(|byte| format!("{:02x}", byte)).collect();
+    let name = url.rsplit('/').next().filter(|name| !name.is_empty()).unwrap_or("download");
+    let name = name.split(['?', '#']).next().unwrap_or(name);
+    cache_dir.join(format!("{}-{}", hash, name))
+}
+
+#[cfg(feature = "s3")]
+fn to_http_url(url: &str) -> FetchRes<String> {
+    let rest = match url.strip_prefix("s3://") {
+        Some(rest) => rest,
+        None => return Ok(url.to_string()),
+    };
+
+    // Objects must be readable anonymously (or the URL presigned elsewhere)
+    let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+    Ok(match std::env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+        Err(_) => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+    })
+}
+
+#[cfg(not(feature = "s3"))]
+fn to_http_url(url: &str) -> FetchRes<String> {
+    match url.starts_with("s3://") {
+        true => Err(FetchErr::S3Disabled(url.to_string())),
+        false => Ok(url.to_string()),
+    }
+}
+
+/// Stream `url` into `partial`, resuming from the end of `partial` if it
+/// already exists and is of the same version of the file.
+fn download(url: &str, partial: &Path, max_rate: Option<u64>) -> FetchRes<()> {
+    let offset = fs::metadata(partial).map(|meta| meta.len()).unwrap_or(0);
+    let mut request = ureq::get(url);
+
+    // Without a validator there is no telling whether the rest of the file
+    // would come from the same version, so it is downloaded whole
+    match (offset, read_validator(partial)) {
+        (0, _) => (),
+        (_, None) => log::info!("Restarting download of {} (cannot resume it safely).", url),
+        (_, Some(validator)) => {
+            log::info!("Resuming download of {} from byte {}.", url, offset);
+            request =
+                request.set("Range", &format!("bytes={}-", offset)).set("If-Range", &validator);
+        }
+    }
+
+    let response = match request.call() {
+        Ok(response) => response,
+        // The partial file is already complete (or stale), so start over
+        Err(ureq::Error::Status(416, _)) => {
+            fs::remove_file(partial)?;
+            return Err(FetchErr::Http(url.to_string(), "invalid range".to_string()));
+        }
+        Err(err) => return Err(FetchErr::Http(url.to_string(), err.to_string())),
+    };
+
+    // Anything but a 206 is the whole file, either because it changed since
+    // the partial file was downloaded (per If-Range) or because the server
+    // ignores ranges
+    let resumed = format!("bytes {}-", offset);
+    let mut file = match response.status() {
+        206 if response.header("Content-Range").map_or(false, |r| r.starts_with(&resumed)) => {
+            OpenOptions::new().create(true).append(true).open(partial)?
+        }
+        206 => {
+            fs::remove_file(partial)?;
+            return Err(FetchErr::Http(url.to_string(), "unexpected range".to_string()));
+        }
+        _ => File::create(partial)?,
+    };
+
+    match validator(&response) {
+        Some(validator) => fs::write(validator_path(partial), validator)?,
+        None => remove_if_exists(&validator_path(partial))?,
+    }
+
+    copy_limited(&mut response.into_reader(), &mut file, max_rate)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Copy everything from `reader` to `writer`, sleeping as needed to stay under
+/// `max_rate` bytes per second.
+fn copy_limited<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    max_rate: Option<u64>,
+) -> io::Result<u64> {
+    let start = Instant::now();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut total = 0u64;
+
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(total),
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        writer.write_all(&buf[..len])?;
+        total += len as u64;
+
+        if let Some(rate) = max_rate.filter(|rate| *rate > 0) {
+            let expected = Duration::from_secs_f64(total as f64 / rate as f64);
+            let elapsed = start.elapsed();
+
+            if expected > elapsed {
+                thread::sleep(expected - elapsed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_path() {
+        let dir = Path::new("/cache");
+        let a = cache_path(dir, "https://example.com/builds/1/entries.kzip?token=x");
+        let b = cache_path(dir, "https://example.com/builds/2/entries.kzip");
+
+        assert!(a.to_str().unwrap().ends_with("-entries.kzip"));
+        assert!(b.to_str().unwrap().ends_with("-entries.kzip"));
+        assert_ne!(a, b);
+        assert!(cache_path(dir, "https://example.com/").to_str().unwrap().ends_with("-download"));
+        assert_eq!(validator_path(&b).extension().unwrap(), "validator");
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use itertools::Itertools;
+
+use crate::cycles::strongly_connected;
+
+/// How to order the variables of a DSM. A good order puts variables which
+/// depend on each other close together, so that the structure of the system
+/// shows up as blocks along the diagonal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Seriation {
+    /// Keep the variables in order of name.
+    #[default]
+    Name,
+    /// Reverse Cuthill-McKee, which keeps every variable close to those it is
+    /// connected to (in either direction), minimizing the bandwidth of the
+    /// matrix.
+    Rcm,
+    /// Place variables in the order they are merged by average-linkage
+    /// hierarchical clustering, so that tightly coupled groups are adjacent.
+    Cluster,
+    /// DSM partitioning: order the variables so that each only depends on
+    /// those before it, leaving every dependency below the diagonal except
+    /// those within cycles, whose members are kept together.
+    Partition,
+}
+
+impl Seriation {
+    /// The new order of the `n` variables of a matrix, as the old index of
+    /// the variable at each position. Each key of `weights` is a pair of
+    /// variables where the first depends on the second.
+    pub fn order(&self, n: usize, weights: &BTreeMap<(usize, usize), usize>) -> Vec<usize> {
+        match self {
+            Seriation::Name => (0..n).collect(),
+            Seriation::Rcm => reverse_cuthill_mckee(n, weights),
+            Seriation::Cluster => cluster(n, weights),
+            Seriation::Partition => partition(n, weights),
+        }
+    }
+}
+
+/// The neighbors of each variable (ignoring direction) with the combined
+/// weight of the dependencies between them.
+fn undirected(n: usize, weights: &BTreeMap<(usize, usize), usize>) -> Vec<BTreeMap<usize, usize>> {
+    let mut neighbors = vec![BTreeMap::new(); n];
+
+    for (&(src, tgt), &weight) in weights {
+        if src != tgt {
+            *neighbors[src].entry(tgt).or_default() += weight;
+            *neighbors[tgt].entry(src).or_default() += weight;
+        }
+    }
+
+    neighbors
+}
+
+fn reverse_cuthill_mckee(n: usize, weights: &BTreeMap<(usize, usize), usize>) -> Vec<usize> {
+    let neighbors = undirected(n, weights);
+    let degree = |v: &usize| neighbors[*v].len();
+    let mut visited = vec![false; n];
+    let mut order = Vec::with_capacity(n);
+
+    // Start each component from its least connected variable
+    for start in (0..n).sorted_by_key(|v| (degree(v), *v)) {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(v) = queue.pop_front() {
+            order.push(v);
+
+            for &w in neighbors[v].keys().sorted_by_key(|w| (degree(w), **w)) {
+                if !visited[w] {
+                    visited[w] = true;
+                    queue.push_back(w);
+                }
+            }
+        }
+    }
+
+    order.reverse();
+    order
+}
+
+fn cluster(n: usize, weights: &BTreeMap<(usize, usize), usize>) -> Vec<usize> {
+    let mut members: Vec<Vec<usize>> = (0..n).map(|v| vec![v]).collect();
+    let mut links: Vec<HashMap<usize, usize>> =
+        undirected(n, weights).into_iter().map(|links| links.into_iter().collect()).collect();
+    let mut alive: BTreeSet<usize> = (0..n).collect();
+
+    loop {
+        // The pair with the greatest average weight between their members,
+        // compared as fractions to stay exact
+        let best = alive
+            .iter()
+            .flat_map(|&a| {
+                links[a].iter().filter(move |(b, _)| a < **b).map(move |(b, w)| (a, *b, *w))
+            })
+            .map(|(a, b, w)| (a, b, w as u128, (members[a].len() * members[b].len()) as u128))
+            .max_by(|(a1, b1, w1, n1), (a2, b2, w2, n2)| {
+                (w1 * n2).cmp(&(w2 * n1)).then((a2, b2).cmp(&(a1, b1)))
+            });
+
+        let (a, b) = match best {
+            Some((a, b, _, _)) => (a, b),
+            None => break,
+        };
+
+        let moved = std::mem::take(&mut members[b]);
+        members[a].extend(moved);
+        alive.remove(&b);
+
+        for (c, w) in std::mem::take(&mut links[b]) {
+            links[c].remove(&b);
+
+            if c != a {
+                *links[a].entry(c).or_default() += w;
+                *links[c].entry(a).or_default() += w;
+            }
+        }
+
+        links[a].remove(&b);
+    }
+
+    // Clusters which never connected stay in order of their first member
+    alive.into_iter().flat_map(|a| std::mem::take(&mut members[a])).collect()
+}
+
+fn partition(n: usize, weights: &BTreeMap<(usize, usize), usize>) -> Vec<usize> {
+    // Every variable in a cycle joins the same component, named by its first
+    // member
+    let mut component_of: Vec<usize> = (0..n).collect();
+    let mut members: BTreeMap<usize, Vec<usize>> = (0..n).map(|v| (v, vec![v])).collect();
+
+    for cycle in strongly_connected(weights.keys().copied()) {
+        for &v in &cycle {
+            component_of[v] = cycle[0];
+            members.remove(&v);
+        }
+
+        members.insert(cycle[0], cycle);
+    }
+
+    let mut remaining: HashMap<usize, BTreeSet<usize>> = HashMap::new();
+    let mut preds: HashMap<usize, BTreeSet<usize>> = HashMap::new();
+
+    for &(src, tgt) in weights.keys() {
+        let (src, tgt) = (component_of[src], component_of[tgt]);
+
+        if src != tgt {
+            remaining.entry(src).or_default().insert(tgt);
+            preds.entry(tgt).or_default().insert(src);
+        }
+    }
+
+    // Place each component once everything it depends on has been placed,
+    // breaking ties by name
+    let mut ready: BTreeSet<usize> =
+        members.keys().filter(|c| !remaining.contains_key(c)).copied().collect();
+    let mut order = Vec::with_capacity(n);
+
+    while let Some(component) = ready.pop_first() {
+        order.extend(&members[&component]);
+
+        for pred in preds.get(&component).into_iter().flatten() {
+            let deps = remaining.get_mut(pred).unwrap();
+            deps.remove(&component);
+
+            if deps.is_empty() {
+                ready.insert(*pred);
+            }
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights(pairs: &[(usize, usize)]) -> BTreeMap<(usize, usize), usize> {
+        pairs.iter().map(|pair| (*pair, 1)).collect()
+    }
+
+    #[test]
+    fn test_partition() {
+        // 0 -> 1 -> 2 -> 1, 3 -> 0, 4 alone
+        let order = Seriation::Partition.order(5, &weights(&[(0, 1), (1, 2), (2, 1), (3, 0)]));
+        assert_eq!(order, vec![1, 2, 0, 3, 4]);
+    }
+
+    #[test]
+    fn test_cluster() {
+        // Two triangles joined by a single dep
+        let pairs = [(0, 2), (2, 4), (4, 0), (1, 3), (3, 5), (5, 1), (4, 5)];
+        let order = Seriation::Cluster.order(6, &weights(&pairs));
+        assert_eq!(order, vec![0, 2, 4, 1, 3, 5]);
+    }
+
+    #[test]
+    fn test_rcm() {
+        // A path which is numbered out of order
+        let order = Seriation::Rcm.order(4, &weights(&[(0, 2), (2, 1), (1, 3)]));
+        assert_eq!(order, vec![3, 1, 2, 0]);
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use itertools::Itertools;
+
+use crate::ir::{Dep, Entity, EntityGraph, NodeIndex, StableId};
+
+/// A destination for the entities and deps of an `EntityGraph`, such as a
+/// file in some particular format.
+///
+/// Every entity is written before any dep, so a sink which needs to know about
+/// the endpoints of a dep may look them up from the entities it has seen.
+pub trait OutputSink {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()>;
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()>;
+
+    /// Called once after the last entity and dep have been written.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl<S: OutputSink + ?Sized> OutputSink for Box<S> {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+        (**self).write_entity(entity)
+    }
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+        (**self).write_dep(dep)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Write each entity and then each dep to `sink`, both in sorted order.
+pub fn write_graph<S: OutputSink + ?Sized>(graph: &EntityGraph, sink: &mut S) -> io::Result<()> {
+    for entity in graph.entities.values().sorted() {
+        sink.write_entity(entity)?;
+    }
+
+    for dep in graph.deps.iter().sorted() {
+        sink.write_dep(dep)?;
+    }
+
+    sink.finish()
+}
+
+/// Writes each entity and dep as a line of JSON.
+pub struct NdjsonSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> OutputSink for NdjsonSink<W> {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entity)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, dep)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A dep along with the path and name of both of its endpoints, so that it can
+/// be understood without a separate table of entities.
+#[derive(Debug, serde::Serialize)]
+struct DepRow<'a> {
+    src: usize,
+    src_stable_id: String,
+    src_path: &'a str,
+    src_name: &'a str,
+    tgt: usize,
+    tgt_stable_id: String,
+    tgt_path: &'a str,
+    tgt_name: &'a str,
+    kind: String,
+    count: usize,
+    config: Option<&'a str>,
+}
+
+/// The stable id, path, and name of each entity seen so far, for filling in
+/// `DepRow`s.
+#[derive(Default)]
+struct Endpoints(HashMap<NodeIndex, (StableId, String, String)>);
+
+impl Endpoints {
+    fn insert(&mut self, entity: &Entity) {
+        self.0.insert(entity.id, (entity.stable_id, entity.path.clone(), entity.name.clone()));
+    }
+
+    /// Returns `None` if either endpoint of `dep` was never seen as an entity.
+    fn row<'a>(&'a self, dep: &'a Dep) -> Option<DepRow<'a>> {
+        let (src_stable_id, src_path, src_name) = self.0.get(&dep.src)?;
+        let (tgt_stable_id, tgt_path, tgt_name) = self.0.get(&dep.tgt)?;
+
+        Some(DepRow {
+            src: dep.src.0,
+            src_stable_id: src_stable_id.to_string(),
+            src_path,
+            src_name,
+            tgt: dep.tgt.0,
+            tgt_stable_id: tgt_stable_id.to_string(),
+            tgt_path,
+            tgt_name,
+            kind: format!("{:?}", dep.kind),
+            count: dep.count,
+            config: dep.config.as_deref(),
+        })
+    }
+}
+
+/// Writes one CSV row per dep. Entities only appear as the endpoints of deps.
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+    endpoints: Endpoints,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer: csv::Writer::from_writer(writer), endpoints: Endpoints::default() }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+        self.endpoints.insert(entity);
+        Ok(())
+    }
+
+    fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+        if let Some(row) = self.endpoints.row(dep) {
+            self.writer.serialize(row)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub use self::columnar::ParquetSink;
+
+#[cfg(feature = "parquet")]
+mod columnar {
+    use std::io::{self, Write};
+    use std::sync::Arc;
+
+    use parquet::column::writer::ColumnWriter;
+    use parquet::data_type::ByteArray;
+    use parquet::errors::{ParquetError, Result};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+    use parquet::schema::parser::parse_message_type;
+
+    use super::{Endpoints, OutputSink};
+    use crate::ir::{Dep, Entity};
+
+    /// Has the same columns as `DepRow`.
+    const SCHEMA: &str = "
+        message dep {
+            required int64 src;
+            required binary src_stable_id (UTF8);
+            required binary src_path (UTF8);
+            required binary src_name (UTF8);
+            required int64 tgt;
+            required binary tgt_stable_id (UTF8);
+            required binary tgt_path (UTF8);
+            required binary tgt_name (UTF8);
+            required binary kind (UTF8);
+            required int64 count;
+            optional binary config (UTF8);
+        }
+    ";
+
+    /// Number of deps to buffer before writing them out as a row group.
+    const ROW_GROUP_SIZE: usize = 1 << 20;
+
+    #[derive(Default)]
+    struct Columns {
+        src: Vec<i64>,
+        src_stable_id: Vec<ByteArray>,
+        src_path: Vec<ByteArray>,
+        src_name: Vec<ByteArray>,
+        tgt: Vec<i64>,
+        tgt_stable_id: Vec<ByteArray>,
+        tgt_path: Vec<ByteArray>,
+        tgt_name: Vec<ByteArray>,
+        kind: Vec<ByteArray>,
+        count: Vec<i64>,
+        config: Vec<ByteArray>,
+        config_levels: Vec<i16>,
+    }
+
+    /// Writes one Parquet row per dep, with the same columns as `CsvSink`.
+    pub struct ParquetSink<W: Write> {
+        writer: Option<SerializedFileWriter<W>>,
+        endpoints: Endpoints,
+        columns: Columns,
+    }
+
+    impl<W: Write> ParquetSink<W> {
+        pub fn new(writer: W) -> io::Result<Self> {
+            let schema = Arc::new(parse_message_type(SCHEMA).map_err(to_io)?);
+            let props = Arc::new(WriterProperties::builder().build());
+            let writer = SerializedFileWriter::new(writer, schema, props).map_err(to_io)?;
+            Ok(Self {
+                writer: Some(writer),
+                endpoints: Endpoints::default(),
+                columns: Columns::default(),
+            })
+        }
+
+        fn write_row_group(&mut self) -> Result<()> {
+            let columns = std::mem::take(&mut self.columns);
+            let writer = match &mut self.writer {
+                Some(writer) if !columns.src.is_empty() => writer,
+                _ => return Ok(()),
+            };
+
+            let mut row_group = writer.next_row_group()?;
+            write_int64(&mut row_group, &columns.src)?;
+            write_bytes(&mut row_group, &columns.src_stable_id, None)?;
+            write_bytes(&mut row_group, &columns.src_path, None)?;
+            write_bytes(&mut row_group, &columns.src_name, None)?;
+            write_int64(&mut row_group, &columns.tgt)?;
+            write_bytes(&mut row_group, &columns.tgt_stable_id, None)?;
+            write_bytes(&mut row_group, &columns.tgt_path, None)?;
+            write_bytes(&mut row_group, &columns.tgt_name, None)?;
+            write_bytes(&mut row_group, &columns.kind, None)?;
+            write_int64(&mut row_group, &columns.count)?;
+            write_bytes(&mut row_group, &columns.config, Some(&columns.config_levels))?;
+            row_group.close()?;
+            Ok(())
+        }
+    }
+
+    impl<W: Write> OutputSink for ParquetSink<W> {
+        fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+            self.endpoints.insert(entity);
+            Ok(())
+        }
+
+        fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+            let row = match self.endpoints.row(dep) {
+                Some(row) => row,
+                None => return Ok(()),
+            };
+
+            let columns = &mut self.columns;
+            columns.src.push(row.src as i64);
+            columns.src_stable_id.push(ByteArray::from(row.src_stable_id.as_str()));
+            columns.src_path.push(ByteArray::from(row.src_path));
+            columns.src_name.push(ByteArray::from(row.src_name));
+            columns.tgt.push(row.tgt as i64);
+            columns.tgt_stable_id.push(ByteArray::from(row.tgt_stable_id.as_str()));
+            columns.tgt_path.push(ByteArray::from(row.tgt_path));
+            columns.tgt_name.push(ByteArray::from(row.tgt_name));
+            columns.kind.push(ByteArray::from(row.kind.as_str()));
+            columns.count.push(row.count as i64);
+
+            match row.config {
+                Some(config) => {
+                    columns.config.push(ByteArray::from(config));
+                    columns.config_levels.push(1);
+                }
+                None => columns.config_levels.push(0),
+            }
+
+            if columns.src.len() >= ROW_GROUP_SIZE {
+                self.write_row_group().map_err(to_io)?;
+            }
+
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.write_row_group().map_err(to_io)?;
+
+            if let Some(writer) = self.writer.take() {
+                writer.close().map_err(to_io)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn next_column<'a, W: Write>(
+        row_group: &'a mut SerializedRowGroupWriter<'_, W>,
+    ) -> Result<parquet::file::writer::SerializedColumnWriter<'a>> {
+        row_group
+            .next_column()?
+            .ok_or_else(|| ParquetError::General("schema has too few columns".to_string()))
+    }
+
+    fn write_int64<W: Write>(
+        row_group: &mut SerializedRowGroupWriter<'_, W>,
+        values: &[i64],
+    ) -> Result<()> {
+        let mut column = next_column(row_group)?;
+
+        match column.untyped() {
+            ColumnWriter::Int64ColumnWriter(writer) => writer.write_batch(values, None, None)?,
+            _ => return Err(ParquetError::General("expected an int64 column".to_string())),
+        };
+
+        column.close()
+    }
+
+    fn write_bytes<W: Write>(
+        row_group: &mut SerializedRowGroupWriter<'_, W>,
+        values: &[ByteArray],
+        def_levels: Option<&[i16]>,
+    ) -> Result<()> {
+        let mut column = next_column(row_group)?;
+
+        match column.untyped() {
+            ColumnWriter::ByteArrayColumnWriter(writer) => {
+                writer.write_batch(values, def_levels, None)?
+            }
+            _ => return Err(ParquetError::General("expected a binary column".to_string())),
+        };
+
+        column.close()
+    }
+
+    fn to_io(err: ParquetError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
+
+#[cfg(feature = "flight")]
+pub use self::batches::{ArrowSink, Table};
+
+#[cfg(feature = "flight")]
+mod batches {
+    use std::io;
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+    use arrow::error::{ArrowError, Result};
+    use arrow::record_batch::RecordBatch;
+
+    use super::{Endpoints, OutputSink};
+    use crate::ir::{Dep, Entity};
+
+    /// Number of rows to buffer before cutting them into a record batch.
+    const BATCH_SIZE: usize = 1 << 16;
+
+    /// A named table of record batches which all have the same schema.
+    pub struct Table {
+        pub name: &'static str,
+        pub schema: SchemaRef,
+        pub batches: Vec<RecordBatch>,
+    }
+
+    impl Table {
+        fn new(name: &'static str, fields: Vec<Field>) -> Self {
+            Self { name, schema: Arc::new(Schema::new(fields)), batches: Vec::new() }
+        }
+
+        fn push(&mut self, columns: Vec<ArrayRef>) -> Result<()> {
+            self.batches.push(RecordBatch::try_new(self.schema.clone(), columns)?);
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct EntityColumns {
+        id: Vec<i64>,
+        stable_id: Vec<String>,
+        kind: Vec<&'static str>,
+        name: Vec<String>,
+        qualified_name: Vec<String>,
+        path: Vec<String>,
+        package: Vec<Option<String>>,
+    }
+
+    impl EntityColumns {
+        fn take(&mut self) -> Vec<ArrayRef> {
+            let columns = std::mem::take(self);
+            vec![
+                Arc::new(Int64Array::from(columns.id)),
+                Arc::new(StringArray::from(columns.stable_id)),
+                Arc::new(StringArray::from(columns.kind)),
+                Arc::new(StringArray::from(columns.name)),
+                Arc::new(StringArray::from(columns.qualified_name)),
+                Arc::new(StringArray::from(columns.path)),
+                Arc::new(StringArray::from(columns.package)),
+            ]
+        }
+    }
+
+    /// Has the same columns as `DepRow`.
+    #[derive(Default)]
+    struct DepColumns {
+        src: Vec<i64>,
+        src_stable_id: Vec<String>,
+        src_path: Vec<String>,
+        src_name: Vec<String>,
+        tgt: Vec<i64>,
+        tgt_stable_id: Vec<String>,
+        tgt_path: Vec<String>,
+        tgt_name: Vec<String>,
+        kind: Vec<String>,
+        count: Vec<i64>,
+        config: Vec<Option<String>>,
+    }
+
+    impl DepColumns {
+        fn take(&mut self) -> Vec<ArrayRef> {
+            let columns = std::mem::take(self);
+            vec![
+                Arc::new(Int64Array::from(columns.src)),
+                Arc::new(StringArray::from(columns.src_stable_id)),
+                Arc::new(StringArray::from(columns.src_path)),
+                Arc::new(StringArray::from(columns.src_name)),
+                Arc::new(Int64Array::from(columns.tgt)),
+                Arc::new(StringArray::from(columns.tgt_stable_id)),
+                Arc::new(StringArray::from(columns.tgt_path)),
+                Arc::new(StringArray::from(columns.tgt_name)),
+                Arc::new(StringArray::from(columns.kind)),
+                Arc::new(Int64Array::from(columns.count)),
+                Arc::new(StringArray::from(columns.config)),
+            ]
+        }
+    }
+
+    /// Collects an "entities" table and a "deps" table of Arrow record batches
+    /// in memory. The deps table has the same columns as `CsvSink`.
+    pub struct ArrowSink {
+        endpoints: Endpoints,
+        entity_columns: EntityColumns,
+        dep_columns: DepColumns,
+        entities: Table,
+        deps: Table,
+    }
+
+    impl Default for ArrowSink {
+        fn default() -> Self {
+            let utf8 = |name| Field::new(name, DataType::Utf8, false);
+            let int64 = |name| Field::new(name, DataType::Int64, false);
+
+            Self {
+                endpoints: Endpoints::default(),
+                entity_columns: EntityColumns::default(),
+                dep_columns: DepColumns::default(),
+                entities: Table::new(
+                    "entities",
+                    vec![
+                        int64("id"),
+                        utf8("stable_id"),
+                        utf8("kind"),
+                        utf8("name"),
+                        utf8("qualified_name"),
+                        utf8("path"),
+                        Field::new("package", DataType::Utf8, true),
+                    ],
+                ),
+                deps: Table::new(
+                    "deps",
+                    vec![
+                        int64("src"),
+                        utf8("src_stable_id"),
+                        utf8("src_path"),
+                        utf8("src_name"),
+                        int64("tgt"),
+                        utf8("tgt_stable_id"),
+                        utf8("tgt_path"),
+                        utf8("tgt_name"),
+                        utf8("kind"),
+                        int64("count"),
+                        Field::new("config", DataType::Utf8, true),
+                    ],
+                ),
+            }
+        }
+    }
+
+    impl ArrowSink {
+        /// The entities table followed by the deps table. Only complete after
+        /// `finish`.
+        pub fn into_tables(self) -> Vec<Table> {
+            vec![self.entities, self.deps]
+        }
+    }
+
+    impl OutputSink for ArrowSink {
+        fn write_entity(&mut self, entity: &Entity) -> io::Result<()> {
+            self.endpoints.insert(entity);
+
+            let columns = &mut self.entity_columns;
+            columns.id.push(entity.id.0 as i64);
+            columns.stable_id.push(entity.stable_id.to_string());
+            columns.kind.push(entity.kind.name());
+            columns.name.push(entity.name.clone());
+            columns.qualified_name.push(entity.qualified_name.clone());
+            columns.path.push(entity.path.clone());
+            columns.package.push(entity.package.clone());
+
+            if columns.id.len() >= BATCH_SIZE {
+                self.entities.push(self.entity_columns.take()).map_err(to_io)?;
+            }
+
+            Ok(())
+        }
+
+        fn write_dep(&mut self, dep: &Dep) -> io::Result<()> {
+            let row = match self.endpoints.row(dep) {
+                Some(row) => row,
+                None => return Ok(()),
+            };
+
+            let columns = &mut self.dep_columns;
+            columns.src.push(row.src as i64);
+            columns.src_stable_id.push(row.src_stable_id);
+            columns.src_path.push(row.src_path.to_string());
+            columns.src_name.push(row.src_name.to_string());
+            columns.tgt.push(row.tgt as i64);
+            columns.tgt_stable_id.push(row.tgt_stable_id);
+            columns.tgt_path.push(row.tgt_path.to_string());
+            columns.tgt_name.push(row.tgt_name.to_string());
+            columns.kind.push(row.kind);
+            columns.count.push(row.count as i64);
+            columns.config.push(row.config.map(str::to_string));
+
+            if columns.src.len() >= BATCH_SIZE {
+                self.deps.push(self.dep_columns.take()).map_err(to_io)?;
+            }
+
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            if !self.entity_columns.id.is_empty() {
+                self.entities.push(self.entity_columns.take()).map_err(to_io)?;
+            }
+
+            if !self.dep_columns.src.is_empty() {
+                self.deps.push(self.dep_columns.take()).map_err(to_io)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    fn to_io(err: ArrowError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, err)
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::io::Ticket;
+use crate::ir::{EdgeKind, RawEdge, RawGraph, RawNodeValue};
+
+const MAGIC: &[u8; 8] = b"SFTSNAP\0";
+
+/// The version of the snapshots written by this build. Bump whenever the
+/// layout of `Snapshot` or `SnapshotStats` (or anything within them) changes,
+/// and either add a migration from the previous version to `read_body` or
+/// raise `OLDEST_MIGRATABLE` so that older snapshots ask to be re-indexed.
+///
+/// - 1: The first release.
+/// - 2: Entity names record the source they were taken from.
+/// - 3: Nodes and deps carry their build configs.
+/// - 4: Summary stats are stored in a header ahead of the graph.
+/// - 5: Nodes keep the facts of diagnostic and VCS nodes.
+pub const VERSION: u32 = 5;
+
+/// The oldest version which can still be read (and upgraded).
+const OLDEST_MIGRATABLE: u32 = 3;
+
+/// How many of the largest top-level directories to keep in the stats.
+const NUM_TOP_DIRS: usize = 20;
+
+#[derive(Debug, Error)]
+pub enum SnapshotErr {
+    #[error("failed to read or write snapshot")]
+    Io(#[from] io::Error),
+    #[error("failed to encode or decode snapshot")]
+    Encoding(#[from] bincode::Error),
+    #[error("not a snapshot file")]
+    NotSnapshot,
+    #[error("unsupported snapshot version {0} (expected at most {})", VERSION)]
+    UnsupportedVersion(u32),
+    #[error("snapshot version {0} is too old to upgrade (re-index to create a new snapshot)")]
+    ReindexRequired(u32),
+}
+
+type SnapshotRes<T> = Result<T, SnapshotErr>;
+
+/// A compact binary image of a `RawGraph`.
+///
+/// Loading a snapshot skips JSON and base64 decoding entirely, and since
+/// entries are already deduplicated it is usually much smaller than the
+/// entries it was built from.
+///
+/// Summary statistics are stored in a header ahead of the graph itself, so
+/// they can be read without loading the whole snapshot.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    tickets: Vec<Ticket>,
+    nodes: Vec<RawNodeValue>,
+    edges: Vec<RawEdge>,
+}
+
+/// An overview of the graph within a snapshot.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotStats {
+    pub num_nodes: usize,
+    pub num_edges: usize,
+    pub node_kinds: BTreeMap<String, usize>,
+    pub edge_kinds: BTreeMap<String, usize>,
+    pub languages: BTreeMap<String, usize>,
+    /// The number of nodes under each of the largest top-level directories.
+    pub top_dirs: Vec<(String, usize)>,
+}
+
+impl From<&Snapshot> for SnapshotStats {
+    fn from(snapshot: &Snapshot) -> Self {
+        let node_kinds = snapshot
+            .nodes
+            .iter()
+            .map(|node| node.node_kind().unwrap_or("<none>").to_string())
+            .counts()
+            .into_iter()
+            .collect();
+
+        let edge_kinds = snapshot
+            .edges
+            .iter()
+            .map(|(kind, _, _, _)| match kind {
+                EdgeKind::Param(_) => "Param".to_string(),
+                kind => format!("{:?}", kind),
+            })
+            .counts()
+            .into_iter()
+            .collect();
+
+        let languages = snapshot
+            .tickets
+            .iter()
+            .map(|ticket| ticket.language.clone().unwrap_or_else(|| "<none>".to_string()))
+            .counts()
+            .into_iter()
+            .collect();
+
+        let top_dirs = snapshot
+            .tickets
+            .iter()
+            .filter_map(|ticket| ticket.path.as_deref())
+            .filter_map(|path| path.split_once('/').map(|(dir, _)| dir))
+            .counts()
+            .into_iter()
+            .sorted_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)))
+            .take(NUM_TOP_DIRS)
+            .map(|(dir, count)| (dir.to_string(), count))
+            .collect();
+
+        SnapshotStats {
+            num_nodes: snapshot.nodes.len(),
+            num_edges: snapshot.edges.len(),
+            node_kinds,
+            edge_kinds,
+            languages,
+            top_dirs,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn write(&self, path: &Path) -> SnapshotRes<()> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, &SnapshotStats::from(self))?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read a snapshot, migrating it in memory if it was written by an older
+    /// version.
+    pub fn read(path: &Path) -> SnapshotRes<Self> {
+        let (reader, version) = open(path)?;
+
+        if version != VERSION {
+            log::warn!(
+                "Migrating snapshot {} from version {} to {}. Run `snapshot upgrade` to skip this \
+                 next time.",
+                path.display(),
+                version,
+                VERSION
+            );
+        }
+
+        read_body(reader, version)
+    }
+
+    /// Read only the stats from the header of a snapshot. Snapshots from
+    /// before stats were stored are read in full instead.
+    pub fn read_stats(path: &Path) -> SnapshotRes<SnapshotStats> {
+        match open(path)? {
+            (reader, 4..) => Ok(bincode::deserialize_from(reader)?),
+            (reader, version) => Ok(SnapshotStats::from(&read_body(reader, version)?)),
+        }
+    }
+
+    /// The version of the snapshot at `path`, without reading any further.
+    pub fn version(path: &Path) -> SnapshotRes<u32> {
+        Ok(open(path)?.1)
+    }
+
+    /// Rewrite the snapshot at `src` in the current version to `dest` (which
+    /// may be `src` itself). The new snapshot is only moved into place once
+    /// complete. Returns the version of the original snapshot.
+    pub fn upgrade(src: &Path, dest: &Path) -> SnapshotRes<u32> {
+        let (reader, version) = open(src)?;
+
+        if version == VERSION && src == dest {
+            return Ok(version);
+        }
+
+        let snapshot = read_body(reader, version)?;
+        let mut partial = dest.as_os_str().to_owned();
+        partial.push(".part");
+        let partial = PathBuf::from(partial);
+
+        snapshot.write(&partial)?;
+        fs::rename(&partial, dest)?;
+        Ok(version)
+    }
+}
+
+/// Open a snapshot and check its magic bytes and version, leaving the reader
+/// at the start of the header (or, before version 4, of the graph).
+fn open(path: &Path) -> SnapshotRes<(BufReader<fs::File>, u32)> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(|_| SnapshotErr::NotSnapshot)?;
+
+    if &magic != MAGIC {
+        return Err(SnapshotErr::NotSnapshot);
+    }
+
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+
+    match version {
+        0 => Err(SnapshotErr::NotSnapshot),
+        v if v > VERSION => Err(SnapshotErr::UnsupportedVersion(v)),
+        v if v < OLDEST_MIGRATABLE => Err(SnapshotErr::ReindexRequired(v)),
+        v => Ok((reader, v)),
+    }
+}
+
+/// Read the rest of a snapshot of the given version (just after its version
+/// number) and migrate it to the current version.
+fn read_body<R: Read>(mut reader: R, version: u32) -> SnapshotRes<Snapshot> {
+    match version {
+        VERSION => {
+            let _: SnapshotStats = bincode::deserialize_from(&mut reader)?;
+            Ok(bincode::deserialize_from(reader)?)
+        }
+        4 => {
+            let _: SnapshotStats = bincode::deserialize_from(&mut reader)?;
+            let snapshot: SnapshotV3 = bincode::deserialize_from(reader)?;
+            Ok(snapshot.into())
+        }
+        3 => {
+            let snapshot: SnapshotV3 = bincode::deserialize_from(reader)?;
+            Ok(snapshot.into())
+        }
+        version => Err(SnapshotErr::ReindexRequired(version)),
+    }
+}
+
+/// The graph of a snapshot of version 3 or 4, before the facts of diagnostic
+/// and VCS nodes were kept.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnapshotV3 {
+    tickets: Vec<Ticket>,
+    nodes: Vec<RawNodeValueV3>,
+    edges: Vec<RawEdge>,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct RawNodeValueV3 {
+    build_config: Option<String>,
+    code: Option<String>,
+    complete: Option<String>,
+    loc_end: Option<String>,
+    loc_start: Option<String>,
+    node_kind: Option<String>,
+    param_default: Option<String>,
+    subkind: Option<String>,
+    tag_deprecated: Option<String>,
+    tag_static: Option<String>,
+    text: Option<String>,
+}
+
+impl From<SnapshotV3> for Snapshot {
+    fn from(old: SnapshotV3) -> Self {
+        let nodes = old.nodes.into_iter().map(RawNodeValue::from).collect();
+        Snapshot { tickets: old.tickets, nodes, edges: old.edges }
+    }
+}
+
+impl From<RawNodeValueV3> for RawNodeValue {
+    fn from(old: RawNodeValueV3) -> Self {
+        RawNodeValue {
+            build_config: old.build_config,
+            code: old.code,
+            complete: old.complete,
+            loc_end: old.loc_end,
+            loc_start: old.loc_start,
+            node_kind: old.node_kind,
+            param_default: old.param_default,
+            subkind: old.subkind,
+            tag_deprecated: old.tag_deprecated,
+            tag_static: old.tag_static,
+            text: old.text,
+            ..RawNodeValue::default()
+        }
+    }
+}
+
+impl From<RawGraph> for Snapshot {
+    fn from(graph: RawGraph) -> Self {
+        let (tickets, nodes, edges) = graph.into_parts();
+        Snapshot { tickets, nodes, edges }
+    }
+}
+
+impl From<Snapshot> for RawGraph {
+    fn from(snapshot: Snapshot) -> Self {
+        RawGraph::from_parts(snapshot.tickets, snapshot.nodes, snapshot.edges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_v3() {
+        let dir = std::env::temp_dir().join(format!("sft-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("v3.snap");
+
+        let node = RawNodeValueV3 {
+            node_kind: Some("file".to_string()),
+            text: Some("text".to_string()),
+            ..RawNodeValueV3::default()
+        };
+        let graph =
+            SnapshotV3 { tickets: vec![Ticket::default()], nodes: vec![node], edges: vec![] };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(bincode::serialize(&graph).unwrap());
+        fs::write(&path, bytes).unwrap();
+
+        let stats = Snapshot::read_stats(&path).unwrap();
+        assert_eq!(stats.node_kinds, BTreeMap::from([("file".to_string(), 1)]));
+
+        assert_eq!(Snapshot::upgrade(&path, &path).unwrap(), 3);
+        assert_eq!(Snapshot::version(&path).unwrap(), VERSION);
+        let snapshot = Snapshot::read(&path).unwrap();
+        assert_eq!(snapshot.nodes[0].text.as_deref(), Some("text"));
+
+        fs::write(&path, [MAGIC.as_slice(), &2u32.to_le_bytes()].concat()).unwrap();
+        assert!(matches!(Snapshot::read(&path), Err(SnapshotErr::ReindexRequired(2))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::ir::{FilePath, FileTable};
+use kythe_bridge::files::FileStore;
+
+/// Whether the text a file was indexed with still matches the checkout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Freshness {
+    Fresh,
+    /// The file has changed since it was indexed.
+    Stale,
+    /// The file is no longer in the checkout.
+    Missing,
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+pub struct FileFreshness {
+    pub path: String,
+    pub freshness: Freshness,
+    /// The SHA-256 of the text in the graph.
+    pub indexed_sha256: String,
+    /// The SHA-256 of the file in the checkout, if it exists.
+    pub current_sha256: Option<String>,
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The path of `file_path` relative to the root of the checkout. Files with a
+/// root (usually generated under a build output directory) are skipped unless
+/// `include_roots`, in which case the root is prepended to the path.
+fn repo_path(file_path: &FilePath, include_roots: bool) -> Option<String> {
+    let path = file_path.path.as_deref()?;
+
+    match file_path.root.as_deref() {
+        Some(root) if !root.is_empty() => match include_roots {
+            true => Some(format!("{}/{}", root, path)),
+            false => None,
+        },
+        _ => Some(path.to_string()),
+    }
+}
+
+/// Compare the hash of each text in `texts` with the hash of the same file
+/// under `repo`, sorted by path. Files which cannot be read for any reason
+/// other than not existing are an error.
+pub fn check_freshness(
+    texts: &FileStore,
+    paths: &FileTable,
+    repo: &Path,
+    include_roots: bool,
+) -> io::Result<Vec<FileFreshness>> {
+    let mut files = Vec::new();
+
+    for file_key in texts.keys() {
+        let path = match repo_path(paths.resolve(file_key), include_roots) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let indexed_sha256 = sha256(texts.text(file_key).unwrap_or_default().as_bytes());
+        let current_sha256 = match fs::read(repo.join(&path)) {
+            Ok(bytes) => Some(sha256(&bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        let freshness = match &current_sha256 {
+            None => Freshness::Missing,
+            Some(current) if *current == indexed_sha256 => Freshness::Fresh,
+            Some(_) => Freshness::Stale,
+        };
+
+        files.push(FileFreshness { path, freshness, indexed_sha256, current_sha256 });
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Ticket;
+
+    #[test]
+    fn test_check_freshness() {
+        let repo = std::env::temp_dir().join(format!("staleness-{}", std::process::id()));
+        fs::create_dir_all(repo.join("src")).unwrap();
+        fs::write(repo.join("src/a.cc"), "int a;\n").unwrap();
+        fs::write(repo.join("src/b.cc"), "int b = 2;\n").unwrap();
+
+        let mut paths = FileTable::default();
+        let mut texts = FileStore::new();
+
+        for (path, text) in [("src/a.cc", "int a;\n"), ("src/b.cc", "int b;\n"), ("c.cc", "")] {
+            let ticket = Ticket { path: Some(path.to_string()), ..Default::default() };
+            texts.insert(paths.intern(&ticket), text.to_string()).unwrap();
+        }
+
+        // Generated files are skipped by default
+        let ticket = Ticket {
+            path: Some("src/a.pb.cc".to_string()),
+            root: Some("bazel-out/k8-opt/bin".to_string()),
+            ..Default::default()
+        };
+        texts.insert(paths.intern(&ticket), String::new()).unwrap();
+        assert_eq!(check_freshness(&texts, &paths, &repo, true).unwrap().len(), 4);
+
+        let files = check_freshness(&texts, &paths, &repo, false).unwrap();
+        fs::remove_dir_all(&repo).unwrap();
+
+        let freshness = files.iter().map(|f| (f.path.as_str(), f.freshness)).collect::<Vec<_>>();
+        assert_eq!(
+            freshness,
+            vec![
+                ("c.cc", Freshness::Missing),
+                ("src/a.cc", Freshness::Fresh),
+                ("src/b.cc", Freshness::Stale)
+            ]
+        );
+    }
+}
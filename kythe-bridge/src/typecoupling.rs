@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use crate::ir::{EdgeKind, EntityGraph, NodeIndex, NodeIndices, NodeKind, SpecGraph};
+
+/// How deeply to follow type applications and aliases before giving up.
+const MAX_TYPE_DEPTH: usize = 16;
+
+/// How many times the entities of a file are typed by a particular type.
+///
+/// Type applications (e.g. `List<Foo>`) count as a use of each of their
+/// parameters (`List` and `Foo`) and aliases count as a use of the aliased
+/// type. Builtin types are ignored.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+pub struct TypeUse {
+    pub path: String,
+    pub type_path: String,
+    pub type_name: String,
+    pub count: usize,
+}
+
+pub fn type_uses(spec: &SpecGraph, graph: &EntityGraph) -> Vec<TypeUse> {
+    let mut counts: BTreeMap<(&str, &str, &str), usize> = BTreeMap::new();
+
+    for dep in graph.deps.iter().filter(|dep| dep.kind == EdgeKind::Typed) {
+        let src = match graph.entities.get(&dep.src) {
+            Some(src) => src,
+            None => continue,
+        };
+
+        let mut types = Vec::new();
+        resolve_types(spec, dep.tgt, &mut types, 0);
+
+        for tgt in types.iter().filter_map(|index| graph.entities.get(index)) {
+            let key = (src.path.as_str(), tgt.path.as_str(), tgt.name.as_str());
+            *counts.entry(key).or_default() += dep.count;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|((path, type_path, type_name), count)| TypeUse {
+            path: path.to_string(),
+            type_path: type_path.to_string(),
+            type_name: type_name.to_string(),
+            count,
+        })
+        .collect()
+}
+
+/// Collect the declared types which make up the type at `index`.
+fn resolve_types(spec: &SpecGraph, index: NodeIndex, types: &mut Vec<NodeIndex>, depth: usize) {
+    if depth > MAX_TYPE_DEPTH {
+        log::warn!("Gave up resolving type {} after {} levels.", index, MAX_TYPE_DEPTH);
+        return;
+    }
+
+    match spec.get_node(index).kind {
+        NodeKind::Tapp => {
+            for (kind, tgt, _) in spec.outgoing_all(index) {
+                if let EdgeKind::Param(_) = kind {
+                    resolve_types(spec, tgt, types, depth + 1);
+                }
+            }
+        }
+        NodeKind::Talias => match spec.outgoing(EdgeKind::Aliases, index) {
+            NodeIndices::Sole(tgt) => resolve_types(spec, tgt, types, depth + 1),
+            _ => types.push(index),
+        },
+        NodeKind::Tbuiltin => {}
+        _ => types.push(index),
+    }
+}
@@ -1,15 +1,18 @@
+use std::collections::HashSet;
 use std::fmt::format;
-use std::io::Read;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use clap::{CommandFactory, Parser};
 use colored::Colorize;
 use sled::Db;
-use tokio::join;
-use tokio::process::Command;
-
-use rand::Rng;
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStdout, Command};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 
@@ -21,6 +24,11 @@ use tokio::task::JoinSet;
 
 use itertools::Itertools;
 
+use kythe_bridge::canonical::content_hash;
+use kythe_bridge::exclusion::{read_rule_file, ExclusionSet};
+use kythe_bridge::io::{compress, EntryLineReader};
+use kythe_bridge::paths;
+
 ///
 #[derive(clap::Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -62,17 +70,165 @@ struct CliIndexCommand {
     #[clap(value_parser, default_value_t = String::from("*.kzip"))]
     glob_pattern: String,
 
-    /// Number of Kythe indexer processes to _attempt_ to run at one time
+    /// Number of Kythe indexer processes to keep running at one time. A new
+    /// process is started as soon as any other finishes.
     #[clap(short, long)]
     batch_size: usize,
+
+    /// Argument to pass to the indexer before the path of each kzip. May be
+    /// repeated, e.g. `--indexer-arg=--ignore_unimplemented`
+    #[clap(long = "indexer-arg", value_name = "ARG", allow_hyphen_values = true)]
+    indexer_args: Vec<String>,
+
+    /// Number of seconds an indexer process may run before it is killed and
+    /// its kzip counted as failed. If ommitted, processes may run forever.
+    #[clap(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Number of times to retry an indexer process which fails (or times
+    /// out), waiting longer before each retry
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    retries: usize,
+
+    /// Path of a JSON file to write the kzips which failed to, along with the
+    /// exit status and the end of the stderr of their last attempt
+    #[clap(long, value_parser)]
+    failure_report: Option<PathBuf>,
+
+    /// Number of new entries to collect from an indexer before applying them
+    /// to the database in a single batch and flushing. Output is stored as it
+    /// is read, so this bounds how much of it is held in memory.
+    #[clap(long, default_value_t = 10_000)]
+    flush_every: usize,
+
+    /// Path to a file of exclusion rules (in the format accepted by `sft
+    /// exclude --rules`). Matching entries are dropped before being stored.
+    #[clap(long, value_parser)]
+    exclusion_rules: Option<PathBuf>,
 }
 
 /// Write out the contents of a cache file created with `index`
+///
+/// Entries are written as newline-delimited JSON, which can be passed straight
+/// to `sft exclude`, `sft format`, etc.
 #[derive(clap::Args)]
 struct CliDumpCommand {
     /// The cache_db created with `index`
     #[clap(value_parser)]
     db: PathBuf,
+
+    /// Path of the output file. If ommitted, will write to stdout.
+    #[clap(short, long, value_parser)]
+    output: Option<PathBuf>,
+
+    /// Gzip the output (which `sft` decompresses on its own)
+    #[clap(short = 'z', long)]
+    compress: bool,
+
+    /// Include the kzip and indexer version that produced each entry
+    #[clap(long)]
+    provenance: bool,
+}
+
+/// Name of the sled tree mapping each source id to an encoded `Provenance`
+const SOURCES_TREE: &str = "sources";
+
+/// Name of the sled tree mapping each entry key to the id of its source
+const PROVENANCE_TREE: &str = "provenance";
+
+/// Where a group of entries came from, so that bad regions of the graph can be
+/// traced back to the exact compilation unit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Provenance {
+    kzip: PathBuf,
+    indexer: PathBuf,
+    indexer_version: String,
+}
+
+impl Provenance {
+    /// Encode as tab-separated fields (none of which may contain a tab)
+    fn to_bytes(&self) -> Vec<u8> {
+        let fields = [
+            self.kzip.to_string_lossy().replace('\t', " "),
+            self.indexer.to_string_lossy().replace('\t', " "),
+            self.indexer_version.replace('\t', " "),
+        ];
+        fields.join("\t").into_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(bytes).context("Provenance is not valid UTF-8")?;
+
+        match text.split('\t').collect_vec().as_slice() {
+            [kzip, indexer, indexer_version] => Ok(Self {
+                kzip: PathBuf::from(kzip),
+                indexer: PathBuf::from(indexer),
+                indexer_version: indexer_version.to_string(),
+            }),
+            _ => anyhow::bail!("Malformed provenance `{}`", text),
+        }
+    }
+}
+
+/// Store `provenance` (unless an identical one is already stored) and return
+/// its id, which is then recorded alongside each entry it produced. The id is
+/// a hash of the provenance, or the next free id after it on a collision.
+fn register_source(db: &Db, provenance: &Provenance) -> Result<u64> {
+    let sources = db.open_tree(SOURCES_TREE).context("Failed to open sources tree")?;
+    let bytes = provenance.to_bytes();
+    let mut id = fnv1a(&bytes);
+
+    loop {
+        let stored = sources
+            .compare_and_swap(id.to_be_bytes(), None as Option<&[u8]>, Some(bytes.as_slice()))
+            .context("Failed to store provenance")?;
+
+        match stored {
+            Ok(()) => return Ok(id),
+            Err(err) if err.current.as_deref() == Some(bytes.as_slice()) => return Ok(id),
+            Err(_) => id = id.wrapping_add(1),
+        }
+    }
+}
+
+/// A hash which (unlike `DefaultHasher`) is the same in every build, since
+/// the ids it produces are persisted
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Record that the entry stored under `key` came from source `id`
+fn record_provenance(pending: &mut PendingWrites, key: &[u8], id: u64) {
+    pending.provenance.insert(key, &id.to_be_bytes());
+}
+
+/// Look up where the entry stored under `key` came from, if known
+fn lookup_provenance(db: &Db, key: &[u8]) -> Result<Option<Provenance>> {
+    let id = match db.open_tree(PROVENANCE_TREE)?.get(key)? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    match db.open_tree(SOURCES_TREE)?.get(id)? {
+        Some(bytes) => Ok(Some(Provenance::from_bytes(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Ask the indexer for its version, falling back to `unknown`
+async fn indexer_version(indexer: &Path) -> String {
+    match Command::new(indexer).arg("--version").output().await {
+        Ok(output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            match stdout.lines().next() {
+                Some(line) if !line.trim().is_empty() => line.trim().to_string(),
+                _ => String::from("unknown"),
+            }
+        }
+        _ => String::from("unknown"),
+    }
 }
 
 #[tokio::main]
@@ -89,7 +245,7 @@ async fn main() -> Result<()> {
 
 async fn index(args: CliIndexCommand) -> Result<()> {
     // Open database
-    let mut db = sled::open(&args.db).context("Failed to open database")?;
+    let db = sled::open(&args.db).context("Failed to open database")?;
     if sled::Db::was_recovered(&db) {
         log::info!("Connected to existing database `{}`", &args.db.to_string_lossy());
     } else {
@@ -103,63 +259,535 @@ async fn index(args: CliIndexCommand) -> Result<()> {
     let elapsed = start.elapsed().as_secs_f32();
     log::info!("Found {} files in {} secs", files.len(), elapsed);
 
-    let n_batches = div_ceil(files.len(), args.batch_size);
-    log::info!("Breaking into {} batches of at most {} files each...", n_batches, args.batch_size);
+    let version = indexer_version(&args.indexer).await;
+    log::info!("Using indexer `{}` ({})", &args.indexer.to_string_lossy(), version);
+
+    let rules = match &args.exclusion_rules {
+        Some(path) => read_rule_file(path)
+            .with_context(|| format!("Failed to read exclusion rules `{}`", path.display()))?,
+        None => ExclusionSet::new(),
+    };
+    log::info!("Loaded {} exclusion rule(s)", rules.len());
+
+    let store = Arc::new(Store { db: db.clone(), rules, flush_every: args.flush_every.max(1) });
 
     // Launch subprocess for each file
-    let mut rng = rand::thread_rng();
+    let n_files = files.len();
+    let concurrency = args.batch_size.max(1);
+    log::info!("Indexing with up to {} processes at a time...", concurrency);
+
+    let start = Instant::now();
+    let run = Arc::new(IndexerRun {
+        indexer: args.indexer.clone(),
+        indexer_args: args.indexer_args.clone(),
+        indexer_version: version,
+        timeout: args.timeout.map(Duration::from_secs),
+        retries: args.retries,
+    });
+    let (stats, failed) = process_files(run, store, files, concurrency).await?;
+    log::info!("Indexed {} files in {} secs", n_files, start.elapsed().as_secs_f32());
+    log::info!(
+        "Stored {} new entries ({} duplicate, {} excluded, {} malformed)",
+        stats.n_new,
+        stats.n_duplicate,
+        stats.n_excluded,
+        stats.n_malformed
+    );
+
+    db.flush().context("Failed to flush database")?;
+
+    if !failed.is_empty() {
+        log::warn!("Indexer failed on {} of {} files", failed.len(), n_files);
+    }
+
+    if let Some(path) = &args.failure_report {
+        write_failure_report(path, &failed)?;
+        log::info!("Wrote failure report to `{}`", path.to_string_lossy());
+    }
+
+    Ok(())
+}
 
-    let batches = &files.into_iter().chunks(args.batch_size);
-    let batches = batches.into_iter().enumerate();
+/// Number of bytes read from the stdout of an indexer at a time
+const CHUNK_BYTES: usize = 64 * 1024;
 
-    for (i, batch) in batches {
-        let files = batch.collect_vec();
+/// Number of chunks of stdout which may wait to be stored. Once this many are
+/// waiting, stdout is no longer read, so the indexer waits as well.
+const CHUNKS_IN_FLIGHT: usize = 16;
 
-        let start = files.first().unwrap().to_string_lossy();
-        let end = files.last().unwrap().to_string_lossy();
+/// Where (and which) entries are stored
+struct Store {
+    db: Db,
+    rules: ExclusionSet,
+    /// Number of new entries to collect before applying them in one batch
+    flush_every: usize,
+}
 
-        log::info!(
-            "Starting batch ({} / {})...\n{}\n{}",
-            i + 1,
-            n_batches,
-            format!("From: {}", start).dimmed(),
-            format!("To:   {}", end).dimmed()
-        );
+/// Counts of what became of the entries read from indexer output
+#[derive(Default, Clone, Copy)]
+struct StoreStats {
+    n_new: usize,
+    n_duplicate: usize,
+    n_excluded: usize,
+    /// Entries which could not be read, such as the last entry of an indexer
+    /// which was killed while writing it
+    n_malformed: usize,
+}
 
-        let start = Instant::now();
-        process_files(&mut db, files, &mut rng).await.context("Failed to run batch")?;
-        log::info!("Completed batch in {} secs", start.elapsed().as_secs_f32());
+impl StoreStats {
+    fn add(&mut self, other: &StoreStats) {
+        self.n_new += other.n_new;
+        self.n_duplicate += other.n_duplicate;
+        self.n_excluded += other.n_excluded;
+        self.n_malformed += other.n_malformed;
     }
+}
 
-    Ok(())
+/// Writes waiting to be applied to the database together
+#[derive(Default)]
+struct PendingWrites {
+    entries: sled::Batch,
+    provenance: sled::Batch,
+    /// The keys of `entries`, since a batch cannot be searched
+    keys: HashSet<[u8; 32]>,
+}
+
+impl PendingWrites {
+    fn apply(&mut self, db: &Db, provenance_tree: &sled::Tree) -> sled::Result<()> {
+        let pending = std::mem::take(self);
+
+        if pending.keys.is_empty() {
+            return Ok(());
+        }
+
+        db.apply_batch(pending.entries)?;
+        provenance_tree.apply_batch(pending.provenance)?;
+        db.flush()?;
+        log::debug!("Stored a batch of {} new entries", pending.keys.len());
+        Ok(())
+    }
+}
+
+/// Reads the stdout of an indexer a chunk at a time as it arrives, so that
+/// entries can be stored on a blocking thread while the indexer still runs
+struct ChunkReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ChunkReader {
+    fn new(receiver: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self { receiver, chunk: Vec::new(), pos: 0 }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Send the stdout of an indexer to `sender` a chunk at a time
+async fn pump(mut stdout: ChildStdout, sender: mpsc::Sender<Vec<u8>>) -> std::io::Result<()> {
+    loop {
+        let mut chunk = vec![0; CHUNK_BYTES];
+        let n = stdout.read(&mut chunk).await?;
+
+        if n == 0 {
+            return Ok(());
+        }
+
+        chunk.truncate(n);
+
+        // The receiver is only dropped if storing failed, which is reported
+        // by the storing task
+        if sender.send(chunk).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// How to run the indexer on each kzip
+struct IndexerRun {
+    indexer: PathBuf,
+    indexer_args: Vec<String>,
+    indexer_version: String,
+    /// How long a process may run before it is killed
+    timeout: Option<Duration>,
+    /// How many more times to run the indexer on a kzip after it fails
+    retries: usize,
+}
+
+/// Number of trailing lines of stderr kept for the failure report
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Why a run of the indexer did not succeed
+struct Failure {
+    /// One of `spawn`, `io`, `store`, `exit`, or `timeout`
+    kind: &'static str,
+    /// The error, exit status, or time limit
+    detail: String,
+    /// The exit code, if the process exited on its own
+    code: Option<i32>,
+    /// The last lines the indexer printed to stderr
+    stderr_tail: Vec<String>,
+}
+
+impl Failure {
+    fn from_error(kind: &'static str, err: &std::io::Error) -> Self {
+        let kind = match err.kind() {
+            std::io::ErrorKind::TimedOut => "timeout",
+            _ => kind,
+        };
+        Self { kind, detail: err.to_string(), code: None, stderr_tail: Vec::new() }
+    }
 }
 
-async fn process_files<R: Rng>(db: &mut Db, files: Vec<PathBuf>, rng: &mut R) -> Result<()> {
+/// A kzip the indexer failed on, even after retrying
+struct FailedKzip {
+    kzip: String,
+    attempts: usize,
+    failure: Failure,
+}
+
+/// The outcome of running the indexer on one kzip (perhaps several times)
+type RunOutcome = (PathBuf, usize, Result<StoreStats, Failure>);
+
+/// Run the indexer on every file, keeping `concurrency` processes running at
+/// once, and store their entries in `store`. Returns the totals of what was
+/// stored and the files which failed (or timed out) on every attempt.
+async fn process_files(
+    run: Arc<IndexerRun>,
+    store: Arc<Store>,
+    files: Vec<PathBuf>,
+    concurrency: usize,
+) -> Result<(StoreStats, Vec<FailedKzip>)> {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let n_files = files.len();
+    let mut files = files.into_iter();
     let mut join_set = JoinSet::new();
+    let mut n_done = 0;
+    let mut stats = StoreStats::default();
+    let mut failed = Vec::new();
+
+    loop {
+        tokio::select! {
+            // Only start a process once another has released its permit
+            permit = Arc::clone(&semaphore).acquire_owned(), if !files.as_slice().is_empty() => {
+                let permit = permit.context("Failed to acquire permit")?;
+                let file = files.next().unwrap();
+                let run = Arc::clone(&run);
+                let store = Arc::clone(&store);
+                let semaphore = Arc::clone(&semaphore);
+                join_set.spawn(run_with_retries(run, store, semaphore, permit, file));
+            }
+            Some(res) = join_set.join_next() => {
+                let (file, attempts, outcome) = res.context("Failed to join tasks...")?;
+                n_done += 1;
+                log::info!(
+                    "Finished ({} / {}) {}",
+                    n_done,
+                    n_files,
+                    file.to_string_lossy().dimmed()
+                );
+
+                let kzip = paths::normalize(&file.to_string_lossy());
+
+                match outcome {
+                    Ok(file_stats) => {
+                        log::debug!(
+                            concat!(
+                                "Stored {} new entries from `{}` ",
+                                "({} duplicate, {} excluded, {} malformed)"
+                            ),
+                            file_stats.n_new,
+                            kzip,
+                            file_stats.n_duplicate,
+                            file_stats.n_excluded,
+                            file_stats.n_malformed
+                        );
+                        stats.add(&file_stats);
+                    }
+                    Err(failure) => {
+                        log::error!("Indexer failed on `{}` ({})", kzip, failure.detail);
+                        failed.push(FailedKzip { kzip, attempts, failure });
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+
+    Ok((stats, failed))
+}
 
-    for file in files {
+/// Run the indexer on `file`, retrying (after a delay, without holding a
+/// permit) up to `run.retries` times. Returns the file, the number of
+/// attempts, and the outcome of the last attempt.
+async fn run_with_retries(
+    run: Arc<IndexerRun>,
+    store: Arc<Store>,
+    semaphore: Arc<Semaphore>,
+    permit: OwnedSemaphorePermit,
+    file: PathBuf,
+) -> RunOutcome {
+    let mut permit = permit;
+    let mut attempts = 1;
+
+    loop {
         log::debug!("Starting process for `{}`...", file.to_string_lossy());
-        join_set.spawn(dummy_cmd(rng).output());
+
+        match run_once(&run, &store, &file).await {
+            Err(failure) if attempts <= run.retries => {
+                let delay = retry_delay(attempts);
+                log::warn!(
+                    "Indexer failed on `{}` ({}), retrying in {} secs",
+                    file.to_string_lossy(),
+                    failure.detail,
+                    delay.as_secs()
+                );
+
+                drop(permit);
+                tokio::time::sleep(delay).await;
+                permit = Arc::clone(&semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("The semaphore is never closed");
+                attempts += 1;
+            }
+            outcome => return (file, attempts, outcome),
+        }
     }
+}
+
+/// Run the indexer on `file` once, storing its entries as its stdout is read
+/// rather than once it exits, so that memory use does not grow with the size
+/// of the output. Entries are kept even if the run goes on to fail (a retry
+/// then counts them as duplicates). Failing to store them fails the run, so
+/// that it is retried like any other failure.
+async fn run_once(
+    run: &IndexerRun,
+    store: &Arc<Store>,
+    file: &Path,
+) -> Result<StoreStats, Failure> {
+    let mut child = match indexer_cmd(&run.indexer, &run.indexer_args, file).spawn() {
+        Ok(child) => child,
+        Err(err) => return Err(Failure::from_error("spawn", &err)),
+    };
+
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+
+    let provenance = Provenance {
+        kzip: PathBuf::from(paths::normalize(&file.to_string_lossy())),
+        indexer: run.indexer.clone(),
+        indexer_version: run.indexer_version.clone(),
+    };
+    let storing = {
+        let store = Arc::clone(store);
+        let reader = ChunkReader::new(receiver);
+        tokio::task::spawn_blocking(move || store_output(&store, reader, &provenance))
+    };
+
+    // On a timeout, this is dropped along with the child, which kills it
+    let finished = async move {
+        let mut stderr_bytes = Vec::new();
+        let (pumped, read) =
+            tokio::join!(pump(stdout, sender), stderr.read_to_end(&mut stderr_bytes));
+        pumped?;
+        read?;
+        Ok((child.wait().await?, stderr_bytes))
+    };
+
+    let finished = with_timeout(finished, run.timeout).await;
+
+    // If storing failed, the indexer most likely failed too for want of a
+    // reader, so the storing error is the one to report
+    let stats = match storing.await.context("Failed to join storing task") {
+        Ok(Ok(stats)) => stats,
+        Ok(Err(err)) | Err(err) => {
+            let detail = format!("{:#}", err);
+            return Err(Failure { kind: "store", detail, code: None, stderr_tail: Vec::new() });
+        }
+    };
+
+    check_status(file, finished).map(|()| stats)
+}
 
-    while let Some(res) = join_set.join_next().await {
-        let output = res
-            .context("Failed to join tasks...")?
-            .context("Encountered error running process...")?;
+/// How long to wait before the given retry, doubling from one second up to
+/// about a minute
+fn retry_delay(attempt: usize) -> Duration {
+    Duration::from_secs(1 << (attempt - 1).min(6))
+}
 
-        log::debug!("Collected {} bytes from stdout", output.stdout.len());
+/// Wait for `future`, giving up after `timeout` (if any)
+async fn with_timeout<T>(
+    future: impl std::future::Future<Output = std::io::Result<T>>,
+    timeout: Option<Duration>,
+) -> std::io::Result<T> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return future.await,
+    };
+
+    match tokio::time::timeout(timeout, future).await {
+        Ok(res) => res,
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("killed after {} secs", timeout.as_secs()),
+        )),
+    }
+}
 
-        // store_entries(db, output.stdout)?;
+/// Check whether a run of the indexer succeeded, logging what it printed to
+/// stderr
+fn check_status(
+    file: &Path,
+    finished: std::io::Result<(ExitStatus, Vec<u8>)>,
+) -> Result<(), Failure> {
+    let kzip = file.to_string_lossy();
+    let (status, stderr) = match finished {
+        Ok(finished) => finished,
+        Err(err) => return Err(Failure::from_error("io", &err)),
+    };
+
+    // The indexer prints its log messages to stderr
+    let stderr = String::from_utf8_lossy(&stderr);
+
+    for line in stderr.lines() {
+        log::debug!("{}: {}", kzip, line);
+    }
 
-        // TODO: log stderr as warn or debug or error?
-        // I think the indexer prints log messages to stderr
+    if status.success() {
+        return Ok(());
     }
 
-    Ok(())
+    let lines = stderr.lines().collect_vec();
+    let tail = &lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..];
+
+    Err(Failure {
+        kind: "exit",
+        detail: status.to_string(),
+        code: status.code(),
+        stderr_tail: tail.iter().map(|line| line.to_string()).collect(),
+    })
+}
+
+/// Write each failed kzip as a JSON object in an array
+fn write_failure_report(path: &Path, failed: &[FailedKzip]) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create `{}`", path.to_string_lossy()))?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "[")?;
+
+    for (i, failed) in failed.iter().enumerate() {
+        let failure = &failed.failure;
+        let code = match failure.code {
+            Some(code) => code.to_string(),
+            None => String::from("null"),
+        };
+        let stderr_tail = failure.stderr_tail.iter().map(|line| json_string(line)).join(",");
+
+        write!(
+            writer,
+            concat!(
+                "  {{\"kzip\":{},\"attempts\":{},\"kind\":{},",
+                "\"detail\":{},\"code\":{},\"stderr_tail\":[{}]}}"
+            ),
+            json_string(&failed.kzip),
+            failed.attempts,
+            json_string(failure.kind),
+            json_string(&failure.detail),
+            code,
+            stderr_tail
+        )?;
+        writeln!(writer, "{}", if i + 1 < failed.len() { "," } else { "" })?;
+    }
+
+    writeln!(writer, "]")?;
+    writer.flush().context("Failed to write failure report")
+}
+
+/// The command which indexes `kzip`, writing its entries to stdout
+fn indexer_cmd(indexer: &Path, indexer_args: &[String], kzip: &Path) -> Command {
+    let mut command = Command::new(indexer);
+    command
+        .args(indexer_args)
+        .arg(kzip)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    command
 }
 
-fn store_entries(db: &mut Db, bytes: Vec<u8>) -> Result<()> {
-    todo!();
+/// Store each entry read from `stdout` in `store.db` unless it is excluded by
+/// `store.rules`, recording that it came from `provenance` (see
+/// `record_provenance`).
+///
+/// `stdout` may hold entries as JSON lines or as delimited protobuf (the
+/// default output of Kythe indexers). Each entry is stored as a line of JSON
+/// keyed by its content hash, so entries already stored (or pending) are
+/// counted as duplicates rather than stored again. New entries are applied
+/// every `store.flush_every` entries, so only that many are held in memory.
+/// Malformed entries (including one cut short when the indexer is killed)
+/// are skipped and counted.
+fn store_output(
+    store: &Store,
+    stdout: impl Read + 'static,
+    provenance: &Provenance,
+) -> Result<StoreStats> {
+    let db = &store.db;
+    let provenance_tree =
+        db.open_tree(PROVENANCE_TREE).context("Failed to open provenance tree")?;
+    let source = register_source(db, provenance)?;
+    let mut pending = PendingWrites::default();
+    let mut stats = StoreStats::default();
+
+    stats.n_malformed = EntryLineReader::from_read(stdout)
+        .lenient(true)
+        .for_each_ref(|line, entry| {
+            if store.rules.is_excluded(entry) {
+                stats.n_excluded += 1;
+                return Ok(());
+            }
+
+            let key = content_hash(entry);
+
+            if !pending.keys.insert(key) || db.contains_key(key)? {
+                stats.n_duplicate += 1;
+                return Ok(());
+            }
+
+            pending.entries.insert(&key[..], line.trim_end().as_bytes());
+            record_provenance(&mut pending, &key, source);
+            stats.n_new += 1;
+
+            if pending.keys.len() >= store.flush_every {
+                pending.apply(db, &provenance_tree)?;
+            }
+
+            Ok(())
+        })
+        .context("Failed to store indexer output")?;
+
+    pending.apply(db, &provenance_tree).context("Failed to write entries")?;
+    Ok(stats)
 }
 
 fn collect_files(glob_pattern: &String) -> Result<Vec<PathBuf>> {
@@ -180,49 +808,88 @@ fn collect_files(glob_pattern: &String) -> Result<Vec<PathBuf>> {
 }
 
 async fn dump(args: CliDumpCommand) -> Result<()> {
-    Ok(())
-}
+    if !args.db.exists() {
+        anyhow::bail!("Database `{}` does not exist", args.db.to_string_lossy());
+    }
 
-#[tokio::main]
-async fn main2() -> Result<(), Box<dyn std::error::Error>> {
-    let mut rng = rand::thread_rng();
+    let db = sled::open(&args.db).context("Failed to open database")?;
+    let output: Box<dyn Write> = match &args.output {
+        None => Box::new(std::io::stdout().lock()),
+        Some(path) => Box::new(
+            File::create(path)
+                .with_context(|| format!("Failed to create `{}`", path.to_string_lossy()))?,
+        ),
+    };
 
     let start = Instant::now();
+    let writer = BufWriter::new(output);
+    let n_entries = match args.compress {
+        true => {
+            let mut writer = compress(writer);
+            let n_entries = dump_entries(&db, &mut writer, args.provenance)?;
+            writer.finish().context("Failed to write output")?.flush()?;
+            n_entries
+        }
+        false => {
+            let mut writer = writer;
+            let n_entries = dump_entries(&db, &mut writer, args.provenance)?;
+            writer.flush().context("Failed to write output")?;
+            n_entries
+        }
+    };
 
-    println!("Staring A...");
-    let fut_a = dummy_cmd(&mut rng).output();
-
-    println!("Staring B...");
-    let fut_b = dummy_cmd(&mut rng).output();
-
-    println!("Staring C...");
-    let fut_c = dummy_cmd(&mut rng).output();
-
-    let (res_a, res_b, res_c) = join!(fut_a, fut_b, fut_c);
-
-    println!("---A---\n{}", String::from_utf8(res_a?.stdout)?);
-    println!("---B---\n{}", String::from_utf8(res_b?.stdout)?);
-    println!("---C---\n{}", String::from_utf8(res_c?.stdout)?);
-
-    let elapsed = start.elapsed();
-    println!("Completed in {} secs.", elapsed.as_secs_f32());
-
+    log::info!("Wrote {} entries in {} secs", n_entries, start.elapsed().as_secs_f32());
     Ok(())
 }
 
-fn dummy_cmd<R: Rng>(rng: &mut R) -> Command {
-    let mut command = Command::new("ping");
-    command.arg(gen_local_ip_str(rng));
-    command
-}
+/// Write each entry of `db` as a line of JSON, returning the number written.
+/// With `provenance`, the kzip and indexer of each entry are added as extra
+/// fields (which readers of entries ignore).
+fn dump_entries<W: Write>(db: &Db, writer: &mut W, provenance: bool) -> Result<usize> {
+    let mut n_entries = 0;
+
+    for item in db.iter() {
+        let (key, value) = item.context("Failed to read database")?;
+        let source = match provenance {
+            true => lookup_provenance(db, &key)?,
+            false => None,
+        };
+
+        match (value.as_ref().strip_suffix(b"}"), source) {
+            (Some(line), Some(source)) => {
+                writer.write_all(line)?;
+                write!(
+                    writer,
+                    ",\"kzip\":{},\"indexer\":{},\"indexer_version\":{}}}",
+                    json_string(&source.kzip.to_string_lossy()),
+                    json_string(&source.indexer.to_string_lossy()),
+                    json_string(&source.indexer_version)
+                )?;
+            }
+            _ => writer.write_all(&value)?,
+        }
 
-fn gen_local_ip_str<R: Rng>(rng: &mut R) -> String {
-    let block1 = rng.gen::<u8>();
-    let block2 = rng.gen::<u8>();
-    let block3 = rng.gen::<u8>();
-    format!("127.{}.{}.{}", block1, block2, block3)
+        writer.write_all(b"\n")?;
+        n_entries += 1;
+    }
+
+    Ok(n_entries)
 }
 
-fn div_ceil(a: usize, b: usize) -> usize {
-    (a + b - 1) / b
+/// Quote `text` as a JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
 }
@@ -0,0 +1,56 @@
+/*
+ * C API for loading sft snapshots and querying their entities, deps, and
+ * metrics. See sft-ffi/src/lib.rs for details.
+ *
+ * Functions which can fail return NULL or a negative number, in which case
+ * sft_last_error() describes what went wrong.
+ */
+
+#ifndef SFT_H
+#define SFT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SftGraph SftGraph;
+
+/* A dep in its semantic direction. */
+typedef struct SftDep {
+    uint64_t src;
+    uint64_t tgt;
+    /* 0 = contains, 1 = defined by, 2 = depends on, 3 = other. */
+    uint32_t relation;
+    uint64_t count;
+} SftDep;
+
+typedef struct SftFileMetrics {
+    uint64_t entities;
+    uint64_t fan_in;
+    uint64_t fan_out;
+    uint64_t deps_in;
+    uint64_t deps_out;
+} SftFileMetrics;
+
+const char *sft_last_error(void);
+
+SftGraph *sft_graph_load_snapshot(const char *path);
+void sft_graph_free(SftGraph *graph);
+
+uint64_t sft_graph_num_entities(const SftGraph *graph);
+int64_t sft_graph_entities_in_path(const SftGraph *graph, const char *path, uint64_t *out,
+                                   size_t cap);
+const char *sft_entity_name(const SftGraph *graph, uint64_t id);
+const char *sft_entity_kind(const SftGraph *graph, uint64_t id);
+uint64_t sft_entity_stable_id(const SftGraph *graph, uint64_t id);
+int64_t sft_graph_deps_of(const SftGraph *graph, uint64_t id, SftDep *out, size_t cap);
+int sft_graph_file_metrics(const SftGraph *graph, const char *path, SftFileMetrics *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SFT_H */
@@ -0,0 +1,434 @@
+//! A C API over `kythe-bridge`, so that tools written in other languages can
+//! load a snapshot and query its entities, deps, and metrics in process.
+//!
+//! See `include/sft.h` for the declarations. Every function which can fail
+//! returns a null pointer or a negative number, in which case
+//! `sft_last_error` describes what went wrong. A panic never unwinds into the
+//! caller. Instead, the function fails (or returns zero, if it cannot fail)
+//! and `sft_last_error` describes the panic.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use kythe_bridge::ir::{
+    EntityGraph, EntityGraphOptions, GroupBy, NodeIndex, RawGraph, Relation, SpecGraph,
+};
+use kythe_bridge::metrics::{file_metrics, FileMetrics};
+use kythe_bridge::snapshot::Snapshot;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_message(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn set_last_error(err: &dyn Error) {
+    let mut message = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    set_last_message(&message);
+}
+
+/// Run `f`, but if it panics, record the panic as the last error and return
+/// `on_panic` instead. Unwinding out of an `extern "C"` function is undefined
+/// behavior, so every one of them goes through this.
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+                (Some(message), _) => *message,
+                (_, Some(message)) => message.as_str(),
+                (None, None) => "unknown error",
+            };
+            set_last_message(&format!("panicked: {}", message));
+            on_panic
+        }
+    }
+}
+
+/// A loaded entity graph along with the indices needed to answer queries.
+pub struct SftGraph {
+    graph: EntityGraph,
+    names: HashMap<NodeIndex, CString>,
+    kinds: HashMap<NodeIndex, CString>,
+    by_path: HashMap<String, Vec<NodeIndex>>,
+    deps_by_src: HashMap<NodeIndex, Vec<usize>>,
+    metrics: HashMap<String, FileMetrics>,
+}
+
+impl SftGraph {
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let raw: RawGraph = Snapshot::read(path)?.into();
+        let spec = SpecGraph::try_from(raw)?;
+        let graph = EntityGraph::new(&spec, &EntityGraphOptions::default())?;
+
+        let mut names = HashMap::new();
+        let mut kinds = HashMap::new();
+        let mut by_path: HashMap<String, Vec<NodeIndex>> = HashMap::new();
+
+        for entity in graph.entities.values() {
+            names.insert(entity.id, CString::new(entity.name.replace('\0', " "))?);
+            kinds.insert(entity.id, CString::new(entity.kind.name())?);
+            by_path.entry(entity.path.clone()).or_default().push(entity.id);
+        }
+
+        for ids in by_path.values_mut() {
+            ids.sort();
+        }
+
+        // Index deps by their semantic source
+        let mut deps_by_src: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+
+        for (i, dep) in graph.deps.iter().enumerate() {
+            let (_, src, _) = dep.normalized();
+            deps_by_src.entry(src).or_default().push(i);
+        }
+
+        let metrics = file_metrics(&graph, GroupBy::Path)
+            .into_iter()
+            .map(|row| (row.path.clone(), row))
+            .collect();
+
+        Ok(SftGraph { graph, names, kinds, by_path, deps_by_src, metrics })
+    }
+}
+
+/// A dep in its semantic direction (see `Relation`).
+#[repr(C)]
+pub struct SftDep {
+    pub src: u64,
+    pub tgt: u64,
+    /// 0 = contains, 1 = defined by, 2 = depends on, 3 = other.
+    pub relation: u32,
+    pub count: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct SftFileMetrics {
+    pub entities: u64,
+    pub fan_in: u64,
+    pub fan_out: u64,
+    pub deps_in: u64,
+    pub deps_out: u64,
+}
+
+/// Read a C string, recording an error if it is null or not UTF-8.
+unsafe fn to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        set_last_message("unexpected null pointer");
+        return None;
+    }
+
+    match CStr::from_ptr(ptr).to_str() {
+        Ok(str) => Some(str),
+        Err(err) => {
+            set_last_error(&err);
+            None
+        }
+    }
+}
+
+/// The message of the last error on this thread, or null if there has been
+/// none. The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn sft_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|last| match &*last.borrow() {
+            Some(message) => message.as_ptr(),
+            None => ptr::null(),
+        })
+    })
+}
+
+/// Load the snapshot (or cache) at `path`. Returns null on failure. The graph
+/// must be released with `sft_graph_free`.
+///
+/// # Safety
+///
+/// `path` must be a valid, null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_load_snapshot(path: *const c_char) -> *mut SftGraph {
+    guard(ptr::null_mut(), || {
+        let path = match to_str(path) {
+            Some(path) => path,
+            None => return ptr::null_mut(),
+        };
+
+        match SftGraph::load(Path::new(path)) {
+            Ok(graph) => Box::into_raw(Box::new(graph)),
+            Err(err) => {
+                set_last_error(err.as_ref());
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// # Safety
+///
+/// `graph` must have come from `sft_graph_load_snapshot` (or be null) and must
+/// not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_free(graph: *mut SftGraph) {
+    guard((), || {
+        if !graph.is_null() {
+            drop(Box::from_raw(graph));
+        }
+    })
+}
+
+/// # Safety
+///
+/// `graph` must be a valid graph.
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_num_entities(graph: *const SftGraph) -> u64 {
+    guard(0, || (*graph).graph.entities.len() as u64)
+}
+
+/// Write the ids of the entities in the file at `path` into `out` (up to
+/// `cap` of them) in ascending order. Returns the total number of such
+/// entities, which may exceed `cap`, or -1 on failure.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph, `path` a null-terminated string, and `out`
+/// must have room for `cap` ids (it may be null if `cap` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_entities_in_path(
+    graph: *const SftGraph,
+    path: *const c_char,
+    out: *mut u64,
+    cap: usize,
+) -> i64 {
+    guard(-1, || {
+        let path = match to_str(path) {
+            Some(path) => path,
+            None => return -1,
+        };
+
+        let ids = match (*graph).by_path.get(path) {
+            Some(ids) => ids.as_slice(),
+            None => &[],
+        };
+
+        for (i, id) in ids.iter().take(cap).enumerate() {
+            *out.add(i) = id.0 as u64;
+        }
+
+        ids.len() as i64
+    })
+}
+
+/// The name of entity `id`, or null if there is no such entity. The string is
+/// owned by the graph.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph.
+#[no_mangle]
+pub unsafe extern "C" fn sft_entity_name(graph: *const SftGraph, id: u64) -> *const c_char {
+    guard(ptr::null(), || match (*graph).names.get(&NodeIndex(id as usize)) {
+        Some(name) => name.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// The Kythe node kind (e.g. "function") of entity `id`, or null if there is
+/// no such entity. The string is owned by the graph.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph.
+#[no_mangle]
+pub unsafe extern "C" fn sft_entity_kind(graph: *const SftGraph, id: u64) -> *const c_char {
+    guard(ptr::null(), || match (*graph).kinds.get(&NodeIndex(id as usize)) {
+        Some(kind) => kind.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// The stable id of entity `id` (see `StableId`), which is the same across
+/// snapshots of slightly different inputs, or 0 if there is no such entity.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph.
+#[no_mangle]
+pub unsafe extern "C" fn sft_entity_stable_id(graph: *const SftGraph, id: u64) -> u64 {
+    guard(0, || match (*graph).graph.entities.get(&NodeIndex(id as usize)) {
+        Some(entity) => entity.stable_id.0,
+        None => 0,
+    })
+}
+
+/// Write the deps whose (semantic) source is entity `id` into `out` (up to
+/// `cap` of them). Returns the total number of such deps, which may exceed
+/// `cap`.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph and `out` must have room for `cap` deps (it
+/// may be null if `cap` is 0).
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_deps_of(
+    graph: *const SftGraph,
+    id: u64,
+    out: *mut SftDep,
+    cap: usize,
+) -> i64 {
+    guard(-1, || {
+        let graph = &*graph;
+        let deps = match graph.deps_by_src.get(&NodeIndex(id as usize)) {
+            Some(deps) => deps.as_slice(),
+            None => &[],
+        };
+
+        for (i, dep) in deps.iter().take(cap).enumerate() {
+            let dep = &graph.graph.deps[*dep];
+            let (relation, src, tgt) = dep.normalized();
+            let relation = match relation {
+                Relation::Contains => 0,
+                Relation::DefinedBy => 1,
+                Relation::DependsOn => 2,
+                Relation::Other => 3,
+            };
+
+            *out.add(i) =
+                SftDep { src: src.0 as u64, tgt: tgt.0 as u64, relation, count: dep.count as u64 };
+        }
+
+        deps.len() as i64
+    })
+}
+
+/// Fill `out` with the metrics of the file at `path`. Returns 0 on success, 1
+/// if there is no such file, or -1 on failure.
+///
+/// # Safety
+///
+/// `graph` must be a valid graph, `path` a null-terminated string, and `out`
+/// a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn sft_graph_file_metrics(
+    graph: *const SftGraph,
+    path: *const c_char,
+    out: *mut SftFileMetrics,
+) -> c_int {
+    guard(-1, || {
+        let path = match to_str(path) {
+            Some(path) => path,
+            None => return -1,
+        };
+
+        match (*graph).metrics.get(path) {
+            Some(row) => {
+                *out = SftFileMetrics {
+                    entities: row.entities as u64,
+                    fan_in: row.fan_in as u64,
+                    fan_out: row.fan_out as u64,
+                    deps_in: row.deps_in as u64,
+                    deps_out: row.deps_out as u64,
+                };
+                0
+            }
+            None => 1,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kythe_bridge::io::Entry;
+
+    fn node(signature: &str, fact_name: &str, fact_value: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"source": {{"corpus": "c", "path": "a.cc", "signature": "{}"}}, "#,
+                r#""fact_name": "{}", "fact_value": "{}"}}"#
+            ),
+            signature, fact_name, fact_value
+        )
+    }
+
+    #[test]
+    fn test_load_and_query() {
+        let dir = std::env::temp_dir().join(format!("sft-ffi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("graph.snap");
+
+        let edge = concat!(
+            r#"{"source": {"corpus": "c", "path": "a.cc", "signature": "f"}, "#,
+            r#""target": {"corpus": "c", "path": "a.cc", "signature": "g"}, "#,
+            r#""edge_kind": "/kythe/edge/ref/call", "fact_name": "/"}"#
+        );
+        let entries = [
+            node("", "/kythe/node/kind", "ZmlsZQ=="),
+            node("", "/kythe/text", "dm9pZCBmKCk7CnZvaWQgZygpOwo="),
+            node("f", "/kythe/node/kind", "ZnVuY3Rpb24="),
+            node("g", "/kythe/node/kind", "ZnVuY3Rpb24="),
+            edge.to_string(),
+        ];
+        let entries = entries.iter().map(|json| Entry::from_json(json).unwrap());
+        Snapshot::from(RawGraph::from_entries(entries).unwrap()).write(&path).unwrap();
+
+        unsafe {
+            let path = CString::new(path.to_str().unwrap()).unwrap();
+            let graph = sft_graph_load_snapshot(path.as_ptr());
+            assert!(!graph.is_null());
+            assert_eq!(sft_graph_num_entities(graph), 3);
+
+            let file = CString::new("a.cc").unwrap();
+            let mut ids = [0; 3];
+            assert_eq!(sft_graph_entities_in_path(graph, file.as_ptr(), ids.as_mut_ptr(), 3), 3);
+
+            let calls = ids
+                .iter()
+                .flat_map(|id| {
+                    let mut deps = [SftDep { src: 0, tgt: 0, relation: 0, count: 0 }];
+                    let n = sft_graph_deps_of(graph, *id, deps.as_mut_ptr(), 1);
+                    deps.into_iter().take(n as usize)
+                })
+                .filter(|dep| dep.relation == 2)
+                .collect::<Vec<_>>();
+            assert_eq!(calls.len(), 1);
+            assert_eq!(
+                CStr::from_ptr(sft_entity_kind(graph, calls[0].tgt)).to_str(),
+                Ok("function")
+            );
+            assert!(sft_entity_name(graph, u64::MAX).is_null());
+
+            assert_eq!(sft_graph_entities_in_path(graph, ptr::null(), ptr::null_mut(), 0), -1);
+            assert_eq!(CStr::from_ptr(sft_last_error()).to_str(), Ok("unexpected null pointer"));
+            sft_graph_free(graph);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_guard() {
+        assert_eq!(guard(-1, || 1), 1);
+        assert_eq!(guard(-1, || panic!("oops {}", 1)), -1);
+        let message = unsafe { CStr::from_ptr(sft_last_error()) };
+        assert_eq!(message.to_str(), Ok("panicked: oops 1"));
+    }
+}